@@ -0,0 +1,86 @@
+-- PostgreSQL counterpart of migrations/sqlite/0001_initial_schema.sql. Integer columns
+-- are BIGINT throughout so they decode as i64 like SQLite's INTEGER.
+
+CREATE TABLE IF NOT EXISTS agent_records (
+  vm_id TEXT PRIMARY KEY,
+  telegram_chat_id BIGINT,
+  up_secs BIGINT DEFAULT 0,
+  paid_secs BIGINT DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS giftcards (
+  id BIGSERIAL PRIMARY KEY,
+  telegram_chat_id BIGINT NOT NULL,
+  code TEXT NOT NULL,
+  days BIGINT NOT NULL,
+  created_at BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS claims (
+  idempotency_key TEXT PRIMARY KEY,
+  telegram_chat_id BIGINT NOT NULL,
+  days BIGINT NOT NULL,
+  status TEXT NOT NULL,
+  response TEXT,
+  created_at BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS claim_locks (
+  telegram_chat_id BIGINT PRIMARY KEY,
+  idempotency_key TEXT NOT NULL,
+  days BIGINT NOT NULL,
+  locked_at BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS pending_claims (
+  idempotency_key TEXT PRIMARY KEY,
+  telegram_chat_id BIGINT NOT NULL,
+  days BIGINT NOT NULL,
+  attempts BIGINT NOT NULL,
+  next_attempt_at BIGINT NOT NULL,
+  last_error TEXT
+);
+
+CREATE TABLE IF NOT EXISTS vm_status (
+  vm_id TEXT PRIMARY KEY,
+  last_seen BIGINT NOT NULL,
+  first_seen BIGINT,
+  online_since BIGINT,
+  offline_alerted_at BIGINT
+);
+
+CREATE TABLE IF NOT EXISTS registration_tokens (
+  token TEXT PRIMARY KEY,
+  vm_id TEXT NOT NULL,
+  created_at BIGINT NOT NULL,
+  expires_at BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS uptime_history (
+  vm_id TEXT NOT NULL,
+  day TEXT NOT NULL,
+  up_secs BIGINT NOT NULL,
+  PRIMARY KEY (vm_id, day)
+);
+
+CREATE TABLE IF NOT EXISTS pending_deletions (
+  chat_id BIGINT NOT NULL,
+  message_id BIGINT NOT NULL,
+  delete_at BIGINT NOT NULL,
+  PRIMARY KEY (chat_id, message_id)
+);
+
+CREATE TABLE IF NOT EXISTS outages (
+  id BIGSERIAL PRIMARY KEY,
+  vm_id TEXT NOT NULL,
+  started_at BIGINT NOT NULL,
+  ended_at BIGINT
+);
+
+CREATE TABLE IF NOT EXISTS user_prefs (
+  telegram_chat_id BIGINT PRIMARY KEY,
+  weekly_digest BIGINT NOT NULL DEFAULT 0,
+  digest_sent_at BIGINT,
+  daily_notify BIGINT NOT NULL DEFAULT 1,
+  offline_alerts BIGINT NOT NULL DEFAULT 1
+);
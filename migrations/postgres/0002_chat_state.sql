@@ -0,0 +1,7 @@
+-- Where each chat stands in a multi-step conversation, so it can be resumed later
+CREATE TABLE chat_state (
+  telegram_chat_id BIGINT PRIMARY KEY,
+  dialogue TEXT NOT NULL,
+  data TEXT,
+  updated_at BIGINT NOT NULL
+);
@@ -0,0 +1,14 @@
+-- Time-limited reward multipliers scheduled by admins
+CREATE TABLE reward_events (
+  id BIGSERIAL PRIMARY KEY,
+  starts_at BIGINT NOT NULL,
+  ends_at BIGINT NOT NULL,
+  multiplier DOUBLE PRECISION NOT NULL,
+  start_announced BIGINT NOT NULL DEFAULT 0,
+  end_announced BIGINT NOT NULL DEFAULT 0
+);
+
+-- Extra reward seconds credited by events, kept apart from real uptime
+ALTER TABLE agent_records ADD COLUMN bonus_secs BIGINT NOT NULL DEFAULT 0;
+
+ALTER TABLE user_prefs ADD COLUMN event_announcements BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,10 @@
+-- Record of testers moving their balance and history from a reprovisioned VM to its
+-- successor with /replace
+CREATE TABLE vm_replacements (
+  id BIGSERIAL PRIMARY KEY,
+  telegram_chat_id BIGINT NOT NULL,
+  old_vm_id TEXT NOT NULL,
+  new_vm_id TEXT NOT NULL,
+  moved_up_secs BIGINT NOT NULL,
+  created_at BIGINT NOT NULL
+);
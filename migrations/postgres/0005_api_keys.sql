@@ -0,0 +1,14 @@
+-- Keys other Geph services use to call the embedded HTTP API. Only a SHA-256 hash of each
+-- key is kept; the key itself is shown once, when an admin creates it.
+CREATE TABLE api_keys (
+  id BIGSERIAL PRIMARY KEY,
+  name TEXT NOT NULL UNIQUE,
+  key_hash TEXT NOT NULL UNIQUE,
+  scope TEXT NOT NULL,
+  rate_per_min BIGINT NOT NULL,
+  created_at BIGINT NOT NULL,
+  revoked_at BIGINT,
+  request_count BIGINT NOT NULL DEFAULT 0,
+  rejected_count BIGINT NOT NULL DEFAULT 0,
+  last_used_at BIGINT
+);
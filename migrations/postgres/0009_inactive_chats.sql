@@ -0,0 +1,7 @@
+-- Chats Telegram refuses to deliver to (the user blocked the bot or deleted their
+-- account). Background loops skip them until the chat messages the bot again.
+CREATE TABLE inactive_chats (
+  telegram_chat_id BIGINT PRIMARY KEY,
+  reason TEXT NOT NULL,
+  since BIGINT NOT NULL
+);
@@ -0,0 +1,17 @@
+-- Admin broadcasts and how delivery went for each recipient. A delivery row is written
+-- per chat as it's attempted, so an interrupted broadcast shows exactly who got it.
+CREATE TABLE broadcasts (
+  id BIGSERIAL PRIMARY KEY,
+  admin_chat_id BIGINT NOT NULL,
+  text TEXT NOT NULL,
+  recipients BIGINT NOT NULL,
+  created_at BIGINT NOT NULL,
+  finished_at BIGINT
+);
+CREATE TABLE broadcast_deliveries (
+  broadcast_id BIGINT NOT NULL,
+  telegram_chat_id BIGINT NOT NULL,
+  error TEXT,
+  attempted_at BIGINT NOT NULL,
+  PRIMARY KEY (broadcast_id, telegram_chat_id)
+);
@@ -0,0 +1,15 @@
+-- Each tester's code for their referral link (`t.me/<bot>?start=ref-<code>`)
+CREATE TABLE referral_codes (
+  telegram_chat_id BIGINT PRIMARY KEY,
+  code TEXT NOT NULL UNIQUE
+);
+
+-- Who brought each new tester in. Both get bonus uptime once the referee's VMs have
+-- accumulated a full day; `rewarded_at` records that this has happened.
+CREATE TABLE referrals (
+  referee_chat_id BIGINT PRIMARY KEY,
+  referrer_chat_id BIGINT NOT NULL,
+  created_at BIGINT NOT NULL,
+  rewarded_at BIGINT
+);
+CREATE INDEX referrals_referrer ON referrals (referrer_chat_id);
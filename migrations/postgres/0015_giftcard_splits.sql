@@ -0,0 +1,5 @@
+-- How many cards a claim's days are split into; `days` stays the total, so each card is
+-- worth days / num_cards
+ALTER TABLE claim_locks ADD COLUMN num_cards BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE claims ADD COLUMN num_cards BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE pending_claims ADD COLUMN num_cards BIGINT NOT NULL DEFAULT 1;
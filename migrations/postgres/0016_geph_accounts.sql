@@ -0,0 +1,9 @@
+-- The Geph account each chat has linked, for crediting Plus days to it directly
+CREATE TABLE geph_accounts (
+  telegram_chat_id BIGINT PRIMARY KEY,
+  username TEXT NOT NULL,
+  linked_at BIGINT NOT NULL
+);
+
+-- Whether claims go straight to the linked account instead of producing giftcards
+ALTER TABLE user_prefs ADD COLUMN direct_credit BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+-- Support looks accounts up by username as well as by chat
+CREATE INDEX geph_accounts_username ON geph_accounts (username);
@@ -0,0 +1,6 @@
+-- Automatic claiming: `auto_claim` is 'sunday' (claim every Sunday) or 'threshold' (claim
+-- once `auto_claim_threshold` days are unclaimed); NULL means the tester claims by hand.
+-- `auto_claimed_on` is the last day a Sunday claim ran, so it runs once per Sunday.
+ALTER TABLE user_prefs ADD COLUMN auto_claim TEXT;
+ALTER TABLE user_prefs ADD COLUMN auto_claim_threshold BIGINT;
+ALTER TABLE user_prefs ADD COLUMN auto_claimed_on TEXT;
@@ -0,0 +1,6 @@
+-- Status messages pinned with /pin, which the bot keeps editing with live figures
+CREATE TABLE pinned_status (
+  telegram_chat_id BIGINT PRIMARY KEY,
+  message_id BIGINT NOT NULL,
+  pinned_at BIGINT NOT NULL
+);
@@ -0,0 +1,3 @@
+-- When each chat was last sent its daily unclaimed-days reminder, so restarts keep the
+-- cadence
+ALTER TABLE user_prefs ADD COLUMN notified_at BIGINT;
@@ -0,0 +1,2 @@
+-- The availability API a VM was last polled from; see `fleets` in the config
+ALTER TABLE agent_records ADD COLUMN fleet TEXT NOT NULL DEFAULT 'default';
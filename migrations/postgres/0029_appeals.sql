@@ -0,0 +1,12 @@
+-- Statements sent with /appeal by banned or flagged chats. `outcome` is 'accepted' or
+-- 'rejected' once an admin has decided; a chat has at most one appeal open at a time.
+CREATE TABLE appeals (
+  id BIGSERIAL PRIMARY KEY,
+  telegram_chat_id BIGINT NOT NULL,
+  statement TEXT NOT NULL,
+  created_at BIGINT NOT NULL,
+  outcome TEXT,
+  resolved_by BIGINT,
+  resolved_at BIGINT
+);
+CREATE UNIQUE INDEX appeals_open ON appeals (telegram_chat_id) WHERE resolved_at IS NULL;
@@ -0,0 +1,86 @@
+-- Schema as it stood when migrations were introduced. Every statement is idempotent so
+-- the migration also applies cleanly to databases created by the old inline initializer.
+
+CREATE TABLE IF NOT EXISTS agent_records (
+  vm_id TEXT PRIMARY KEY,
+  telegram_chat_id INTEGER,
+  up_secs INTEGER DEFAULT 0,
+  paid_secs INTEGER DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS giftcards (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  telegram_chat_id INTEGER NOT NULL,
+  code TEXT NOT NULL,
+  days INTEGER NOT NULL,
+  created_at INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS claims (
+  idempotency_key TEXT PRIMARY KEY,
+  telegram_chat_id INTEGER NOT NULL,
+  days INTEGER NOT NULL,
+  status TEXT NOT NULL,
+  response TEXT,
+  created_at INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS claim_locks (
+  telegram_chat_id INTEGER PRIMARY KEY,
+  idempotency_key TEXT NOT NULL,
+  days INTEGER NOT NULL,
+  locked_at INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS pending_claims (
+  idempotency_key TEXT PRIMARY KEY,
+  telegram_chat_id INTEGER NOT NULL,
+  days INTEGER NOT NULL,
+  attempts INTEGER NOT NULL,
+  next_attempt_at INTEGER NOT NULL,
+  last_error TEXT
+);
+
+CREATE TABLE IF NOT EXISTS vm_status (
+  vm_id TEXT PRIMARY KEY,
+  last_seen INTEGER NOT NULL,
+  first_seen INTEGER,
+  online_since INTEGER,
+  offline_alerted_at INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS registration_tokens (
+  token TEXT PRIMARY KEY,
+  vm_id TEXT NOT NULL,
+  created_at INTEGER NOT NULL,
+  expires_at INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS uptime_history (
+  vm_id TEXT NOT NULL,
+  day TEXT NOT NULL,
+  up_secs INTEGER NOT NULL,
+  PRIMARY KEY (vm_id, day)
+);
+
+CREATE TABLE IF NOT EXISTS pending_deletions (
+  chat_id INTEGER NOT NULL,
+  message_id INTEGER NOT NULL,
+  delete_at INTEGER NOT NULL,
+  PRIMARY KEY (chat_id, message_id)
+);
+
+CREATE TABLE IF NOT EXISTS outages (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  vm_id TEXT NOT NULL,
+  started_at INTEGER NOT NULL,
+  ended_at INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS user_prefs (
+  telegram_chat_id INTEGER PRIMARY KEY,
+  weekly_digest INTEGER NOT NULL DEFAULT 0,
+  digest_sent_at INTEGER,
+  daily_notify INTEGER NOT NULL DEFAULT 1,
+  offline_alerts INTEGER NOT NULL DEFAULT 1
+);
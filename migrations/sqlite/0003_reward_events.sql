@@ -0,0 +1,14 @@
+-- Time-limited reward multipliers scheduled by admins
+CREATE TABLE reward_events (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  starts_at INTEGER NOT NULL,
+  ends_at INTEGER NOT NULL,
+  multiplier REAL NOT NULL,
+  start_announced INTEGER NOT NULL DEFAULT 0,
+  end_announced INTEGER NOT NULL DEFAULT 0
+);
+
+-- Extra reward seconds credited by events, kept apart from real uptime
+ALTER TABLE agent_records ADD COLUMN bonus_secs INTEGER NOT NULL DEFAULT 0;
+
+ALTER TABLE user_prefs ADD COLUMN event_announcements INTEGER NOT NULL DEFAULT 0;
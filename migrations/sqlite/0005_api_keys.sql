@@ -0,0 +1,14 @@
+-- Keys other Geph services use to call the embedded HTTP API. Only a SHA-256 hash of each
+-- key is kept; the key itself is shown once, when an admin creates it.
+CREATE TABLE api_keys (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  name TEXT NOT NULL UNIQUE,
+  key_hash TEXT NOT NULL UNIQUE,
+  scope TEXT NOT NULL,
+  rate_per_min INTEGER NOT NULL,
+  created_at INTEGER NOT NULL,
+  revoked_at INTEGER,
+  request_count INTEGER NOT NULL DEFAULT 0,
+  rejected_count INTEGER NOT NULL DEFAULT 0,
+  last_used_at INTEGER
+);
@@ -0,0 +1,7 @@
+-- Metadata reported by the VM availability API on the most recent poll. `extra` holds any
+-- fields the bot doesn't model yet, as a JSON object.
+ALTER TABLE vm_status ADD COLUMN region TEXT;
+ALTER TABLE vm_status ADD COLUMN version TEXT;
+ALTER TABLE vm_status ADD COLUMN bandwidth_mbps REAL;
+ALTER TABLE vm_status ADD COLUMN last_heartbeat INTEGER;
+ALTER TABLE vm_status ADD COLUMN extra TEXT;
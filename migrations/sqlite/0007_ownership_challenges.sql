@@ -0,0 +1,9 @@
+-- Pending proofs that a tester controls the VM they're registering: the token must show up
+-- in the VM's reported metadata before the chat is linked
+CREATE TABLE ownership_challenges (
+  telegram_chat_id INTEGER PRIMARY KEY,
+  vm_id TEXT NOT NULL,
+  token TEXT NOT NULL,
+  created_at INTEGER NOT NULL,
+  expires_at INTEGER NOT NULL
+);
@@ -0,0 +1,16 @@
+-- When each VM was linked to its current chat, for spotting registration bursts
+ALTER TABLE agent_records ADD COLUMN linked_at INTEGER;
+
+-- Suspicious patterns found by the fraud analyzer. Claims are held while a chat has an
+-- unresolved flag. `evidence` identifies what was seen, so the same finding is only
+-- raised once even after an admin clears it.
+CREATE TABLE fraud_flags (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  telegram_chat_id INTEGER NOT NULL,
+  kind TEXT NOT NULL,
+  evidence TEXT NOT NULL,
+  detail TEXT NOT NULL,
+  created_at INTEGER NOT NULL,
+  resolved_at INTEGER,
+  UNIQUE (kind, evidence)
+);
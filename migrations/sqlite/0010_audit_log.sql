@@ -0,0 +1,12 @@
+-- Append-only record of every state-changing action, for settling support disputes.
+-- `actor` is who did it (`chat:<id>`, `admin:<id>`, `agent:<vm_id>` or `system`) and
+-- `telegram_chat_id` whose data it touched, if anyone's. Nothing updates or deletes rows.
+CREATE TABLE audit_log (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  actor TEXT NOT NULL,
+  action TEXT NOT NULL,
+  telegram_chat_id INTEGER,
+  payload TEXT NOT NULL,
+  created_at INTEGER NOT NULL
+);
+CREATE INDEX audit_log_chat ON audit_log (telegram_chat_id, created_at);
@@ -0,0 +1,17 @@
+-- Admin broadcasts and how delivery went for each recipient. A delivery row is written
+-- per chat as it's attempted, so an interrupted broadcast shows exactly who got it.
+CREATE TABLE broadcasts (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  admin_chat_id INTEGER NOT NULL,
+  text TEXT NOT NULL,
+  recipients INTEGER NOT NULL,
+  created_at INTEGER NOT NULL,
+  finished_at INTEGER
+);
+CREATE TABLE broadcast_deliveries (
+  broadcast_id INTEGER NOT NULL,
+  telegram_chat_id INTEGER NOT NULL,
+  error TEXT,
+  attempted_at INTEGER NOT NULL,
+  PRIMARY KEY (broadcast_id, telegram_chat_id)
+);
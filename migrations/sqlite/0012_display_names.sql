@@ -0,0 +1,3 @@
+-- Optional name a tester chose to appear under on the leaderboard instead of an
+-- anonymous label
+ALTER TABLE user_prefs ADD COLUMN display_name TEXT;
@@ -0,0 +1,10 @@
+-- Streak milestones already paid out. A streak is identified by the day it reached the
+-- milestone, so each streak earns each milestone once and a new streak can earn it again.
+CREATE TABLE streak_awards (
+  vm_id TEXT NOT NULL,
+  milestone INTEGER NOT NULL,
+  reached_on TEXT NOT NULL,
+  bonus_secs INTEGER NOT NULL,
+  awarded_at INTEGER NOT NULL,
+  PRIMARY KEY (vm_id, milestone, reached_on)
+);
@@ -0,0 +1,2 @@
+-- Owner-chosen name shown instead of the raw VM id; cleared whenever the VM is unlinked
+ALTER TABLE agent_records ADD COLUMN nickname TEXT;
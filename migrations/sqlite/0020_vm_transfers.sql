@@ -0,0 +1,11 @@
+-- One-time codes handing a VM to another chat. `with_balance` says whether the VM's
+-- unclaimed balance goes along; otherwise it moves to another of the sender's VMs.
+CREATE TABLE vm_transfers (
+  code TEXT PRIMARY KEY,
+  vm_id TEXT NOT NULL,
+  from_chat_id INTEGER NOT NULL,
+  with_balance INTEGER NOT NULL,
+  created_at INTEGER NOT NULL,
+  expires_at INTEGER NOT NULL
+);
+CREATE INDEX vm_transfers_vm ON vm_transfers (vm_id);
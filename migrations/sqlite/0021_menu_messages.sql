@@ -0,0 +1,6 @@
+-- The last menu the bot sent each chat, which later menus edit instead of adding another
+CREATE TABLE menu_messages (
+  telegram_chat_id INTEGER PRIMARY KEY,
+  message_id INTEGER NOT NULL,
+  updated_at INTEGER NOT NULL
+);
@@ -0,0 +1,8 @@
+-- Testers seen in each group chat, whose private accounts /groupstats sums up
+CREATE TABLE group_members (
+  group_chat_id INTEGER NOT NULL,
+  user_id INTEGER NOT NULL,
+  last_seen_at INTEGER NOT NULL,
+  PRIMARY KEY (group_chat_id, user_id)
+);
+CREATE INDEX group_members_user ON group_members (user_id);
@@ -0,0 +1,6 @@
+-- Daily summaries posted to stats_channel_id, one per UTC day they cover
+CREATE TABLE channel_posts (
+  day TEXT PRIMARY KEY,
+  message_id INTEGER NOT NULL,
+  posted_at INTEGER NOT NULL
+);
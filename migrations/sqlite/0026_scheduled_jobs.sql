@@ -0,0 +1,7 @@
+-- Last run of each scheduler job, so schedules carry over restarts
+CREATE TABLE scheduled_jobs (
+  name TEXT PRIMARY KEY,
+  last_run_at INTEGER NOT NULL,
+  -- Why the last run failed; NULL when it succeeded
+  last_error TEXT
+);
@@ -0,0 +1,13 @@
+-- Chats and VMs an admin has banned, each row naming exactly one. A ban drops everything
+-- from the chat and credits nothing; a shadow ban only stops crediting, without the
+-- subject being able to tell.
+CREATE TABLE bans (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  telegram_chat_id INTEGER UNIQUE,
+  vm_id TEXT UNIQUE,
+  shadow INTEGER NOT NULL,
+  reason TEXT NOT NULL,
+  banned_by INTEGER NOT NULL,
+  created_at INTEGER NOT NULL,
+  CHECK ((telegram_chat_id IS NULL) <> (vm_id IS NULL))
+);
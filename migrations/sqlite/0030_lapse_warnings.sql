@@ -0,0 +1,3 @@
+-- When each chat was last warned of unclaimed days about to lapse, so the warning goes
+-- out once per warning window rather than every day
+ALTER TABLE user_prefs ADD COLUMN lapse_warned_at INTEGER;
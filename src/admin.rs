@@ -0,0 +1,838 @@
+use teloxide::types::{ChatId, InlineKeyboardMarkup};
+
+use std::collections::BTreeMap;
+
+use chrono::NaiveDateTime;
+use serde_json::json;
+
+use crate::{
+    CONFIG, DB,
+    apikeys::{self, Scope},
+    appeals,
+    audit::{self, Actor},
+    bans, begin_write,
+    bot_error::{BotError, Context},
+    broadcast, events, export, fraud, geph_account, i18n, now_unix, outbox,
+    policy::{Limits, RewardPolicy},
+    render, selftest,
+    telegram::Telegram,
+    tokens,
+};
+
+/// VMs unlinked for longer than this show up in the orphan report
+const ORPHAN_AFTER_SECS: i64 = 7 * 86400;
+/// Orphans must have reported within this window to be worth chasing
+const ORPHAN_RECENTLY_SEEN_SECS: i64 = 3600;
+/// Lifetime of outreach tokens generated from the report
+const OUTREACH_TOKEN_TTL_SECS: i64 = 14 * 86400;
+
+#[derive(Clone, Debug)]
+pub enum AdminCommand {
+    /// `/admin orphans`
+    Orphans,
+    /// `/admin token <vm_id>`
+    Token(String),
+    /// `/admin simulate [secs_per_day=N] [min_daily_secs=N] [max_days_per_month=N]`
+    Simulate(RewardPolicy, Limits),
+    /// `/admin event <start> <end> <multiplier>`, times as `YYYY-MM-DDTHH:MM` UTC
+    Event {
+        starts_at: i64,
+        ends_at: i64,
+        multiplier: f64,
+    },
+    /// `/admin events`
+    Events,
+    /// `/admin event_cancel <id>`
+    CancelEvent(i64),
+    /// `/admin replacements`
+    Replacements,
+    /// `/admin apikey_create <name> <read|admin> [requests_per_min]`
+    CreateApiKey {
+        name: String,
+        scope: Scope,
+        rate_per_min: i64,
+    },
+    /// `/admin apikey_revoke <name>`
+    RevokeApiKey(String),
+    /// `/admin apikeys`
+    ApiKeys,
+    /// `/admin selftest`, also reachable as `/admin_selftest`
+    SelfTest,
+    /// `/admin flags`
+    Flags,
+    /// `/admin flag_clear <id>`
+    ClearFlag(i64),
+    /// `/admin audit <chat_id>`
+    Audit(ChatId),
+    /// `/admin broadcast <text>`
+    Broadcast(String),
+    /// `/admin export [json|csv]`
+    Export(export::Format),
+    /// `/admin account <chat_id|username>`
+    Account(String),
+    /// `/admin adjust <vm_id> +/-<hours> <reason>`, correcting a VM's recorded uptime
+    Adjust {
+        vm_id: String,
+        secs: i64,
+        reason: String,
+    },
+    /// `/admin ban chat|vm <id> <reason>`, or `/admin shadowban` to only stop crediting
+    Ban {
+        target: bans::Target,
+        shadow: bool,
+        reason: String,
+    },
+    /// `/admin unban chat|vm <id>`
+    Unban(bans::Target),
+    /// `/admin bans`
+    Bans,
+    /// `/admin appeal_accept <id> [shadow]` or `/admin appeal_reject <id>`, sent by the
+    /// buttons under a forwarded appeal. `shadow` lifts a shadow ban along with the rest.
+    ResolveAppeal {
+        id: i64,
+        accepted: bool,
+        lift_shadow: bool,
+    },
+}
+
+/// Parses what follows `/admin`
+pub fn parse(rest: &str) -> Option<AdminCommand> {
+    let mut words = rest.split_whitespace();
+    match words.next()? {
+        "orphans" => Some(AdminCommand::Orphans),
+        "token" => words.next().map(|id| AdminCommand::Token(id.to_owned())),
+        "simulate" => {
+            let (policy, limits) = parse_policy(words)?;
+            Some(AdminCommand::Simulate(policy, limits))
+        }
+        "event" => {
+            let starts_at = parse_utc(words.next()?)?;
+            let ends_at = parse_utc(words.next()?)?;
+            let multiplier: f64 = words.next()?.parse().ok()?;
+            (ends_at > starts_at && multiplier > 1.0).then_some(AdminCommand::Event {
+                starts_at,
+                ends_at,
+                multiplier,
+            })
+        }
+        "events" => Some(AdminCommand::Events),
+        "event_cancel" => words.next()?.parse().ok().map(AdminCommand::CancelEvent),
+        "replacements" => Some(AdminCommand::Replacements),
+        "apikey_create" => {
+            let name = words.next()?;
+            // Names end up as metric labels, so keep them to a safe alphabet
+            if !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return None;
+            }
+            let scope = Scope::parse(words.next()?)?;
+            let rate_per_min = match words.next() {
+                Some(rate) => rate.parse().ok().filter(|r| *r > 0)?,
+                None => apikeys::DEFAULT_RATE_PER_MIN,
+            };
+            Some(AdminCommand::CreateApiKey {
+                name: name.to_owned(),
+                scope,
+                rate_per_min,
+            })
+        }
+        "apikey_revoke" => words
+            .next()
+            .map(|name| AdminCommand::RevokeApiKey(name.to_owned())),
+        "apikeys" => Some(AdminCommand::ApiKeys),
+        "selftest" => Some(AdminCommand::SelfTest),
+        "flags" => Some(AdminCommand::Flags),
+        "flag_clear" => words.next()?.parse().ok().map(AdminCommand::ClearFlag),
+        "broadcast" => {
+            let text = rest.trim_start().strip_prefix("broadcast")?.trim();
+            (!text.is_empty()).then(|| AdminCommand::Broadcast(text.to_owned()))
+        }
+        "export" => match words.next() {
+            None => Some(AdminCommand::Export(export::Format::Json)),
+            Some(format) => export::Format::parse(format).map(AdminCommand::Export),
+        },
+        "audit" => words
+            .next()?
+            .parse()
+            .ok()
+            .map(|id| AdminCommand::Audit(ChatId(id))),
+        "account" => words
+            .next()
+            .map(|subject| AdminCommand::Account(subject.to_owned())),
+        "adjust" => {
+            let vm_id = words.next()?.to_owned();
+            // The sign is required, so a typo can't be read as the new total
+            let hours = words.next()?;
+            if !hours.starts_with(['+', '-']) {
+                return None;
+            }
+            let secs = (hours.parse::<f64>().ok()? * 3600.0).round() as i64;
+            let reason = words.collect::<Vec<_>>().join(" ");
+            (secs != 0 && !reason.is_empty()).then_some(AdminCommand::Adjust {
+                vm_id,
+                secs,
+                reason,
+            })
+        }
+        verb @ ("ban" | "shadowban") => {
+            let target = bans::Target::parse(words.next()?, words.next()?)?;
+            let reason = words.collect::<Vec<_>>().join(" ");
+            (!reason.is_empty()).then_some(AdminCommand::Ban {
+                target,
+                shadow: verb == "shadowban",
+                reason,
+            })
+        }
+        "unban" => bans::Target::parse(words.next()?, words.next()?).map(AdminCommand::Unban),
+        "bans" => Some(AdminCommand::Bans),
+        verb @ ("appeal_accept" | "appeal_reject") => {
+            let id = words.next()?.parse().ok()?;
+            let accepted = verb == "appeal_accept";
+            let lift_shadow = match words.next() {
+                None => false,
+                Some("shadow") if accepted => true,
+                Some(_) => return None,
+            };
+            Some(AdminCommand::ResolveAppeal {
+                id,
+                accepted,
+                lift_shadow,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Parses `key=value` overrides on top of the current policy, which has no limits
+fn parse_policy<'a>(words: impl Iterator<Item = &'a str>) -> Option<(RewardPolicy, Limits)> {
+    let (mut policy, mut limits) = (RewardPolicy::current(), Limits::default());
+    for word in words {
+        let (key, value) = word.split_once('=')?;
+        let value: i64 = value.parse().ok()?;
+        match key {
+            "secs_per_day" if value > 0 => policy.secs_per_day = value,
+            "min_daily_secs" => limits.min_daily_secs = value,
+            "max_days_per_month" => limits.max_days_per_month = Some(value),
+            _ => return None,
+        }
+    }
+    Some((policy, limits))
+}
+
+fn parse_utc(s: &str) -> Option<i64> {
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M")
+        .ok()
+        .map(|t| t.and_utc().timestamp())
+}
+
+pub fn is_admin(chat_id: ChatId) -> bool {
+    CONFIG.admin_chat_ids.contains(&chat_id.0)
+}
+
+/// Best-effort message to every admin chat, for operational alerts
+pub async fn notify_admins(bot: &impl Telegram, text: &str) {
+    for &chat in &CONFIG.admin_chat_ids {
+        let sent = outbox::deliver(ChatId(chat), || {
+            bot.send_message(ChatId(chat), text).into_future()
+        })
+        .await;
+        if let Err(e) = sent {
+            tracing::warn!("could not alert admin chat {chat}: {e}");
+        }
+    }
+}
+
+/// Like [`notify_admins`], with buttons for the admins to act on
+pub async fn ask_admins(bot: &impl Telegram, text: &str, markup: InlineKeyboardMarkup) {
+    for &chat in &CONFIG.admin_chat_ids {
+        let sent = outbox::deliver(ChatId(chat), || {
+            bot.send_message(ChatId(chat), text)
+                .reply_markup(markup.clone())
+                .into_future()
+        })
+        .await;
+        if let Err(e) = sent {
+            tracing::warn!("could not ask admin chat {chat}: {e}");
+        }
+    }
+}
+
+/// Records an admin's action in the audit log
+async fn audit_admin(
+    admin: ChatId,
+    action: &str,
+    subject: Option<ChatId>,
+    payload: serde_json::Value,
+) -> Result<(), BotError> {
+    audit::log(&Actor::Admin(admin), action, subject, payload)
+        .await
+        .context("writing the audit log")
+}
+
+pub async fn handle(
+    bot: &impl Telegram,
+    chat_id: ChatId,
+    cmd: AdminCommand,
+) -> Result<(), BotError> {
+    match cmd {
+        AdminCommand::Orphans => {
+            let now = now_unix();
+            let orphans: Vec<(String, i64, i64)> = sqlx::query_as(
+                r#"
+SELECT a.vm_id, a.up_secs, s.first_seen
+FROM agent_records a JOIN vm_status s ON s.vm_id = a.vm_id
+WHERE a.telegram_chat_id IS NULL
+  AND s.first_seen <= $1
+  AND s.last_seen >= $2
+ORDER BY a.up_secs DESC
+LIMIT 50
+                "#,
+            )
+            .bind(now - ORPHAN_AFTER_SECS)
+            .bind(now - ORPHAN_RECENTLY_SEEN_SECS)
+            .fetch_all(&*DB)
+            .await
+            .context("finding orphaned VMs")?;
+            if orphans.is_empty() {
+                bot.send_message(chat_id, "No orphaned VMs.").await?;
+                return Ok(());
+            }
+            let lines: Vec<String> = orphans
+                .iter()
+                .map(|(vm_id, up_secs, first_seen)| {
+                    format!(
+                        "{vm_id}: {}h up, unlinked for {}d",
+                        up_secs / 3600,
+                        (now - first_seen) / 86400
+                    )
+                })
+                .collect();
+            let buttons = orphans
+                .iter()
+                .map(|(vm_id, _, _)| {
+                    vec![render::command_button(
+                        format!("Token for {vm_id}"),
+                        format!("/admin token {vm_id}"),
+                    )]
+                })
+                .collect::<Vec<_>>();
+            bot.send_message(
+                chat_id,
+                format!(
+                    "Orphaned VMs (reporting, never linked):\n{}",
+                    lines.join("\n")
+                ),
+            )
+            .reply_markup(InlineKeyboardMarkup::new(buttons))
+            .await?;
+        }
+        AdminCommand::Simulate(proposed, limits) => {
+            let rows: Vec<(i64, String, i64)> = sqlx::query_as(
+                r#"
+SELECT a.telegram_chat_id, h.day, h.up_secs
+FROM uptime_history h JOIN agent_records a ON a.vm_id = h.vm_id
+WHERE a.telegram_chat_id IS NOT NULL
+ORDER BY h.day
+                "#,
+            )
+            .fetch_all(&*DB)
+            .await
+            .context("loading uptime history")?;
+            let Some(first_day) = rows.first().map(|(_, day, _)| day.clone()) else {
+                bot.send_message(chat_id, "No uptime history recorded yet.")
+                    .await?;
+                return Ok(());
+            };
+            let mut by_chat: BTreeMap<i64, Vec<(&str, i64)>> = BTreeMap::new();
+            for (chat, day, up_secs) in &rows {
+                by_chat.entry(*chat).or_default().push((day, *up_secs));
+            }
+            let mut diffs: Vec<(i64, i64, i64)> = by_chat
+                .iter()
+                .map(|(chat, samples)| {
+                    let current = RewardPolicy::current()
+                        .earned_days(Limits::default(), samples.iter().copied());
+                    let simulated = proposed.earned_days(limits, samples.iter().copied());
+                    (*chat, current, simulated)
+                })
+                .collect();
+            let total_current: i64 = diffs.iter().map(|d| d.1).sum();
+            let total_simulated: i64 = diffs.iter().map(|d| d.2).sum();
+            diffs.sort_by_key(|(_, current, simulated)| -(simulated - current).abs());
+            let lines: Vec<String> = diffs
+                .iter()
+                .take(30)
+                .map(|(chat, current, simulated)| {
+                    format!(
+                        "{chat}: {current}d → {simulated}d ({:+})",
+                        simulated - current
+                    )
+                })
+                .collect();
+            bot.send_message(
+                chat_id,
+                format!(
+                    "Simulated {proposed:?} with {limits:?} over history since {first_day}\n{} testers: {total_current}d → {total_simulated}d ({:+})\n\nLargest changes:\n{}",
+                    diffs.len(),
+                    total_simulated - total_current,
+                    lines.join("\n")
+                ),
+            )
+            .await?;
+        }
+        AdminCommand::Token(vm_id) => {
+            let unlinked = sqlx::query_scalar::<_, i64>(
+                "SELECT COUNT(*) FROM agent_records WHERE vm_id = $1 AND telegram_chat_id IS NULL",
+            )
+            .bind(&vm_id)
+            .fetch_one(&*DB)
+            .await
+            .context("checking the VM is unlinked")?;
+            if unlinked == 0 {
+                bot.send_message(chat_id, format!("{vm_id} is unknown or already linked."))
+                    .await?;
+                return Ok(());
+            }
+            let token = tokens::issue(&vm_id, OUTREACH_TOKEN_TTL_SECS)
+                .await
+                .context("issuing an outreach token")?;
+            audit_admin(
+                chat_id,
+                "registration_token_issued",
+                None,
+                json!({ "vm_id": vm_id, "ttl_secs": OUTREACH_TOKEN_TTL_SECS }),
+            )
+            .await?;
+            let mut text = format!(
+                "Outreach token for {vm_id} (valid {} days):\n{token}\n\nThe operator can link the VM by sending the bot: /register {token}",
+                OUTREACH_TOKEN_TTL_SECS / 86400
+            );
+            if let Some(link) = tokens::deep_link(&token) {
+                text.push_str(&format!("\nor by opening: {link}"));
+            }
+            bot.send_message(chat_id, text).await?;
+        }
+        AdminCommand::Event {
+            starts_at,
+            ends_at,
+            multiplier,
+        } => {
+            let id = events::schedule(starts_at, ends_at, multiplier)
+                .await
+                .context("scheduling the event")?;
+            audit_admin(
+                chat_id,
+                "event_scheduled",
+                None,
+                json!({ "id": id, "starts_at": starts_at, "ends_at": ends_at, "multiplier": multiplier }),
+            )
+            .await?;
+            let event = events::RewardEvent {
+                id,
+                starts_at,
+                ends_at,
+                multiplier,
+            };
+            bot.send_message(
+                chat_id,
+                format!("Scheduled event #{id}: {}", event.describe()),
+            )
+            .await?;
+        }
+        AdminCommand::Events => {
+            let upcoming = events::upcoming().await.context("loading events")?;
+            let text = if upcoming.is_empty() {
+                "No upcoming reward events.".to_owned()
+            } else {
+                upcoming
+                    .iter()
+                    .map(|e| format!("#{}: {}", e.id, e.describe()))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+            bot.send_message(chat_id, text).await?;
+        }
+        AdminCommand::Replacements => {
+            let rows: Vec<(i64, String, String, i64, i64)> = sqlx::query_as(
+                r#"
+SELECT telegram_chat_id, old_vm_id, new_vm_id, moved_up_secs, created_at
+FROM vm_replacements
+ORDER BY created_at DESC
+LIMIT 30
+                "#,
+            )
+            .fetch_all(&*DB)
+            .await
+            .context("loading replacements")?;
+            let text = if rows.is_empty() {
+                "No VM replacements yet.".to_owned()
+            } else {
+                let lines: Vec<String> = rows
+                    .into_iter()
+                    .map(|(chat, old_vm, new_vm, secs, at)| {
+                        format!(
+                            "{} · chat {chat}: {old_vm} → {new_vm} ({}h)",
+                            render::format_timestamp(at),
+                            secs / 3600
+                        )
+                    })
+                    .collect();
+                format!("Recent VM replacements:\n{}", lines.join("\n"))
+            };
+            bot.send_message(chat_id, text).await?;
+        }
+        AdminCommand::CreateApiKey {
+            name,
+            scope,
+            rate_per_min,
+        } => {
+            let text = match apikeys::create(&name, scope, rate_per_min).await {
+                Ok(key) => {
+                    audit_admin(
+                        chat_id,
+                        "api_key_created",
+                        None,
+                        json!({ "name": name, "scope": scope.as_str(), "rate_per_min": rate_per_min }),
+                    )
+                    .await?;
+                    format!(
+                        "API key {name} ({}, {rate_per_min}/min):\n{key}\n\nThis is the only time it will be shown.",
+                        scope.as_str()
+                    )
+                }
+                // Names are unique, so this is almost always a reused name
+                Err(sqlx::Error::Database(e)) => {
+                    tracing::debug!("creating api key {name}: {e}");
+                    format!("An API key named {name} already exists.")
+                }
+                Err(e) => return Err(e).context("creating the API key"),
+            };
+            bot.send_message(chat_id, text).await?;
+        }
+        AdminCommand::RevokeApiKey(name) => {
+            let text = if apikeys::revoke(&name)
+                .await
+                .context("revoking the API key")?
+            {
+                audit_admin(chat_id, "api_key_revoked", None, json!({ "name": name })).await?;
+                format!("Revoked API key {name}.")
+            } else {
+                format!("No live API key named {name}.")
+            };
+            bot.send_message(chat_id, text).await?;
+        }
+        AdminCommand::ApiKeys => {
+            let keys = apikeys::usage().await.context("loading API key usage")?;
+            let text = if keys.is_empty() {
+                "No API keys yet.".to_owned()
+            } else {
+                let lines: Vec<String> = keys
+                    .iter()
+                    .map(|k| {
+                        let state = match k.revoked_at {
+                            Some(at) => format!("revoked {}", render::format_date(at)),
+                            None => format!("{}/min", k.rate_per_min),
+                        };
+                        let last_used = k
+                            .last_used_at
+                            .map(render::format_timestamp)
+                            .unwrap_or_else(|| "never".to_owned());
+                        format!(
+                            "{} ({}, {state}): {} requests, {} rate limited, last used {last_used}",
+                            k.name,
+                            k.scope.as_str(),
+                            k.request_count,
+                            k.rejected_count
+                        )
+                    })
+                    .collect();
+                format!("API keys:\n{}", lines.join("\n"))
+            };
+            bot.send_message(chat_id, text).await?;
+        }
+        AdminCommand::Flags => {
+            let flags = fraud::open_flags().await.context("loading fraud flags")?;
+            let text = if flags.is_empty() {
+                "No open fraud flags.".to_owned()
+            } else {
+                let lines: Vec<String> = flags
+                    .iter()
+                    .map(|f| {
+                        format!(
+                            "#{} · {} · chat {} · {}: {}",
+                            f.id,
+                            render::format_timestamp(f.created_at),
+                            f.chat_id,
+                            f.kind,
+                            f.detail
+                        )
+                    })
+                    .collect();
+                format!(
+                    "Open fraud flags (claims held):\n{}\n\nClear one with /admin flag_clear <id>.",
+                    lines.join("\n")
+                )
+            };
+            bot.send_message(chat_id, text).await?;
+        }
+        AdminCommand::Adjust {
+            vm_id,
+            secs,
+            reason,
+        } => {
+            let (_write, mut tx) = begin_write().await.context("starting the adjustment")?;
+            let record: Option<(Option<i64>, i64)> = sqlx::query_as(
+                "SELECT telegram_chat_id, up_secs FROM agent_records WHERE vm_id = $1",
+            )
+            .bind(&vm_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .context("loading the VM")?;
+            let Some((owner, before)) = record else {
+                bot.send_message(chat_id, format!("Unknown VM {vm_id}."))
+                    .await?;
+                return Ok(());
+            };
+            let after = before + secs;
+            if after < 0 {
+                bot.send_message(
+                    chat_id,
+                    format!(
+                        "{vm_id} only has {} of uptime; that would leave it negative.",
+                        render::format_duration(before)
+                    ),
+                )
+                .await?;
+                return Ok(());
+            }
+            sqlx::query("UPDATE agent_records SET up_secs = $1 WHERE vm_id = $2")
+                .bind(after)
+                .bind(&vm_id)
+                .execute(&mut *tx)
+                .await
+                .context("adjusting the uptime")?;
+            audit::record(
+                &mut tx,
+                &Actor::Admin(chat_id),
+                "uptime_adjusted",
+                owner.map(ChatId),
+                json!({
+                    "vm_id": vm_id,
+                    "delta_secs": secs,
+                    "up_secs_before": before,
+                    "up_secs_after": after,
+                    "reason": reason,
+                }),
+            )
+            .await
+            .context("writing the audit log")?;
+            tx.commit().await.context("committing the adjustment")?;
+            let owner = owner.map_or("unlinked".to_owned(), |owner| format!("chat {owner}"));
+            bot.send_message(
+                chat_id,
+                format!(
+                    "Adjusted {vm_id} ({owner}) by {:+.2}h: {} → {}.\nReason: {reason}",
+                    secs as f64 / 3600.0,
+                    render::format_duration(before),
+                    render::format_duration(after)
+                ),
+            )
+            .await?;
+        }
+        AdminCommand::Ban {
+            target,
+            shadow,
+            reason,
+        } => {
+            // An admin locked out of the bot couldn't lift it again
+            if matches!(target, bans::Target::Chat(banned) if is_admin(banned)) {
+                bot.send_message(chat_id, "Admin chats can't be banned.")
+                    .await?;
+                return Ok(());
+            }
+            bans::ban(&target, shadow, &reason, chat_id)
+                .await
+                .context("banning")?;
+            let kind = if shadow { "Shadow-banned" } else { "Banned" };
+            bot.send_message(chat_id, format!("{kind} {target}.\nReason: {reason}"))
+                .await?;
+        }
+        AdminCommand::Unban(target) => {
+            let text = if bans::unban(&target, chat_id).await.context("unbanning")? {
+                format!("Unbanned {target}.")
+            } else {
+                format!("{target} isn't banned.")
+            };
+            bot.send_message(chat_id, text).await?;
+        }
+        AdminCommand::Bans => {
+            let bans = bans::list().await.context("listing bans")?;
+            let text = if bans.is_empty() {
+                "Nobody is banned.".to_owned()
+            } else {
+                let lines: Vec<String> = bans
+                    .iter()
+                    .map(|ban| {
+                        format!(
+                            "{}{} · {} by {}: {}",
+                            ban.target,
+                            if ban.shadow { " (shadow)" } else { "" },
+                            render::format_timestamp(ban.created_at),
+                            ban.banned_by,
+                            ban.reason
+                        )
+                    })
+                    .collect();
+                format!(
+                    "Bans in force:\n{}\n\nLift one with /admin unban chat|vm <id>.",
+                    lines.join("\n")
+                )
+            };
+            bot.send_message(chat_id, text).await?;
+        }
+        AdminCommand::ResolveAppeal {
+            id,
+            accepted,
+            lift_shadow,
+        } => {
+            // Every admin gets the buttons, so another may have decided already
+            let Some(appellant) = appeals::resolve(id, accepted, lift_shadow, chat_id)
+                .await
+                .context("resolving the appeal")?
+            else {
+                bot.send_message(chat_id, format!("Appeal #{id} is already decided."))
+                    .await?;
+                return Ok(());
+            };
+            let (outcome, verdict) = if accepted {
+                ("Accepted", "appeal.accepted")
+            } else {
+                ("Rejected", "appeal.rejected")
+            };
+            let verdict = i18n::text(verdict, &[]);
+            let sent = outbox::deliver(appellant, || {
+                bot.send_message(appellant, verdict.clone()).into_future()
+            })
+            .await;
+            if let Err(e) = sent {
+                tracing::warn!("could not tell chat {appellant} about appeal #{id}: {e}");
+            }
+            bot.send_message(
+                chat_id,
+                format!("{outcome} appeal #{id} from chat {appellant}."),
+            )
+            .await?;
+        }
+        AdminCommand::ClearFlag(id) => {
+            let text = match fraud::resolve(id).await.context("resolving the flag")? {
+                Some(flagged) => {
+                    audit_admin(
+                        chat_id,
+                        "fraud_flag_cleared",
+                        Some(flagged),
+                        json!({ "id": id }),
+                    )
+                    .await?;
+                    format!("Cleared flag #{id}.")
+                }
+                None => format!("No open flag #{id}."),
+            };
+            bot.send_message(chat_id, text).await?;
+        }
+        AdminCommand::Broadcast(text) => {
+            if let Err(e) = broadcast::start(bot, chat_id, text).await {
+                tracing::error!("starting broadcast failed: {e:?}");
+                bot.send_message(chat_id, format!("Could not start the broadcast: {e}"))
+                    .await?;
+            }
+        }
+        AdminCommand::Export(format) => {
+            let export = export::collect().await.context("collecting the export")?;
+            let files = export.render(format).context("rendering the export")?;
+            audit_admin(
+                chat_id,
+                "data_exported",
+                None,
+                json!({ "format": format.as_str() }),
+            )
+            .await?;
+            for (name, contents) in files {
+                bot.send_document(chat_id, name, contents.into_bytes())
+                    .await?;
+            }
+        }
+        AdminCommand::Audit(subject) => {
+            let entries = audit::for_chat(subject, 30)
+                .await
+                .context("loading the audit log")?;
+            let text = if entries.is_empty() {
+                format!("No audit entries for chat {subject}.")
+            } else {
+                let lines: Vec<String> = entries
+                    .iter()
+                    .map(|e| {
+                        format!(
+                            "{} · {} · {} {}",
+                            render::format_timestamp(e.created_at),
+                            e.actor,
+                            e.action,
+                            e.payload
+                        )
+                    })
+                    .collect();
+                format!(
+                    "Audit log for chat {subject}, newest first:\n{}",
+                    lines.join("\n")
+                )
+            };
+            bot.send_message(chat_id, text).await?;
+        }
+        AdminCommand::SelfTest => {
+            bot.send_message(chat_id, selftest::run().await).await?;
+        }
+        AdminCommand::Account(subject) => {
+            // Telegram chat ids are numeric; anything else is taken as a username
+            let text = match subject.parse::<i64>() {
+                Ok(id) => match geph_account::linked(ChatId(id))
+                    .await
+                    .context("loading the linked Geph account")?
+                {
+                    Some(username) => format!("Chat {id} is linked to Geph account {username}."),
+                    None => format!("Chat {id} has no Geph account linked."),
+                },
+                Err(_) => {
+                    let chats = geph_account::chats_for(&subject)
+                        .await
+                        .context("finding the chats for the account")?;
+                    if chats.is_empty() {
+                        format!("No chat has linked Geph account {subject}.")
+                    } else {
+                        let lines: Vec<String> = chats
+                            .iter()
+                            .map(|(chat, linked_at)| {
+                                format!(
+                                    "chat {chat} since {}",
+                                    render::format_timestamp(*linked_at)
+                                )
+                            })
+                            .collect();
+                        format!("Geph account {subject} is linked to:\n{}", lines.join("\n"))
+                    }
+                }
+            };
+            bot.send_message(chat_id, text).await?;
+        }
+        AdminCommand::CancelEvent(id) => {
+            let text = if events::cancel(id).await.context("cancelling the event")? {
+                audit_admin(chat_id, "event_cancelled", None, json!({ "id": id })).await?;
+                format!("Cancelled event #{id}.")
+            } else {
+                format!("No event #{id}.")
+            };
+            bot.send_message(chat_id, text).await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use teloxide::{prelude::*, types::ChatId};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::{
+    CONFIG, DB, OFFLINE_AFTER_SECS, begin_write, i18n, next_tick, nicknames, now_unix, outbox,
+    render,
+};
+
+/// After an alert, a VM must stay online this long before another outage alerts again
+const REARM_AFTER_SECS: i64 = 15 * 60;
+/// VMs gone for longer than this are considered abandoned rather than down
+const ABANDONED_AFTER_SECS: i64 = 7 * 86400;
+
+/// Tells owners when a registered VM stops reporting, and again once it has recovered.
+///
+/// Each outage alerts at most once: `offline_alerted_at` stays set until the VM has been
+/// back for [`REARM_AFTER_SECS`], so a flapping VM doesn't produce a stream of messages.
+pub async fn offline_alert_loop(bot: Bot, shutdown: CancellationToken) -> anyhow::Result<()> {
+    let mut ticker = smol::Timer::interval(Duration::from_secs(60));
+    loop {
+        async {
+            let now = now_unix();
+            let down: Vec<(String, Option<String>, i64, i64, i64)> = sqlx::query_as(
+                r#"
+SELECT
+    a.vm_id, a.nickname, a.telegram_chat_id, s.last_seen,
+    CASE WHEN i.telegram_chat_id IS NULL THEN COALESCE(p.offline_alerts, 1) ELSE 0 END
+FROM agent_records a
+JOIN vm_status s ON s.vm_id = a.vm_id
+LEFT JOIN user_prefs p ON p.telegram_chat_id = a.telegram_chat_id
+LEFT JOIN inactive_chats i ON i.telegram_chat_id = a.telegram_chat_id
+WHERE a.telegram_chat_id IS NOT NULL
+  AND s.offline_alerted_at IS NULL
+  AND s.last_seen < $1
+  AND s.last_seen >= $2
+                "#,
+            )
+            .bind(now - CONFIG.offline_alert_after_mins * 60)
+            .bind(now - ABANDONED_AFTER_SECS)
+            .fetch_all(&*DB)
+            .await?;
+            for (vm_id, nickname, chat_id, last_seen, wanted) in down {
+                // Outages are recorded (for the digest) even when the owner muted alerts or
+                // blocked the bot
+                if wanted != 0 {
+                    let ago = render::format_duration(now - last_seen);
+                    let name = nicknames::label(&vm_id, nickname.as_deref());
+                    let text = i18n::text("alert.down", &[("name", &name), ("ago", &ago)]);
+                    let _ = outbox::deliver(ChatId(chat_id), || {
+                        bot.send_message(ChatId(chat_id), &text).send()
+                    })
+                    .await;
+                }
+                let (_write, mut tx) = begin_write().await?;
+                sqlx::query("UPDATE vm_status SET offline_alerted_at = $1 WHERE vm_id = $2")
+                    .bind(now)
+                    .bind(&vm_id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query("INSERT INTO outages (vm_id, started_at) VALUES ($1, $2)")
+                    .bind(&vm_id)
+                    .bind(last_seen)
+                    .execute(&mut *tx)
+                    .await?;
+                tx.commit().await?;
+            }
+
+            let recovered: Vec<(String, Option<String>, i64, i64, i64)> = sqlx::query_as(
+                r#"
+SELECT
+    a.vm_id, a.nickname, a.telegram_chat_id, s.online_since,
+    CASE WHEN i.telegram_chat_id IS NULL THEN COALESCE(p.offline_alerts, 1) ELSE 0 END
+FROM agent_records a
+JOIN vm_status s ON s.vm_id = a.vm_id
+LEFT JOIN user_prefs p ON p.telegram_chat_id = a.telegram_chat_id
+LEFT JOIN inactive_chats i ON i.telegram_chat_id = a.telegram_chat_id
+WHERE a.telegram_chat_id IS NOT NULL
+  AND s.offline_alerted_at IS NOT NULL
+  AND s.last_seen >= $1
+  AND s.online_since <= $2
+                "#,
+            )
+            .bind(now - OFFLINE_AFTER_SECS)
+            .bind(now - REARM_AFTER_SECS)
+            .fetch_all(&*DB)
+            .await?;
+            for (vm_id, nickname, chat_id, online_since, wanted) in recovered {
+                if wanted != 0 {
+                    let name = nicknames::label(&vm_id, nickname.as_deref());
+                    let text = i18n::text("alert.back_online", &[("name", &name)]);
+                    let _ = outbox::deliver(ChatId(chat_id), || {
+                        bot.send_message(ChatId(chat_id), &text).send()
+                    })
+                    .await;
+                }
+                let (_write, mut tx) = begin_write().await?;
+                sqlx::query("UPDATE vm_status SET offline_alerted_at = NULL WHERE vm_id = $1")
+                    .bind(&vm_id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query(
+                    "UPDATE outages SET ended_at = $1 WHERE vm_id = $2 AND ended_at IS NULL",
+                )
+                .bind(online_since)
+                .bind(&vm_id)
+                .execute(&mut *tx)
+                .await?;
+                tx.commit().await?;
+            }
+            anyhow::Ok(())
+        }
+        .instrument(tracing::info_span!("tick"))
+        .await?;
+
+        if !next_tick("offline_alerts", &mut ticker, &shutdown).await {
+            return Ok(());
+        }
+    }
+}
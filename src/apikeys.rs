@@ -0,0 +1,206 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use rand::{Rng, distributions::Alphanumeric};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{DB, now_unix};
+
+/// Prefix that makes leaked keys easy to recognize in logs and config files
+const KEY_PREFIX: &str = "gtb_";
+/// Requests per minute a new key gets unless the admin asks for something else
+pub const DEFAULT_RATE_PER_MIN: i64 = 60;
+
+/// What a key may do. Admin keys can do everything read keys can.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    Read,
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Admin => "admin",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Scope> {
+        match s {
+            "read" => Some(Scope::Read),
+            "admin" => Some(Scope::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// A key that authenticated successfully
+#[derive(Clone, Debug)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    pub scope: Scope,
+    pub rate_per_min: i64,
+}
+
+/// One row of the usage report
+#[derive(Clone, Debug, Serialize)]
+pub struct KeyUsage {
+    pub id: i64,
+    pub name: String,
+    pub scope: Scope,
+    pub rate_per_min: i64,
+    pub created_at: i64,
+    pub revoked_at: Option<i64>,
+    pub request_count: i64,
+    pub rejected_count: i64,
+    pub last_used_at: Option<i64>,
+}
+
+/// Raw `api_keys` row behind [`KeyUsage`], with the scope still as text
+type UsageRow = (
+    i64,
+    String,
+    String,
+    i64,
+    i64,
+    Option<i64>,
+    i64,
+    i64,
+    Option<i64>,
+);
+
+fn hash(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+/// Creates a key and returns it in the clear. This is the only time it is ever visible.
+pub async fn create(name: &str, scope: Scope, rate_per_min: i64) -> sqlx::Result<String> {
+    let secret: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+    let key = format!("{KEY_PREFIX}{secret}");
+    sqlx::query(
+        "INSERT INTO api_keys (name, key_hash, scope, rate_per_min, created_at) VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(name)
+    .bind(hash(&key))
+    .bind(scope.as_str())
+    .bind(rate_per_min)
+    .bind(now_unix())
+    .execute(&*DB)
+    .await?;
+    Ok(key)
+}
+
+/// Revokes the named key, returning whether there was a live key by that name
+pub async fn revoke(name: &str) -> sqlx::Result<bool> {
+    let result =
+        sqlx::query("UPDATE api_keys SET revoked_at = $1 WHERE name = $2 AND revoked_at IS NULL")
+            .bind(now_unix())
+            .bind(name)
+            .execute(&*DB)
+            .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Every key ever created, live ones first
+pub async fn usage() -> sqlx::Result<Vec<KeyUsage>> {
+    let rows: Vec<UsageRow> = sqlx::query_as(
+            r#"
+SELECT id, name, scope, rate_per_min, created_at, revoked_at, request_count, rejected_count, last_used_at
+FROM api_keys
+ORDER BY CASE WHEN revoked_at IS NULL THEN 0 ELSE 1 END, id
+            "#,
+        )
+        .fetch_all(&*DB)
+        .await?;
+    Ok(rows
+        .into_iter()
+        .map(
+            |(
+                id,
+                name,
+                scope,
+                rate_per_min,
+                created_at,
+                revoked_at,
+                request_count,
+                rejected_count,
+                last_used_at,
+            )| KeyUsage {
+                id,
+                name,
+                scope: Scope::parse(&scope).unwrap_or(Scope::Read),
+                rate_per_min,
+                created_at,
+                revoked_at,
+                request_count,
+                rejected_count,
+                last_used_at,
+            },
+        )
+        .collect())
+}
+
+/// Looks up a live key. Keys are compared by hash, so lookups don't leak timing about the
+/// key itself.
+pub async fn authenticate(key: &str) -> sqlx::Result<Option<ApiKey>> {
+    if !key.starts_with(KEY_PREFIX) {
+        return Ok(None);
+    }
+    let row: Option<(i64, String, String, i64)> = sqlx::query_as(
+        "SELECT id, name, scope, rate_per_min FROM api_keys WHERE key_hash = $1 AND revoked_at IS NULL",
+    )
+    .bind(hash(key))
+    .fetch_optional(&*DB)
+    .await?;
+    Ok(row.and_then(|(id, name, scope, rate_per_min)| {
+        Some(ApiKey {
+            id,
+            name,
+            scope: Scope::parse(&scope)?,
+            rate_per_min,
+        })
+    }))
+}
+
+/// Requests seen per key in the current one-minute window
+static WINDOWS: Lazy<Mutex<HashMap<i64, (Instant, i64)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Counts a request against the key's per-minute budget, returning whether it may proceed.
+/// Both outcomes are recorded for the usage report.
+pub async fn admit(key: &ApiKey) -> sqlx::Result<bool> {
+    let allowed = {
+        let mut windows = WINDOWS.lock().unwrap();
+        let window = windows.entry(key.id).or_insert((Instant::now(), 0));
+        if window.0.elapsed() >= Duration::from_secs(60) {
+            *window = (Instant::now(), 0);
+        }
+        window.1 += 1;
+        window.1 <= key.rate_per_min
+    };
+    let column = if allowed {
+        "request_count"
+    } else {
+        "rejected_count"
+    };
+    sqlx::query(&format!(
+        "UPDATE api_keys SET {column} = {column} + 1, last_used_at = $1 WHERE id = $2"
+    ))
+    .bind(now_unix())
+    .bind(key.id)
+    .execute(&*DB)
+    .await?;
+    Ok(allowed)
+}
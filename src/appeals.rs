@@ -0,0 +1,150 @@
+use serde_json::json;
+use teloxide::types::{ChatId, InlineKeyboardMarkup};
+
+use crate::{
+    DB, admin,
+    audit::{self, Actor},
+    begin_write, now_unix, render,
+    telegram::Telegram,
+};
+
+/// How [`grounds`] lists a shadow ban
+const SHADOW_BANNED: &str = "Shadow-banned";
+
+/// What `chat_id` could appeal against, one line each as shown to admins: a ban, or open
+/// fraud flags. A shadow ban alone is no grounds, since the chat isn't to know of it.
+pub async fn grounds(chat_id: ChatId) -> sqlx::Result<Vec<String>> {
+    let bans: Vec<(i64, String)> =
+        sqlx::query_as("SELECT shadow, reason FROM bans WHERE telegram_chat_id = $1")
+            .bind(chat_id.0)
+            .fetch_all(&*DB)
+            .await?;
+    let flags: Vec<(String, String)> = sqlx::query_as(
+        "SELECT kind, detail FROM fraud_flags WHERE telegram_chat_id = $1 AND resolved_at IS NULL",
+    )
+    .bind(chat_id.0)
+    .fetch_all(&*DB)
+    .await?;
+    if flags.is_empty() && bans.iter().all(|(shadow, _)| *shadow != 0) {
+        return Ok(vec![]);
+    }
+    Ok(bans
+        .into_iter()
+        .map(|(shadow, reason)| {
+            let kind = if shadow != 0 { SHADOW_BANNED } else { "Banned" };
+            format!("{kind}: {reason}")
+        })
+        .chain(
+            flags
+                .into_iter()
+                .map(|(kind, detail)| format!("🚩 {kind}: {detail}")),
+        )
+        .collect())
+}
+
+/// Records `chat_id`'s appeal, returning its id, or `None` if one is already open
+pub async fn file(chat_id: ChatId, statement: &str) -> sqlx::Result<Option<i64>> {
+    let (_write, mut tx) = begin_write().await?;
+    let id: Option<i64> = sqlx::query_scalar(
+        "INSERT INTO appeals (telegram_chat_id, statement, created_at) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING RETURNING id",
+    )
+    .bind(chat_id.0)
+    .bind(statement)
+    .bind(now_unix())
+    .fetch_optional(&mut *tx)
+    .await?;
+    if let Some(id) = id {
+        audit::record(
+            &mut tx,
+            &Actor::Chat(chat_id),
+            "appeal_filed",
+            Some(chat_id),
+            json!({ "id": id, "statement": statement }),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(id)
+}
+
+/// Forwards appeal `id` to every admin chat, with buttons to decide it
+pub async fn forward(
+    bot: &impl Telegram,
+    id: i64,
+    chat_id: ChatId,
+    statement: &str,
+    grounds: &[String],
+) {
+    let text = format!(
+        "📨 Appeal #{id} from chat {chat_id}:\n\n{statement}\n\nAgainst:\n{}",
+        grounds.join("\n")
+    );
+    let mut buttons = vec![
+        render::command_button("✅ Accept", format!("/admin appeal_accept {id}")),
+        render::command_button("❌ Reject", format!("/admin appeal_reject {id}")),
+    ];
+    // A shadow ban stays unless lifted on purpose, since the chat never knew of it
+    if grounds
+        .iter()
+        .any(|ground| ground.starts_with(SHADOW_BANNED))
+    {
+        buttons.insert(
+            1,
+            render::command_button(
+                "✅ Accept, lift shadow ban",
+                format!("/admin appeal_accept {id} shadow"),
+            ),
+        );
+    }
+    let markup = InlineKeyboardMarkup::new(vec![buttons]);
+    admin::ask_admins(bot, &text, markup).await;
+}
+
+/// Decides appeal `id` on behalf of `admin`, returning the chat that filed it if it was
+/// still open. Accepting lifts the chat's ban and clears its fraud flags, but leaves a
+/// shadow ban unless `lift_shadow`; rejecting leaves everything as it is.
+pub async fn resolve(
+    id: i64,
+    accepted: bool,
+    lift_shadow: bool,
+    admin: ChatId,
+) -> sqlx::Result<Option<ChatId>> {
+    let outcome = if accepted { "accepted" } else { "rejected" };
+    let (_write, mut tx) = begin_write().await?;
+    let chat_id: Option<i64> = sqlx::query_scalar(
+        "UPDATE appeals SET outcome = $1, resolved_by = $2, resolved_at = $3 WHERE id = $4 AND resolved_at IS NULL RETURNING telegram_chat_id",
+    )
+    .bind(outcome)
+    .bind(admin.0)
+    .bind(now_unix())
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(chat_id) = chat_id.map(ChatId) else {
+        return Ok(None);
+    };
+    if accepted {
+        sqlx::query("DELETE FROM bans WHERE telegram_chat_id = $1 AND ($2 <> 0 OR shadow = 0)")
+            .bind(chat_id.0)
+            .bind(i64::from(lift_shadow))
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "UPDATE fraud_flags SET resolved_at = $1 WHERE telegram_chat_id = $2 AND resolved_at IS NULL",
+        )
+        .bind(now_unix())
+        .bind(chat_id.0)
+        .execute(&mut *tx)
+        .await?;
+    }
+    audit::record(
+        &mut tx,
+        &Actor::Admin(admin),
+        &format!("appeal_{outcome}"),
+        Some(chat_id),
+        json!({ "id": id, "lift_shadow": lift_shadow }),
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(Some(chat_id))
+}
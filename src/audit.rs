@@ -0,0 +1,96 @@
+use serde_json::Value;
+use sqlx::AnyConnection;
+use teloxide::types::ChatId;
+
+use crate::{DB, begin_write, now_unix};
+
+/// Who performed an audited action
+pub enum Actor {
+    /// A tester acting on their own data
+    Chat(ChatId),
+    Admin(ChatId),
+    /// A VM agent calling the HTTP API
+    Agent(String),
+    /// Background loops and other automatic actions
+    System,
+    /// An operator running a subcommand of the binary
+    Cli,
+}
+
+impl Actor {
+    fn encode(&self) -> String {
+        match self {
+            Actor::Chat(chat_id) => format!("chat:{chat_id}"),
+            Actor::Admin(chat_id) => format!("admin:{chat_id}"),
+            Actor::Agent(vm_id) => format!("agent:{vm_id}"),
+            Actor::System => "system".to_owned(),
+            Actor::Cli => "cli".to_owned(),
+        }
+    }
+}
+
+/// One row of the audit log, as shown to admins
+pub struct Entry {
+    pub actor: String,
+    pub action: String,
+    pub payload: String,
+    pub created_at: i64,
+}
+
+/// Appends an entry on `conn`, so it commits or rolls back with the action it describes
+pub async fn record(
+    conn: &mut AnyConnection,
+    actor: &Actor,
+    action: &str,
+    chat_id: Option<ChatId>,
+    payload: Value,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO audit_log (actor, action, telegram_chat_id, payload, created_at) VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(actor.encode())
+    .bind(action)
+    .bind(chat_id.map(|c| c.0))
+    .bind(payload.to_string())
+    .bind(now_unix())
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Appends an entry in its own transaction, for actions that were written outside one
+pub async fn log(
+    actor: &Actor,
+    action: &str,
+    chat_id: Option<ChatId>,
+    payload: Value,
+) -> sqlx::Result<()> {
+    let (_write, mut tx) = begin_write().await?;
+    record(&mut tx, actor, action, chat_id, payload).await?;
+    tx.commit().await
+}
+
+/// The most recent entries touching `chat_id`, newest first
+pub async fn for_chat(chat_id: ChatId, limit: i64) -> sqlx::Result<Vec<Entry>> {
+    let rows: Vec<(String, String, String, i64)> = sqlx::query_as(
+        r#"
+SELECT actor, action, payload, created_at FROM audit_log
+WHERE telegram_chat_id = $1
+ORDER BY id DESC
+LIMIT $2
+        "#,
+    )
+    .bind(chat_id.0)
+    .bind(limit)
+    .fetch_all(&*DB)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(actor, action, payload, created_at)| Entry {
+            actor,
+            action,
+            payload,
+            created_at,
+        })
+        .collect())
+}
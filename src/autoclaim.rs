@@ -0,0 +1,195 @@
+use std::time::Duration;
+
+use chrono::{Datelike, Utc, Weekday};
+use serde_json::json;
+use teloxide::{prelude::*, types::ChatId};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::{
+    DB,
+    audit::{self, Actor},
+    begin_write,
+    claim::{self, ClaimOutcome},
+    i18n, next_tick, outbox, render,
+};
+
+/// Largest threshold accepted by `/autoclaim <days>`
+pub const MAX_THRESHOLD_DAYS: i64 = 365;
+
+/// When a chat's balance is claimed without it asking
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    /// Every Sunday (UTC), whatever has accrued by then
+    Sunday,
+    /// As soon as this many whole days are unclaimed
+    Threshold(i64),
+}
+
+impl Schedule {
+    /// Parses the argument of `/autoclaim`: `sunday`, or a number of days
+    pub fn parse(arg: &str) -> Option<Schedule> {
+        match arg {
+            "sunday" | "weekly" => Some(Schedule::Sunday),
+            days => days
+                .trim_end_matches("days")
+                .trim_end_matches('d')
+                .parse()
+                .ok()
+                .map(Schedule::Threshold),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Schedule::Sunday => true,
+            Schedule::Threshold(days) => (1..=MAX_THRESHOLD_DAYS).contains(days),
+        }
+    }
+
+    /// The message key describing the schedule, and the `count` it is filled in with
+    pub fn message(&self) -> (&'static str, i64) {
+        match self {
+            Schedule::Sunday => ("autoclaim.sunday", 0),
+            Schedule::Threshold(days) => ("autoclaim.threshold", *days),
+        }
+    }
+
+    fn encode(&self) -> (&'static str, Option<i64>) {
+        match self {
+            Schedule::Sunday => ("sunday", None),
+            Schedule::Threshold(days) => ("threshold", Some(*days)),
+        }
+    }
+
+    fn decode(mode: &str, threshold: Option<i64>) -> Option<Schedule> {
+        match mode {
+            "sunday" => Some(Schedule::Sunday),
+            "threshold" => threshold.map(Schedule::Threshold),
+            _ => None,
+        }
+    }
+}
+
+pub async fn get(chat_id: ChatId) -> sqlx::Result<Option<Schedule>> {
+    let row: Option<(Option<String>, Option<i64>)> = sqlx::query_as(
+        "SELECT auto_claim, auto_claim_threshold FROM user_prefs WHERE telegram_chat_id = $1",
+    )
+    .bind(chat_id.0)
+    .fetch_optional(&*DB)
+    .await?;
+    Ok(row.and_then(|(mode, threshold)| Schedule::decode(mode.as_deref()?, threshold)))
+}
+
+/// Turns automatic claiming on with `schedule`, or off with `None`
+pub async fn set(chat_id: ChatId, schedule: Option<Schedule>) -> sqlx::Result<()> {
+    let (mode, threshold) = schedule.as_ref().map(Schedule::encode).unzip();
+    let (_write, mut tx) = begin_write().await?;
+    sqlx::query(
+        r#"
+INSERT INTO user_prefs (telegram_chat_id, auto_claim, auto_claim_threshold) VALUES ($1, $2, $3)
+ON CONFLICT(telegram_chat_id) DO UPDATE SET
+    auto_claim = excluded.auto_claim,
+    auto_claim_threshold = excluded.auto_claim_threshold
+        "#,
+    )
+    .bind(chat_id.0)
+    .bind(mode)
+    .bind(threshold.flatten())
+    .execute(&mut *tx)
+    .await?;
+    audit::record(
+        &mut tx,
+        &Actor::Chat(chat_id),
+        "auto_claim_set",
+        Some(chat_id),
+        json!({ "mode": mode, "threshold": threshold.flatten() }),
+    )
+    .await?;
+    tx.commit().await
+}
+
+/// Claims the whole balance of every opted-in chat that is due, hourly.
+///
+/// A Sunday schedule runs once per Sunday even if there was nothing to claim, so a balance
+/// reached later that day waits for the next Sunday; a threshold schedule fires on the first
+/// pass after the balance reaches it. Claims go through [`claim::claim`] like `/claim` does,
+/// so fraud holds, direct credit and the retry queue all apply.
+pub async fn auto_claim_loop(bot: Bot, shutdown: CancellationToken) -> anyhow::Result<()> {
+    let mut ticker = smol::Timer::interval(Duration::from_secs(3600));
+    loop {
+        async {
+            let today = Utc::now().date_naive();
+            let today_str = today.format("%Y-%m-%d").to_string();
+            let rows: Vec<(i64, String, Option<i64>, Option<String>)> = sqlx::query_as(
+                r#"
+SELECT p.telegram_chat_id, p.auto_claim, p.auto_claim_threshold, p.auto_claimed_on
+FROM user_prefs p
+WHERE p.auto_claim IS NOT NULL
+  AND EXISTS(SELECT 1 FROM agent_records a WHERE a.telegram_chat_id = p.telegram_chat_id)
+  AND NOT EXISTS(SELECT 1 FROM inactive_chats i WHERE i.telegram_chat_id = p.telegram_chat_id)
+                "#,
+            )
+            .fetch_all(&*DB)
+            .await?;
+
+            for (chat_id, mode, threshold, claimed_on) in rows {
+                let chat_id = ChatId(chat_id);
+                let due = match Schedule::decode(&mode, threshold) {
+                    Some(Schedule::Sunday) => {
+                        today.weekday() == Weekday::Sun && claimed_on.as_deref() != Some(&today_str)
+                    }
+                    Some(Schedule::Threshold(days)) => claim::available(chat_id).await? >= days,
+                    None => false,
+                };
+                if !due {
+                    continue;
+                }
+                sqlx::query(
+                    "UPDATE user_prefs SET auto_claimed_on = $1 WHERE telegram_chat_id = $2",
+                )
+                .bind(&today_str)
+                .bind(chat_id.0)
+                .execute(&*DB)
+                .await?;
+                let mut codes = vec![];
+                let text = match claim::claim(chat_id, None).await? {
+                    ClaimOutcome::Issued { giftcards } => {
+                        codes = giftcards;
+                        i18n::text("autoclaim.issued", &[])
+                    }
+                    ClaimOutcome::Credited { days, username } => i18n::text(
+                        "autoclaim.credited",
+                        &[("count", &days), ("username", &username)],
+                    ),
+                    ClaimOutcome::Queued { days } => {
+                        i18n::text("autoclaim.queued", &[("count", &days)])
+                    }
+                    ClaimOutcome::UnderReview => {
+                        tracing::info!("auto-claim for chat {chat_id} held for fraud review");
+                        continue;
+                    }
+                    ClaimOutcome::NothingToClaim
+                    | ClaimOutcome::NotEnough { .. }
+                    | ClaimOutcome::InProgress => continue,
+                };
+                let sent =
+                    outbox::deliver(chat_id, || bot.send_message(chat_id, &text).send()).await;
+                if let Err(e) = sent {
+                    tracing::warn!("sending auto-claim result to {chat_id} failed: {e}");
+                }
+                for code in &codes {
+                    let _ =
+                        outbox::deliver(chat_id, || render::send_code(&bot, chat_id, code)).await;
+                }
+            }
+            anyhow::Ok(())
+        }
+        .instrument(tracing::info_span!("tick"))
+        .await?;
+
+        if !next_tick("auto_claim", &mut ticker, &shutdown).await {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::path::{Path, PathBuf};
+
+use sqlx::{Connection, sqlite::SqliteConnection};
+
+use crate::{CONFIG, DB, now_unix};
+
+/// Snapshot files are `<PREFIX><unix time><SUFFIX>`, so they sort by age
+const PREFIX: &str = "geph-testing-bot-";
+const SUFFIX: &str = ".db";
+
+/// Snapshots the database into `dir`, keeping the newest `backup_keep` snapshots. Runs as
+/// the `backup` job, every `backup_interval_hours` unless `job_schedules` says otherwise.
+pub async fn back_up(dir: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let path = snapshot(dir).await?;
+    let removed = rotate(dir, CONFIG.backup_keep)?;
+    tracing::info!(
+        "backed up database to {}, removed {removed} old snapshot(s)",
+        path.display()
+    );
+    Ok(())
+}
+
+/// Writes a consistent copy of the live database. `VACUUM INTO` is SQLite's online
+/// snapshot: it reads inside one transaction, so writers carry on meanwhile and the copy
+/// never contains half a transaction, and it works through `sqlx::Any` without reaching
+/// for the raw C handle the page-by-page backup API needs.
+async fn snapshot(dir: &Path) -> anyhow::Result<PathBuf> {
+    let path = dir.join(format!("{PREFIX}{}{SUFFIX}", now_unix()));
+    anyhow::ensure!(!path.exists(), "{} already exists", path.display());
+    let target = path
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("backup path {} is not UTF-8", path.display()))?;
+    sqlx::query("VACUUM INTO $1")
+        .bind(target)
+        .execute(&*DB)
+        .await?;
+    Ok(path)
+}
+
+/// Deletes all but the newest `keep` snapshots in `dir`, returning how many went
+fn rotate(dir: &Path, keep: usize) -> anyhow::Result<usize> {
+    let mut snapshots: Vec<(i64, PathBuf)> = std::fs::read_dir(dir)?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_str()?;
+            let taken_at = name
+                .strip_prefix(PREFIX)?
+                .strip_suffix(SUFFIX)?
+                .parse()
+                .ok()?;
+            Some((taken_at, path))
+        })
+        .collect();
+    snapshots.sort();
+    let excess = snapshots.len().saturating_sub(keep);
+    for (_, path) in &snapshots[..excess] {
+        std::fs::remove_file(path)?;
+    }
+    Ok(excess)
+}
+
+/// File path of the configured SQLite database
+fn database_path() -> anyhow::Result<PathBuf> {
+    let url = &CONFIG.database_url;
+    let rest = url
+        .strip_prefix("sqlite://")
+        .or_else(|| url.strip_prefix("sqlite:"))
+        .ok_or_else(|| anyhow::anyhow!("restore only works on SQLite, not {url}"))?;
+    let path = rest.split('?').next().unwrap_or(rest);
+    anyhow::ensure!(
+        !path.is_empty() && path != ":memory:",
+        "{url} is not a database file"
+    );
+    Ok(PathBuf::from(path))
+}
+
+/// Replaces the database with `backup` after checking the snapshot is intact. Must run
+/// with the bot stopped: it swaps files underneath any open connection.
+pub async fn restore(backup: &Path) -> anyhow::Result<()> {
+    let target = database_path()?;
+    let mut conn =
+        SqliteConnection::connect(&format!("sqlite://{}?mode=ro", backup.display())).await?;
+    let integrity: String = sqlx::query_scalar("PRAGMA integrity_check")
+        .fetch_one(&mut conn)
+        .await?;
+    anyhow::ensure!(
+        integrity == "ok",
+        "{} failed its integrity check: {integrity}",
+        backup.display()
+    );
+    let version: Option<i64> = sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations")
+        .fetch_one(&mut conn)
+        .await
+        .map_err(|e| anyhow::anyhow!("{} is not a bot database: {e}", backup.display()))?;
+    conn.close().await?;
+
+    if target.exists() {
+        let mut kept = target.clone().into_os_string();
+        kept.push(format!(".before-restore-{}", now_unix()));
+        // A leftover write-ahead log belongs to the old database: it moves along with it,
+        // rather than being replayed into the restored one
+        for suffix in ["", "-wal", "-shm"] {
+            let (mut from, mut to) = (target.clone().into_os_string(), kept.clone());
+            from.push(suffix);
+            to.push(suffix);
+            if Path::new(&from).exists() {
+                std::fs::rename(&from, &to)?;
+            }
+        }
+        println!("kept the previous database as {}", kept.to_string_lossy());
+    }
+    std::fs::copy(backup, &target)?;
+    println!(
+        "restored {} (schema version {}) to {}; newer migrations run on next start",
+        backup.display(),
+        version.unwrap_or(0),
+        target.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,169 @@
+use std::fmt;
+
+use serde_json::json;
+use sqlx::AnyConnection;
+use teloxide::types::ChatId;
+
+use crate::{
+    DB,
+    audit::{self, Actor},
+    begin_write, now_unix,
+};
+
+/// What a ban applies to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    Chat(ChatId),
+    Vm(String),
+}
+
+impl Target {
+    /// Parses `chat <id>` or `vm <id>` as given to the admin commands
+    pub fn parse(kind: &str, id: &str) -> Option<Target> {
+        match kind {
+            "chat" => id.parse().ok().map(|id| Target::Chat(ChatId(id))),
+            "vm" => Some(Target::Vm(id.to_owned())),
+            _ => None,
+        }
+    }
+
+    /// `(telegram_chat_id, vm_id)` as stored, one of them `None`
+    fn columns(&self) -> (Option<i64>, Option<&str>) {
+        match self {
+            Target::Chat(chat_id) => (Some(chat_id.0), None),
+            Target::Vm(vm_id) => (None, Some(vm_id)),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Chat(chat_id) => write!(f, "chat {chat_id}"),
+            Target::Vm(vm_id) => write!(f, "VM {vm_id}"),
+        }
+    }
+}
+
+/// `(telegram_chat_id, vm_id, shadow, reason, banned_by, created_at)`
+type BanRow = (Option<i64>, Option<String>, i64, String, i64, i64);
+
+/// A ban in force, as listed to admins
+pub struct Ban {
+    pub target: Target,
+    pub shadow: bool,
+    pub reason: String,
+    pub banned_by: i64,
+    pub created_at: i64,
+}
+
+/// Bans `target` on behalf of `admin`, replacing any ban it's already under. A `shadow`
+/// ban only stops uptime from being credited; a full one also drops everything a banned
+/// chat sends, and keeps a banned VM from being registered.
+pub async fn ban(target: &Target, shadow: bool, reason: &str, admin: ChatId) -> sqlx::Result<()> {
+    let (chat_id, vm_id) = target.columns();
+    let (_write, mut tx) = begin_write().await?;
+    sqlx::query("DELETE FROM bans WHERE telegram_chat_id = $1 OR vm_id = $2")
+        .bind(chat_id)
+        .bind(vm_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        "INSERT INTO bans (telegram_chat_id, vm_id, shadow, reason, banned_by, created_at) VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(chat_id)
+    .bind(vm_id)
+    .bind(i64::from(shadow))
+    .bind(reason)
+    .bind(admin.0)
+    .bind(now_unix())
+    .execute(&mut *tx)
+    .await?;
+    audit::record(
+        &mut tx,
+        &Actor::Admin(admin),
+        if shadow { "shadow_banned" } else { "banned" },
+        chat_id.map(ChatId),
+        json!({ "vm_id": vm_id, "reason": reason }),
+    )
+    .await?;
+    tx.commit().await
+}
+
+/// Lifts the ban on `target`, returning whether there was one
+pub async fn unban(target: &Target, admin: ChatId) -> sqlx::Result<bool> {
+    let (chat_id, vm_id) = target.columns();
+    let (_write, mut tx) = begin_write().await?;
+    let lifted = sqlx::query("DELETE FROM bans WHERE telegram_chat_id = $1 OR vm_id = $2")
+        .bind(chat_id)
+        .bind(vm_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+        > 0;
+    if lifted {
+        audit::record(
+            &mut tx,
+            &Actor::Admin(admin),
+            "unbanned",
+            chat_id.map(ChatId),
+            json!({ "vm_id": vm_id }),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(lifted)
+}
+
+/// Every ban in force, newest first
+pub async fn list() -> sqlx::Result<Vec<Ban>> {
+    let rows: Vec<BanRow> = sqlx::query_as(
+        "SELECT telegram_chat_id, vm_id, shadow, reason, banned_by, created_at FROM bans ORDER BY created_at DESC",
+    )
+    .fetch_all(&*DB)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(chat_id, vm_id, shadow, reason, banned_by, created_at)| {
+            let target = match (chat_id, vm_id) {
+                (Some(chat_id), _) => Target::Chat(ChatId(chat_id)),
+                (None, Some(vm_id)) => Target::Vm(vm_id),
+                (None, None) => return None,
+            };
+            Some(Ban {
+                target,
+                shadow: shadow != 0,
+                reason,
+                banned_by,
+                created_at,
+            })
+        })
+        .collect())
+}
+
+/// Whether anything from `chat_id` is to be dropped unanswered
+pub async fn is_banned(chat_id: ChatId) -> sqlx::Result<bool> {
+    sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM bans WHERE telegram_chat_id = $1 AND shadow = 0",
+    )
+    .bind(chat_id.0)
+    .fetch_one(&*DB)
+    .await
+    .map(|n| n > 0)
+}
+
+/// Whether `vm_id`'s uptime goes uncredited, being banned itself or owned by a banned
+/// chat, in either way
+pub async fn withholds_credit(conn: &mut AnyConnection, vm_id: &str) -> sqlx::Result<bool> {
+    sqlx::query_scalar::<_, i64>(
+        r#"
+SELECT COUNT(*) FROM bans
+WHERE vm_id = $1
+   OR telegram_chat_id = (SELECT telegram_chat_id FROM agent_records WHERE vm_id = $1)
+        "#,
+    )
+    .bind(vm_id)
+    .fetch_one(conn)
+    .await
+    .map(|n| n > 0)
+}
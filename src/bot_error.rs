@@ -0,0 +1,95 @@
+use std::fmt;
+
+use teloxide::{RequestError, types::ChatId};
+
+use crate::{
+    errors::{ErrorCode, send_error},
+    inactive, reporting,
+    telegram::Telegram,
+};
+
+/// Why handling an update failed. Handlers return it up to the dispatcher, which tells the
+/// chat the problem is on our side and logs what went wrong and where.
+#[derive(Debug)]
+pub enum BotError {
+    /// A query failed while the handler was `context`
+    Database {
+        context: &'static str,
+        source: sqlx::Error,
+    },
+    /// Something other than the database or Telegram failed while the handler was `context`
+    Internal {
+        context: &'static str,
+        source: anyhow::Error,
+    },
+    /// Telegram refused a request or couldn't be reached
+    Telegram(RequestError),
+}
+
+impl fmt::Display for BotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BotError::Database { context, source } => write!(f, "{context}: {source}"),
+            BotError::Internal { context, source } => write!(f, "{context}: {source:#}"),
+            BotError::Telegram(e) => write!(f, "telegram: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for BotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BotError::Database { source, .. } => Some(source),
+            BotError::Internal { source, .. } => Some(source.as_ref()),
+            BotError::Telegram(e) => Some(e),
+        }
+    }
+}
+
+impl From<RequestError> for BotError {
+    fn from(e: RequestError) -> Self {
+        BotError::Telegram(e)
+    }
+}
+
+/// Attaches what the handler was doing to a failure, like `anyhow::Context`
+pub trait Context<T> {
+    fn context(self, context: &'static str) -> Result<T, BotError>;
+}
+
+impl<T> Context<T> for sqlx::Result<T> {
+    fn context(self, context: &'static str) -> Result<T, BotError> {
+        self.map_err(|source| BotError::Database { context, source })
+    }
+}
+
+impl<T> Context<T> for anyhow::Result<T> {
+    fn context(self, context: &'static str) -> Result<T, BotError> {
+        self.map_err(|source| BotError::Internal { context, source })
+    }
+}
+
+/// Logs a failed `command` and ships it to the error reporter, then lets the chat know,
+/// unless it can't be messaged anymore
+pub async fn report(
+    bot: &impl Telegram,
+    chat_id: ChatId,
+    command: &str,
+    result: Result<(), BotError>,
+) {
+    let Err(e) = result else {
+        return;
+    };
+    tracing::error!("handling an update failed: {e}");
+    if matches!(&e, BotError::Telegram(e) if inactive::dead_chat_reason(e).is_some()) {
+        return;
+    }
+    let origin = reporting::Origin {
+        command: Some(command),
+        chat_id: Some(chat_id),
+    };
+    reporting::capture_error(&e, origin);
+    if let Err(send) = send_error(bot, chat_id, ErrorCode::Temporary).await {
+        tracing::debug!("telling {chat_id} about a failed update failed too: {send}");
+    }
+}
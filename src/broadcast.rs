@@ -0,0 +1,175 @@
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+use teloxide::types::{ChatId, MessageId};
+
+use crate::{
+    DB,
+    audit::{self, Actor},
+    begin_write, now_unix, outbox,
+    telegram::Telegram,
+};
+
+/// How often the admin's progress message is edited while a broadcast runs
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Sends `text` to every registered chat that hasn't blocked the bot, in the background.
+/// Sends go through [`outbox`], so a broadcast never trips Telegram's limits; progress is
+/// kept up to date in a message to the admin, and each chat's outcome is recorded in
+/// `broadcast_deliveries`.
+pub async fn start(bot: &impl Telegram, admin: ChatId, text: String) -> anyhow::Result<()> {
+    let (id, recipients) = create(Some(admin), &text).await?;
+    let progress = bot
+        .send_message(
+            admin,
+            format!("Broadcast #{id}: sending to {} chat(s)…", recipients.len()),
+        )
+        .await?;
+    let bot = bot.clone();
+    smolscale::spawn(async move {
+        let progress = Some((admin, progress));
+        if let Err(e) = run(&bot, progress, id, &text, &recipients).await {
+            tracing::error!("broadcast #{id} stopped: {e:?}");
+            let _ = bot
+                .send_message(admin, format!("Broadcast #{id} stopped: {e}"))
+                .await;
+        }
+    })
+    .detach();
+    Ok(())
+}
+
+/// Broadcasts from the command line, returning once every chat has been tried
+pub async fn send_now(bot: &impl Telegram, text: &str) -> anyhow::Result<Summary> {
+    let (id, recipients) = create(None, text).await?;
+    tracing::info!("broadcast #{id}: sending to {} chat(s)", recipients.len());
+    run(bot, None, id, text, &recipients).await
+}
+
+pub struct Summary {
+    pub id: i64,
+    pub sent: u64,
+    pub failed: u64,
+}
+
+/// Records a new broadcast by `admin`, or by the operator at the command line when `None`,
+/// and returns its id and recipients
+async fn create(admin: Option<ChatId>, text: &str) -> anyhow::Result<(i64, Vec<i64>)> {
+    let recipients: Vec<i64> = sqlx::query_scalar(
+        r#"
+SELECT DISTINCT a.telegram_chat_id FROM agent_records a
+WHERE a.telegram_chat_id IS NOT NULL
+  AND NOT EXISTS(SELECT 1 FROM inactive_chats i WHERE i.telegram_chat_id = a.telegram_chat_id)
+ORDER BY a.telegram_chat_id
+        "#,
+    )
+    .fetch_all(&*DB)
+    .await?;
+
+    let (_write, mut tx) = begin_write().await?;
+    let id: i64 = sqlx::query_scalar(
+        "INSERT INTO broadcasts (admin_chat_id, text, recipients, created_at) VALUES ($1, $2, $3, $4) RETURNING id",
+    )
+    // There's no chat behind the command line; 0 stands in for it
+    .bind(admin.map_or(0, |a| a.0))
+    .bind(text)
+    .bind(recipients.len() as i64)
+    .bind(now_unix())
+    .fetch_one(&mut *tx)
+    .await?;
+    audit::record(
+        &mut tx,
+        &actor(admin),
+        "broadcast_started",
+        None,
+        json!({ "id": id, "recipients": recipients.len() }),
+    )
+    .await?;
+    tx.commit().await?;
+    Ok((id, recipients))
+}
+
+fn actor(admin: Option<ChatId>) -> Actor {
+    admin.map_or(Actor::Cli, Actor::Admin)
+}
+
+/// Delivers to every recipient. Progress goes to the admin's `progress` message when
+/// there is one and to the log otherwise.
+async fn run(
+    bot: &impl Telegram,
+    progress: Option<(ChatId, MessageId)>,
+    id: i64,
+    text: &str,
+    recipients: &[i64],
+) -> anyhow::Result<Summary> {
+    let (mut sent, mut failed) = (0, 0);
+    let mut last_report = Instant::now();
+    for &chat_id in recipients {
+        let result = outbox::deliver(ChatId(chat_id), || {
+            bot.send_message(ChatId(chat_id), text).into_future()
+        })
+        .await;
+        let error = match &result {
+            Ok(_) => {
+                sent += 1;
+                None
+            }
+            Err(e) => {
+                failed += 1;
+                tracing::warn!("broadcast #{id} to {chat_id} failed: {e}");
+                Some(e.to_string())
+            }
+        };
+        sqlx::query(
+            "INSERT INTO broadcast_deliveries (broadcast_id, telegram_chat_id, error, attempted_at) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(id)
+        .bind(chat_id)
+        .bind(error)
+        .bind(now_unix())
+        .execute(&*DB)
+        .await?;
+
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            last_report = Instant::now();
+            let status = format!(
+                "Broadcast #{id}: {}/{} done, {sent} sent, {failed} failed…",
+                sent + failed,
+                recipients.len()
+            );
+            match progress {
+                Some((admin, message)) => {
+                    let _ = bot.edit_message_text(admin, message, status).await;
+                }
+                None => tracing::info!("{status}"),
+            }
+        }
+    }
+
+    let (_write, mut tx) = begin_write().await?;
+    sqlx::query("UPDATE broadcasts SET finished_at = $1 WHERE id = $2")
+        .bind(now_unix())
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    audit::record(
+        &mut tx,
+        &actor(progress.map(|(admin, _)| admin)),
+        "broadcast_finished",
+        None,
+        json!({ "id": id, "sent": sent, "failed": failed }),
+    )
+    .await?;
+    tx.commit().await?;
+    tracing::info!("broadcast #{id} finished: {sent} sent, {failed} failed");
+    if let Some((admin, message)) = progress {
+        let _ = bot
+            .edit_message_text(
+                admin,
+                message,
+                format!("Broadcast #{id} finished: {sent} sent, {failed} failed."),
+            )
+            .await;
+    }
+    Ok(Summary { id, sent, failed })
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use chrono::{Days, NaiveDate, Timelike, Utc};
+use teloxide::{prelude::*, types::ChatId};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::{CONFIG, DB, community, i18n, next_tick, now_unix, outbox, tokens};
+
+/// The public summary of `day` (UTC): VMs that were up, the uptime they contributed and the
+/// Plus days awarded, followed by the all-time totals
+pub async fn compose(day: NaiveDate) -> sqlx::Result<String> {
+    let day_str = day.format("%Y-%m-%d").to_string();
+    let start = day.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
+    let (active, up_secs, awarded): (i64, i64, i64) = sqlx::query_as(
+        r#"
+SELECT
+    (SELECT COUNT(*) FROM uptime_history WHERE day = $1 AND up_secs > 0),
+    (SELECT CAST(COALESCE(SUM(up_secs), 0) AS BIGINT) FROM uptime_history WHERE day = $1),
+    (SELECT CAST(COALESCE(SUM(days), 0) AS BIGINT) FROM claims
+     WHERE status IN ('issued', 'credited') AND created_at >= $2 AND created_at < $3)
+        "#,
+    )
+    .bind(&day_str)
+    .bind(start)
+    .bind(start + 86400)
+    .fetch_one(&*DB)
+    .await?;
+    let totals = community::network_totals().await?;
+    let (hours, total_hours) = (up_secs / 3600, totals.up_secs / 3600);
+    let mut text = [
+        i18n::text("channel.title", &[("day", &day_str)]),
+        i18n::text("channel.active", &[("count", &active)]),
+        i18n::text("channel.uptime", &[("hours", &hours)]),
+        i18n::text("channel.awarded", &[("count", &awarded)]),
+        String::new(),
+        i18n::text("channel.registered", &[("count", &totals.registered)]),
+        i18n::text("channel.total_uptime", &[("hours", &total_hours)]),
+        i18n::text("channel.total_awarded", &[("count", &totals.claimed_days)]),
+    ]
+    .join("\n");
+    if let Some(link) = tokens::bot_link() {
+        text.push_str(&format!(
+            "\n\n{}",
+            i18n::text("channel.invite", &[("link", &link)])
+        ));
+    }
+    Ok(text)
+}
+
+/// Posts yesterday's summary to `stats_channel_id` once the UTC clock passes
+/// `stats_channel_post_hour`. Posted days are recorded, so a restart neither skips nor
+/// repeats one; a failed post is retried on the next hourly pass.
+pub async fn daily_post_loop(
+    bot: Bot,
+    channel: ChatId,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let mut ticker = smol::Timer::interval(Duration::from_secs(3600));
+    loop {
+        async {
+            let now = Utc::now();
+            let yesterday = now.date_naive() - Days::new(1);
+            let day_str = yesterday.format("%Y-%m-%d").to_string();
+            let posted: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM channel_posts WHERE day = $1")
+                .bind(&day_str)
+                .fetch_one(&*DB)
+                .await?;
+            if posted == 0 && now.hour() >= CONFIG.stats_channel_post_hour {
+                let text = compose(yesterday).await?;
+                match outbox::deliver(channel, || bot.send_message(channel, &text).send()).await {
+                    Ok(message) => {
+                        sqlx::query(
+                            "INSERT INTO channel_posts (day, message_id, posted_at) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+                        )
+                        .bind(&day_str)
+                        .bind(i64::from(message.id.0))
+                        .bind(now_unix())
+                        .execute(&*DB)
+                        .await?;
+                        tracing::info!("posted the summary of {day_str} to channel {channel}");
+                    }
+                    Err(e) => tracing::warn!("posting the summary of {day_str} to {channel} failed: {e}"),
+                }
+            }
+            anyhow::Ok(())
+        }
+        .instrument(tracing::info_span!("tick"))
+        .await?;
+
+        if !next_tick("channel_stats", &mut ticker, &shutdown).await {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::io::Cursor;
+
+use anyhow::Context;
+use chrono::{Days, Utc};
+use once_cell::sync::Lazy;
+use plotters::prelude::*;
+
+use crate::CONFIG;
+
+const WIDTH: u32 = 800;
+const HEIGHT: u32 = 400;
+
+/// Loads the configured font into plotters once; charts can't be drawn without it
+static FONT: Lazy<anyhow::Result<()>> = Lazy::new(|| {
+    let bytes = std::fs::read(&CONFIG.chart_font_path)
+        .with_context(|| format!("read chart font {}", CONFIG.chart_font_path))?;
+    plotters::style::register_font("sans-serif", FontStyle::Normal, bytes.leak())
+        .map_err(|_| anyhow::anyhow!("{} is not a usable font", CONFIG.chart_font_path))
+});
+
+/// The last `n` UTC days as `YYYY-MM-DD`, oldest first, matching `uptime_history.day`
+pub fn last_days(n: u64) -> Vec<String> {
+    let today = Utc::now().date_naive();
+    (0..n)
+        .rev()
+        .filter_map(|back| today.checked_sub_days(Days::new(back)))
+        .map(|d| d.format("%Y-%m-%d").to_string())
+        .collect()
+}
+
+/// Renders daily uptime (`(day, up_secs)`, oldest first) as a PNG bar chart
+pub fn render_uptime_chart(days: &[(String, i64)]) -> anyhow::Result<Vec<u8>> {
+    if let Err(e) = &*FONT {
+        anyhow::bail!("chart font unavailable: {e:#}");
+    }
+    let hours: Vec<f64> = days.iter().map(|(_, secs)| *secs as f64 / 3600.0).collect();
+    let y_max = hours.iter().copied().fold(24.0, f64::max);
+
+    let mut buf = vec![0u8; (WIDTH * HEIGHT * 3) as usize];
+    {
+        let root = BitMapBackend::with_buffer(&mut buf, (WIDTH, HEIGHT)).into_drawing_area();
+        root.fill(&WHITE)?;
+        let mut chart = ChartBuilder::on(&root)
+            .caption("Daily uptime (hours, UTC)", ("sans-serif", 22))
+            .margin(12)
+            .x_label_area_size(32)
+            .y_label_area_size(40)
+            .build_cartesian_2d(0..days.len(), 0.0..y_max)?;
+        chart
+            .configure_mesh()
+            .disable_x_mesh()
+            .x_labels(days.len().min(10))
+            .x_label_formatter(&|i| {
+                days.get(*i)
+                    .map(|(d, _)| d[5..].to_owned())
+                    .unwrap_or_default()
+            })
+            .draw()?;
+        chart.draw_series(hours.iter().enumerate().map(|(i, h)| {
+            Rectangle::new([(i, 0.0), (i + 1, *h)], RGBColor(46, 125, 200).filled())
+        }))?;
+        root.present()?;
+    }
+
+    let image = image::RgbImage::from_raw(WIDTH, HEIGHT, buf).context("chart buffer size")?;
+    let mut png = Cursor::new(Vec::new());
+    image.write_to(&mut png, image::ImageFormat::Png)?;
+    Ok(png.into_inner())
+}
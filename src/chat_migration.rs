@@ -0,0 +1,72 @@
+use teloxide::types::ChatId;
+
+use serde_json::json;
+
+use crate::{
+    CONFIG,
+    audit::{self, Actor},
+    begin_write,
+};
+
+/// Every table column holding a chat id, which should follow a group when Telegram
+/// upgrades it to a supergroup and is erased by `/deletemydata`. Queued message deletions
+/// stay behind: those messages remain in the old, now read-only, group.
+pub const CHAT_COLUMNS: &[(&str, &str)] = &[
+    ("agent_records", "telegram_chat_id"),
+    ("giftcards", "telegram_chat_id"),
+    ("claims", "telegram_chat_id"),
+    ("claim_locks", "telegram_chat_id"),
+    ("pending_claims", "telegram_chat_id"),
+    ("user_prefs", "telegram_chat_id"),
+    ("chat_state", "telegram_chat_id"),
+    ("ownership_challenges", "telegram_chat_id"),
+    ("inactive_chats", "telegram_chat_id"),
+    ("vm_replacements", "telegram_chat_id"),
+    ("fraud_flags", "telegram_chat_id"),
+    ("referral_codes", "telegram_chat_id"),
+    ("geph_accounts", "telegram_chat_id"),
+    ("referrals", "referee_chat_id"),
+    ("referrals", "referrer_chat_id"),
+    ("vm_transfers", "from_chat_id"),
+    ("menu_messages", "telegram_chat_id"),
+    ("pinned_status", "telegram_chat_id"),
+    ("group_members", "group_chat_id"),
+    ("group_members", "user_id"),
+];
+
+/// Moves everything owned by chat `from` over to `to`, returning the number of rows
+/// changed. Telegram tells both chats about the migration, so this runs twice; the second
+/// run finds nothing left to move.
+pub async fn migrate(from: ChatId, to: ChatId) -> sqlx::Result<u64> {
+    let (_write, mut tx) = begin_write().await?;
+    let mut moved = 0;
+    for (table, column) in CHAT_COLUMNS {
+        moved += sqlx::query(&format!(
+            "UPDATE {table} SET {column} = $1 WHERE {column} = $2"
+        ))
+        .bind(to.0)
+        .bind(from.0)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    }
+    // The log itself is append-only: old entries keep the old id, this one links the two
+    if moved > 0 {
+        audit::record(
+            &mut tx,
+            &Actor::System,
+            "chat_migrated",
+            Some(to),
+            json!({ "from": from.0, "to": to.0, "rows": moved }),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    if moved > 0 {
+        tracing::info!("chat {from} migrated to {to}, moved {moved} row(s)");
+    }
+    if CONFIG.community_chat_id == Some(from.0) {
+        tracing::warn!("the community group is now {to}; update community_chat_id in the config");
+    }
+    Ok(moved)
+}
@@ -0,0 +1,581 @@
+use std::time::Duration;
+
+use rand::{Rng, distributions::Alphanumeric};
+use serde_json::json;
+use sqlx::AnyConnection;
+use teloxide::{prelude::*, types::ChatId};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::{
+    CONFIG, DB,
+    audit::{self, Actor},
+    begin_write, fraud, geph_account, i18n, next_tick, now_unix,
+    policy::RewardPolicy,
+    prefs,
+    render::{Indicator, send_codes, send_status},
+    rewards::{self, Issued},
+};
+
+/// A claim lock older than this is assumed to belong to a crashed claim and gets resumed
+const STALE_LOCK_SECS: i64 = 300;
+/// Delay before the first retry of a queued claim; doubled per failed attempt
+const RETRY_BASE_SECS: i64 = 60;
+const RETRY_MAX_SECS: i64 = 6 * 3600;
+/// Most cards one claim may be split into
+pub const MAX_CARDS: i64 = 30;
+
+/// How a claim's days are divided into cards: `cards` cards of `days_per_card` days each
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Split {
+    pub cards: i64,
+    pub days_per_card: i64,
+}
+
+impl Split {
+    pub fn single(days: i64) -> Split {
+        Split {
+            cards: 1,
+            days_per_card: days,
+        }
+    }
+
+    /// Parses `<cards>x<days_per_card>`, e.g. `3x2`
+    pub fn parse(s: &str) -> Option<Split> {
+        let (cards, days_per_card) = s.split_once(['x', 'X', '×'])?;
+        Some(Split {
+            cards: cards.parse().ok()?,
+            days_per_card: days_per_card.parse().ok()?,
+        })
+    }
+
+    pub fn days(&self) -> i64 {
+        self.cards * self.days_per_card
+    }
+
+    /// Between one and [`MAX_CARDS`] cards of at least a day each, and few enough days
+    /// in total to count
+    pub fn is_valid(&self) -> bool {
+        (1..=MAX_CARDS).contains(&self.cards)
+            && self.days_per_card >= 1
+            && self.cards.checked_mul(self.days_per_card).is_some()
+    }
+}
+
+pub enum ClaimOutcome {
+    /// One code per card
+    Issued {
+        giftcards: Vec<String>,
+    },
+    /// Added straight to the chat's linked Geph account
+    Credited {
+        days: i64,
+        username: String,
+    },
+    /// The backend failed; the days stay reserved and the card is sent once a retry succeeds
+    Queued {
+        days: i64,
+    },
+    NothingToClaim,
+    /// Fewer whole days are unclaimed than were asked for
+    NotEnough {
+        available: i64,
+    },
+    InProgress,
+    /// The fraud analyzer flagged this chat and an admin hasn't cleared it yet
+    UnderReview,
+}
+
+/// Days set aside by a claim, identified by the idempotency key sent to the giftcard backend
+pub struct Reservation {
+    pub key: String,
+    /// Total over all cards
+    pub days: i64,
+    pub num_cards: i64,
+}
+
+impl Reservation {
+    pub fn days_per_card(&self) -> i64 {
+        self.days / self.num_cards
+    }
+}
+
+pub enum Reserve {
+    Ready(Reservation),
+    Nothing,
+    NotEnough { available: i64 },
+    Busy,
+    Held,
+}
+
+/// Claims `split.days()` of `chat_id`'s unclaimed Plus days, divided into cards as given,
+/// or all of them as a single card when `None`; the rest of the balance keeps accruing.
+///
+/// The days are reserved (and the chat locked) in one transaction before the reward
+/// providers are called, then either finalized or handed back in a second one. A crash in
+/// between leaves the lock behind; the next `/claim` after [`STALE_LOCK_SECS`] resumes it
+/// with the same idempotency key so the backend can deduplicate the request.
+///
+/// If no provider could issue it the reservation is moved to `pending_claims` instead,
+/// where [`retry_pending_loop`] keeps retrying it.
+#[tracing::instrument(skip_all, fields(chat_id = %chat_id, key = tracing::field::Empty))]
+pub async fn claim(chat_id: ChatId, split: Option<Split>) -> anyhow::Result<ClaimOutcome> {
+    let reservation = match reserve(chat_id, split).await? {
+        Reserve::Ready(reservation) => reservation,
+        Reserve::Nothing => return Ok(ClaimOutcome::NothingToClaim),
+        Reserve::NotEnough { available } => return Ok(ClaimOutcome::NotEnough { available }),
+        Reserve::Busy => return Ok(ClaimOutcome::InProgress),
+        Reserve::Held => return Ok(ClaimOutcome::UnderReview),
+    };
+    tracing::Span::current().record("key", reservation.key.as_str());
+    tracing::debug!(
+        days = reservation.days,
+        cards = reservation.num_cards,
+        "reserved"
+    );
+    match rewards::issue(chat_id, &reservation).await {
+        Ok(Issued::Giftcards(giftcards)) => {
+            finish(chat_id, &reservation, &giftcards).await?;
+            Ok(ClaimOutcome::Issued { giftcards })
+        }
+        Ok(Issued::Credited { username }) => {
+            finish_credited(chat_id, &reservation, &username).await?;
+            Ok(ClaimOutcome::Credited {
+                days: reservation.days,
+                username,
+            })
+        }
+        Err(e) => {
+            tracing::warn!("claim {} failed, queueing: {e:#}", reservation.key);
+            enqueue(chat_id, &reservation, &e).await?;
+            Ok(ClaimOutcome::Queued {
+                days: reservation.days,
+            })
+        }
+    }
+}
+
+/// The account `chat_id`'s single-card claims are credited to: the `direct_credit`
+/// provider is in use, and the chat opted in and has linked an account
+pub async fn direct_credit_account(chat_id: ChatId) -> sqlx::Result<Option<String>> {
+    if !CONFIG
+        .reward_providers()
+        .contains(&rewards::Kind::DirectCredit)
+        || !prefs::load(chat_id).await?.direct_credit
+    {
+        return Ok(None);
+    }
+    geph_account::linked(chat_id).await
+}
+
+/// `chat_id`'s unclaimed balance and the seconds spent so far, summed over its VMs
+pub async fn balance(conn: &mut AnyConnection, chat_id: ChatId) -> sqlx::Result<(i64, i64)> {
+    sqlx::query_as(
+        r#"
+SELECT CAST(COALESCE(SUM(up_secs + bonus_secs - paid_secs), 0) AS BIGINT),
+       CAST(COALESCE(SUM(paid_secs), 0) AS BIGINT)
+FROM agent_records WHERE telegram_chat_id = $1
+        "#,
+    )
+    .bind(chat_id.0)
+    .fetch_one(conn)
+    .await
+}
+
+/// Charges `secs` to `chat_id`'s VMs, the fullest first, taking no VM below zero. Only
+/// what `nearest` rounding granted beyond the balance is left, and the last VM takes it.
+async fn spend(conn: &mut AnyConnection, chat_id: ChatId, secs: i64) -> sqlx::Result<()> {
+    let vms: Vec<(String, i64)> = sqlx::query_as(
+        "SELECT vm_id, up_secs + bonus_secs - paid_secs FROM agent_records WHERE telegram_chat_id = $1 ORDER BY 2 DESC, vm_id",
+    )
+    .bind(chat_id.0)
+    .fetch_all(&mut *conn)
+    .await?;
+    let (count, mut left) = (vms.len(), secs);
+    for (i, (vm_id, balance)) in vms.into_iter().enumerate() {
+        let share = if i + 1 == count {
+            left
+        } else {
+            left.min(balance.max(0))
+        };
+        if share == 0 {
+            continue;
+        }
+        sqlx::query("UPDATE agent_records SET paid_secs = paid_secs + $1 WHERE vm_id = $2")
+            .bind(share)
+            .bind(&vm_id)
+            .execute(&mut *conn)
+            .await?;
+        left -= share;
+        if left == 0 {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Whole Plus days `chat_id` could claim right now
+pub async fn available(chat_id: ChatId) -> sqlx::Result<i64> {
+    let (balance, _) = balance(&mut *DB.acquire().await?, chat_id).await?;
+    Ok(RewardPolicy::current().days(balance))
+}
+
+#[tracing::instrument(skip_all)]
+async fn reserve(chat_id: ChatId, split: Option<Split>) -> anyhow::Result<Reserve> {
+    let (_write, mut tx) = begin_write().await?;
+    let reserve = reserve_in(&mut tx, chat_id, split).await?;
+    tx.commit().await?;
+    Ok(reserve)
+}
+
+/// Reserves the days of `split` (or all, as one card) from the chat's balance on `conn`,
+/// which the caller commits. A stale claim being resumed keeps the split it was started with.
+pub async fn reserve_in(
+    conn: &mut AnyConnection,
+    chat_id: ChatId,
+    split: Option<Split>,
+) -> anyhow::Result<Reserve> {
+    if fraud::under_review(conn, chat_id).await? {
+        return Ok(Reserve::Held);
+    }
+    let lock: Option<(String, i64, i64, i64)> = sqlx::query_as(
+        "SELECT idempotency_key, days, num_cards, locked_at FROM claim_locks WHERE telegram_chat_id = $1",
+    )
+    .bind(chat_id.0)
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some((key, days, num_cards, locked_at)) = lock {
+        if now_unix() - locked_at < STALE_LOCK_SECS {
+            return Ok(Reserve::Busy);
+        }
+        tracing::warn!("resuming stale claim {key} for chat {chat_id}");
+        sqlx::query("UPDATE claim_locks SET locked_at = $1 WHERE telegram_chat_id = $2")
+            .bind(now_unix())
+            .bind(chat_id.0)
+            .execute(&mut *conn)
+            .await?;
+        return Ok(Reserve::Ready(Reservation {
+            key,
+            days,
+            num_cards,
+        }));
+    }
+
+    let (balance, _) = balance(&mut *conn, chat_id).await?;
+    let policy = RewardPolicy::current();
+    let available = policy.days(balance);
+    if available <= 0 {
+        return Ok(Reserve::Nothing);
+    }
+    let split = match split {
+        Some(split) if split.days() > available => return Ok(Reserve::NotEnough { available }),
+        Some(split) => split,
+        None => Split::single(available),
+    };
+    let (days, num_cards) = (split.days(), split.cards);
+    // New for every reservation, since balances can come back to an earlier total after a
+    // VM leaves the chat. A retry reuses it through the lock above.
+    let nonce: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect();
+    let key = format!("claim-{}-{nonce}", chat_id.0);
+
+    sqlx::query(
+        "INSERT INTO claim_locks (telegram_chat_id, idempotency_key, days, num_cards, locked_at) VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(chat_id.0)
+    .bind(&key)
+    .bind(days)
+    .bind(num_cards)
+    .bind(now_unix())
+    .execute(&mut *conn)
+    .await?;
+    let inserted = sqlx::query(
+        r#"
+INSERT INTO claims (idempotency_key, telegram_chat_id, days, num_cards, status, created_at)
+VALUES ($1, $2, $3, $4, 'pending', $5)
+ON CONFLICT(idempotency_key) DO NOTHING
+        "#,
+    )
+    .bind(&key)
+    .bind(chat_id.0)
+    .bind(days)
+    .bind(num_cards)
+    .bind(now_unix())
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+    // Never rewrite a claim already on record
+    anyhow::ensure!(inserted == 1, "claim key {key} is already taken");
+    spend(&mut *conn, chat_id, policy.secs(days)).await?;
+    audit::record(
+        conn,
+        &Actor::Chat(chat_id),
+        "claim_reserved",
+        Some(chat_id),
+        json!({ "key": key, "days": days, "num_cards": num_cards }),
+    )
+    .await?;
+    Ok(Reserve::Ready(Reservation {
+        key,
+        days,
+        num_cards,
+    }))
+}
+
+#[tracing::instrument(skip_all)]
+async fn finish(
+    chat_id: ChatId,
+    reservation: &Reservation,
+    giftcards: &[String],
+) -> anyhow::Result<()> {
+    let (_write, mut tx) = begin_write().await?;
+    sqlx::query("UPDATE claims SET status = 'issued', response = $1 WHERE idempotency_key = $2")
+        .bind(giftcards.join("\n"))
+        .bind(&reservation.key)
+        .execute(&mut *tx)
+        .await?;
+    for code in giftcards {
+        sqlx::query(
+            "INSERT INTO giftcards (telegram_chat_id, code, days, created_at) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(chat_id.0)
+        .bind(code)
+        .bind(reservation.days_per_card())
+        .bind(now_unix())
+        .execute(&mut *tx)
+        .await?;
+    }
+    sqlx::query("DELETE FROM pending_claims WHERE idempotency_key = $1")
+        .bind(&reservation.key)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM claim_locks WHERE telegram_chat_id = $1")
+        .bind(chat_id.0)
+        .execute(&mut *tx)
+        .await?;
+    audit::record(
+        &mut tx,
+        &Actor::System,
+        "giftcard_issued",
+        Some(chat_id),
+        json!({ "key": reservation.key, "days": reservation.days, "num_cards": giftcards.len() }),
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn finish_credited(
+    chat_id: ChatId,
+    reservation: &Reservation,
+    username: &str,
+) -> anyhow::Result<()> {
+    let (_write, mut tx) = begin_write().await?;
+    sqlx::query("UPDATE claims SET status = 'credited', response = $1 WHERE idempotency_key = $2")
+        .bind(username)
+        .bind(&reservation.key)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM pending_claims WHERE idempotency_key = $1")
+        .bind(&reservation.key)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM claim_locks WHERE telegram_chat_id = $1")
+        .bind(chat_id.0)
+        .execute(&mut *tx)
+        .await?;
+    audit::record(
+        &mut tx,
+        &Actor::System,
+        "plus_credited",
+        Some(chat_id),
+        json!({ "key": reservation.key, "days": reservation.days, "username": username }),
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Parks a reservation no provider could issue, releasing the chat's claim lock so
+/// newly accrued days can still be claimed separately
+#[tracing::instrument(skip_all)]
+async fn enqueue(
+    chat_id: ChatId,
+    reservation: &Reservation,
+    error: &anyhow::Error,
+) -> anyhow::Result<()> {
+    let (_write, mut tx) = begin_write().await?;
+    sqlx::query(
+        r#"
+INSERT INTO pending_claims (idempotency_key, telegram_chat_id, days, num_cards, attempts, next_attempt_at, last_error)
+VALUES ($1, $2, $3, $4, 1, $5, $6)
+ON CONFLICT(idempotency_key) DO NOTHING
+        "#,
+    )
+    .bind(&reservation.key)
+    .bind(chat_id.0)
+    .bind(reservation.days)
+    .bind(reservation.num_cards)
+    .bind(now_unix() + RETRY_BASE_SECS)
+    .bind(format!("{error:#}"))
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("UPDATE claims SET status = 'queued' WHERE idempotency_key = $1")
+        .bind(&reservation.key)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM claim_locks WHERE telegram_chat_id = $1")
+        .bind(chat_id.0)
+        .execute(&mut *tx)
+        .await?;
+    audit::record(
+        &mut tx,
+        &Actor::System,
+        "claim_queued",
+        Some(chat_id),
+        json!({ "key": reservation.key, "days": reservation.days, "error": format!("{error:#}") }),
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Retries queued claims as they come due, delivering each once it is issued
+pub async fn retry_pending_loop(bot: Bot, shutdown: CancellationToken) -> anyhow::Result<()> {
+    let mut ticker = smol::Timer::interval(Duration::from_secs(30));
+    loop {
+        async {
+            let due: Vec<(String, i64, i64, i64, i64)> = sqlx::query_as(
+                "SELECT idempotency_key, telegram_chat_id, days, num_cards, attempts FROM pending_claims WHERE next_attempt_at <= $1",
+            )
+            .bind(now_unix())
+            .fetch_all(&*DB)
+            .await?;
+
+            for (key, chat_id, days, num_cards, attempts) in due {
+                let chat_id = ChatId(chat_id);
+                let reservation = Reservation {
+                    key,
+                    days,
+                    num_cards,
+                };
+                match rewards::issue(chat_id, &reservation).await {
+                    Ok(Issued::Credited { username }) => {
+                        finish_credited(chat_id, &reservation, &username).await?;
+                        tracing::info!("queued claim {} credited", reservation.key);
+                        let _ = send_status(
+                            &bot,
+                            chat_id,
+                            Indicator::Gift,
+                            i18n::text(
+                                "claim.delayed_credited",
+                                &[("count", &days), ("username", &username)],
+                            ),
+                        )
+                        .await;
+                    }
+                    Ok(Issued::Giftcards(giftcards)) => {
+                        finish(chat_id, &reservation, &giftcards).await?;
+                        tracing::info!("queued claim {} issued", reservation.key);
+                        let _ = send_status(
+                            &bot,
+                            chat_id,
+                            Indicator::Gift,
+                            i18n::text("claim.delayed_issued", &[("count", &days)]),
+                        )
+                        .await;
+                        let _ = send_codes(&bot, chat_id, &giftcards).await;
+                    }
+                    Err(e) => {
+                        let delay = (RETRY_BASE_SECS << attempts.min(20)).min(RETRY_MAX_SECS);
+                        tracing::warn!(
+                            "retry {attempts} of claim {} failed, next in {delay}s: {e:#}",
+                            reservation.key
+                        );
+                        sqlx::query(
+                            "UPDATE pending_claims SET attempts = attempts + 1, next_attempt_at = $1, last_error = $2 WHERE idempotency_key = $3",
+                        )
+                        .bind(now_unix() + delay)
+                        .bind(format!("{e:#}"))
+                        .bind(&reservation.key)
+                        .execute(&*DB)
+                        .await?;
+                    }
+                }
+            }
+            anyhow::Ok(())
+        }
+        .instrument(tracing::info_span!("tick"))
+        .await?;
+
+        if !next_tick("claim_retry", &mut ticker, &shutdown).await {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(cards: i64, days_per_card: i64) -> Option<Split> {
+        Some(Split {
+            cards,
+            days_per_card,
+        })
+    }
+
+    #[test]
+    fn split_parses_cards_by_days() {
+        assert_eq!(Split::parse("3x2"), split(3, 2));
+        assert_eq!(Split::parse("3X2"), split(3, 2));
+        assert_eq!(Split::parse("3×2"), split(3, 2));
+        assert_eq!(Split::parse("30x1"), split(30, 1));
+        assert_eq!(split(3, 2).unwrap().days(), 6);
+    }
+
+    #[test]
+    fn split_refuses_malformed_input() {
+        for s in [
+            "",
+            "x",
+            "3",
+            "3x",
+            "x2",
+            "3x2x1",
+            "3 x 2",
+            " 3x2",
+            "3x2 ",
+            "ax2",
+            "3xb",
+            "3.5x2",
+            "3*2",
+            "3x٢",
+            "99999999999999999999x1",
+        ] {
+            assert_eq!(Split::parse(s), None, "{s:?}");
+        }
+    }
+
+    #[test]
+    fn split_is_valid_within_the_card_limit() {
+        assert!(split(1, 1).unwrap().is_valid());
+        assert!(split(MAX_CARDS, 7).unwrap().is_valid());
+        for invalid in [
+            Split::parse("0x5"),
+            Split::parse("31x1"),
+            Split::parse("3x0"),
+            Split::parse("3x-2"),
+            Split::parse("-3x-2"),
+            // Parses, but the total doesn't fit
+            Split::parse("30x999999999999999999"),
+        ] {
+            let split = invalid.unwrap();
+            assert!(!split.is_valid(), "{split:?}");
+        }
+    }
+}
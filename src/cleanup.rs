@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use teloxide::{
+    prelude::*,
+    types::{ChatId, MessageId},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::{CONFIG, DB, next_tick, now_unix};
+
+/// Queues a group message for deletion after `group_message_ttl_secs`; does nothing when
+/// the option is unset. The queue lives in the database so a restart doesn't leave
+/// messages behind.
+pub async fn schedule(chat_id: ChatId, message_id: MessageId) -> sqlx::Result<()> {
+    let Some(ttl) = CONFIG.group_message_ttl_secs else {
+        return Ok(());
+    };
+    sqlx::query(
+        "INSERT INTO pending_deletions (chat_id, message_id, delete_at) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+    )
+    .bind(chat_id.0)
+    .bind(message_id.0)
+    .bind(now_unix() + ttl)
+    .execute(&*DB)
+    .await?;
+    Ok(())
+}
+
+/// Deletes queued messages as they come due
+pub async fn delete_due_loop(bot: Bot, shutdown: CancellationToken) -> anyhow::Result<()> {
+    let mut ticker = smol::Timer::interval(Duration::from_secs(15));
+    loop {
+        async {
+            let due: Vec<(i64, i32)> = sqlx::query_as(
+                "SELECT chat_id, message_id FROM pending_deletions WHERE delete_at <= $1",
+            )
+            .bind(now_unix())
+            .fetch_all(&*DB)
+            .await?;
+            for (chat_id, message_id) in due {
+                // Already gone, too old, or we lack the rights; retrying won't help either way
+                if let Err(e) = bot
+                    .delete_message(ChatId(chat_id), MessageId(message_id))
+                    .await
+                {
+                    tracing::debug!("deleting message {message_id} in {chat_id} failed: {e}");
+                }
+                sqlx::query("DELETE FROM pending_deletions WHERE chat_id = $1 AND message_id = $2")
+                    .bind(chat_id)
+                    .bind(message_id)
+                    .execute(&*DB)
+                    .await?;
+            }
+            anyhow::Ok(())
+        }
+        .instrument(tracing::info_span!("tick"))
+        .await?;
+
+        if !next_tick("message_cleanup", &mut ticker, &shutdown).await {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::{fs::File, io::Write, path::PathBuf};
+
+use clap::Subcommand;
+use once_cell::sync::Lazy;
+use teloxide::Bot;
+
+use crate::{
+    CONFIG, DB, broadcast, community,
+    export::{self, Format},
+};
+
+/// What the binary should do; `serve` when no subcommand is given
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Run the bot and all of its background tasks
+    Serve,
+    /// Apply pending schema migrations and exit
+    Migrate,
+    /// Dump agent records, uptime history and claims as JSON or CSV
+    Export {
+        #[arg(short, long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        /// For JSON, a file to write instead of stdout; for CSV, the directory to write
+        /// one file per table into (default: the current directory)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Print aggregate numbers about the testing network
+    Stats,
+    /// Replace the SQLite database with a snapshot from `backup_dir`. Stop the bot first;
+    /// the current database is kept next to it as `<name>.before-restore-<time>`.
+    Restore {
+        /// Snapshot file to restore
+        backup: PathBuf,
+    },
+    /// Send a one-off announcement to every registered chat, without starting the bot
+    Broadcast {
+        /// Message text, sent as is
+        text: String,
+    },
+}
+
+pub async fn migrate() -> anyhow::Result<()> {
+    // Connecting runs the migrations
+    Lazy::force(&DB);
+    let version: Option<i64> =
+        sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success = TRUE")
+            .fetch_one(&*DB)
+            .await?;
+    println!(
+        "{} is at schema version {}",
+        CONFIG.database_url,
+        version.unwrap_or(0)
+    );
+    Ok(())
+}
+
+pub async fn export(format: Format, output: Option<PathBuf>) -> anyhow::Result<()> {
+    let files = export::collect().await?.render(format)?;
+    match (format, output) {
+        (Format::Json, None) => {
+            for (_, contents) in files {
+                println!("{contents}");
+            }
+        }
+        (Format::Json, Some(path)) => {
+            for (_, contents) in files {
+                writeln!(File::create(&path)?, "{contents}")?;
+            }
+        }
+        (Format::Csv, dir) => {
+            let dir = dir.unwrap_or_else(|| PathBuf::from("."));
+            std::fs::create_dir_all(&dir)?;
+            for (name, contents) in files {
+                let path = dir.join(name);
+                std::fs::write(&path, contents)?;
+                eprintln!("wrote {}", path.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn stats() -> anyhow::Result<()> {
+    let totals = community::network_totals().await?;
+    let (chats, issued_days, pending_claims, open_flags, inactive): (i64, i64, i64, i64, i64) =
+        sqlx::query_as(
+            r#"
+SELECT
+    (SELECT COUNT(DISTINCT telegram_chat_id) FROM agent_records),
+    (SELECT CAST(COALESCE(SUM(days), 0) AS BIGINT) FROM claims WHERE status = 'issued'),
+    (SELECT COUNT(*) FROM pending_claims),
+    (SELECT COUNT(*) FROM fraud_flags WHERE resolved_at IS NULL),
+    (SELECT COUNT(*) FROM inactive_chats)
+            "#,
+        )
+        .fetch_one(&*DB)
+        .await?;
+    println!("VMs online:          {}", totals.online);
+    println!("VMs registered:      {}", totals.registered);
+    println!("Testers:             {chats}");
+    println!("Total uptime:        {}h", totals.up_secs / 3600);
+    println!("Plus days claimed:   {}", totals.claimed_days);
+    println!("Plus days issued:    {issued_days}");
+    println!("Queued claims:       {pending_claims}");
+    println!("Open fraud flags:    {open_flags}");
+    println!("Unreachable chats:   {inactive}");
+    Ok(())
+}
+
+pub async fn broadcast(bot: &Bot, text: &str) -> anyhow::Result<()> {
+    let summary = broadcast::send_now(bot, text).await?;
+    println!(
+        "Broadcast #{}: {} sent, {} failed",
+        summary.id, summary.sent, summary.failed
+    );
+    Ok(())
+}
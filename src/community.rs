@@ -0,0 +1,271 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use teloxide::{prelude::*, types::ChatId};
+
+use crate::{
+    CONFIG, Command, DB, OFFLINE_AFTER_SECS,
+    bot_error::{BotError, Context},
+    cleanup, groups, i18n, now_unix, parse_command, render,
+};
+
+/// How long a computed aggregate answer is reused
+const CACHE_TTL: Duration = Duration::from_secs(300);
+/// Minimum gap between two replies to the same command in the community group
+const REPLY_INTERVAL: Duration = Duration::from_secs(60);
+/// Testers listed on the leaderboard
+const LEADERBOARD_SIZE: i64 = 10;
+const MAX_DISPLAY_NAME_CHARS: usize = 24;
+
+static CACHE: Lazy<Mutex<HashMap<&'static str, (Instant, String)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static LAST_REPLY: Lazy<Mutex<HashMap<&'static str, Instant>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub fn is_community_chat(chat_id: ChatId) -> bool {
+    CONFIG.community_chat_id == Some(chat_id.0)
+}
+
+/// Handles a message in the community group: aggregate queries get a (cached) answer,
+/// account-specific commands a pointer to the private chat, and anything else nothing.
+/// Every kind of reply is throttled so the group can't be flooded through the bot.
+pub async fn handle(bot: &Bot, msg: &Message, text: &str) -> Result<(), BotError> {
+    let chat_id = msg.chat.id;
+    if let Some(user) = msg.from.as_ref().filter(|user| !user.is_bot) {
+        groups::seen(chat_id, user.id)
+            .await
+            .context("recording a community member")?;
+    }
+    let reply = match parse_command(text) {
+        Some(Command::NetworkStats) => ("networkstats", network_stats().await),
+        Some(Command::GroupStats) => ("groupstats", groups::stats(chat_id).await),
+        Some(Command::Leaderboard) => ("leaderboard", leaderboard().await),
+        Some(_) => (
+            "private_only",
+            Ok(i18n::text("community.private_only", &[])),
+        ),
+        None => return Ok(()),
+    };
+    let (kind, text) = reply;
+    {
+        let mut last = LAST_REPLY.lock().unwrap();
+        if last.get(kind).is_some_and(|t| t.elapsed() < REPLY_INTERVAL) {
+            return Ok(());
+        }
+        last.insert(kind, Instant::now());
+    }
+    let text = text.context("composing the community reply")?;
+    let reply = bot.send_message(chat_id, text).await?;
+    for id in [msg.id, reply.id] {
+        cleanup::schedule(chat_id, id)
+            .await
+            .context("scheduling cleanup")?;
+    }
+    Ok(())
+}
+
+fn cached(key: &'static str) -> Option<String> {
+    CACHE
+        .lock()
+        .unwrap()
+        .get(key)
+        .filter(|(at, _)| at.elapsed() < CACHE_TTL)
+        .map(|(_, text)| text.clone())
+}
+
+fn store(key: &'static str, text: String) -> String {
+    CACHE
+        .lock()
+        .unwrap()
+        .insert(key, (Instant::now(), text.clone()));
+    text
+}
+
+/// Network-wide totals, safe to show publicly
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct NetworkTotals {
+    pub online: i64,
+    pub registered: i64,
+    pub up_secs: i64,
+    pub paid_secs: i64,
+    /// Plus days reserved by claims, whatever rate they were earned at
+    pub claimed_days: i64,
+}
+
+pub async fn network_totals() -> sqlx::Result<NetworkTotals> {
+    let (online, registered, up_secs, paid_secs, claimed_days): (i64, i64, i64, i64, i64) =
+        sqlx::query_as(
+            r#"
+SELECT
+    (SELECT COUNT(*) FROM vm_status WHERE last_seen >= $1),
+    (SELECT COUNT(*) FROM agent_records WHERE telegram_chat_id IS NOT NULL),
+    (SELECT CAST(COALESCE(SUM(up_secs), 0) AS BIGINT) FROM agent_records),
+    (SELECT CAST(COALESCE(SUM(paid_secs), 0) AS BIGINT) FROM agent_records),
+    (SELECT CAST(COALESCE(SUM(days), 0) AS BIGINT) FROM claims)
+        "#,
+        )
+        .bind(now_unix() - OFFLINE_AFTER_SECS)
+        .fetch_one(&*DB)
+        .await?;
+    Ok(NetworkTotals {
+        online,
+        registered,
+        up_secs,
+        paid_secs,
+        claimed_days,
+    })
+}
+
+pub async fn network_stats() -> sqlx::Result<String> {
+    if let Some(text) = cached("networkstats") {
+        return Ok(text);
+    }
+    let NetworkTotals {
+        online,
+        registered,
+        up_secs,
+        claimed_days: days,
+        ..
+    } = network_totals().await?;
+    let hours = up_secs / 3600;
+    Ok(store(
+        "networkstats",
+        [
+            i18n::text("community.title", &[]),
+            i18n::text("community.online", &[("count", &online)]),
+            i18n::text("community.registered", &[("count", &registered)]),
+            i18n::text("community.uptime", &[("hours", &hours)]),
+            i18n::text("community.claimed", &[("count", &days)]),
+        ]
+        .join("\n"),
+    ))
+}
+
+/// Anonymous label for a tester, stable across calls
+pub fn anonymous_label(chat_id: i64) -> String {
+    format!(
+        "Tester {:04X}",
+        (chat_id as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 48
+    )
+}
+
+/// First day of the current (UTC) month, as stored in `uptime_history.day`
+pub fn month_start() -> String {
+    format!("{}-01", &render::format_date(now_unix())[..7])
+}
+
+/// Top testers by uptime this month, under their chosen display name or else anonymized
+pub async fn leaderboard() -> sqlx::Result<String> {
+    if let Some(text) = cached("leaderboard") {
+        return Ok(text);
+    }
+    let top: Vec<(i64, i64, Option<String>)> = sqlx::query_as(
+        r#"
+SELECT a.telegram_chat_id, CAST(SUM(h.up_secs) AS BIGINT) AS total, MAX(p.display_name)
+FROM uptime_history h
+JOIN agent_records a ON a.vm_id = h.vm_id
+LEFT JOIN user_prefs p ON p.telegram_chat_id = a.telegram_chat_id
+WHERE a.telegram_chat_id IS NOT NULL AND h.day >= $1
+GROUP BY a.telegram_chat_id
+ORDER BY total DESC
+LIMIT $2
+        "#,
+    )
+    .bind(month_start())
+    .bind(LEADERBOARD_SIZE)
+    .fetch_all(&*DB)
+    .await?;
+    let lines: Vec<String> = top
+        .iter()
+        .enumerate()
+        .map(|(i, (chat, secs, name))| {
+            let label = name.clone().unwrap_or_else(|| anonymous_label(*chat));
+            format!("{}. {label} - {}h", i + 1, secs / 3600)
+        })
+        .collect();
+    Ok(store(
+        "leaderboard",
+        format!(
+            "{}\n{}",
+            i18n::text("community.leaderboard_title", &[]),
+            lines.join("\n")
+        ),
+    ))
+}
+
+/// Where a tester stands this month
+pub struct Rank {
+    pub rank: i64,
+    pub testers: i64,
+    pub up_secs: i64,
+}
+
+/// `chat_id`'s position on this month's leaderboard, if it has any uptime this month
+pub async fn own_rank(chat_id: ChatId) -> sqlx::Result<Option<Rank>> {
+    let since = month_start();
+    let up_secs: i64 = sqlx::query_scalar(
+        r#"
+SELECT CAST(COALESCE(SUM(h.up_secs), 0) AS BIGINT)
+FROM uptime_history h JOIN agent_records a ON a.vm_id = h.vm_id
+WHERE a.telegram_chat_id = $1 AND h.day >= $2
+        "#,
+    )
+    .bind(chat_id.0)
+    .bind(&since)
+    .fetch_one(&*DB)
+    .await?;
+    if up_secs == 0 {
+        return Ok(None);
+    }
+    let (ahead, testers): (i64, i64) = sqlx::query_as(
+        r#"
+SELECT COUNT(CASE WHEN t.total > $2 THEN 1 END), COUNT(*)
+FROM (
+    SELECT CAST(SUM(h.up_secs) AS BIGINT) AS total
+    FROM uptime_history h JOIN agent_records a ON a.vm_id = h.vm_id
+    WHERE a.telegram_chat_id IS NOT NULL AND h.day >= $1
+    GROUP BY a.telegram_chat_id
+) t
+        "#,
+    )
+    .bind(&since)
+    .bind(up_secs)
+    .fetch_one(&*DB)
+    .await?;
+    Ok(Some(Rank {
+        rank: ahead + 1,
+        testers,
+        up_secs,
+    }))
+}
+
+/// Shows `chat_id` on the leaderboard under `name`, or anonymized again when `None`
+pub async fn set_display_name(chat_id: ChatId, name: Option<&str>) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+INSERT INTO user_prefs (telegram_chat_id, display_name) VALUES ($1, $2)
+ON CONFLICT(telegram_chat_id) DO UPDATE SET display_name = excluded.display_name
+        "#,
+    )
+    .bind(chat_id.0)
+    .bind(name)
+    .execute(&*DB)
+    .await?;
+    CACHE.lock().unwrap().remove("leaderboard");
+    Ok(())
+}
+
+/// Whether `name` can be shown publicly: short, single-line, and not posing as the
+/// anonymous labels other testers get
+pub fn valid_display_name(name: &str) -> bool {
+    let name = name.trim();
+    !name.is_empty()
+        && name.chars().count() <= MAX_DISPLAY_NAME_CHARS
+        && !name.chars().any(char::is_control)
+        && !name.starts_with("Tester ")
+}
@@ -0,0 +1,322 @@
+use std::{collections::HashMap, io::Cursor, path::PathBuf};
+
+use serde::de::{
+    self, DeserializeOwned, Deserializer, IntoDeserializer, Visitor, value::MapDeserializer,
+};
+use serde_yaml::{Mapping, Value};
+
+/// Environment variables named `<ENV_PREFIX><SETTING>` override `setting` from the file
+const ENV_PREFIX: &str = "GEPH_BOT_";
+/// `<setting><FILE_SUFFIX>` names a file holding the value of `setting`
+const FILE_SUFFIX: &str = "_file";
+
+/// One setting's value and where it came from. Its key is kept to name it in errors.
+enum Source {
+    /// Read from the config file, or verbatim from a `_file`
+    Yaml(String, Value),
+    /// An environment variable, read as YAML text: a `String` setting takes it as it is,
+    /// any other kind of setting parses it (`GEPH_BOT_ADMIN_CHAT_IDS=[1, 2]`)
+    Text(String, String),
+}
+
+/// Deserializes `T` from the config file's top-level mapping, layered with
+///
+/// - `GEPH_BOT_*` environment variables, e.g. `GEPH_BOT_TELEGRAM_BOT_TOKEN`, each replacing
+///   one setting of the file
+/// - `*_file` settings, in the file or the environment, e.g. `telegram_bot_token_file`,
+///   whose value is read from the named file with trailing newlines dropped. A relative
+///   path is looked up in `$CREDENTIALS_DIRECTORY` when systemd sets it, so
+///   `LoadCredential=` and Docker secrets can hold tokens instead of the YAML.
+pub fn deserialize<T: DeserializeOwned>(file: Mapping) -> anyhow::Result<T> {
+    layer(file, std::env::vars())
+}
+
+/// [`deserialize`] against the given environment rather than the process's own
+fn layer<T: DeserializeOwned>(
+    file: Mapping,
+    env: impl IntoIterator<Item = (String, String)>,
+) -> anyhow::Result<T> {
+    let mut credentials = None;
+    let mut settings = HashMap::new();
+    for (key, value) in file {
+        let Value::String(key) = key else {
+            anyhow::bail!("config keys must be strings, found {key:?}");
+        };
+        settings.insert(key.clone(), Source::Yaml(key, value));
+    }
+    for (name, value) in env {
+        if name == "CREDENTIALS_DIRECTORY" {
+            credentials = Some(PathBuf::from(&value));
+        }
+        let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let key = key.to_lowercase();
+        // An environment variable outranks the file whichever form either one uses
+        settings.remove(&key);
+        match key.strip_suffix(FILE_SUFFIX) {
+            Some(setting) => settings.remove(setting),
+            None => settings.remove(&format!("{key}{FILE_SUFFIX}")),
+        };
+        settings.insert(key.clone(), Source::Text(name, value));
+    }
+
+    let files: Vec<String> = settings
+        .keys()
+        .filter(|key| key.ends_with(FILE_SUFFIX))
+        .cloned()
+        .collect();
+    for key in files {
+        let setting = key.strip_suffix(FILE_SUFFIX).unwrap().to_owned();
+        anyhow::ensure!(
+            !settings.contains_key(&setting),
+            "set {setting} or {key}, not both"
+        );
+        let path = match settings.remove(&key) {
+            Some(Source::Yaml(_, Value::String(path)) | Source::Text(_, path)) => {
+                PathBuf::from(path)
+            }
+            _ => anyhow::bail!("{key} must be a path"),
+        };
+        let path = match &credentials {
+            Some(dir) if path.is_relative() => dir.join(path),
+            _ => path,
+        };
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("read {key} {}: {e}", path.display()))?;
+        let contents = contents.trim_end_matches(['\n', '\r']).to_owned();
+        settings.insert(
+            setting.clone(),
+            Source::Yaml(setting, Value::String(contents)),
+        );
+    }
+
+    Ok(T::deserialize(MapDeserializer::new(settings.into_iter()))?)
+}
+
+impl<'de> IntoDeserializer<'de, serde_yaml::Error> for Source {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+/// Hands each request on to the source's own deserializer, so both keep their usual
+/// reading of YAML
+macro_rules! forward {
+    ($($method:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(
+                self,
+                $($arg: $ty,)*
+                visitor: V,
+            ) -> Result<V::Value, serde_yaml::Error> {
+                let (key, result) = match self {
+                    Source::Yaml(key, value) => (key, value.$method($($arg,)* visitor)),
+                    Source::Text(key, text) => (
+                        key,
+                        serde_yaml::Deserializer::from_reader(Cursor::new(text))
+                            .$method($($arg,)* visitor),
+                    ),
+                };
+                result.map_err(|e| de::Error::custom(format_args!("{key}: {e}")))
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for Source {
+    type Error = serde_yaml::Error;
+
+    forward! {
+        deserialize_any();
+        deserialize_bool();
+        deserialize_i8();
+        deserialize_i16();
+        deserialize_i32();
+        deserialize_i64();
+        deserialize_i128();
+        deserialize_u8();
+        deserialize_u16();
+        deserialize_u32();
+        deserialize_u64();
+        deserialize_u128();
+        deserialize_f32();
+        deserialize_f64();
+        deserialize_char();
+        deserialize_bytes();
+        deserialize_byte_buf();
+        deserialize_option();
+        deserialize_unit();
+        deserialize_unit_struct(name: &'static str);
+        deserialize_newtype_struct(name: &'static str);
+        deserialize_seq();
+        deserialize_tuple(len: usize);
+        deserialize_tuple_struct(name: &'static str, len: usize);
+        deserialize_map();
+        deserialize_struct(name: &'static str, fields: &'static [&'static str]);
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]);
+        deserialize_identifier();
+        deserialize_ignored_any();
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, serde_yaml::Error> {
+        self.deserialize_string(visitor)
+    }
+
+    /// Environment text is taken as it is, even when it would read as YAML of another kind
+    fn deserialize_string<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, serde_yaml::Error> {
+        match self {
+            Source::Yaml(key, value) => value
+                .deserialize_string(visitor)
+                .map_err(|e| de::Error::custom(format_args!("{key}: {e}"))),
+            Source::Text(_, text) => visitor.visit_string(text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Settings {
+        token: String,
+        #[serde(default)]
+        ids: Vec<i64>,
+        #[serde(default)]
+        port: Option<u16>,
+    }
+
+    fn file(yaml: &str) -> Mapping {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// Writes `contents` to a file of its own in the temporary directory
+    fn secret(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("config-sources-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn error(file: Mapping, vars: &[(&str, &str)]) -> String {
+        layer::<Settings>(file, env(vars)).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn the_file_is_read_as_it_is() {
+        let settings: Settings = layer(
+            file("token: abc\nids: [1, 2]\nport: 80"),
+            env(&[("PATH", "/bin")]),
+        )
+        .unwrap();
+        assert_eq!(
+            settings,
+            Settings {
+                token: "abc".into(),
+                ids: vec![1, 2],
+                port: Some(80),
+            }
+        );
+    }
+
+    #[test]
+    fn the_environment_outranks_the_file() {
+        let settings: Settings = layer(
+            file("token: abc\nids: [1]"),
+            env(&[
+                ("GEPH_BOT_TOKEN", "123:xyz"),
+                ("GEPH_BOT_IDS", "[3, 4]"),
+                ("GEPH_BOT_PORT", "8080"),
+                ("OTHER_PORT", "1"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(settings.token, "123:xyz");
+        assert_eq!(settings.ids, vec![3, 4]);
+        assert_eq!(settings.port, Some(8080));
+    }
+
+    #[test]
+    fn string_settings_take_the_environment_verbatim() {
+        for value in ["12345", "yes", "[not, a, list]", "~"] {
+            let settings: Settings =
+                layer(file("token: abc"), env(&[("GEPH_BOT_TOKEN", value)])).unwrap();
+            assert_eq!(settings.token, value);
+        }
+    }
+
+    #[test]
+    fn file_settings_are_read_without_trailing_newlines() {
+        let path = secret("token", "from-file\r\n\n");
+        let yaml = format!("token_file: {}", path.display());
+        let settings: Settings = layer(file(&yaml), env(&[])).unwrap();
+        assert_eq!(settings.token, "from-file");
+    }
+
+    #[test]
+    fn relative_paths_are_found_in_the_credentials_directory() {
+        let path = secret("relative-token", "credential");
+        let dir = path.parent().unwrap().to_str().unwrap();
+        let settings: Settings = layer(
+            file("token_file: relative-token"),
+            env(&[("CREDENTIALS_DIRECTORY", dir)]),
+        )
+        .unwrap();
+        assert_eq!(settings.token, "credential");
+    }
+
+    #[test]
+    fn the_environment_outranks_the_file_in_either_form() {
+        let path = secret("env-token", "env-file");
+        let settings: Settings = layer(
+            file("token: abc"),
+            env(&[("GEPH_BOT_TOKEN_FILE", path.to_str().unwrap())]),
+        )
+        .unwrap();
+        assert_eq!(settings.token, "env-file");
+
+        let settings: Settings = layer(
+            file("token_file: /nonexistent"),
+            env(&[("GEPH_BOT_TOKEN", "env")]),
+        )
+        .unwrap();
+        assert_eq!(settings.token, "env");
+    }
+
+    #[test]
+    fn malformed_sources_are_refused_by_name() {
+        assert_eq!(
+            error(file("token: abc\ntoken_file: /x"), &[]),
+            "set token or token_file, not both"
+        );
+        assert_eq!(
+            error(file("token_file: [1]"), &[]),
+            "token_file must be a path"
+        );
+        assert!(error(file("1: abc"), &[]).starts_with("config keys must be strings"));
+        assert!(
+            error(file("token_file: /nonexistent/token"), &[])
+                .starts_with("read token_file /nonexistent/token: ")
+        );
+        assert!(error(file("token: abc\nport: high"), &[]).starts_with("port: "));
+        assert!(
+            error(file("token: abc"), &[("GEPH_BOT_PORT", "99999")]).starts_with("GEPH_BOT_PORT: ")
+        );
+        assert!(error(file("ids: [1]"), &[]).contains("token"));
+    }
+}
@@ -0,0 +1,125 @@
+use teloxide::types::ChatId;
+
+use crate::{DB, now_unix};
+
+/// How long a question asked mid-command waits for its answer. Onboarding steps don't
+/// expire, since `/start` picks them up again however long the user was away.
+pub const PROMPT_TTL_SECS: i64 = 15 * 60;
+
+/// Step of a conversation the bot is waiting on the user to continue
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Dialogue {
+    /// Onboarding: greeted, waiting for a VM id or registration token
+    AwaitingVmId,
+    /// Onboarding: the last id sent didn't match a known VM
+    VmNotFound { vm_id: String },
+    /// Onboarding: an ownership token was issued for this VM and awaits `/verify`
+    AwaitingOwnershipProof { vm_id: String },
+    /// `/transfer` without a VM: waiting for which VM to hand over
+    AwaitingTransferVm,
+    /// `/replace` with only the old VM: waiting for the id of the new one
+    AwaitingReplacementVm { old_vm: String },
+    /// The rename button of `/myvms`: waiting for the new nickname of `vm_id`
+    AwaitingNickname { vm_id: String },
+    /// Asked to confirm something destructive: "yes" runs `command`, anything else cancels
+    AwaitingConfirmation { command: String },
+}
+
+impl Dialogue {
+    fn encode(&self) -> (&'static str, Option<&str>) {
+        match self {
+            Dialogue::AwaitingVmId => ("awaiting_vm_id", None),
+            Dialogue::VmNotFound { vm_id } => ("vm_not_found", Some(vm_id)),
+            Dialogue::AwaitingOwnershipProof { vm_id } => ("awaiting_ownership_proof", Some(vm_id)),
+            Dialogue::AwaitingTransferVm => ("awaiting_transfer_vm", None),
+            Dialogue::AwaitingReplacementVm { old_vm } => ("awaiting_replacement_vm", Some(old_vm)),
+            Dialogue::AwaitingNickname { vm_id } => ("awaiting_nickname", Some(vm_id)),
+            Dialogue::AwaitingConfirmation { command } => ("awaiting_confirmation", Some(command)),
+        }
+    }
+
+    fn decode(name: &str, data: Option<String>) -> Option<Dialogue> {
+        match (name, data) {
+            ("awaiting_vm_id", _) => Some(Dialogue::AwaitingVmId),
+            ("vm_not_found", Some(vm_id)) => Some(Dialogue::VmNotFound { vm_id }),
+            ("awaiting_ownership_proof", Some(vm_id)) => {
+                Some(Dialogue::AwaitingOwnershipProof { vm_id })
+            }
+            ("awaiting_transfer_vm", _) => Some(Dialogue::AwaitingTransferVm),
+            ("awaiting_replacement_vm", Some(old_vm)) => {
+                Some(Dialogue::AwaitingReplacementVm { old_vm })
+            }
+            ("awaiting_nickname", Some(vm_id)) => Some(Dialogue::AwaitingNickname { vm_id }),
+            ("awaiting_confirmation", Some(command)) => {
+                Some(Dialogue::AwaitingConfirmation { command })
+            }
+            _ => None,
+        }
+    }
+
+    /// Onboarding steps, as opposed to a question asked by a registered user's command
+    pub fn is_onboarding(&self) -> bool {
+        matches!(
+            self,
+            Dialogue::AwaitingVmId
+                | Dialogue::VmNotFound { .. }
+                | Dialogue::AwaitingOwnershipProof { .. }
+        )
+    }
+}
+
+/// The step the chat is at; a question left unanswered for [`PROMPT_TTL_SECS`] is dropped
+pub async fn load(chat_id: ChatId) -> sqlx::Result<Option<Dialogue>> {
+    let row: Option<(String, Option<String>, i64)> = sqlx::query_as(
+        "SELECT dialogue, data, updated_at FROM chat_state WHERE telegram_chat_id = $1",
+    )
+    .bind(chat_id.0)
+    .fetch_optional(&*DB)
+    .await?;
+    Ok(row.and_then(|(name, data, updated_at)| {
+        Dialogue::decode(&name, data).filter(|dialogue| {
+            dialogue.is_onboarding() || updated_at > now_unix() - PROMPT_TTL_SECS
+        })
+    }))
+}
+
+pub async fn save(chat_id: ChatId, dialogue: &Dialogue) -> sqlx::Result<()> {
+    let (name, data) = dialogue.encode();
+    sqlx::query(
+        r#"
+INSERT INTO chat_state (telegram_chat_id, dialogue, data, updated_at) VALUES ($1, $2, $3, $4)
+ON CONFLICT(telegram_chat_id) DO UPDATE SET
+    dialogue = excluded.dialogue, data = excluded.data, updated_at = excluded.updated_at
+        "#,
+    )
+    .bind(chat_id.0)
+    .bind(name)
+    .bind(data)
+    .bind(now_unix())
+    .execute(&*DB)
+    .await?;
+    Ok(())
+}
+
+/// Drops a question a command asked, leaving onboarding alone; sending another command
+/// abandons the question, so a stray "yes" later can't confirm something old
+pub async fn drop_question(chat_id: ChatId) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+DELETE FROM chat_state WHERE telegram_chat_id = $1
+AND dialogue NOT IN ('awaiting_vm_id', 'vm_not_found', 'awaiting_ownership_proof')
+        "#,
+    )
+    .bind(chat_id.0)
+    .execute(&*DB)
+    .await?;
+    Ok(())
+}
+
+pub async fn clear(chat_id: ChatId) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM chat_state WHERE telegram_chat_id = $1")
+        .bind(chat_id.0)
+        .execute(&*DB)
+        .await?;
+    Ok(())
+}
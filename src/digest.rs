@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use teloxide::{prelude::*, types::ChatId};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::{DB, chart, i18n, next_tick, now_unix, outbox, policy::RewardPolicy, render};
+
+const WEEK_SECS: i64 = 7 * 86400;
+
+/// Summary of the last seven days for every VM linked to `chat_id`
+pub async fn compose(chat_id: ChatId) -> sqlx::Result<String> {
+    let now = now_unix();
+    let since_day = chart::last_days(7).swap_remove(0);
+    let (week_secs, unclaimed_secs, claimed_days, outages): (i64, i64, i64, i64) = sqlx::query_as(
+        r#"
+SELECT
+    (SELECT CAST(COALESCE(SUM(h.up_secs), 0) AS BIGINT)
+     FROM uptime_history h JOIN agent_records a ON a.vm_id = h.vm_id
+     WHERE a.telegram_chat_id = $1 AND h.day >= $2),
+    (SELECT CAST(COALESCE(SUM(up_secs + bonus_secs - paid_secs), 0) AS BIGINT)
+     FROM agent_records WHERE telegram_chat_id = $1),
+    (SELECT CAST(COALESCE(SUM(days), 0) AS BIGINT)
+     FROM giftcards WHERE telegram_chat_id = $1 AND created_at >= $3),
+    (SELECT COUNT(*)
+     FROM outages o JOIN agent_records a ON a.vm_id = o.vm_id
+     WHERE a.telegram_chat_id = $1 AND (o.ended_at IS NULL OR o.ended_at >= $3))
+            "#,
+    )
+    .bind(chat_id.0)
+    .bind(since_day)
+    .bind(now - WEEK_SECS)
+    .fetch_one(&*DB)
+    .await?;
+    let hours = week_secs / 3600;
+    let policy = RewardPolicy::current();
+    let earned = week_secs as f64 / policy.secs_per_day as f64;
+    let unclaimed_days = policy.days(unclaimed_secs);
+    let outages_key = if outages == 0 {
+        "digest.no_outages"
+    } else {
+        "digest.outage_count"
+    };
+    Ok([
+        i18n::text("digest.title", &[("date", &render::format_date(now))]),
+        i18n::text("digest.uptime", &[("hours", &hours)]),
+        i18n::text("digest.earned", &[("days", &format!("{earned:.1}"))]),
+        i18n::text("digest.claimed", &[("count", &claimed_days)]),
+        i18n::text("digest.unclaimed", &[("count", &unclaimed_days)]),
+        i18n::text_nested(
+            "digest.outages",
+            "outages",
+            outages_key,
+            &[("count", &outages)],
+        ),
+        String::new(),
+        i18n::text("digest.stop", &[]),
+    ]
+    .join("\n"))
+}
+
+/// Sends the digest to every opted-in chat once a week
+pub async fn weekly_digest_loop(bot: Bot, shutdown: CancellationToken) -> anyhow::Result<()> {
+    let mut ticker = smol::Timer::interval(Duration::from_secs(3600));
+    loop {
+        async {
+            let now = now_unix();
+            let due: Vec<i64> = sqlx::query_scalar(
+                r#"
+SELECT p.telegram_chat_id FROM user_prefs p
+WHERE p.weekly_digest = 1
+  AND (p.digest_sent_at IS NULL OR p.digest_sent_at <= $1)
+  AND EXISTS(SELECT 1 FROM agent_records a WHERE a.telegram_chat_id = p.telegram_chat_id)
+  AND NOT EXISTS(SELECT 1 FROM inactive_chats i WHERE i.telegram_chat_id = p.telegram_chat_id)
+                "#,
+            )
+            .bind(now - WEEK_SECS)
+            .fetch_all(&*DB)
+            .await?;
+            for chat_id in due {
+                let text = compose(ChatId(chat_id)).await?;
+                let sent = outbox::deliver(ChatId(chat_id), || {
+                    bot.send_message(ChatId(chat_id), &text).send()
+                })
+                .await;
+                if let Err(e) = sent {
+                    tracing::warn!("sending weekly digest to {chat_id} failed: {e}");
+                }
+                sqlx::query(
+                    "UPDATE user_prefs SET digest_sent_at = $1 WHERE telegram_chat_id = $2",
+                )
+                .bind(now)
+                .bind(chat_id)
+                .execute(&*DB)
+                .await?;
+            }
+            anyhow::Ok(())
+        }
+        .instrument(tracing::info_span!("tick"))
+        .await?;
+
+        if !next_tick("weekly_digest", &mut ticker, &shutdown).await {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use serde_json::json;
+use teloxide::types::ChatId;
+
+use crate::{
+    audit::{self, Actor},
+    begin_write,
+    chat_migration::CHAT_COLUMNS,
+};
+
+/// What `/deletemydata` removed, reported back to the user as a receipt
+pub struct Receipt {
+    pub vms_unlinked: u64,
+    pub claims: u64,
+    pub giftcards: u64,
+    /// Preferences, conversation state, pending claims and the like
+    pub other_rows: u64,
+}
+
+/// Erases everything tied to `chat_id` in one transaction. VMs are unlinked rather than
+/// deleted: their uptime belongs to the machine, not the person, and a VM can be linked
+/// again by whoever runs it next. The audit log is append-only and keeps its entries, plus
+/// one recording the erasure, so past payouts can still be accounted for.
+pub async fn erase(chat_id: ChatId) -> sqlx::Result<Receipt> {
+    let (_write, mut tx) = begin_write().await?;
+    let mut receipt = Receipt {
+        vms_unlinked: 0,
+        claims: 0,
+        giftcards: 0,
+        other_rows: 0,
+    };
+    for (table, column) in CHAT_COLUMNS {
+        let sql = if *table == "agent_records" {
+            format!(
+                "UPDATE {table} SET {column} = NULL, linked_at = NULL, nickname = NULL WHERE {column} = $1"
+            )
+        } else {
+            format!("DELETE FROM {table} WHERE {column} = $1")
+        };
+        let rows = sqlx::query(&sql)
+            .bind(chat_id.0)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        match *table {
+            "agent_records" => receipt.vms_unlinked += rows,
+            "claims" => receipt.claims += rows,
+            "giftcards" => receipt.giftcards += rows,
+            _ => receipt.other_rows += rows,
+        }
+    }
+    audit::record(
+        &mut tx,
+        &Actor::Chat(chat_id),
+        "data_erased",
+        Some(chat_id),
+        json!({
+            "vms_unlinked": receipt.vms_unlinked,
+            "claims": receipt.claims,
+            "giftcards": receipt.giftcards,
+            "other_rows": receipt.other_rows,
+        }),
+    )
+    .await?;
+    tx.commit().await?;
+    tracing::info!(
+        "erased chat {chat_id}: {} VM(s) unlinked, {} claim(s), {} giftcard(s), {} other row(s)",
+        receipt.vms_unlinked,
+        receipt.claims,
+        receipt.giftcards,
+        receipt.other_rows
+    );
+    Ok(receipt)
+}
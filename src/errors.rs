@@ -0,0 +1,93 @@
+use teloxide::{RequestError, types::ChatId};
+
+use crate::{i18n, telegram::Telegram};
+
+/// User-visible failure modes. Codes are stable: never renumber or reuse one, so support
+/// conversations and logs keep meaning the same thing.
+#[derive(Clone, Copy, Debug)]
+pub enum ErrorCode {
+    UnknownVm,
+    InvalidToken,
+    NotYourVm,
+    OldVmStillOnline,
+    NewVmUnavailable,
+    OwnershipNotProven,
+    ChallengeExpired,
+    ClaimInProgress,
+    ClaimUnderReview,
+    SlowDown,
+    GiftcardBackendDown,
+    /// Handling the update failed on our side; see [`crate::bot_error::report`]
+    Temporary,
+    Maintenance,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 13] = [
+        ErrorCode::UnknownVm,
+        ErrorCode::InvalidToken,
+        ErrorCode::NotYourVm,
+        ErrorCode::OldVmStillOnline,
+        ErrorCode::NewVmUnavailable,
+        ErrorCode::OwnershipNotProven,
+        ErrorCode::ChallengeExpired,
+        ErrorCode::ClaimInProgress,
+        ErrorCode::ClaimUnderReview,
+        ErrorCode::SlowDown,
+        ErrorCode::GiftcardBackendDown,
+        ErrorCode::Temporary,
+        ErrorCode::Maintenance,
+    ];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorCode::UnknownVm => "E001",
+            ErrorCode::InvalidToken => "E002",
+            ErrorCode::NotYourVm => "E003",
+            ErrorCode::OldVmStillOnline => "E004",
+            ErrorCode::NewVmUnavailable => "E005",
+            ErrorCode::OwnershipNotProven => "E006",
+            ErrorCode::ChallengeExpired => "E007",
+            ErrorCode::ClaimInProgress => "E010",
+            ErrorCode::ClaimUnderReview => "E011",
+            ErrorCode::SlowDown => "E012",
+            ErrorCode::GiftcardBackendDown => "E014",
+            ErrorCode::Temporary => "E015",
+            ErrorCode::Maintenance => "E020",
+        }
+    }
+
+    /// Catalog key of the message explaining the code
+    fn message_key(self) -> String {
+        format!("error.{}", self.code())
+    }
+
+    /// The message in every shown language, tagged with the code
+    pub fn render(self) -> String {
+        format!("[{}] {}", self.code(), i18n::text(&self.message_key(), &[]))
+    }
+}
+
+/// Sends the localized message for `code` and logs which chat hit it
+pub async fn send_error(
+    bot: &impl Telegram,
+    chat_id: ChatId,
+    code: ErrorCode,
+) -> Result<(), RequestError> {
+    tracing::info!("chat {chat_id} got {} ({code:?})", code.code());
+    bot.send_message(chat_id, code.render()).await?;
+    Ok(())
+}
+
+/// Text for `/help errors`
+pub fn help_text() -> String {
+    let lines: Vec<String> = ErrorCode::ALL
+        .iter()
+        .map(|code| format!("{} - {}", code.code(), i18n::text(&code.message_key(), &[])))
+        .collect();
+    format!(
+        "{}：\n\n{}",
+        i18n::text("error_codes_title", &[]),
+        lines.join("\n\n")
+    )
+}
@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use teloxide::{prelude::*, types::ChatId};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::{DB, i18n, next_tick, now_unix, outbox, render};
+
+/// A window during which credited uptime earns `multiplier` times the usual reward
+#[derive(Clone, Debug)]
+pub struct RewardEvent {
+    pub id: i64,
+    pub starts_at: i64,
+    pub ends_at: i64,
+    pub multiplier: f64,
+}
+
+impl RewardEvent {
+    fn from_row((id, starts_at, ends_at, multiplier): (i64, i64, i64, f64)) -> Self {
+        RewardEvent {
+            id,
+            starts_at,
+            ends_at,
+            multiplier,
+        }
+    }
+
+    /// e.g. `2x from 2025-05-01 00:00 UTC until 2025-05-03 00:00 UTC`
+    pub fn describe(&self) -> String {
+        format!(
+            "{}x from {} until {}",
+            self.multiplier,
+            render::format_timestamp(self.starts_at),
+            render::format_timestamp(self.ends_at)
+        )
+    }
+}
+
+pub async fn schedule(starts_at: i64, ends_at: i64, multiplier: f64) -> sqlx::Result<i64> {
+    sqlx::query_scalar(
+        "INSERT INTO reward_events (starts_at, ends_at, multiplier) VALUES ($1, $2, $3) RETURNING id",
+    )
+    .bind(starts_at)
+    .bind(ends_at)
+    .bind(multiplier)
+    .fetch_one(&*DB)
+    .await
+}
+
+/// Removes an event, returning whether it existed
+pub async fn cancel(id: i64) -> sqlx::Result<bool> {
+    let result = sqlx::query("DELETE FROM reward_events WHERE id = $1")
+        .bind(id)
+        .execute(&*DB)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Events that haven't ended yet, soonest first
+pub async fn upcoming() -> sqlx::Result<Vec<RewardEvent>> {
+    let rows: Vec<(i64, i64, i64, f64)> = sqlx::query_as(
+        "SELECT id, starts_at, ends_at, multiplier FROM reward_events WHERE ends_at > $1 ORDER BY starts_at",
+    )
+    .bind(now_unix())
+    .fetch_all(&*DB)
+    .await?;
+    Ok(rows.into_iter().map(RewardEvent::from_row).collect())
+}
+
+/// The event in effect at `at`; when several overlap, the most generous one wins
+pub async fn active_at(at: i64) -> sqlx::Result<Option<RewardEvent>> {
+    let row: Option<(i64, i64, i64, f64)> = sqlx::query_as(
+        r#"
+SELECT id, starts_at, ends_at, multiplier FROM reward_events
+WHERE starts_at <= $1 AND ends_at > $1
+ORDER BY multiplier DESC
+LIMIT 1
+        "#,
+    )
+    .bind(at)
+    .fetch_optional(&*DB)
+    .await?;
+    Ok(row.map(RewardEvent::from_row))
+}
+
+/// Announces each event's start and end once to chats that opted in
+pub async fn announce_loop(bot: Bot, shutdown: CancellationToken) -> anyhow::Result<()> {
+    let mut ticker = smol::Timer::interval(Duration::from_secs(60));
+    loop {
+        async {
+            let now = now_unix();
+            let started: Vec<(i64, i64, i64, f64)> = sqlx::query_as(
+                r#"
+SELECT id, starts_at, ends_at, multiplier FROM reward_events
+WHERE start_announced = 0 AND starts_at <= $1 AND ends_at > $1
+                "#,
+            )
+            .bind(now)
+            .fetch_all(&*DB)
+            .await?;
+            for event in started.into_iter().map(RewardEvent::from_row) {
+                let until = render::format_timestamp(event.ends_at);
+                let x = event.multiplier;
+                let text = i18n::text("event.started", &[("x", &x), ("until", &until)]);
+                broadcast(&bot, &text).await?;
+                sqlx::query("UPDATE reward_events SET start_announced = 1 WHERE id = $1")
+                    .bind(event.id)
+                    .execute(&*DB)
+                    .await?;
+            }
+
+            let ended: Vec<(i64, i64, i64, f64)> = sqlx::query_as(
+                r#"
+SELECT id, starts_at, ends_at, multiplier FROM reward_events
+WHERE end_announced = 0 AND start_announced = 1 AND ends_at <= $1
+                "#,
+            )
+            .bind(now)
+            .fetch_all(&*DB)
+            .await?;
+            for event in ended.into_iter().map(RewardEvent::from_row) {
+                let x = event.multiplier;
+                broadcast(&bot, &i18n::text("event.ended", &[("x", &x)])).await?;
+                sqlx::query("UPDATE reward_events SET end_announced = 1 WHERE id = $1")
+                    .bind(event.id)
+                    .execute(&*DB)
+                    .await?;
+            }
+            anyhow::Ok(())
+        }
+        .instrument(tracing::info_span!("tick"))
+        .await?;
+
+        if !next_tick("event_announcements", &mut ticker, &shutdown).await {
+            return Ok(());
+        }
+    }
+}
+
+async fn broadcast(bot: &Bot, text: &str) -> sqlx::Result<()> {
+    let chats: Vec<i64> = sqlx::query_scalar(
+        r#"
+SELECT p.telegram_chat_id FROM user_prefs p
+WHERE p.event_announcements = 1
+  AND EXISTS(SELECT 1 FROM agent_records a WHERE a.telegram_chat_id = p.telegram_chat_id)
+  AND NOT EXISTS(SELECT 1 FROM inactive_chats i WHERE i.telegram_chat_id = p.telegram_chat_id)
+        "#,
+    )
+    .fetch_all(&*DB)
+    .await?;
+    for chat_id in chats {
+        let sent = outbox::deliver(ChatId(chat_id), || {
+            bot.send_message(ChatId(chat_id), text).send()
+        })
+        .await;
+        if let Err(e) = sent {
+            tracing::warn!("event announcement to {chat_id} failed: {e}");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,225 @@
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::DB;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// One document holding every table
+    Json,
+    /// One file per table, for spreadsheets
+    Csv,
+}
+
+impl Format {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Csv => "csv",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Format> {
+        Format::from_str(s, true).ok()
+    }
+}
+
+/// Everything needed to reconcile uptime against payouts offline. Giftcard codes are left
+/// out: they're spendable, and nothing downstream needs them.
+#[derive(Serialize)]
+pub struct Export {
+    pub agent_records: Vec<AgentRecord>,
+    pub uptime_history: Vec<UptimeDay>,
+    pub claims: Vec<Claim>,
+}
+
+#[derive(Serialize)]
+pub struct AgentRecord {
+    pub vm_id: String,
+    pub telegram_chat_id: Option<i64>,
+    pub up_secs: i64,
+    pub bonus_secs: i64,
+    pub paid_secs: i64,
+    pub linked_at: Option<i64>,
+    pub fleet: String,
+}
+
+#[derive(Serialize)]
+pub struct UptimeDay {
+    pub vm_id: String,
+    pub day: String,
+    pub up_secs: i64,
+}
+
+#[derive(Serialize)]
+pub struct Claim {
+    pub idempotency_key: String,
+    pub telegram_chat_id: i64,
+    pub days: i64,
+    pub status: String,
+    pub created_at: i64,
+}
+
+/// A table row as CSV fields, in [`CsvRow::HEADER`] order
+trait CsvRow {
+    const HEADER: &'static [&'static str];
+    fn fields(&self) -> Vec<String>;
+}
+
+impl CsvRow for AgentRecord {
+    const HEADER: &'static [&'static str] = &[
+        "vm_id",
+        "telegram_chat_id",
+        "up_secs",
+        "bonus_secs",
+        "paid_secs",
+        "linked_at",
+        "fleet",
+    ];
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.vm_id.clone(),
+            optional(self.telegram_chat_id),
+            self.up_secs.to_string(),
+            self.bonus_secs.to_string(),
+            self.paid_secs.to_string(),
+            optional(self.linked_at),
+            self.fleet.clone(),
+        ]
+    }
+}
+
+impl CsvRow for UptimeDay {
+    const HEADER: &'static [&'static str] = &["vm_id", "day", "up_secs"];
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.vm_id.clone(),
+            self.day.clone(),
+            self.up_secs.to_string(),
+        ]
+    }
+}
+
+impl CsvRow for Claim {
+    const HEADER: &'static [&'static str] = &[
+        "idempotency_key",
+        "telegram_chat_id",
+        "days",
+        "status",
+        "created_at",
+    ];
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.idempotency_key.clone(),
+            self.telegram_chat_id.to_string(),
+            self.days.to_string(),
+            self.status.clone(),
+            self.created_at.to_string(),
+        ]
+    }
+}
+
+fn optional(value: Option<i64>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+/// RFC 4180: quote a field if it holds a separator, quote or line break
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+fn to_csv<R: CsvRow>(rows: &[R]) -> String {
+    let mut out = R::HEADER.join(",");
+    out.push_str("\r\n");
+    for row in rows {
+        let fields: Vec<String> = row.fields().iter().map(|f| escape(f)).collect();
+        out.push_str(&fields.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+impl Export {
+    /// The export rendered as `(file name, contents)` pairs
+    pub fn render(&self, format: Format) -> anyhow::Result<Vec<(String, String)>> {
+        Ok(match format {
+            Format::Json => vec![(
+                "export.json".to_owned(),
+                serde_json::to_string_pretty(self)?,
+            )],
+            Format::Csv => vec![
+                ("agent_records.csv".to_owned(), to_csv(&self.agent_records)),
+                (
+                    "uptime_history.csv".to_owned(),
+                    to_csv(&self.uptime_history),
+                ),
+                ("claims.csv".to_owned(), to_csv(&self.claims)),
+            ],
+        })
+    }
+}
+
+type AgentRow = (String, Option<i64>, i64, i64, i64, Option<i64>, String);
+
+pub async fn collect() -> sqlx::Result<Export> {
+    let agent_records: Vec<AgentRow> = sqlx::query_as(
+        r#"
+SELECT vm_id, telegram_chat_id, COALESCE(up_secs, 0), COALESCE(bonus_secs, 0),
+       COALESCE(paid_secs, 0), linked_at, fleet
+FROM agent_records ORDER BY vm_id
+        "#,
+    )
+    .fetch_all(&*DB)
+    .await?;
+    let uptime_history: Vec<(String, String, i64)> =
+        sqlx::query_as("SELECT vm_id, day, up_secs FROM uptime_history ORDER BY vm_id, day")
+            .fetch_all(&*DB)
+            .await?;
+    let claims: Vec<(String, i64, i64, String, i64)> = sqlx::query_as(
+        "SELECT idempotency_key, telegram_chat_id, days, status, created_at FROM claims ORDER BY created_at",
+    )
+    .fetch_all(&*DB)
+    .await?;
+    Ok(Export {
+        agent_records: agent_records
+            .into_iter()
+            .map(
+                |(vm_id, telegram_chat_id, up_secs, bonus_secs, paid_secs, linked_at, fleet)| {
+                    AgentRecord {
+                        vm_id,
+                        telegram_chat_id,
+                        up_secs,
+                        bonus_secs,
+                        paid_secs,
+                        linked_at,
+                        fleet,
+                    }
+                },
+            )
+            .collect(),
+        uptime_history: uptime_history
+            .into_iter()
+            .map(|(vm_id, day, up_secs)| UptimeDay {
+                vm_id,
+                day,
+                up_secs,
+            })
+            .collect(),
+        claims: claims
+            .into_iter()
+            .map(
+                |(idempotency_key, telegram_chat_id, days, status, created_at)| Claim {
+                    idempotency_key,
+                    telegram_chat_id,
+                    days,
+                    status,
+                    created_at,
+                },
+            )
+            .collect(),
+    })
+}
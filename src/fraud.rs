@@ -0,0 +1,286 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use teloxide::{prelude::*, types::ChatId};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use serde_json::json;
+
+use crate::{
+    DB, POLL_SECS, admin,
+    audit::{self, Actor},
+    next_tick, now_unix, render,
+};
+
+/// Days of uptime history re-examined on every run
+const LOOKBACK_DAYS: i64 = 7;
+/// Registering this many VMs within [`BURST_WINDOW_SECS`] looks like a scripted farm
+const BURST_VMS: usize = 5;
+const BURST_WINDOW_SECS: i64 = 600;
+/// Only VMs reporting this recently are compared for cloned heartbeats
+const FINGERPRINT_RECENT_SECS: i64 = 3600;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlagKind {
+    /// More uptime credited for a day than the day has seconds
+    Overcredit,
+    /// Many VMs linked to one chat within minutes
+    RegistrationBurst,
+    /// Distinct VMs reporting identical heartbeat metadata
+    ClonedHeartbeat,
+}
+
+impl FlagKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            FlagKind::Overcredit => "overcredit",
+            FlagKind::RegistrationBurst => "registration_burst",
+            FlagKind::ClonedHeartbeat => "cloned_heartbeat",
+        }
+    }
+}
+
+/// An unresolved flag, as listed to admins
+pub struct Flag {
+    pub id: i64,
+    pub chat_id: i64,
+    pub kind: String,
+    pub detail: String,
+    pub created_at: i64,
+}
+
+struct Finding {
+    chat_id: i64,
+    kind: FlagKind,
+    evidence: String,
+    detail: String,
+}
+
+/// Periodically looks for uptime that can't be genuine and flags the chats it would be
+/// paid to. Flags are raised to admins and hold the chat's claims until cleared with
+/// `/admin flag_clear`, so nothing is paid out silently while it's being looked at.
+pub async fn analyze_loop(bot: Bot, shutdown: CancellationToken) -> anyhow::Result<()> {
+    let mut ticker = smol::Timer::interval(Duration::from_secs(3600));
+    loop {
+        async {
+            let now = now_unix();
+            let mut findings = overcredited_days(now).await?;
+            findings.extend(registration_bursts(now).await?);
+            findings.extend(cloned_heartbeats(now).await?);
+            for finding in findings {
+                if record(&finding, now).await? {
+                    audit::log(
+                        &Actor::System,
+                        "fraud_flagged",
+                        Some(ChatId(finding.chat_id)),
+                        json!({ "kind": finding.kind.as_str(), "evidence": finding.evidence }),
+                    )
+                    .await?;
+                    tracing::warn!(
+                        "fraud flag {} for chat {}: {}",
+                        finding.kind.as_str(),
+                        finding.chat_id,
+                        finding.detail
+                    );
+                    admin::notify_admins(
+                        &bot,
+                        &format!(
+                            "🚩 {} for chat {}: {}\nClaims are on hold until /admin flag_clear.",
+                            finding.kind.as_str(),
+                            finding.chat_id,
+                            finding.detail
+                        ),
+                    )
+                    .await;
+                }
+            }
+            anyhow::Ok(())
+        }
+        .instrument(tracing::info_span!("tick"))
+        .await?;
+
+        if !next_tick("fraud_analyzer", &mut ticker, &shutdown).await {
+            return Ok(());
+        }
+    }
+}
+
+/// Stores a finding, returning whether it is new
+async fn record(finding: &Finding, now: i64) -> sqlx::Result<bool> {
+    let result = sqlx::query(
+        r#"
+INSERT INTO fraud_flags (telegram_chat_id, kind, evidence, detail, created_at)
+VALUES ($1, $2, $3, $4, $5)
+ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(finding.chat_id)
+    .bind(finding.kind.as_str())
+    .bind(&finding.evidence)
+    .bind(&finding.detail)
+    .bind(now)
+    .execute(&*DB)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+async fn overcredited_days(now: i64) -> sqlx::Result<Vec<Finding>> {
+    // One poll period of slack absorbs a tick landing right on midnight
+    let rows: Vec<(String, i64, String, i64)> = sqlx::query_as(
+        r#"
+SELECT h.vm_id, a.telegram_chat_id, h.day, h.up_secs
+FROM uptime_history h JOIN agent_records a ON a.vm_id = h.vm_id
+WHERE a.telegram_chat_id IS NOT NULL AND h.day >= $1 AND h.up_secs > $2
+        "#,
+    )
+    .bind(render::format_date(now - LOOKBACK_DAYS * 86400))
+    .bind(86400 + POLL_SECS)
+    .fetch_all(&*DB)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(vm_id, chat_id, day, up_secs)| Finding {
+            chat_id,
+            kind: FlagKind::Overcredit,
+            evidence: format!("{vm_id}/{day}"),
+            detail: format!("{vm_id} was credited {up_secs}s on {day}, a day has 86400s"),
+        })
+        .collect())
+}
+
+async fn registration_bursts(now: i64) -> sqlx::Result<Vec<Finding>> {
+    let rows: Vec<(i64, String, i64)> = sqlx::query_as(
+        r#"
+SELECT telegram_chat_id, vm_id, linked_at FROM agent_records
+WHERE telegram_chat_id IS NOT NULL AND linked_at >= $1
+ORDER BY telegram_chat_id, linked_at
+        "#,
+    )
+    .bind(now - LOOKBACK_DAYS * 86400)
+    .fetch_all(&*DB)
+    .await?;
+    let mut by_chat: BTreeMap<i64, Vec<(String, i64)>> = BTreeMap::new();
+    for (chat_id, vm_id, linked_at) in rows {
+        by_chat.entry(chat_id).or_default().push((vm_id, linked_at));
+    }
+    let mut findings = Vec::new();
+    for (chat_id, links) in by_chat {
+        // Report the first window that's too dense; later VMs land in the same flag
+        if let Some(window) = links
+            .windows(BURST_VMS)
+            .find(|w| w[BURST_VMS - 1].1 - w[0].1 <= BURST_WINDOW_SECS)
+        {
+            let started = window[0].1;
+            findings.push(Finding {
+                chat_id,
+                kind: FlagKind::RegistrationBurst,
+                evidence: format!("{chat_id}/{started}"),
+                detail: format!(
+                    "{BURST_VMS} VMs linked within {}m from {}: {}",
+                    BURST_WINDOW_SECS / 60,
+                    render::format_timestamp(started),
+                    window
+                        .iter()
+                        .map(|(vm_id, _)| vm_id.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            });
+        }
+    }
+    Ok(findings)
+}
+
+async fn cloned_heartbeats(now: i64) -> sqlx::Result<Vec<Finding>> {
+    let rows: Vec<(String, i64, i64, f64, String)> = sqlx::query_as(
+        r#"
+SELECT
+    a.vm_id, a.telegram_chat_id, s.last_heartbeat, s.bandwidth_mbps,
+    COALESCE(s.version, '') || '|' || COALESCE(s.region, '')
+FROM agent_records a JOIN vm_status s ON s.vm_id = a.vm_id
+WHERE a.telegram_chat_id IS NOT NULL
+  AND s.last_seen >= $1
+  AND s.last_heartbeat IS NOT NULL
+  AND s.bandwidth_mbps IS NOT NULL
+        "#,
+    )
+    .bind(now - FINGERPRINT_RECENT_SECS)
+    .fetch_all(&*DB)
+    .await?;
+    // Independent machines don't report the same heartbeat second and bandwidth
+    let mut groups: BTreeMap<String, Vec<(String, i64)>> = BTreeMap::new();
+    for (vm_id, chat_id, heartbeat, bandwidth, release) in rows {
+        let fingerprint = format!("{heartbeat}|{bandwidth}|{release}");
+        groups
+            .entry(fingerprint)
+            .or_default()
+            .push((vm_id, chat_id));
+    }
+    let mut findings = Vec::new();
+    for (_, mut group) in groups.into_iter().filter(|(_, g)| g.len() > 1) {
+        group.sort();
+        let vms = group
+            .iter()
+            .map(|(vm_id, _)| vm_id.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut chats: Vec<i64> = group.iter().map(|(_, chat)| *chat).collect();
+        chats.sort();
+        chats.dedup();
+        for chat_id in chats {
+            findings.push(Finding {
+                chat_id,
+                kind: FlagKind::ClonedHeartbeat,
+                evidence: format!("{vms}/{chat_id}"),
+                detail: format!("identical heartbeats from {vms}"),
+            });
+        }
+    }
+    Ok(findings)
+}
+
+/// Whether claims for `chat_id` are held for review
+pub async fn under_review(conn: &mut sqlx::AnyConnection, chat_id: ChatId) -> sqlx::Result<bool> {
+    let open: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM fraud_flags WHERE telegram_chat_id = $1 AND resolved_at IS NULL",
+    )
+    .bind(chat_id.0)
+    .fetch_one(conn)
+    .await?;
+    Ok(open > 0)
+}
+
+pub async fn open_flags() -> sqlx::Result<Vec<Flag>> {
+    let rows: Vec<(i64, i64, String, String, i64)> = sqlx::query_as(
+        r#"
+SELECT id, telegram_chat_id, kind, detail, created_at FROM fraud_flags
+WHERE resolved_at IS NULL
+ORDER BY created_at
+LIMIT 50
+        "#,
+    )
+    .fetch_all(&*DB)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(id, chat_id, kind, detail, created_at)| Flag {
+            id,
+            chat_id,
+            kind,
+            detail,
+            created_at,
+        })
+        .collect())
+}
+
+/// Marks a flag as reviewed, returning the flagged chat if the flag was open
+pub async fn resolve(id: i64) -> sqlx::Result<Option<ChatId>> {
+    let chat_id: Option<i64> = sqlx::query_scalar(
+        "UPDATE fraud_flags SET resolved_at = $1 WHERE id = $2 AND resolved_at IS NULL RETURNING telegram_chat_id",
+    )
+    .bind(now_unix())
+    .bind(id)
+    .fetch_optional(&*DB)
+    .await?;
+    Ok(chat_id.map(ChatId))
+}
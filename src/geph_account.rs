@@ -0,0 +1,125 @@
+use isahc::prelude::*;
+use serde_json::json;
+use teloxide::types::ChatId;
+
+use crate::{
+    CONFIG, DB, HTTP_TIMEOUT,
+    audit::{self, Actor},
+    begin_write, now_unix, proxy,
+};
+
+/// Whether `username` looks like a Geph account name, so typos like pasted commands or
+/// whole sentences are caught before they are stored
+pub fn valid_username(username: &str) -> bool {
+    (3..=64).contains(&username.len())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-.@+".contains(c))
+}
+
+/// Links `chat_id` to `username`, replacing any account it had linked before
+pub async fn link(chat_id: ChatId, username: &str) -> sqlx::Result<()> {
+    let (_write, mut tx) = begin_write().await?;
+    let previous: Option<String> =
+        sqlx::query_scalar("SELECT username FROM geph_accounts WHERE telegram_chat_id = $1")
+            .bind(chat_id.0)
+            .fetch_optional(&mut *tx)
+            .await?;
+    sqlx::query(
+        r#"
+INSERT INTO geph_accounts (telegram_chat_id, username, linked_at) VALUES ($1, $2, $3)
+ON CONFLICT(telegram_chat_id) DO UPDATE SET
+    username = excluded.username, linked_at = excluded.linked_at
+        "#,
+    )
+    .bind(chat_id.0)
+    .bind(username)
+    .bind(now_unix())
+    .execute(&mut *tx)
+    .await?;
+    audit::record(
+        &mut tx,
+        &Actor::Chat(chat_id),
+        "geph_account_linked",
+        Some(chat_id),
+        json!({ "username": username, "previous": previous }),
+    )
+    .await?;
+    tx.commit().await
+}
+
+/// Removes `chat_id`'s link, returning the account it pointed to
+pub async fn unlink(chat_id: ChatId) -> sqlx::Result<Option<String>> {
+    let (_write, mut tx) = begin_write().await?;
+    let username: Option<String> = sqlx::query_scalar(
+        "DELETE FROM geph_accounts WHERE telegram_chat_id = $1 RETURNING username",
+    )
+    .bind(chat_id.0)
+    .fetch_optional(&mut *tx)
+    .await?;
+    if let Some(username) = &username {
+        audit::record(
+            &mut tx,
+            &Actor::Chat(chat_id),
+            "geph_account_unlinked",
+            Some(chat_id),
+            json!({ "username": username }),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(username)
+}
+
+/// Chats linked to `username` with when they linked it, for support
+pub async fn chats_for(username: &str) -> sqlx::Result<Vec<(i64, i64)>> {
+    sqlx::query_as(
+        "SELECT telegram_chat_id, linked_at FROM geph_accounts WHERE username = $1 ORDER BY linked_at",
+    )
+    .bind(username)
+    .fetch_all(&*DB)
+    .await
+}
+
+/// Username of the Geph account linked to `chat_id`
+pub async fn linked(chat_id: ChatId) -> sqlx::Result<Option<String>> {
+    sqlx::query_scalar("SELECT username FROM geph_accounts WHERE telegram_chat_id = $1")
+        .bind(chat_id.0)
+        .fetch_optional(&*DB)
+        .await
+}
+
+/// Adds `days` of Plus to `username` through the backend's credit endpoint. The
+/// idempotency key is the claim's, so the backend can recognise a retried claim whichever
+/// endpoint it went to.
+#[tracing::instrument(
+    name = "plus_credit_api.credit",
+    skip(url, username),
+    fields(otel.kind = "client", http.response.status_code = tracing::field::Empty)
+)]
+pub async fn credit(
+    url: &str,
+    username: &str,
+    days: i64,
+    idempotency_key: &str,
+) -> anyhow::Result<()> {
+    let body = json!({
+        "username": username,
+        "days": days,
+        "secret": CONFIG.giftcard_api_secret
+    });
+    let request = isahc::Request::post(url)
+        .header(isahc::http::header::CONTENT_TYPE, "application/json")
+        .header("Idempotency-Key", idempotency_key)
+        .timeout(HTTP_TIMEOUT)
+        .body(body.to_string())?;
+    let mut response = proxy::HTTP.send_async(request).await?;
+    tracing::Span::current().record("http.response.status_code", response.status().as_u16());
+    anyhow::ensure!(
+        response.status().is_success(),
+        "credit endpoint returned {}: {}",
+        response.status(),
+        response.text().await.unwrap_or_default()
+    );
+    Ok(())
+}
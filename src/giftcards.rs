@@ -0,0 +1,130 @@
+use serde_json::json;
+use teloxide::types::{ChatId, InlineKeyboardMarkup};
+
+use crate::{
+    CONFIG, DB,
+    audit::{self, Actor},
+    i18n, now_unix, render,
+};
+
+/// Cards listed by `/mycards`, newest first
+const LIST_LIMIT: i64 = 20;
+/// Cards offered as re-send buttons under the list
+const BUTTON_LIMIT: usize = 5;
+/// Characters of a redacted code still shown, so the tester can tell cards apart
+const REDACTED_PREFIX_CHARS: usize = 4;
+
+/// A giftcard issued to a chat, as stored in `giftcards`
+pub struct Card {
+    pub id: i64,
+    pub code: String,
+    pub days: i64,
+    pub created_at: i64,
+}
+
+impl Card {
+    /// Cards older than `giftcard_resend_max_age_days` are only ever shown redacted:
+    /// a code that old has either been redeemed or leaked, and re-posting it helps neither
+    pub fn is_redacted(&self) -> bool {
+        now_unix() - self.created_at > CONFIG.giftcard_resend_max_age_days * 86400
+    }
+
+    fn shown_code(&self) -> String {
+        if self.is_redacted() {
+            let prefix: String = self.code.chars().take(REDACTED_PREFIX_CHARS).collect();
+            i18n::text("giftcards.redacted", &[("prefix", &prefix)])
+        } else {
+            self.code.clone()
+        }
+    }
+
+    fn line(&self) -> String {
+        format!(
+            "#{} · {} · {}d · {}",
+            self.id,
+            render::format_date(self.created_at),
+            self.days,
+            self.shown_code()
+        )
+    }
+}
+
+pub async fn list(chat_id: ChatId) -> sqlx::Result<Vec<Card>> {
+    let rows: Vec<(i64, String, i64, i64)> = sqlx::query_as(
+        "SELECT id, code, days, created_at FROM giftcards WHERE telegram_chat_id = $1 ORDER BY created_at DESC, id DESC LIMIT $2",
+    )
+    .bind(chat_id.0)
+    .bind(LIST_LIMIT)
+    .fetch_all(&*DB)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(id, code, days, created_at)| Card {
+            id,
+            code,
+            days,
+            created_at,
+        })
+        .collect())
+}
+
+/// `chat_id`'s card `id`, or `None` if there is no such card or it belongs to someone else
+pub async fn get(chat_id: ChatId, id: i64) -> sqlx::Result<Option<Card>> {
+    let row: Option<(String, i64, i64)> = sqlx::query_as(
+        "SELECT code, days, created_at FROM giftcards WHERE id = $1 AND telegram_chat_id = $2",
+    )
+    .bind(id)
+    .bind(chat_id.0)
+    .fetch_optional(&*DB)
+    .await?;
+    Ok(row.map(|(code, days, created_at)| Card {
+        id,
+        code,
+        days,
+        created_at,
+    }))
+}
+
+/// Records that a card's code was sent to its owner again
+pub async fn log_resend(chat_id: ChatId, card: &Card) -> sqlx::Result<()> {
+    audit::log(
+        &Actor::Chat(chat_id),
+        "giftcard_resent",
+        Some(chat_id),
+        json!({ "giftcard_id": card.id, "days": card.days }),
+    )
+    .await
+}
+
+/// The `/mycards` list, with buttons re-sending the newest cards that aren't redacted
+pub fn render(cards: &[Card]) -> (String, InlineKeyboardMarkup) {
+    let mut lines = vec![i18n::text("giftcards.title", &[])];
+    lines.extend(cards.iter().map(Card::line));
+    if cards.iter().any(Card::is_redacted) {
+        let days = CONFIG.giftcard_resend_max_age_days;
+        lines.push(format!(
+            "\n{}",
+            i18n::text("giftcards.hidden", &[("days", &days)])
+        ));
+    }
+    if cards.iter().any(|card| !card.is_redacted()) {
+        lines.push(format!("\n{}", i18n::text("giftcards.resend_hint", &[])));
+    }
+    let buttons = cards
+        .iter()
+        .filter(|card| !card.is_redacted())
+        .take(BUTTON_LIMIT)
+        .map(|card| {
+            vec![render::command_button(
+                format!(
+                    "#{} · {} · {}d",
+                    card.id,
+                    render::format_date(card.created_at),
+                    card.days
+                ),
+                format!("/mycards {}", card.id),
+            )]
+        })
+        .collect::<Vec<_>>();
+    (lines.join("\n"), InlineKeyboardMarkup::new(buttons))
+}
@@ -0,0 +1,222 @@
+use teloxide::{
+    prelude::*,
+    types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup, ReplyParameters, UserId},
+};
+
+use crate::{
+    Command, DB, OFFLINE_AFTER_SECS,
+    bot_error::{BotError, Context},
+    cleanup,
+    community::{self, anonymous_label},
+    i18n, now_unix, parse_command, pinned, ratelimit, tokens,
+};
+
+/// Testers listed by `/groupstats`
+const TOP_MEMBERS: i64 = 5;
+
+/// Handles a message in a group other than the community one. Everyone may ask for the
+/// group's stats and the public aggregates; pinning a live copy of the stats is for the
+/// group's administrators; anything touching an account, above all claims and
+/// deregistration, is refused with a pointer to the private chat, so codes and VM ids
+/// never land in front of the whole group. Replies quote the command, which also keeps
+/// them in its forum topic.
+pub async fn handle(bot: &Bot, msg: &Message, text: &str) -> Result<(), BotError> {
+    let chat_id = msg.chat.id;
+    if let Some(user) = msg.from.as_ref().filter(|user| !user.is_bot) {
+        seen(chat_id, user.id)
+            .await
+            .context("recording a group member")?;
+    }
+    let Some(command) = parse_command(text) else {
+        return Ok(());
+    };
+    if !matches!(ratelimit::check(chat_id), ratelimit::Verdict::Allowed) {
+        return Ok(());
+    }
+
+    let (text, markup) = match command {
+        Command::GroupStats => (stats(chat_id).await, None),
+        Command::NetworkStats => (community::network_stats().await, None),
+        Command::Leaderboard => (community::leaderboard().await, None),
+        Command::Pin(enabled) => {
+            let sender = msg.from.as_ref().map(|user| user.id);
+            let admin = match sender {
+                Some(user) => bot.get_chat_member(chat_id, user).await?.is_privileged(),
+                None => false,
+            };
+            if !admin {
+                (Ok(i18n::text("groups.admins_only", &[])), None)
+            } else if enabled {
+                return pin(bot, msg).await;
+            } else {
+                return unpin(bot, msg).await;
+            }
+        }
+        _ => (
+            Ok(i18n::text("groups.private_only", &[])),
+            tokens::bot_link()
+                .and_then(|link| link.parse().ok())
+                .map(|url| {
+                    InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::url(
+                        i18n::text("groups.open_private_chat", &[]),
+                        url,
+                    )]])
+                }),
+        ),
+    };
+    let text = text.context("composing the group reply")?;
+    let mut reply = bot
+        .send_message(chat_id, text)
+        .reply_parameters(ReplyParameters::new(msg.id).allow_sending_without_reply());
+    if let Some(markup) = markup {
+        reply = reply.reply_markup(markup);
+    }
+    let reply = reply.await?;
+    for id in [msg.id, reply.id] {
+        cleanup::schedule(chat_id, id)
+            .await
+            .context("scheduling cleanup")?;
+    }
+    Ok(())
+}
+
+/// Posts the group's stats and pins them; [`pinned::refresh_loop`] keeps them current.
+/// The pinned message is exempt from `group_message_ttl_secs`.
+async fn pin(bot: &Bot, msg: &Message) -> Result<(), BotError> {
+    let chat_id = msg.chat.id;
+    let lookup = async {
+        Ok::<_, sqlx::Error>((
+            pinned::current(chat_id).await?,
+            pinned::text(chat_id).await?,
+        ))
+    };
+    let (old, text) = lookup.await.context("composing the group stats")?;
+    let status = bot
+        .send_message(chat_id, text)
+        .reply_parameters(ReplyParameters::new(msg.id).allow_sending_without_reply())
+        .await?;
+    pinned::remember(chat_id, status.id)
+        .await
+        .context("remembering the pinned stats")?;
+    if let Some(old) = old
+        && let Err(e) = bot.unpin_chat_message(chat_id).message_id(old).await
+    {
+        tracing::debug!("unpinning old stats {old} in {chat_id} failed: {e}");
+    }
+    if let Err(e) = bot
+        .pin_chat_message(chat_id, status.id)
+        .disable_notification(true)
+        .await
+    {
+        tracing::debug!("pinning stats in {chat_id} failed: {e}");
+        bot.send_message(chat_id, i18n::text("groups.pin_failed", &[]))
+            .reply_parameters(ReplyParameters::new(msg.id).allow_sending_without_reply())
+            .await?;
+    }
+    Ok(())
+}
+
+async fn unpin(bot: &Bot, msg: &Message) -> Result<(), BotError> {
+    let chat_id = msg.chat.id;
+    let unpinned = async {
+        let old = pinned::current(chat_id).await?;
+        if old.is_some() {
+            pinned::forget(chat_id).await?;
+        }
+        Ok::<_, sqlx::Error>(old)
+    };
+    let old = unpinned.await.context("unpinning the group stats")?;
+    if let Some(old) = old
+        && let Err(e) = bot.unpin_chat_message(chat_id).message_id(old).await
+    {
+        tracing::debug!("unpinning stats {old} in {chat_id} failed: {e}");
+    }
+    let text = match old {
+        Some(_) => i18n::text("groups.unpinned", &[]),
+        None => i18n::text("groups.nothing_pinned", &[]),
+    };
+    bot.send_message(chat_id, text)
+        .reply_parameters(ReplyParameters::new(msg.id).allow_sending_without_reply())
+        .await?;
+    Ok(())
+}
+
+/// Remembers that `user` is in `group`, so their VMs count towards its stats
+pub async fn seen(group: ChatId, user: UserId) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+INSERT INTO group_members (group_chat_id, user_id, last_seen_at) VALUES ($1, $2, $3)
+ON CONFLICT(group_chat_id, user_id) DO UPDATE SET last_seen_at = excluded.last_seen_at
+        "#,
+    )
+    .bind(group.0)
+    .bind(user.0 as i64)
+    .bind(now_unix())
+    .execute(&*DB)
+    .await?;
+    Ok(())
+}
+
+/// Stops counting `user` towards `group` once they leave it
+pub async fn left(group: ChatId, user: UserId) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM group_members WHERE group_chat_id = $1 AND user_id = $2")
+        .bind(group.0)
+        .bind(user.0 as i64)
+        .execute(&*DB)
+        .await?;
+    Ok(())
+}
+
+/// Totals over the private accounts of the group's members the bot has seen, with the
+/// top testers this month under their leaderboard names
+pub async fn stats(group: ChatId) -> sqlx::Result<String> {
+    let (testers, registered, online): (i64, i64, i64) = sqlx::query_as(
+        r#"
+SELECT COUNT(DISTINCT a.telegram_chat_id), COUNT(*), COUNT(CASE WHEN s.last_seen >= $2 THEN 1 END)
+FROM group_members m
+JOIN agent_records a ON a.telegram_chat_id = m.user_id
+LEFT JOIN vm_status s ON s.vm_id = a.vm_id
+WHERE m.group_chat_id = $1
+        "#,
+    )
+    .bind(group.0)
+    .bind(now_unix() - OFFLINE_AFTER_SECS)
+    .fetch_one(&*DB)
+    .await?;
+    let top: Vec<(i64, i64, Option<String>)> = sqlx::query_as(
+        r#"
+SELECT a.telegram_chat_id, CAST(SUM(h.up_secs) AS BIGINT) AS total, MAX(p.display_name)
+FROM group_members m
+JOIN agent_records a ON a.telegram_chat_id = m.user_id
+JOIN uptime_history h ON h.vm_id = a.vm_id
+LEFT JOIN user_prefs p ON p.telegram_chat_id = a.telegram_chat_id
+WHERE m.group_chat_id = $1 AND h.day >= $2
+GROUP BY a.telegram_chat_id
+ORDER BY total DESC
+LIMIT $3
+        "#,
+    )
+    .bind(group.0)
+    .bind(community::month_start())
+    .bind(TOP_MEMBERS)
+    .fetch_all(&*DB)
+    .await?;
+
+    let mut lines = vec![
+        i18n::text("groups.title", &[]),
+        i18n::text("groups.testers", &[("count", &testers)]),
+        i18n::text(
+            "groups.online",
+            &[("online", &online), ("registered", &registered)],
+        ),
+    ];
+    if !top.is_empty() {
+        lines.push(i18n::text("groups.top", &[]));
+        lines.extend(top.iter().enumerate().map(|(i, (chat, secs, name))| {
+            let label = name.clone().unwrap_or_else(|| anonymous_label(*chat));
+            format!("{}. {label} - {}h", i + 1, secs / 3600)
+        }));
+    }
+    lines.push(format!("\n{}", i18n::text("groups.counted", &[])));
+    Ok(lines.join("\n"))
+}
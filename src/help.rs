@@ -0,0 +1,425 @@
+use crate::i18n;
+
+/// What `/help <command>` says about one command. The texts are message keys, so they
+/// come in every language the bot speaks.
+struct Topic {
+    name: &'static str,
+    /// Other names the command answers to
+    aliases: &'static [&'static str],
+    summary: &'static str,
+    /// Example invocations, shown verbatim
+    examples: &'static [&'static str],
+    /// What has to be true for the command to work
+    requires: Option<&'static str>,
+    /// What to try when it doesn't
+    tips: Option<&'static str>,
+}
+
+const TOPICS: &[Topic] = &[
+    Topic {
+        name: "register",
+        aliases: &["reg"],
+        summary: "help.register.summary",
+        examples: &["/register <vm_id>", "/register reg-<token>"],
+        requires: Some("help.register.requires"),
+        tips: Some("help.register.tips"),
+    },
+    Topic {
+        name: "verify",
+        aliases: &[],
+        summary: "help.verify.summary",
+        examples: &["/verify"],
+        requires: Some("help.verify.requires"),
+        tips: Some("help.verify.tips"),
+    },
+    Topic {
+        name: "uptime",
+        aliases: &[],
+        summary: "help.uptime.summary",
+        examples: &["/uptime"],
+        requires: Some("help.uptime.requires"),
+        tips: None,
+    },
+    Topic {
+        name: "status",
+        aliases: &["online"],
+        summary: "help.status.summary",
+        examples: &["/status"],
+        requires: Some("help.status.requires"),
+        tips: Some("help.status.tips"),
+    },
+    Topic {
+        name: "myvms",
+        aliases: &[],
+        summary: "help.myvms.summary",
+        examples: &["/myvms", "/myvms 2"],
+        requires: Some("help.myvms.requires"),
+        tips: Some("help.myvms.tips"),
+    },
+    Topic {
+        name: "vm",
+        aliases: &[],
+        summary: "help.vm.summary",
+        examples: &["/vm <vm>", "/vm home server"],
+        requires: Some("help.vm.requires"),
+        tips: Some("help.vm.tips"),
+    },
+    Topic {
+        name: "pin",
+        aliases: &["unpin"],
+        summary: "help.pin.summary",
+        examples: &["/pin", "/pin off"],
+        requires: Some("help.pin.requires"),
+        tips: None,
+    },
+    Topic {
+        name: "chart",
+        aliases: &[],
+        summary: "help.chart.summary",
+        examples: &["/chart"],
+        requires: Some("help.chart.requires"),
+        tips: None,
+    },
+    Topic {
+        name: "unclaimed",
+        aliases: &["balance"],
+        summary: "help.unclaimed.summary",
+        examples: &["/unclaimed"],
+        requires: Some("help.unclaimed.requires"),
+        tips: None,
+    },
+    Topic {
+        name: "next",
+        aliases: &[],
+        summary: "help.next.summary",
+        examples: &["/next"],
+        requires: Some("help.next.requires"),
+        tips: None,
+    },
+    Topic {
+        name: "claim",
+        aliases: &[],
+        summary: "help.claim.summary",
+        examples: &["/claim", "/claim 7", "/claim 3x7"],
+        requires: Some("help.claim.requires"),
+        tips: Some("help.claim.tips"),
+    },
+    Topic {
+        name: "mycards",
+        aliases: &["history", "cards"],
+        summary: "help.mycards.summary",
+        examples: &["/mycards", "/mycards 12"],
+        requires: None,
+        tips: Some("help.mycards.tips"),
+    },
+    Topic {
+        name: "autoclaim",
+        aliases: &[],
+        summary: "help.autoclaim.summary",
+        examples: &[
+            "/autoclaim",
+            "/autoclaim sunday",
+            "/autoclaim 7",
+            "/autoclaim off",
+        ],
+        requires: Some("help.autoclaim.requires"),
+        tips: None,
+    },
+    Topic {
+        name: "replace",
+        aliases: &[],
+        summary: "help.replace.summary",
+        examples: &["/replace <old_vm> <new_vm>", "/replace <old_vm>"],
+        requires: Some("help.replace.requires"),
+        tips: None,
+    },
+    Topic {
+        name: "transfer",
+        aliases: &[],
+        summary: "help.transfer.summary",
+        examples: &[
+            "/transfer",
+            "/transfer <vm>",
+            "/transfer <vm> balance",
+            "/transfer xfr-<code>",
+        ],
+        requires: Some("help.transfer.requires"),
+        tips: Some("help.transfer.tips"),
+    },
+    Topic {
+        name: "rename",
+        aliases: &[],
+        summary: "help.rename.summary",
+        examples: &["/rename <vm> home server", "/rename <vm>"],
+        requires: None,
+        tips: Some("help.rename.tips"),
+    },
+    Topic {
+        name: "deregister",
+        aliases: &[],
+        summary: "help.deregister.summary",
+        examples: &["/deregister", "/deregister <vm>"],
+        requires: Some("help.deregister.requires"),
+        tips: Some("help.deregister.tips"),
+    },
+    Topic {
+        name: "deletemydata",
+        aliases: &[],
+        summary: "help.deletemydata.summary",
+        examples: &["/deletemydata"],
+        requires: None,
+        tips: None,
+    },
+    Topic {
+        name: "settings",
+        aliases: &[],
+        summary: "help.settings.summary",
+        examples: &["/settings", "/settings alerts off", "/settings direct on"],
+        requires: None,
+        tips: Some("help.settings.tips"),
+    },
+    Topic {
+        name: "digest",
+        aliases: &[],
+        summary: "help.digest.summary",
+        examples: &["/digest", "/digest on", "/digest off"],
+        requires: Some("help.digest.requires"),
+        tips: None,
+    },
+    Topic {
+        name: "link",
+        aliases: &["unlink"],
+        summary: "help.link.summary",
+        examples: &["/link <username>", "/link", "/unlink"],
+        requires: None,
+        tips: Some("help.link.tips"),
+    },
+    Topic {
+        name: "leaderboard",
+        aliases: &[],
+        summary: "help.leaderboard.summary",
+        examples: &[
+            "/leaderboard",
+            "/leaderboard name <name>",
+            "/leaderboard anonymous",
+        ],
+        requires: None,
+        tips: None,
+    },
+    Topic {
+        name: "referral",
+        aliases: &[],
+        summary: "help.referral.summary",
+        examples: &["/referral"],
+        requires: None,
+        tips: None,
+    },
+    Topic {
+        name: "networkstats",
+        aliases: &["groupstats", "stats"],
+        summary: "help.networkstats.summary",
+        examples: &["/networkstats", "/groupstats"],
+        requires: Some("help.networkstats.requires"),
+        tips: None,
+    },
+    Topic {
+        name: "how_rewards_work",
+        aliases: &["rewards"],
+        summary: "help.how_rewards_work.summary",
+        examples: &["/how_rewards_work"],
+        requires: None,
+        tips: None,
+    },
+    Topic {
+        name: "help",
+        aliases: &[],
+        summary: "help.help.summary",
+        examples: &["/help", "/help claim", "/help errors"],
+        requires: None,
+        tips: None,
+    },
+    Topic {
+        name: "cancel",
+        aliases: &[],
+        summary: "help.cancel.summary",
+        examples: &["/cancel"],
+        requires: None,
+        tips: Some("help.cancel.tips"),
+    },
+    Topic {
+        name: "appeal",
+        aliases: &[],
+        summary: "help.appeal.summary",
+        examples: &["/appeal <explanation>"],
+        requires: Some("help.appeal.requires"),
+        tips: Some("help.appeal.tips"),
+    },
+    Topic {
+        name: "menu",
+        aliases: &[],
+        summary: "help.menu.summary",
+        examples: &["/menu"],
+        requires: None,
+        tips: None,
+    },
+];
+
+/// Text for `/help`: one line per command
+pub fn overview() -> String {
+    let lines: Vec<String> = TOPICS
+        .iter()
+        .map(|topic| format!("/{} - {}", topic.name, i18n::text(topic.summary, &[])))
+        .collect();
+    format!(
+        "{}\n{}\n\n{}",
+        i18n::text("help.commands", &[]),
+        lines.join("\n"),
+        i18n::text("help.footer", &[])
+    )
+}
+
+/// Text for `/help <command>`, or `None` if there is no such command. The leading `/` is
+/// optional.
+pub fn topic(name: &str) -> Option<String> {
+    let name = name.trim_start_matches('/').to_lowercase();
+    let topic = TOPICS
+        .iter()
+        .find(|topic| topic.name == name || topic.aliases.contains(&name.as_str()))?;
+    let mut text = format!("/{} - {}", topic.name, i18n::text(topic.summary, &[]));
+    if !topic.aliases.is_empty() {
+        let aliases: Vec<String> = topic
+            .aliases
+            .iter()
+            .map(|alias| format!("/{alias}"))
+            .collect();
+        let label = i18n::text("help.aliases", &[]);
+        text.push_str(&format!("\n{label}{}", aliases.join(", ")));
+    }
+    text.push_str(&format!("\n\n{}", i18n::text("help.examples", &[])));
+    for example in topic.examples {
+        text.push_str(&format!("\n{example}"));
+    }
+    if let Some(requires) = topic.requires {
+        let label = i18n::text("help.requires", &[]);
+        text.push_str(&format!("\n\n{label}{}", i18n::text(requires, &[])));
+    }
+    if let Some(tips) = topic.tips {
+        let label = i18n::text("help.tips", &[]);
+        text.push_str(&format!("\n\n{label}{}", i18n::text(tips, &[])));
+    }
+    Some(text)
+}
+
+/// Largest edit distance at which an unknown command is taken for a typo of a known one
+const MAX_TYPO_DISTANCE: usize = 2;
+
+/// The known command that `command` (e.g. `/unclamed`) most likely misspells, if any. A
+/// command that is known already gets no suggestion, nor does one too short to judge.
+pub fn suggest(command: &str) -> Option<&'static str> {
+    let command = command.trim_start_matches('/').to_lowercase();
+    let candidates = TOPICS.iter().flat_map(|topic| {
+        std::iter::once(topic.name)
+            .chain(topic.aliases.iter().copied())
+            .map(move |candidate| (candidate, topic.name))
+    });
+    let mut best = None;
+    for (candidate, name) in candidates {
+        let distance = edit_distance(&command, candidate);
+        if distance == 0 {
+            return None;
+        }
+        if best.is_none_or(|(shortest, _)| distance < shortest) {
+            best = Some((distance, name));
+        }
+    }
+    // Allowing two edits on a three-letter word would match nearly anything
+    let allowed = MAX_TYPO_DISTANCE.min(command.chars().count() / 2);
+    best.filter(|&(distance, _)| distance <= allowed)
+        .map(|(_, name)| name)
+}
+
+/// Edits (insertions, deletions, substitutions and swaps of neighbours) turning `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    rows[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            rows[i][j] = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                rows[i][j] = rows[i][j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_distance_counts_each_kind_of_edit() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("claim", ""), 5);
+        assert_eq!(edit_distance("", "claim"), 5);
+        assert_eq!(edit_distance("claim", "claim"), 0);
+        assert_eq!(edit_distance("clam", "claim"), 1);
+        assert_eq!(edit_distance("claims", "claim"), 1);
+        assert_eq!(edit_distance("clain", "claim"), 1);
+        // A swap of neighbours is one edit, not two
+        assert_eq!(edit_distance("cliam", "claim"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        // Characters, not bytes
+        assert_eq!(edit_distance("领取", "领"), 1);
+    }
+
+    #[test]
+    fn suggest_corrects_typos_of_commands_and_aliases() {
+        assert_eq!(suggest("/unclamed"), Some("unclaimed"));
+        assert_eq!(suggest("/UNCLAMED"), Some("unclaimed"));
+        assert_eq!(suggest("unclamed"), Some("unclaimed"));
+        assert_eq!(suggest("/regsiter"), Some("register"));
+        assert_eq!(suggest("/clai"), Some("claim"));
+        // An alias's typo points to the command it stands for
+        assert_eq!(suggest("/balanse"), Some("unclaimed"));
+        assert_eq!(suggest("/leaderbaord"), Some("leaderboard"));
+    }
+
+    #[test]
+    fn suggest_leaves_known_short_and_distant_words_alone() {
+        for command in [
+            "/claim",
+            "/CLAIM",
+            "/reg",
+            "/balance",
+            "",
+            "/",
+            "/x",
+            "/xy",
+            "/abc",
+            "/zzzzzzzz",
+            "/registration",
+            "/领取",
+        ] {
+            assert_eq!(suggest(command), None, "{command:?}");
+        }
+    }
+
+    #[test]
+    fn topics_are_found_by_name_or_alias() {
+        for entry in TOPICS {
+            assert!(entry.summary.starts_with("help."), "{}", entry.name);
+            assert!(!entry.examples.is_empty(), "{}", entry.name);
+            assert!(topic(entry.name).is_some());
+        }
+        assert!(topic("/REG").is_some_and(|text| text.starts_with("/register")));
+        assert!(topic("nosuch").is_none());
+    }
+}
@@ -0,0 +1,642 @@
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::{
+    Method, Request, Response, StatusCode,
+    body::{Bytes, Incoming},
+    header,
+    http::request::Parts,
+    server::conn::http1,
+    service::service_fn,
+};
+use serde_json::json;
+use smol::future::FutureExt;
+use smol_hyper::rt::FuturesIo;
+use teloxide::{Bot, requests::Requester};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    CONFIG, DB,
+    apikeys::{self, Scope},
+    audit::{self, Actor},
+    begin_write, community, events, now_unix, outbox, ownership,
+    policy::RewardPolicy,
+    record_sighting, signing,
+    supervisor::{TaskState, task_statuses},
+    tokens, vm_api,
+};
+
+/// Response to send instead of serving an unauthorized request
+type Denied = Box<Response<Full<Bytes>>>;
+
+/// Lifetime of registration tokens requested by VM agents
+const AGENT_TOKEN_TTL_SECS: i64 = 86400;
+/// No endpoint takes more than a handful of JSON fields; anything bigger is refused
+const MAX_BODY: usize = 16 * 1024;
+/// How long `/readyz` reuses a Telegram check, so frequent probes don't each call the API
+const TELEGRAM_CHECK_TTL: Duration = Duration::from_secs(30);
+/// Longest a readiness check waits on the database or Telegram
+const READY_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// When Telegram was last checked, and the error if it was unreachable
+static TELEGRAM_CHECK: Mutex<Option<(Instant, Result<(), String>)>> = Mutex::new(None);
+
+/// Serves the embedded HTTP API on `addr` until shutdown
+pub async fn serve(addr: SocketAddr, bot: Bot, shutdown: CancellationToken) -> anyhow::Result<()> {
+    let listener = smol::net::TcpListener::bind(addr).await?;
+    tracing::info!("HTTP API listening on {addr}");
+    loop {
+        let accepted = async { Some(listener.accept().await) }
+            .or(async {
+                shutdown.cancelled().await;
+                None
+            })
+            .await;
+        let Some(accepted) = accepted else {
+            return Ok(());
+        };
+        let (stream, peer) = accepted?;
+        let bot = bot.clone();
+        smolscale::spawn(async move {
+            let service = service_fn(|req| route(req, &bot));
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(FuturesIo::new(stream), service)
+                .await
+            {
+                tracing::debug!("HTTP connection from {peer} failed: {e}");
+            }
+        })
+        .detach();
+    }
+}
+
+async fn route(req: Request<Incoming>, bot: &Bot) -> Result<Response<Full<Bytes>>, Infallible> {
+    let (req, body) = req.into_parts();
+    // Signatures cover the body, so it is read up front for every request
+    let body = match Limited::new(body, MAX_BODY).collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(e) => {
+            return Ok(json_response(
+                StatusCode::BAD_REQUEST,
+                json!({ "error": format!("unreadable body: {e}") }),
+            ));
+        }
+    };
+    Ok(dispatch(&req, &body, bot).await)
+}
+
+/// Answers a request whose body has been read
+async fn dispatch(req: &Parts, body: &[u8], bot: &Bot) -> Response<Full<Bytes>> {
+    let path = req.uri.path();
+    match (&req.method, path) {
+        (&Method::GET, "/healthz") => healthz(),
+        (&Method::GET, "/readyz") => readyz(bot).await,
+        // Open to any scraper, but only a read key sees usage broken down by key name
+        (&Method::GET, "/metrics") => match bearer_token(req) {
+            None => metrics(false).await,
+            Some(_) => match key_authorized(req, Scope::Read).await {
+                Ok(()) => metrics(true).await,
+                Err(denied) => *denied,
+            },
+        },
+        (&Method::GET, _) if let Some(vm_id) = vm_path(path, "/self") => {
+            // VM agents keep using the shared secret; other services need a key
+            let authorized = if agent_authorized(req) {
+                Ok(())
+            } else {
+                key_authorized(req, Scope::Read).await
+            };
+            match authorized {
+                Ok(()) => vm_self(vm_id).await.unwrap_or_else(|e| {
+                    tracing::error!("vm self lookup for {vm_id} failed: {e:?}");
+                    internal_error()
+                }),
+                Err(denied) => *denied,
+            }
+        }
+        (&Method::POST, _) if let Some(vm_id) = vm_path(path, "/heartbeat") => {
+            let vm_id = vm_id.to_owned();
+            if !CONFIG.uptime_source.accepts_heartbeats() {
+                json_response(
+                    StatusCode::NOT_FOUND,
+                    json!({ "error": "heartbeats are disabled" }),
+                )
+            } else {
+                match agent_vm_authorized(req, body, &vm_id).await {
+                    Ok(fleet) => heartbeat(vm_id, body, fleet.as_ref()).await,
+                    Err(denied) => *denied,
+                }
+            }
+        }
+        (&Method::POST, _) if let Some(vm_id) = vm_path(path, "/registration_token") => {
+            match agent_vm_authorized(req, body, vm_id).await {
+                Ok(_) => registration_token(vm_id).await.unwrap_or_else(|e| {
+                    tracing::error!("registration token for {vm_id} failed: {e:?}");
+                    internal_error()
+                }),
+                Err(denied) => *denied,
+            }
+        }
+        (&Method::GET, "/api/network") => match key_authorized(req, Scope::Read).await {
+            Ok(()) => match community::network_totals().await {
+                Ok(totals) => json_response(StatusCode::OK, json!(totals)),
+                Err(e) => {
+                    tracing::error!("network totals failed: {e:?}");
+                    internal_error()
+                }
+            },
+            Err(denied) => *denied,
+        },
+        (&Method::GET, "/api/keys") => match key_authorized(req, Scope::Admin)
+            .await
+            .and_then(|()| control_authorized(req, body))
+        {
+            Ok(()) => match apikeys::usage().await {
+                Ok(keys) => json_response(StatusCode::OK, json!({ "keys": keys })),
+                Err(e) => {
+                    tracing::error!("api key usage failed: {e:?}");
+                    internal_error()
+                }
+            },
+            Err(denied) => *denied,
+        },
+        _ => not_found(),
+    }
+}
+
+/// The VM id in `/api/vm/<id><suffix>`, if `path` is that and the id is a valid one
+fn vm_path<'a>(path: &'a str, suffix: &str) -> Option<&'a str> {
+    path.strip_prefix("/api/vm/")
+        .and_then(|rest| rest.strip_suffix(suffix))
+        .filter(|vm_id| vm_api::valid_vm_id(vm_id))
+}
+
+fn bearer_token(req: &Parts) -> Option<&str> {
+    req.headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+/// Checks the `Authorization: Bearer <agent_api_secret>` header sent by VM agents
+fn agent_authorized(req: &Parts) -> bool {
+    let Some(secret) = CONFIG.agent_api_secret.as_deref() else {
+        return false;
+    };
+    bearer_token(req).is_some_and(|token| constant_time_eq(token.as_bytes(), secret.as_bytes()))
+}
+
+/// Checks for a live API key with at least `scope`, and charges the request to its rate
+/// limit. On failure returns the response to send instead.
+async fn key_authorized(req: &Parts, scope: Scope) -> Result<(), Denied> {
+    let Some(token) = bearer_token(req) else {
+        return Err(Box::new(unauthorized()));
+    };
+    let key = match apikeys::authenticate(token).await {
+        Ok(Some(key)) => key,
+        Ok(None) => return Err(Box::new(unauthorized())),
+        Err(e) => {
+            tracing::error!("api key lookup failed: {e:?}");
+            return Err(Box::new(internal_error()));
+        }
+    };
+    if key.scope < scope {
+        return Err(Box::new(json_response(
+            StatusCode::FORBIDDEN,
+            json!({ "error": format!("key lacks {} scope", scope.as_str()) }),
+        )));
+    }
+    match apikeys::admit(&key).await {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            tracing::debug!(
+                "api key {} hit its limit of {}/min",
+                key.name,
+                key.rate_per_min
+            );
+            let mut response = json_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                json!({ "error": "rate limited" }),
+            );
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, header::HeaderValue::from_static("60"));
+            Err(Box::new(response))
+        }
+        Err(e) => {
+            tracing::error!("api key accounting failed: {e:?}");
+            Err(Box::new(internal_error()))
+        }
+    }
+}
+
+/// Checks the request's fleet HMAC signature, returning the fleet that signed it
+fn signature_authorized(req: &Parts, body: &[u8]) -> Result<String, Denied> {
+    match signing::verify(&req.headers, &req.method, req.uri.path(), body) {
+        Ok(fleet) => {
+            tracing::debug!("{} {} signed by fleet {fleet}", req.method, req.uri.path());
+            Ok(fleet)
+        }
+        Err(reason) => {
+            tracing::info!(
+                "rejected signature on {} {}: {reason}",
+                req.method,
+                req.uri.path()
+            );
+            Err(Box::new(json_response(
+                StatusCode::UNAUTHORIZED,
+                json!({ "error": format!("invalid signature: {reason}") }),
+            )))
+        }
+    }
+}
+
+/// Agent calls that change state, returning the fleet that signed them. With fleet
+/// secrets configured a signature is required and sufficient; otherwise agents
+/// authenticate with the shared bearer secret, and speak for no fleet.
+fn agent_control_authorized(req: &Parts, body: &[u8]) -> Result<Option<String>, Denied> {
+    if signing::required() {
+        signature_authorized(req, body).map(Some)
+    } else if agent_authorized(req) {
+        Ok(None)
+    } else {
+        Err(Box::new(unauthorized()))
+    }
+}
+
+/// [`agent_control_authorized`] for a call about `vm_id`, which a fleet may only make for
+/// its own VMs and ones not seen yet. Returns the fleet that signed.
+async fn agent_vm_authorized(
+    req: &Parts,
+    body: &[u8],
+    vm_id: &str,
+) -> Result<Option<vm_api::Fleet>, Denied> {
+    let Some(name) = agent_control_authorized(req, body)? else {
+        return Ok(None);
+    };
+    let known: Option<String> =
+        match sqlx::query_scalar("SELECT fleet FROM agent_records WHERE vm_id = $1")
+            .bind(vm_id)
+            .fetch_optional(&*DB)
+            .await
+        {
+            Ok(known) => known,
+            Err(e) => {
+                tracing::error!("fleet lookup for {vm_id} failed: {e:?}");
+                return Err(Box::new(internal_error()));
+            }
+        };
+    if known.is_some_and(|known| known != name) {
+        tracing::info!("fleet {name} tried to speak for {vm_id} of another fleet");
+        return Err(Box::new(json_response(
+            StatusCode::FORBIDDEN,
+            json!({ "error": "VM belongs to another fleet" }),
+        )));
+    }
+    // Config validation makes every signing fleet a configured one
+    Ok(CONFIG.fleet(&name))
+}
+
+/// Control endpoints additionally need a fleet signature once fleet secrets are configured
+fn control_authorized(req: &Parts, body: &[u8]) -> Result<(), Denied> {
+    if signing::required() {
+        signature_authorized(req, body).map(drop)
+    } else {
+        Ok(())
+    }
+}
+
+fn unauthorized() -> Response<Full<Bytes>> {
+    json_response(StatusCode::UNAUTHORIZED, json!({ "error": "unauthorized" }))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// What a VM agent may learn about itself: whether a tester has linked it and what it has
+/// accrued, but never who the owner is
+async fn vm_self(vm_id: &str) -> anyhow::Result<Response<Full<Bytes>>> {
+    let record: Option<(Option<i64>, i64, i64, i64)> = sqlx::query_as(
+        "SELECT telegram_chat_id, up_secs, bonus_secs, paid_secs FROM agent_records WHERE vm_id = $1",
+    )
+    .bind(vm_id)
+    .fetch_optional(&*DB)
+    .await?;
+    let Some((owner, up_secs, bonus_secs, paid_secs)) = record else {
+        return Ok(not_found());
+    };
+    Ok(json_response(
+        StatusCode::OK,
+        json!({
+            "vm_id": vm_id,
+            "registered": owner.is_some(),
+            "up_secs": up_secs,
+            "paid_secs": paid_secs,
+            "bonus_secs": bonus_secs,
+            "unclaimed_days": RewardPolicy::current().days(up_secs + bonus_secs - paid_secs),
+        }),
+    ))
+}
+
+/// Credits a heartbeat from a VM agent. The body is optional and may carry the same
+/// metadata fields as the availability API (`region`, `version`, ...). A heartbeat signed
+/// by `fleet` is credited at that fleet's multiplier.
+async fn heartbeat(
+    vm_id: String,
+    body: &[u8],
+    fleet: Option<&vm_api::Fleet>,
+) -> Response<Full<Bytes>> {
+    let metadata = if body.is_empty() {
+        serde_json::Value::Null
+    } else {
+        match serde_json::from_slice(body) {
+            Ok(value) => value,
+            Err(e) => {
+                return json_response(
+                    StatusCode::BAD_REQUEST,
+                    json!({ "error": format!("invalid JSON: {e}") }),
+                );
+            }
+        }
+    };
+    let now = now_unix();
+    let Some(vm) = vm_api::entry(vm_id, metadata, now) else {
+        return json_response(StatusCode::BAD_REQUEST, json!({ "error": "invalid VM id" }));
+    };
+    let credit = async {
+        let event = events::active_at(now).await?;
+        let (_write, mut tx) = begin_write().await?;
+        let credit = record_sighting(&mut tx, &vm, now, event.as_ref(), fleet).await?;
+        tx.commit().await?;
+        anyhow::Ok(credit)
+    };
+    match credit.await {
+        Ok(credit) => json_response(
+            StatusCode::OK,
+            json!({ "vm_id": vm.vm_id, "credited_secs": credit }),
+        ),
+        Err(e) => {
+            tracing::error!("heartbeat from {} failed: {e:?}", vm.vm_id);
+            internal_error()
+        }
+    }
+}
+
+/// Issues a one-time token the agent can show its operator as a `t.me` deep link, so
+/// registering takes one tap instead of copying the VM id
+async fn registration_token(vm_id: &str) -> anyhow::Result<Response<Full<Bytes>>> {
+    if !ownership::is_unlinked(vm_id).await? {
+        return Ok(json_response(
+            StatusCode::CONFLICT,
+            json!({ "error": "VM is unknown or already registered" }),
+        ));
+    }
+    let token = tokens::issue(vm_id, AGENT_TOKEN_TTL_SECS).await?;
+    audit::log(
+        &Actor::Agent(vm_id.to_owned()),
+        "registration_token_issued",
+        None,
+        json!({ "vm_id": vm_id, "ttl_secs": AGENT_TOKEN_TTL_SECS }),
+    )
+    .await?;
+    Ok(json_response(
+        StatusCode::OK,
+        json!({
+            "vm_id": vm_id,
+            "link": tokens::deep_link(&token),
+            "token": token,
+            "expires_at": now_unix() + AGENT_TOKEN_TTL_SECS,
+        }),
+    ))
+}
+
+fn not_found() -> Response<Full<Bytes>> {
+    json_response(StatusCode::NOT_FOUND, json!({ "error": "not found" }))
+}
+
+fn internal_error() -> Response<Full<Bytes>> {
+    json_response(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        json!({ "error": "internal error" }),
+    )
+}
+
+/// Prometheus text exposition of task restarts, API usage and the outgoing queue. Usage
+/// is labelled `by_key` name, or else only summed per scope.
+async fn metrics(by_key: bool) -> Response<Full<Bytes>> {
+    let mut out = String::new();
+    out.push_str("# TYPE task_restarts_total counter\n");
+    for (name, status) in task_statuses() {
+        out.push_str(&format!(
+            "task_restarts_total{{task=\"{name}\"}} {}\n",
+            status.restarts
+        ));
+    }
+    match apikeys::usage().await {
+        Ok(keys) => {
+            // `(labels, requests, rate limited)` per series
+            let mut series: BTreeMap<String, (i64, i64)> = BTreeMap::new();
+            for key in &keys {
+                let labels = if by_key {
+                    format!("key=\"{}\",scope=\"{}\"", key.name, key.scope.as_str())
+                } else {
+                    format!("scope=\"{}\"", key.scope.as_str())
+                };
+                let counts = series.entry(labels).or_default();
+                counts.0 += key.request_count;
+                counts.1 += key.rejected_count;
+            }
+            out.push_str("# TYPE api_requests_total counter\n");
+            for (labels, (requests, _)) in &series {
+                out.push_str(&format!("api_requests_total{{{labels}}} {requests}\n"));
+            }
+            out.push_str("# TYPE api_rate_limited_total counter\n");
+            for (labels, (_, rejected)) in &series {
+                out.push_str(&format!("api_rate_limited_total{{{labels}}} {rejected}\n"));
+            }
+        }
+        Err(e) => tracing::error!("api key usage failed: {e:?}"),
+    }
+    let outbox = outbox::stats();
+    out.push_str("# TYPE telegram_outbox_queued gauge\n");
+    out.push_str(&format!("telegram_outbox_queued {}\n", outbox.queued));
+    out.push_str("# TYPE telegram_outbox_sent_total counter\n");
+    out.push_str(&format!("telegram_outbox_sent_total {}\n", outbox.sent));
+    out.push_str("# TYPE telegram_outbox_retried_total counter\n");
+    out.push_str(&format!(
+        "telegram_outbox_retried_total {}\n",
+        outbox.retried
+    ));
+    out.push_str("# TYPE telegram_outbox_dropped_total counter\n");
+    out.push_str(&format!(
+        "telegram_outbox_dropped_total {}\n",
+        outbox.dropped
+    ));
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(Full::new(Bytes::from(out)))
+        .expect("static response parts are valid")
+}
+
+/// Liveness: reports every supervised task, and is healthy only while all of them are
+/// running and none has stalled. A process supervisor should restart the bot when it fails.
+fn healthz() -> Response<Full<Bytes>> {
+    let now = now_unix();
+    let tasks = task_statuses();
+    let stalled: Vec<_> = tasks
+        .iter()
+        .filter(|(_, task)| task.stalled(now))
+        .map(|(name, _)| *name)
+        .collect();
+    let healthy = stalled.is_empty() && tasks.values().all(|t| t.state == TaskState::Running);
+    let status = if healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    json_response(
+        status,
+        json!({ "healthy": healthy, "stalled": stalled, "tasks": tasks }),
+    )
+}
+
+/// Readiness: healthy, and both the database and the Telegram API answer
+async fn readyz(bot: &Bot) -> Response<Full<Bytes>> {
+    let healthy = healthz().status() == StatusCode::OK;
+    let database = check_database().await;
+    let telegram = check_telegram(bot).await;
+    let ready = healthy && database.is_ok() && telegram.is_ok();
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    json_response(
+        status,
+        json!({
+            "ready": ready,
+            "healthy": healthy,
+            "database": database.err().unwrap_or_else(|| "ok".to_owned()),
+            "telegram": telegram.err().unwrap_or_else(|| "ok".to_owned()),
+        }),
+    )
+}
+
+async fn check_database() -> Result<(), String> {
+    let query = async {
+        sqlx::query_scalar::<_, i64>("SELECT 1")
+            .fetch_one(&*DB)
+            .await
+            .map(drop)
+            .map_err(|e| e.to_string())
+    };
+    query
+        .or(async {
+            smol::Timer::after(READY_CHECK_TIMEOUT).await;
+            Err("timed out".to_owned())
+        })
+        .await
+}
+
+async fn check_telegram(bot: &Bot) -> Result<(), String> {
+    if let Some((at, result)) = &*TELEGRAM_CHECK.lock().unwrap()
+        && at.elapsed() < TELEGRAM_CHECK_TTL
+    {
+        return result.clone();
+    }
+    let result = async { bot.get_me().await.map(drop).map_err(|e| e.to_string()) }
+        .or(async {
+            smol::Timer::after(READY_CHECK_TIMEOUT).await;
+            Err("timed out".to_owned())
+        })
+        .await;
+    *TELEGRAM_CHECK.lock().unwrap() = Some((Instant::now(), result.clone()));
+    result
+}
+
+fn json_response(status: StatusCode, body: serde_json::Value) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body.to_string())))
+        .expect("static response parts are valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers `method path` with no credentials and an empty body
+    async fn call(method: Method, path: &str) -> StatusCode {
+        let (req, ()) = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(())
+            .unwrap()
+            .into_parts();
+        dispatch(&req, &[], &Bot::new("0:test")).await.status()
+    }
+
+    #[test]
+    fn vm_paths_need_a_valid_id() {
+        assert_eq!(vm_path("/api/vm/vm-1/self", "/self"), Some("vm-1"));
+        assert_eq!(vm_path("/api/vm/self", "/self"), None);
+        assert_eq!(vm_path("/api/vm//self", "/self"), None);
+        assert_eq!(vm_path("/api/vm/a/b/self", "/self"), None);
+        assert_eq!(vm_path("/api/other/vm-1/self", "/self"), None);
+    }
+
+    #[test]
+    fn vm_self_without_an_id_is_not_found() {
+        smol::block_on(async {
+            assert_eq!(
+                call(Method::GET, "/api/vm/self").await,
+                StatusCode::NOT_FOUND
+            );
+            assert_eq!(
+                call(Method::GET, "/api/vm//self").await,
+                StatusCode::NOT_FOUND
+            );
+            assert_eq!(
+                call(Method::GET, "/api/vm/vm-1/self").await,
+                StatusCode::UNAUTHORIZED
+            );
+        });
+    }
+
+    #[test]
+    fn registration_token_without_an_id_is_not_found() {
+        smol::block_on(async {
+            assert_eq!(
+                call(Method::POST, "/api/vm/registration_token").await,
+                StatusCode::NOT_FOUND
+            );
+            assert_eq!(
+                call(Method::POST, "/api/vm/vm-1/registration_token").await,
+                StatusCode::UNAUTHORIZED
+            );
+        });
+    }
+
+    #[test]
+    fn heartbeat_without_an_id_is_not_found() {
+        smol::block_on(async {
+            assert_eq!(
+                call(Method::POST, "/api/vm/heartbeat").await,
+                StatusCode::NOT_FOUND
+            );
+            assert_eq!(
+                call(Method::POST, "/api/vm/a/b/heartbeat").await,
+                StatusCode::NOT_FOUND
+            );
+        });
+    }
+}
@@ -0,0 +1,189 @@
+use std::{collections::HashMap, fmt::Display, fs::File};
+
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+
+use crate::CONFIG;
+
+/// The catalog shipped with the bot; `messages_path` entries are layered on top
+const DEFAULT_CATALOG: &str = include_str!("../messages.yaml");
+/// Language used when a message lacks the one asked for
+const FALLBACK_LANGUAGE: &str = "en";
+
+/// One message in one language
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Text {
+    Simple(String),
+    /// Forms by plural category (`one`, `few`, `other`, ...), chosen by the `count` argument
+    Plural(HashMap<String, String>),
+}
+
+/// A placeholder's value. `Sync` so handlers can hold arguments across an `.await`.
+pub type Arg<'a> = dyn Display + Sync + 'a;
+
+/// Message key → language → text
+type Catalog = HashMap<String, HashMap<String, Text>>;
+
+static CATALOG: Lazy<Catalog> = Lazy::new(|| {
+    let mut catalog: Catalog =
+        serde_yaml::from_str(DEFAULT_CATALOG).expect("parse built-in message catalog");
+    if let Some(path) = &CONFIG.messages_path {
+        let overrides: Catalog =
+            serde_yaml::from_reader(File::open(path).expect("read messages_path"))
+                .expect("parse messages_path YAML");
+        for (key, languages) in overrides {
+            catalog.entry(key).or_default().extend(languages);
+        }
+    }
+    catalog
+});
+
+/// Loads the catalog and checks every message is there in every language the bot shows,
+/// so a gap fails at startup rather than in front of a tester
+pub fn check() {
+    for (key, languages) in CATALOG.iter() {
+        for language in &CONFIG.message_languages {
+            assert!(
+                languages.contains_key(language),
+                "message {key:?} has no {language:?} text"
+            );
+        }
+    }
+}
+
+/// The plural category of `n` in `language`, as the CLDR names them
+fn plural_category(language: &str, n: i64) -> &'static str {
+    match language {
+        // No grammatical plural
+        "zh" | "ja" | "ko" | "vi" | "th" | "id" => "other",
+        "fr" | "pt" if n == 0 || n == 1 => "one",
+        "ru" | "uk" => match (n % 10, n % 100) {
+            (1, h) if h != 11 => "one",
+            (2..=4, h) if !(12..=14).contains(&h) => "few",
+            _ => "many",
+        },
+        _ if n == 1 => "one",
+        _ => "other",
+    }
+}
+
+/// `key` in `language` with `args` filled in, falling back to English and, failing that,
+/// to the key itself
+pub fn text_in(language: &str, key: &str, args: &[(&str, &Arg)]) -> String {
+    let Some(languages) = CATALOG.get(key) else {
+        tracing::warn!("message {key:?} missing from the catalog");
+        return key.to_owned();
+    };
+    let Some((language, text)) = languages
+        .get_key_value(language)
+        .or_else(|| languages.get_key_value(FALLBACK_LANGUAGE))
+    else {
+        return key.to_owned();
+    };
+    let template = match text {
+        Text::Simple(template) => template,
+        Text::Plural(forms) => {
+            let count = args
+                .iter()
+                .find(|(name, _)| *name == "count")
+                .and_then(|(_, value)| value.to_string().parse().ok())
+                .unwrap_or(0);
+            match forms
+                .get(plural_category(language, count))
+                .or_else(|| forms.get("other"))
+            {
+                Some(template) => template,
+                None => return key.to_owned(),
+            }
+        }
+    };
+    args.iter().fold(template.clone(), |text, (name, value)| {
+        text.replace(&format!("{{{name}}}"), &value.to_string())
+    })
+}
+
+/// `key` in every language the bot shows, side by side: `English / 中文`
+pub fn text(key: &str, args: &[(&str, &Arg)]) -> String {
+    CONFIG
+        .message_languages
+        .iter()
+        .map(|language| text_in(language, key, args))
+        .collect::<Vec<_>>()
+        .join(" / ")
+}
+
+/// [`text`] of `key` with its `{name}` placeholder filled in by message `inner`, each
+/// language getting `inner` in that language
+pub fn text_nested(key: &str, name: &str, inner: &str, inner_args: &[(&str, &Arg)]) -> String {
+    CONFIG
+        .message_languages
+        .iter()
+        .map(|language| {
+            let inner = text_in(language, inner, inner_args);
+            text_in(language, key, &[(name, &inner)])
+        })
+        .collect::<Vec<_>>()
+        .join(" / ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plurals_and_placeholders_are_filled_in() {
+        assert_eq!(
+            text_in("en", "unclaimed_days", &[("count", &1)]),
+            "You have 1 unclaimed Plus day"
+        );
+        assert_eq!(
+            text_in("en", "unclaimed_days", &[("count", &2)]),
+            "You have 2 unclaimed Plus days"
+        );
+        assert_eq!(
+            text_in("zh", "unclaimed_days", &[("count", &1)]),
+            "未领取的 Plus 天数：1"
+        );
+        assert_eq!(text_in("fr", "menu", &[]), text_in("en", "menu", &[]));
+        assert_eq!(text_in("en", "no.such.message", &[]), "no.such.message");
+        assert_eq!(plural_category("ru", 22), "few");
+        assert_eq!(plural_category("ru", 12), "many");
+    }
+
+    /// Every key the code names as a literal, under a namespace the catalog uses, exists
+    #[test]
+    fn every_message_the_code_uses_is_in_the_catalog() {
+        let namespaces: std::collections::HashSet<&str> = CATALOG
+            .keys()
+            .filter_map(|key| key.split_once('.').map(|(namespace, _)| namespace))
+            .collect();
+        let mut missing = vec![];
+        for entry in std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/src")).unwrap() {
+            let path = entry.unwrap().path();
+            let source = std::fs::read_to_string(&path).unwrap();
+            for literal in source.split('"').skip(1).step_by(2) {
+                let is_key = literal.split_once('.').is_some_and(|(namespace, rest)| {
+                    namespaces.contains(namespace)
+                        && !rest.is_empty()
+                        && literal
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.'))
+                });
+                if is_key && !CATALOG.contains_key(literal) {
+                    missing.push(format!("{}: {literal}", path.display()));
+                }
+            }
+        }
+        assert!(missing.is_empty(), "not in messages.yaml: {missing:#?}");
+    }
+
+    #[test]
+    fn every_message_is_in_english_and_chinese() {
+        for (key, languages) in CATALOG.iter() {
+            for language in ["en", "zh"] {
+                assert!(languages.contains_key(language), "{key} has no {language}");
+            }
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use teloxide::{ApiError, RequestError, types::ChatId};
+
+use crate::{DB, begin_write, now_unix};
+
+/// Why Telegram will never deliver to the chat again, if `error` says so
+pub fn dead_chat_reason(error: &RequestError) -> Option<&'static str> {
+    match error {
+        RequestError::Api(ApiError::BotBlocked) => Some("blocked"),
+        RequestError::Api(ApiError::UserDeactivated) => Some("deactivated"),
+        RequestError::Api(ApiError::BotKicked) => Some("kicked"),
+        _ => None,
+    }
+}
+
+/// Stops background loops from messaging `chat_id`
+pub async fn mark(chat_id: ChatId, reason: &str) -> sqlx::Result<()> {
+    let (_write, mut tx) = begin_write().await?;
+    sqlx::query(
+        "INSERT INTO inactive_chats (telegram_chat_id, reason, since) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+    )
+    .bind(chat_id.0)
+    .bind(reason)
+    .bind(now_unix())
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    tracing::info!("chat {chat_id} is unreachable ({reason}), no longer notifying it");
+    Ok(())
+}
+
+/// Called whenever `chat_id` messages the bot, which proves it unblocked us
+pub async fn reactivate(chat_id: ChatId) -> sqlx::Result<()> {
+    let inactive: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM inactive_chats WHERE telegram_chat_id = $1")
+            .bind(chat_id.0)
+            .fetch_one(&*DB)
+            .await?;
+    if inactive == 0 {
+        return Ok(());
+    }
+    let (_write, mut tx) = begin_write().await?;
+    sqlx::query("DELETE FROM inactive_chats WHERE telegram_chat_id = $1")
+        .bind(chat_id.0)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    tracing::info!("chat {chat_id} is reachable again");
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use teloxide::{
+    prelude::*,
+    types::{
+        ChatId, InlineQuery, InlineQueryResult, InlineQueryResultArticle, InlineQueryResultsButton,
+        InlineQueryResultsButtonKind, InputMessageContent, InputMessageContentText,
+    },
+};
+
+use crate::{
+    CONFIG, DB, OFFLINE_AFTER_SECS, bans,
+    bot_error::{BotError, Context},
+    i18n, now_unix,
+    policy::RewardPolicy,
+    ratelimit, render,
+};
+
+/// `/start` parameter of the button shown to unregistered users, which opens the bot's DM
+pub const START_PARAMETER: &str = "inline";
+/// Seconds Telegram may reuse an answer for the same user
+const CACHE_SECS: u32 = 30;
+
+/// Answers `@bot uptime` (or an empty query, `status` or `unclaimed`) with a card of the
+/// user's own uptime and unclaimed days, ready to be shared into the chat being typed in.
+/// Inline mode must be switched on for the bot with @BotFather first.
+#[tracing::instrument(name = "inline_query", skip_all, fields(user_id = %query.from.id))]
+pub async fn handle(bot: Bot, query: InlineQuery) -> Result<(), BotError> {
+    // The user id doubles as the id of the user's private chat with the bot
+    let chat_id = ChatId(query.from.id.0 as i64);
+    if !matches!(query.query.trim(), "" | "uptime" | "status" | "unclaimed") {
+        bot.answer_inline_query(&query.id, vec![])
+            .cache_time(CACHE_SECS)
+            .is_personal(true)
+            .await?;
+        return Ok(());
+    }
+    if !matches!(ratelimit::check(chat_id), ratelimit::Verdict::Allowed)
+        || bans::is_banned(chat_id).await.context("checking bans")?
+    {
+        return Ok(());
+    }
+
+    let (vms, online, up_secs, unclaimed_secs): (i64, i64, i64, i64) = sqlx::query_as(
+        r#"
+SELECT
+    COUNT(*),
+    COUNT(CASE WHEN s.last_seen >= $2 THEN 1 END),
+    CAST(COALESCE(SUM(a.up_secs), 0) AS BIGINT),
+    CAST(COALESCE(SUM(a.up_secs + a.bonus_secs - a.paid_secs), 0) AS BIGINT)
+FROM agent_records a LEFT JOIN vm_status s ON s.vm_id = a.vm_id
+WHERE a.telegram_chat_id = $1
+        "#,
+    )
+    .bind(chat_id.0)
+    .bind(now_unix() - OFFLINE_AFTER_SECS)
+    .fetch_one(&*DB)
+    .await
+    .context("summing uptime for the inline card")?;
+
+    if vms == 0 {
+        bot.answer_inline_query(&query.id, vec![])
+            .cache_time(CACHE_SECS)
+            .is_personal(true)
+            .button(InlineQueryResultsButton {
+                text: i18n::text("inline.register_first", &[]),
+                kind: InlineQueryResultsButtonKind::StartParameter(START_PARAMETER.to_owned()),
+            })
+            .await?;
+        return Ok(());
+    }
+
+    let uptime = render::format_duration(up_secs);
+    let days = RewardPolicy::current().days(unclaimed_secs);
+    let args: &[(&str, &i18n::Arg)] = &[
+        ("online", &online),
+        ("vms", &vms),
+        ("uptime", &uptime),
+        ("count", &days),
+    ];
+    // The title has room for one language, the first one shown
+    let language = CONFIG
+        .message_languages
+        .first()
+        .map_or("en", String::as_str);
+    let title = i18n::text_in(language, "inline.title", args);
+    let description = i18n::text("inline.description", args);
+    let text = i18n::text("inline.card", args);
+    let card = InlineQueryResultArticle::new(
+        "status",
+        title,
+        InputMessageContent::Text(InputMessageContentText::new(text)),
+    )
+    .description(description);
+    bot.answer_inline_query(&query.id, vec![InlineQueryResult::Article(card)])
+        .cache_time(CACHE_SECS)
+        .is_personal(true)
+        .await?;
+    Ok(())
+}
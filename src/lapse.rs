@@ -0,0 +1,195 @@
+use std::collections::{BTreeMap, HashSet};
+
+use serde_json::json;
+use teloxide::types::ChatId;
+
+use crate::{
+    CONFIG, DB,
+    audit::{self, Actor},
+    begin_write, i18n, nicknames, now_unix, outbox,
+    policy::RewardPolicy,
+    render,
+    telegram::Telegram,
+};
+
+/// `(vm_id, telegram_chat_id, nickname, lapse_warned_at, balance, paid_secs, earned_before,
+/// earned_before_soon)`, the last two summing the VM's uptime on days before the expiry
+/// cutoff now and `unclaimed_warning_days` from now
+type BalanceRow = (
+    String,
+    Option<i64>,
+    Option<String>,
+    Option<i64>,
+    i64,
+    i64,
+    i64,
+    i64,
+);
+
+/// What one chat is warned of
+#[derive(Default)]
+struct Warning {
+    /// Balance lapsing within the warning window, across the chat's VMs
+    expiring_secs: i64,
+    /// VMs that can reach the cap within the window
+    near_cap: Vec<String>,
+}
+
+/// Warns owners of unclaimed uptime that will be past `unclaimed_expiry_days` or over
+/// `unclaimed_cap_days` within `unclaimed_warning_days`, and lets it lapse once a warning
+/// has been out that long. Nothing lapses for a chat that wasn't warned first, nor for
+/// unowned VMs, which have nobody to warn. Runs as the `lapse` job.
+pub async fn run(bot: impl Telegram) -> anyhow::Result<()> {
+    apply(&bot, &RewardPolicy::current(), now_unix()).await
+}
+
+/// [`run`] under `policy` at `now`
+pub async fn apply(bot: &impl Telegram, policy: &RewardPolicy, now: i64) -> anyhow::Result<()> {
+    if policy.cap_days.is_none() && policy.expiry_days.is_none() {
+        return Ok(());
+    }
+    let window = CONFIG.unclaimed_warning_days * 86400;
+    let expiry = policy.expiry_days.unwrap_or_default() * 86400;
+    let (cutoff, cutoff_soon) = (
+        render::format_date(now - expiry),
+        render::format_date(now - expiry + window),
+    );
+    // Only the expiry matters for what lapses within the window; the cap is checked apart
+    let expiry_only = RewardPolicy {
+        cap_days: None,
+        ..*policy
+    };
+
+    let mut warnings: BTreeMap<i64, Warning> = BTreeMap::new();
+    let (_write, mut tx) = begin_write().await?;
+    let rows: Vec<BalanceRow> = sqlx::query_as(
+        r#"
+SELECT a.vm_id, a.telegram_chat_id, a.nickname, p.lapse_warned_at,
+       a.up_secs + a.bonus_secs - a.paid_secs, a.paid_secs,
+       CAST(COALESCE(SUM(CASE WHEN h.day < $1 THEN h.up_secs ELSE 0 END), 0) AS BIGINT),
+       CAST(COALESCE(SUM(CASE WHEN h.day < $2 THEN h.up_secs ELSE 0 END), 0) AS BIGINT)
+FROM agent_records a
+LEFT JOIN uptime_history h ON h.vm_id = a.vm_id
+LEFT JOIN user_prefs p ON p.telegram_chat_id = a.telegram_chat_id
+GROUP BY a.vm_id, a.telegram_chat_id, a.nickname, p.lapse_warned_at,
+         a.up_secs, a.bonus_secs, a.paid_secs
+HAVING a.up_secs + a.bonus_secs - a.paid_secs > 0
+        "#,
+    )
+    .bind(&cutoff)
+    .bind(&cutoff_soon)
+    .fetch_all(&mut *tx)
+    .await?;
+    for (
+        vm_id,
+        owner,
+        nickname,
+        warned_at,
+        balance,
+        paid_secs,
+        earned_before,
+        earned_before_soon,
+    ) in rows
+    {
+        // What lapses now was announced by a warning at least a window ago, which
+        // foresaw everything that would be past the expiry or the cap by today
+        let warned = warned_at.is_some_and(|at| at <= now - window);
+        let lapsed = if warned {
+            policy.lapsing(balance, paid_secs, earned_before)
+        } else {
+            0
+        };
+        if lapsed > 0 {
+            sqlx::query("UPDATE agent_records SET paid_secs = paid_secs + $1 WHERE vm_id = $2")
+                .bind(lapsed)
+                .bind(&vm_id)
+                .execute(&mut *tx)
+                .await?;
+            audit::record(
+                &mut tx,
+                &Actor::System,
+                "balance_lapsed",
+                owner.map(ChatId),
+                json!({ "vm_id": vm_id, "lapsed_secs": lapsed, "balance_secs": balance }),
+            )
+            .await?;
+            tracing::info!("{lapsed}s of unclaimed uptime lapsed on {vm_id}");
+        }
+        let Some(owner) = owner else {
+            continue;
+        };
+        let (balance, paid_secs) = (balance - lapsed, paid_secs + lapsed);
+        // Includes whatever is already due but wasn't warned of yet
+        let expiring = expiry_only.lapsing(balance, paid_secs, earned_before_soon);
+        // A VM earns at most a day's uptime per day, and one already over the cap is too
+        let near_cap = policy
+            .cap_days
+            .is_some_and(|cap| balance + window >= policy.secs(cap));
+        if expiring > 0 || near_cap {
+            let warning = warnings.entry(owner).or_default();
+            warning.expiring_secs += expiring;
+            if near_cap {
+                warning
+                    .near_cap
+                    .push(nicknames::label(&vm_id, nickname.as_deref()));
+            }
+        }
+    }
+    // Warned once per window, and not at all where messages can't be delivered
+    let quiet: HashSet<i64> = sqlx::query_scalar(
+        r#"
+SELECT telegram_chat_id FROM user_prefs WHERE lapse_warned_at > $1
+UNION SELECT telegram_chat_id FROM inactive_chats
+        "#,
+    )
+    .bind(now - window)
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .collect();
+    tx.commit().await?;
+
+    let until = render::format_date(now + window);
+    for (chat_id, warning) in warnings {
+        if quiet.contains(&chat_id) {
+            continue;
+        }
+        let mut lines = Vec::new();
+        if warning.expiring_secs > 0 {
+            let days = format!(
+                "{:.1}",
+                warning.expiring_secs as f64 / policy.secs_per_day as f64
+            );
+            lines.push(i18n::text(
+                "lapse.expiring",
+                &[("days", &days), ("until", &until)],
+            ));
+        }
+        if let (Some(cap), false) = (policy.cap_days, warning.near_cap.is_empty()) {
+            let vms = warning.near_cap.join(", ");
+            lines.push(i18n::text(
+                "lapse.near_cap",
+                &[("vms", &vms), ("count", &cap)],
+            ));
+        }
+        lines.push(i18n::text("lapse.claim", &[]));
+        let (chat_id, text) = (ChatId(chat_id), lines.join("\n"));
+        let sent =
+            outbox::deliver(chat_id, || bot.send_message(chat_id, &text).into_future()).await;
+        if let Err(e) = sent {
+            tracing::warn!("could not warn chat {chat_id} of lapsing days: {e}");
+            continue;
+        }
+        sqlx::query(
+            r#"
+INSERT INTO user_prefs (telegram_chat_id, lapse_warned_at) VALUES ($1, $2)
+ON CONFLICT(telegram_chat_id) DO UPDATE SET lapse_warned_at = excluded.lapse_warned_at
+            "#,
+        )
+        .bind(chat_id.0)
+        .bind(now)
+        .execute(&*DB)
+        .await?;
+    }
+    Ok(())
+}
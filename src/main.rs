@@ -1,157 +1,1534 @@
-use std::{collections::HashMap, fs::File, str::FromStr, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    net::SocketAddr,
+    ops::Deref,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use clap::Parser;
+use clap::{CommandFactory, Parser, error::ErrorKind};
 use futures_util::StreamExt;
-use isahc::prelude::*;
 use once_cell::sync::Lazy;
+use rand::Rng;
 use serde::Deserialize;
-use serde_json::{Value, json};
+use serde_json::Value;
 use smol::future::FutureExt;
-use sqlx::{
-    Pool, Sqlite,
-    sqlite::{SqliteConnectOptions, SqlitePoolOptions, SqliteQueryResult},
-};
+use sqlx::{AnyConnection, AnyPool, any::AnyPoolOptions};
 use teloxide::{
-    RequestError,
+    ApiError, RequestError, dptree,
     prelude::*,
     types::{
-        BotCommand, ChatId, InlineKeyboardButton, InlineKeyboardMarkup, MenuButton, Message,
-        Seconds,
+        BotCommand, BotCommandScope, ChatId, InlineKeyboardButton, InlineKeyboardMarkup,
+        MenuButton, Message, MessageId,
     },
 };
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+mod admin;
+mod alerts;
+mod apikeys;
+mod appeals;
+mod audit;
+mod autoclaim;
+mod backup;
+mod bans;
+mod bot_error;
+mod broadcast;
+mod channel;
+mod chart;
+mod chat_migration;
+mod claim;
+mod cleanup;
+mod cli;
+mod community;
+mod config_sources;
+mod dialogue;
+mod digest;
+mod erasure;
+mod errors;
+mod events;
+mod export;
+mod fraud;
+mod geph_account;
+mod giftcards;
+mod groups;
+mod help;
+mod http;
+mod i18n;
+mod inactive;
+mod inline;
+mod lapse;
+mod nicknames;
+mod outbox;
+mod ownership;
+mod pinned;
+mod policy;
+mod prefs;
+mod proxy;
+mod qr;
+mod ratelimit;
+mod referrals;
+mod reload;
+mod render;
+mod replace;
+mod reporting;
+mod rewards;
+mod scheduler;
+mod selftest;
+mod shutdown;
+mod signing;
+mod streaks;
+mod supervisor;
+mod telegram;
+mod telemetry;
+#[cfg(test)]
+mod testing;
+mod tokens;
+mod transfer;
+mod vm_api;
+mod vm_detail;
+
+use admin::AdminCommand;
+use bot_error::{BotError, Context};
+use claim::ClaimOutcome;
+use dialogue::Dialogue;
+use errors::{ErrorCode, send_error};
+use policy::RewardPolicy;
+use render::{Indicator, send_codes, send_status};
+use replace::ReplaceOutcome;
+use scheduler::Job;
+use supervisor::supervise;
 
 // ---------------------------- Configuration ----------------------------
+const PROD_GIFTCARD_API_URL: &str = "https://web-backend.geph.io/support/create-giftcards";
+
 #[derive(Debug, Deserialize)]
 struct Config {
     telegram_bot_token: String,
     vm_api_secret: String,
+    /// Availability endpoint the VMs' uptime is polled from
+    #[serde(default = "default_vm_api_url")]
+    vm_api_url: String,
+    /// How `vm_api_secret` is sent: `header`, as `Authorization: Bearer <secret>`, or
+    /// `query`, as `?secret=` for servers that predate the header. Defaults to `query` for
+    /// the built-in plain-HTTP endpoint and to `header` for any other.
+    #[serde(default)]
+    vm_api_auth: Option<vm_api::Auth>,
+    /// Whether the VM API's TLS certificate is checked. Only for rehearsals; in `prod`, trust
+    /// a self-signed certificate through `vm_api_ca_path` instead.
+    #[serde(default = "default_vm_api_tls_verify")]
+    vm_api_tls_verify: bool,
+    /// PEM file of the CA certificates the VM API's certificate is checked against, e.g. its
+    /// own self-signed one when it's served from a bare IP
+    #[serde(default)]
+    vm_api_ca_path: Option<PathBuf>,
+    /// Hex-encoded ed25519 key the VM API signs its responses with. When set, only signed
+    /// responses are accepted, so whoever takes over the endpoint can't credit uptime.
+    #[serde(default)]
+    vm_api_public_key: Option<String>,
+    /// Further availability APIs to poll besides the one above, e.g. for mobile testers or
+    /// bridge probes. Each takes `name`, `url` and `secret`, optionally `auth`,
+    /// `tls_verify`, `ca_path` and `public_key` like the `vm_api_*` settings, and a
+    /// `reward_multiplier` (default 1) scaling what its uptime earns.
+    #[serde(default)]
+    fleets: Vec<vm_api::Fleet>,
     giftcard_api_secret: String,
+    /// Deployment this instance belongs to; anything but `prod` labels itself as such.
+    /// Also accepted as `mode`.
+    #[serde(default, alias = "mode")]
+    environment: Environment,
+    /// Token used instead of `telegram_bot_token` outside `prod`, so a rehearsal talks
+    /// through a test bot
+    #[serde(default)]
+    test_telegram_bot_token: Option<String>,
+    /// Giftcard backend endpoint. Outside `prod` it must not be production's, and leaving
+    /// it unset issues fake codes through the `mock` reward provider instead.
+    #[serde(default)]
+    giftcard_api_url: Option<String>,
+    /// Custom emoji ids keyed by status indicator (`success`, `uptime`, `balance`,
+    /// `gift`, `empty`, `removed`); unlisted indicators use plain unicode emoji
+    #[serde(default)]
+    custom_emoji: HashMap<String, String>,
+    /// Address for the embedded HTTP API (`/healthz`, ...); disabled when unset
+    #[serde(default)]
+    http_listen: Option<SocketAddr>,
+    /// Bearer token VM agents present to `/api/vm/<id>/self` and `/api/vm/<id>/heartbeat`;
+    /// those endpoints reject everything when unset
+    #[serde(default)]
+    agent_api_secret: Option<String>,
+    /// Make testers prove control of a VM before registering it by id: the bot issues a
+    /// token that the VM agent must report back as `ownership_token` in its metadata.
+    /// Registration tokens (from admins or the VM agent's deep link) skip the proof.
+    #[serde(default)]
+    require_ownership_proof: bool,
+    /// Where uptime comes from: `poll` (the availability API), `push` (heartbeats VM agents
+    /// send to the HTTP API), or `push_and_poll` (heartbeats, with the poll as a fallback
+    /// for agents that don't send them). Agents should send a heartbeat at least once per
+    /// poll period, since longer gaps are only credited up to one period.
+    #[serde(default)]
+    uptime_source: UptimeSource,
+    /// HMAC secrets keyed by fleet name, `default` or one of `fleets`. Once any is set,
+    /// heartbeats and admin-scope API calls must be signed by one of them (see
+    /// `signing.rs`), and agents may only speak for VMs of the fleet that signed.
+    #[serde(default)]
+    fleet_hmac_secrets: HashMap<String, String>,
+    /// How far a signed request's timestamp may be from the bot's clock
+    #[serde(default = "default_hmac_window_secs")]
+    hmac_window_secs: i64,
+    /// Chats allowed to use `/admin` commands
+    #[serde(default)]
+    admin_chat_ids: Vec<i64>,
+    /// Minutes a registered VM may be missing from the poll before its owner is alerted
+    #[serde(default = "default_offline_alert_after_mins")]
+    offline_alert_after_mins: i64,
+    /// Public group where the bot answers aggregate queries only
+    #[serde(default)]
+    community_chat_id: Option<i64>,
+    /// When set, the bot's replies in groups (and the commands that triggered them) are
+    /// deleted after this many seconds
+    #[serde(default)]
+    group_message_ttl_secs: Option<i64>,
+    /// Public channel that gets a daily summary of the testing network; the bot must be
+    /// one of its admins
+    #[serde(default)]
+    stats_channel_id: Option<i64>,
+    /// UTC hour after which the previous day's summary is posted to `stats_channel_id`
+    #[serde(default)]
+    stats_channel_post_hour: u32,
+    /// TrueType font used to label `/chart` images
+    #[serde(default = "default_chart_font_path")]
+    chart_font_path: String,
+    /// `sqlite://<path>` or `postgres://...`; overridden by `--db`
+    #[serde(default = "default_database_url")]
+    database_url: String,
+    /// Upper bound on pooled database connections
+    #[serde(default = "default_database_max_connections")]
+    database_max_connections: u32,
+    /// SQLite `journal_mode` (`delete`, `truncate`, `persist`, `memory`, `wal` or `off`)
+    /// applied to every connection. WAL lets readers proceed while a write is in progress.
+    #[serde(default = "default_sqlite_journal_mode")]
+    sqlite_journal_mode: String,
+    /// How long a SQLite connection waits for a lock before failing with "database is locked"
+    #[serde(default = "default_sqlite_busy_timeout_ms")]
+    sqlite_busy_timeout_ms: u64,
+    /// Directory for scheduled snapshots of the SQLite database; no backups when unset.
+    /// Restore one with the `restore` subcommand while the bot is stopped.
+    #[serde(default)]
+    backup_dir: Option<PathBuf>,
+    /// Hours between snapshots
+    #[serde(default = "default_backup_interval_hours")]
+    backup_interval_hours: u64,
+    /// Snapshots kept in `backup_dir`; older ones are deleted after each new one
+    #[serde(default = "default_backup_keep")]
+    backup_keep: usize,
+    /// Bonus hours credited to both the referrer and the new tester once the new tester's
+    /// VMs have been up for a full day
+    #[serde(default = "default_referral_bonus_hours")]
+    referral_bonus_hours: i64,
+    /// Seconds of uptime (plus bonus time) worth one Plus day. Lower it to run a promotion,
+    /// e.g. 43200 for one day per 12 hours; balances already accrued convert at the new rate.
+    #[serde(default = "default_reward_secs_per_day")]
+    reward_secs_per_day: i64,
+    /// `floor` (only whole days, the remainder carries over) or `nearest` (half a day or
+    /// more rounds up)
+    #[serde(default)]
+    reward_rounding: policy::Rounding,
+    /// Most unclaimed Plus days a VM can hold; uptime earned beyond that lapses
+    #[serde(default)]
+    unclaimed_cap_days: Option<i64>,
+    /// Days after which unclaimed uptime lapses, oldest first: a claim spends the oldest
+    /// uptime, so only what's older than this and never claimed is lost. Bonus hours only
+    /// count towards `unclaimed_cap_days`.
+    #[serde(default)]
+    unclaimed_expiry_days: Option<i64>,
+    /// How far ahead owners are warned of days about to lapse; nothing lapses until a
+    /// warning has been out this long
+    #[serde(default = "default_unclaimed_warning_days")]
+    unclaimed_warning_days: i64,
+    /// Backend endpoint that adds Plus days to a Geph account (authenticated with
+    /// `giftcard_api_secret`). When set, testers with a linked account can turn on
+    /// `/settings direct on` to have claims credited instead of receiving giftcards; a
+    /// failed credit falls back to a giftcard.
+    #[serde(default)]
+    plus_credit_api_url: Option<String>,
+    /// Reward providers claims are issued through, tried in order (`giftcard`,
+    /// `direct_credit`, `mock`). By default direct credit when `plus_credit_api_url` is
+    /// set, then giftcards, or mock codes outside `prod` without a `giftcard_api_url`.
+    #[serde(default)]
+    reward_providers: Option<Vec<rewards::Kind>>,
+    /// Redemption page for giftcard QR codes, with `{code}` standing for the code, e.g.
+    /// `https://geph.io/redeem?code={code}`. Without it the QR code holds the bare code.
+    #[serde(default)]
+    giftcard_redeem_url: Option<String>,
+    /// Days after which `/mycards` stops showing or re-sending a giftcard's code
+    #[serde(default = "default_giftcard_resend_max_age_days")]
+    giftcard_resend_max_age_days: i64,
+    /// How often messages pinned with `/pin` are refreshed
+    #[serde(default = "default_pinned_status_interval_secs")]
+    pinned_status_interval_secs: u64,
+    /// Languages every message is shown in, side by side, in this order
+    #[serde(default = "default_message_languages")]
+    message_languages: Vec<String>,
+    /// YAML file whose messages override or extend the built-in catalog (`messages.yaml`)
+    #[serde(default)]
+    messages_path: Option<PathBuf>,
+    /// OTLP/HTTP endpoint traces are sent to, e.g. `http://collector:4318/v1/traces`;
+    /// nothing is exported without it
+    #[serde(default)]
+    otlp_endpoint: Option<String>,
+    /// Which spans are exported, in `RUST_LOG` syntax. Separate from the log filter so
+    /// database queries can be traced without also being logged.
+    #[serde(default = "default_otlp_filter")]
+    otlp_filter: String,
+    /// Endpoint panics and handler errors are POSTed to as JSON, since nobody reads the logs
+    /// of an unattended bot; without it they're only logged
+    #[serde(default)]
+    error_report_url: Option<String>,
+    /// Sent as a bearer token with every error report
+    #[serde(default)]
+    error_report_token: Option<String>,
+    /// How long a SIGTERM or SIGINT waits for in-flight work (handlers, ticks, queued
+    /// sends) before the process exits anyway
+    #[serde(default = "default_shutdown_grace_secs")]
+    shutdown_grace_secs: u64,
+    /// Schedules replacing a job's default, keyed by job name (`notify`, `lapse`, `backup`), in the
+    /// syntax of [`scheduler::Schedule`]: `0 4 * * *`, `@daily`, `@every 6h`
+    #[serde(default)]
+    job_schedules: HashMap<String, String>,
+    /// Proxy every outbound connection goes through, Telegram's included:
+    /// `socks5h://host:port`, `socks5://…`, `http://…` or `https://…`. `geph` stands for
+    /// the SOCKS5 proxy of a Geph client on the same host, for where Telegram is blocked.
+    #[serde(default)]
+    proxy: Option<String>,
+    /// Hosts reached directly even though `proxy` is set, e.g. an internal VM API
+    #[serde(default)]
+    no_proxy: Vec<String>,
+}
+
+fn default_vm_api_url() -> String {
+    vm_api::LEGACY_URL.to_owned()
+}
+
+fn default_vm_api_tls_verify() -> bool {
+    true
+}
+
+fn default_offline_alert_after_mins() -> i64 {
+    30
+}
+
+fn default_hmac_window_secs() -> i64 {
+    300
+}
+
+fn default_chart_font_path() -> String {
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf".into()
+}
+
+fn default_database_url() -> String {
+    "sqlite://geph-testing-bot-store.db?mode=rwc".into()
+}
+
+fn default_database_max_connections() -> u32 {
+    10
+}
+
+fn default_sqlite_journal_mode() -> String {
+    "wal".into()
+}
+
+fn default_sqlite_busy_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_backup_interval_hours() -> u64 {
+    6
+}
+
+fn default_backup_keep() -> usize {
+    28
+}
+
+fn default_referral_bonus_hours() -> i64 {
+    24
+}
+
+fn default_reward_secs_per_day() -> i64 {
+    86400
+}
+
+fn default_unclaimed_warning_days() -> i64 {
+    7
+}
+
+fn default_giftcard_resend_max_age_days() -> i64 {
+    180
+}
+
+fn default_pinned_status_interval_secs() -> u64 {
+    300
+}
+
+fn default_otlp_filter() -> String {
+    "geph_testing_bot=info,sqlx::query=debug".into()
+}
+
+fn default_shutdown_grace_secs() -> u64 {
+    30
+}
+
+fn default_message_languages() -> Vec<String> {
+    vec!["en".into(), "zh".into()]
+}
+
+impl Config {
+    /// Giftcard backend to call, or `None` to issue fake codes
+    fn giftcard_api_url(&self) -> Option<&str> {
+        match (&self.giftcard_api_url, self.environment) {
+            (Some(url), _) => Some(url),
+            (None, Environment::Prod) => Some(PROD_GIFTCARD_API_URL),
+            (None, _) => None,
+        }
+    }
+
+    /// Every fleet polled, the default one first
+    fn fleets(&self) -> Vec<vm_api::Fleet> {
+        let default = vm_api::Fleet {
+            name: vm_api::DEFAULT_FLEET.to_owned(),
+            url: self.vm_api_url.clone(),
+            secret: self.vm_api_secret.clone(),
+            auth: self.vm_api_auth,
+            tls_verify: self.vm_api_tls_verify,
+            ca_path: self.vm_api_ca_path.clone(),
+            public_key: self.vm_api_public_key.clone(),
+            reward_multiplier: 1.0,
+        };
+        std::iter::once(default)
+            .chain(self.fleets.iter().cloned())
+            .collect()
+    }
+
+    /// The fleet called `name`, if any still is
+    fn fleet(&self, name: &str) -> Option<vm_api::Fleet> {
+        self.fleets().into_iter().find(|fleet| fleet.name == name)
+    }
+
+    /// The proxy to use, with `geph` spelled out
+    fn proxy_url(&self) -> Option<&str> {
+        match self.proxy.as_deref()? {
+            "geph" => Some(proxy::GEPH_CLIENT),
+            url => Some(url),
+        }
+    }
+
+    fn reward_providers(&self) -> Vec<rewards::Kind> {
+        if let Some(kinds) = &self.reward_providers {
+            return kinds.clone();
+        }
+        let mut kinds = vec![];
+        if self.plus_credit_api_url.is_some() {
+            kinds.push(rewards::Kind::DirectCredit);
+        }
+        kinds.push(match self.giftcard_api_url() {
+            Some(_) => rewards::Kind::Giftcard,
+            None => rewards::Kind::Mock,
+        });
+        kinds
+    }
+
+    fn bot_token(&self) -> &str {
+        match (&self.test_telegram_bot_token, self.environment) {
+            (Some(token), Environment::Staging | Environment::Dev) => token,
+            _ => &self.telegram_bot_token,
+        }
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.environment != Environment::Prod {
+            anyhow::ensure!(
+                self.giftcard_api_url.as_deref() != Some(PROD_GIFTCARD_API_URL),
+                "a {:?} environment must not use the production giftcard_api_url",
+                self.environment
+            );
+        }
+        let providers = self.reward_providers();
+        anyhow::ensure!(
+            providers.last().is_some_and(|kind| kind.issues_all()),
+            "reward_providers must end with giftcard or mock, which take every claim"
+        );
+        anyhow::ensure!(
+            !providers.contains(&rewards::Kind::Mock) || self.environment != Environment::Prod,
+            "the mock reward provider can't be used in prod"
+        );
+        anyhow::ensure!(
+            !providers.contains(&rewards::Kind::Giftcard) || self.giftcard_api_url().is_some(),
+            "the giftcard reward provider needs giftcard_api_url outside prod"
+        );
+        anyhow::ensure!(
+            !providers.contains(&rewards::Kind::DirectCredit) || self.plus_credit_api_url.is_some(),
+            "the direct_credit reward provider needs plus_credit_api_url"
+        );
+        if self.uptime_source != UptimeSource::Poll {
+            anyhow::ensure!(
+                self.http_listen.is_some()
+                    && (self.agent_api_secret.is_some() || !self.fleet_hmac_secrets.is_empty()),
+                "uptime_source {:?} needs http_listen and agent_api_secret or fleet_hmac_secrets",
+                self.uptime_source
+            );
+        }
+        anyhow::ensure!(
+            ["delete", "truncate", "persist", "memory", "wal", "off"]
+                .contains(&self.sqlite_journal_mode.to_lowercase().as_str()),
+            "unknown sqlite_journal_mode {:?}",
+            self.sqlite_journal_mode
+        );
+        anyhow::ensure!(
+            self.reward_secs_per_day > 0,
+            "reward_secs_per_day must be positive"
+        );
+        anyhow::ensure!(
+            self.unclaimed_cap_days.is_none_or(|days| days > 0)
+                && self.unclaimed_expiry_days.is_none_or(|days| days > 0),
+            "unclaimed_cap_days and unclaimed_expiry_days must be positive"
+        );
+        // A warning further ahead than the expiry would go out for uptime earned today
+        anyhow::ensure!(
+            self.unclaimed_warning_days > 0
+                && self
+                    .unclaimed_expiry_days
+                    .is_none_or(|days| self.unclaimed_warning_days < days),
+            "unclaimed_warning_days must be positive and less than unclaimed_expiry_days"
+        );
+        anyhow::ensure!(
+            self.giftcard_resend_max_age_days >= 0,
+            "giftcard_resend_max_age_days can't be negative"
+        );
+        anyhow::ensure!(
+            !self.message_languages.is_empty(),
+            "message_languages needs at least one language"
+        );
+        anyhow::ensure!(
+            self.stats_channel_post_hour < 24,
+            "stats_channel_post_hour must be an hour of the day (0-23)"
+        );
+        if let Some(url) = &self.error_report_url {
+            anyhow::ensure!(
+                url.starts_with("http://") || url.starts_with("https://"),
+                "error_report_url must be an http(s) URL"
+            );
+        }
+        if let Some(url) = self.proxy_url() {
+            proxy::check(url)?;
+        }
+        let mut fleet_names = HashSet::new();
+        for fleet in self.fleets() {
+            anyhow::ensure!(
+                !fleet.name.is_empty()
+                    && fleet
+                        .name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_')),
+                "fleet name {:?} should be letters, digits, - and _",
+                fleet.name
+            );
+            anyhow::ensure!(
+                fleet_names.insert(fleet.name.clone()),
+                "there's more than one fleet called {}",
+                fleet.name
+            );
+            fleet.validate(self.environment == Environment::Prod)?;
+        }
+        for name in self.fleet_hmac_secrets.keys() {
+            anyhow::ensure!(
+                fleet_names.contains(name),
+                "fleet_hmac_secrets has a secret for {name}, which is no fleet"
+            );
+        }
+        if let Some(endpoint) = &self.otlp_endpoint {
+            anyhow::ensure!(
+                endpoint.starts_with("http://") || endpoint.starts_with("https://"),
+                "otlp_endpoint must be an http(s) URL"
+            );
+            anyhow::ensure!(
+                tracing_subscriber::EnvFilter::try_new(&self.otlp_filter).is_ok(),
+                "otlp_filter is not a valid filter"
+            );
+        }
+        // Every refresh is an edit per pinned chat, paced like any other bulk send
+        anyhow::ensure!(
+            self.pinned_status_interval_secs >= 60,
+            "pinned_status_interval_secs must be at least 60"
+        );
+        anyhow::ensure!(
+            self.shutdown_grace_secs > 0,
+            "shutdown_grace_secs must be positive"
+        );
+        for (job, schedule) in &self.job_schedules {
+            if let Err(e) = schedule.parse::<scheduler::Schedule>() {
+                anyhow::bail!("job_schedules.{job}: {e:#}");
+            }
+        }
+        if self.backup_dir.is_some() {
+            anyhow::ensure!(
+                self.database_url.starts_with("sqlite"),
+                "backup_dir only applies to SQLite; back Postgres up with its own tooling"
+            );
+            anyhow::ensure!(
+                self.backup_interval_hours > 0 && self.backup_keep > 0,
+                "backup_interval_hours and backup_keep must be positive"
+            );
+        }
+        Ok(())
+    }
+
+    /// Copies the settings that are only read at startup over from `running`, returning
+    /// the names of those the new file changed. These pick the database, the bot, which
+    /// tasks and jobs run and how logging is set up, so they need a restart.
+    fn keep_startup_settings(&mut self, running: &Config) -> Vec<&'static str> {
+        let mut changed = vec![];
+        macro_rules! keep {
+            ($($field:ident),* $(,)?) => {
+                $(
+                    if self.$field != running.$field {
+                        self.$field = running.$field.clone();
+                        changed.push(stringify!($field));
+                    }
+                )*
+            };
+        }
+        keep!(
+            telegram_bot_token,
+            test_telegram_bot_token,
+            environment,
+            http_listen,
+            uptime_source,
+            stats_channel_id,
+            chart_font_path,
+            database_url,
+            database_max_connections,
+            sqlite_journal_mode,
+            sqlite_busy_timeout_ms,
+            backup_dir,
+            backup_interval_hours,
+            pinned_status_interval_secs,
+            message_languages,
+            messages_path,
+            otlp_endpoint,
+            otlp_filter,
+            job_schedules,
+            proxy,
+            no_proxy,
+            // Each poller holds its fleet's client settings from startup
+            vm_api_url,
+            vm_api_secret,
+            vm_api_auth,
+            vm_api_tls_verify,
+            vm_api_ca_path,
+            vm_api_public_key,
+            fleets,
+        );
+        changed
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Environment {
+    #[default]
+    Prod,
+    Staging,
+    Dev,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum UptimeSource {
+    #[default]
+    Poll,
+    Push,
+    PushAndPoll,
+}
+
+impl UptimeSource {
+    fn polls(self) -> bool {
+        self != UptimeSource::Push
+    }
+
+    fn accepts_heartbeats(self) -> bool {
+        self != UptimeSource::Poll
+    }
+}
+
+impl Environment {
+    /// Marker appended to the bot name and prefixed to command descriptions
+    fn label(self) -> Option<&'static str> {
+        match self {
+            Environment::Prod => None,
+            Environment::Staging => Some("[STAGING]"),
+            Environment::Dev => Some("[DEV]"),
+        }
+    }
+
+    /// As written in the config file
+    fn name(self) -> &'static str {
+        match self {
+            Environment::Prod => "prod",
+            Environment::Staging => "staging",
+            Environment::Dev => "dev",
+        }
+    }
+
+    /// Log filter used when `RUST_LOG` isn't set
+    fn default_log_filter(self) -> &'static str {
+        match self {
+            Environment::Prod => "geph_testing_bot=info",
+            Environment::Staging => "geph_testing_bot=debug",
+            Environment::Dev => "debug",
+        }
+    }
 }
 
-/// CLI wrapper (`-c <config.yaml>`) – parsed inside the lazy initializer
+/// Telegram bot that rewards testers for the uptime of their Geph testing VMs.
+/// Runs the bot (`serve`) unless given another subcommand.
 #[derive(Parser, Debug)]
 struct Cli {
-    /// Path to YAML config file
-    #[arg(short, long)]
-    config: String,
+    /// Path to YAML config file (required)
+    // Checked when the config is loaded, since clap rejects required global arguments
+    #[arg(short, long, global = true)]
+    config: Option<String>,
+    /// Database URL, overriding `database_url` from the config file
+    #[arg(long, global = true)]
+    db: Option<String>,
+    #[command(subcommand)]
+    command: Option<cli::Command>,
+}
+
+static CLI: Lazy<Cli> = Lazy::new(Cli::parse);
+
+/// The config in force. [`reload::reload`] swaps in a new one on SIGHUP; each is leaked, so
+/// whatever still holds a reference into an older one keeps a consistent view of it.
+/// Reloads are rare and a config is small.
+struct LiveConfig(RwLock<&'static Config>);
+
+impl Deref for LiveConfig {
+    type Target = Config;
+
+    fn deref(&self) -> &Config {
+        *self.0.read().unwrap()
+    }
 }
 
-static CONFIG: Lazy<Config> = Lazy::new(|| {
-    let cli = Cli::parse();
-    serde_yaml::from_reader(File::open(&cli.config).expect("read config file"))
-        .expect("parse config YAML")
+static CONFIG: Lazy<LiveConfig> = Lazy::new(|| {
+    #[cfg(test)]
+    let config = testing::config();
+    #[cfg(not(test))]
+    let config = read_config()
+        .and_then(|config| config.validate().map(|()| config))
+        .unwrap_or_else(|e| panic!("invalid config: {e:#}"));
+    LiveConfig(RwLock::new(Box::leak(Box::new(config))))
 });
 
-// ---------------------------- Database ----------------------------
-static DB: Lazy<Pool<Sqlite>> = Lazy::new(|| {
-    smol::block_on(async {
-        let opts = SqliteConnectOptions::from_str("sqlite://geph-testing-bot-store.db")
-            .unwrap()
-            .create_if_missing(true);
-        let pool = SqlitePoolOptions::new().connect_with(opts).await.unwrap();
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS agent_records (
-              vm_id TEXT PRIMARY KEY,
-              telegram_chat_id INTEGER,
-              up_secs INTEGER DEFAULT 0,
-              paid_secs INTEGER DEFAULT 0
+/// Reads the config file named on the command line, with the environment's overrides and
+/// secret files (see [`config_sources::deserialize`]), not yet validated
+fn read_config() -> anyhow::Result<Config> {
+    let Some(path) = CLI.config.as_deref() else {
+        Cli::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--config <CONFIG> is required",
             )
-            "#,
-        )
-        .execute(&pool)
+            .exit()
+    };
+    let file = File::open(path).map_err(|e| anyhow::anyhow!("read config file: {e}"))?;
+    let file =
+        serde_yaml::from_reader(file).map_err(|e| anyhow::anyhow!("parse config YAML: {e}"))?;
+    let mut config: Config = config_sources::deserialize(file)?;
+    if let Some(db) = &CLI.db {
+        config.database_url = db.clone();
+    }
+    Ok(config)
+}
+
+// ---------------------------- Database ----------------------------
+/// Every query goes through `sqlx::Any` so the same code runs on SQLite and PostgreSQL.
+/// Queries therefore stick to the common dialect: `$n` placeholders, integers rather than
+/// booleans in result columns, and `CAST(SUM(..) AS BIGINT)` since Postgres sums are
+/// `NUMERIC`.
+static DB: Lazy<AnyPool> = Lazy::new(|| smol::block_on(open_pool(&CONFIG.database_url)));
+
+/// Connects to `url` and brings its schema up to date
+async fn open_pool(url: &str) -> AnyPool {
+    sqlx::any::install_default_drivers();
+    let pool = AnyPoolOptions::new()
+        .max_connections(CONFIG.database_max_connections)
+        .after_connect(|conn, _| {
+            Box::pin(async move {
+                if conn.backend_name() == "SQLite" {
+                    sqlx::query(&format!(
+                        "PRAGMA journal_mode = {}",
+                        CONFIG.sqlite_journal_mode
+                    ))
+                    .execute(&mut *conn)
+                    .await?;
+                    sqlx::query(&format!(
+                        "PRAGMA busy_timeout = {}",
+                        CONFIG.sqlite_busy_timeout_ms
+                    ))
+                    .execute(&mut *conn)
+                    .await?;
+                }
+                Ok(())
+            })
+        })
+        .connect(url)
         .await
         .unwrap();
-        pool
-    })
-});
+    if url.starts_with("postgres") {
+        sqlx::migrate!("migrations/postgres")
+            .run(&pool)
+            .await
+            .expect("run database migrations");
+    } else {
+        upgrade_legacy_schema(&pool).await.unwrap();
+        sqlx::migrate!("migrations/sqlite")
+            .run(&pool)
+            .await
+            .expect("run database migrations");
+    }
+    pool
+}
+
+/// SQLite runs one write transaction at a time, and a transaction that read before
+/// writing fails outright, without waiting out `busy_timeout`, when another writer got in
+/// first. Read-then-write transactions therefore queue up on this lock instead. Postgres
+/// has no such limitation and skips it.
+static SQLITE_WRITE_LOCK: smol::lock::Mutex<()> = smol::lock::Mutex::new(());
+
+/// Begins a transaction that will write, holding [`SQLITE_WRITE_LOCK`] on SQLite until the
+/// returned guard is dropped. Its span shows how long the lock was waited for.
+#[tracing::instrument(name = "db.begin_write")]
+async fn begin_write() -> sqlx::Result<(
+    Option<smol::lock::MutexGuard<'static, ()>>,
+    sqlx::Transaction<'static, sqlx::Any>,
+)> {
+    let guard = if CONFIG.database_url.starts_with("sqlite") {
+        Some(SQLITE_WRITE_LOCK.lock().await)
+    } else {
+        None
+    };
+    Ok((guard, DB.begin().await?))
+}
+
+/// Brings a database created by the pre-migration inline initializer up to the schema of
+/// the first migration. Its `CREATE TABLE IF NOT EXISTS` statements can't add columns
+/// that were later bolted onto existing tables, so those are added here.
+async fn upgrade_legacy_schema(pool: &AnyPool) -> sqlx::Result<()> {
+    let migrated = table_exists(pool, "_sqlx_migrations").await?;
+    if migrated {
+        return Ok(());
+    }
+    if table_exists(pool, "vm_status").await? {
+        if add_column_if_missing(pool, "vm_status", "first_seen", "INTEGER").await? {
+            sqlx::query("UPDATE vm_status SET first_seen = last_seen")
+                .execute(pool)
+                .await?;
+        }
+        add_column_if_missing(pool, "vm_status", "online_since", "INTEGER").await?;
+        add_column_if_missing(pool, "vm_status", "offline_alerted_at", "INTEGER").await?;
+    }
+    if table_exists(pool, "user_prefs").await? {
+        add_column_if_missing(
+            pool,
+            "user_prefs",
+            "daily_notify",
+            "INTEGER NOT NULL DEFAULT 1",
+        )
+        .await?;
+        add_column_if_missing(
+            pool,
+            "user_prefs",
+            "offline_alerts",
+            "INTEGER NOT NULL DEFAULT 1",
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+async fn table_exists(pool: &AnyPool, table: &str) -> sqlx::Result<bool> {
+    let count: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = $1")
+            .bind(table)
+            .fetch_one(pool)
+            .await?;
+    Ok(count > 0)
+}
+
+/// Adds a column to an existing table, returning whether it was missing
+async fn add_column_if_missing(
+    pool: &AnyPool,
+    table: &str,
+    column: &str,
+    decl: &str,
+) -> sqlx::Result<bool> {
+    let count: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM pragma_table_info($1) WHERE name = $2")
+            .bind(table)
+            .bind(column)
+            .fetch_one(pool)
+            .await?;
+    let exists = count > 0;
+    if !exists {
+        sqlx::query(&format!("ALTER TABLE {table} ADD COLUMN {column} {decl}"))
+            .execute(pool)
+            .await?;
+    }
+    Ok(!exists)
+}
+
+/// Current time as Unix seconds, the format used for every timestamp column
+fn now_unix() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock before Unix epoch")
+        .as_secs() as i64
+}
 
 // ---------------------------- Entry ----------------------------
 
 fn main() {
-    env_logger::init();
+    let tracer = telemetry::init();
+    reporting::install_panic_hook();
+    i18n::check();
 
-    let bot = Bot::new(CONFIG.telegram_bot_token.clone());
+    let bot = Bot::with_client(CONFIG.bot_token(), proxy::telegram_client());
 
-    smolscale::block_on(async move {
-        let commands = vec![
-            BotCommand::new(
-                "register",
-                "Register your VM. Usage: /register id / 注册您的 VM：/register id",
-            ),
-            BotCommand::new("uptime", "Show your VM's total uptime / 查看 VM 总运行时间"),
-            BotCommand::new(
-                "unclaimed",
-                "View unclaimed Plus days / 查看未领取的 Plus 天数",
-            ),
-            BotCommand::new(
-                "claim",
-                "Claim accumulated Plus days / 领取累计的 Plus 天数",
-            ),
-            BotCommand::new("deregister", "Deregister your VM / 取消注册 VM"),
-            BotCommand::new("menu", "Show command menu / 显示命令菜单"),
-        ];
-        let _ = bot
-            .set_chat_menu_button()
-            .menu_button(MenuButton::Commands)
-            .send()
-            .await
-            .map_err(|e| log::error!("ERROR setting chat menu: {e:?}"));
-        let _ = bot
-            .set_my_commands(commands)
-            .await
-            .map_err(|e| log::error!("ERROR setting commands: {e:?}"));
-        teloxide::repl(bot.clone(), handler)
-            .race(async {
-                update_uptime_loop().await.unwrap();
+    let result = smolscale::block_on(async move {
+        match CLI.command.clone().unwrap_or(cli::Command::Serve) {
+            cli::Command::Serve => {
+                serve(bot).await;
+                Ok(())
+            }
+            cli::Command::Migrate => cli::migrate().await,
+            cli::Command::Export { format, output } => cli::export(format, output).await,
+            cli::Command::Stats => cli::stats().await,
+            cli::Command::Restore { backup } => backup::restore(&backup).await,
+            cli::Command::Broadcast { text } => cli::broadcast(&bot, &text).await,
+        }
+    });
+    telemetry::shutdown(tracer);
+    if let Err(e) = result {
+        eprintln!("error: {e:#}");
+        std::process::exit(1);
+    }
+}
+
+/// Commands offered in private chats: name and the message key of its description
+const COMMANDS: &[(&str, &str)] = &[
+    ("register", "command.register"),
+    ("verify", "command.verify"),
+    ("uptime", "command.uptime"),
+    ("status", "command.status"),
+    ("vm", "command.vm"),
+    ("myvms", "command.myvms"),
+    ("pin", "command.pin"),
+    ("chart", "command.chart"),
+    ("unclaimed", "command.unclaimed"),
+    ("next", "command.next"),
+    ("claim", "command.claim"),
+    ("autoclaim", "command.autoclaim"),
+    ("mycards", "command.mycards"),
+    ("deregister", "command.deregister"),
+    ("deletemydata", "command.deletemydata"),
+    ("replace", "command.replace"),
+    ("transfer", "command.transfer"),
+    ("rename", "command.rename"),
+    ("digest", "command.digest"),
+    ("networkstats", "command.networkstats"),
+    ("leaderboard", "command.leaderboard"),
+    ("link", "command.link"),
+    ("referral", "command.referral"),
+    ("how_rewards_work", "command.how_rewards_work"),
+    ("settings", "command.settings"),
+    ("appeal", "command.appeal"),
+    ("menu", "command.menu"),
+    ("help", "command.help"),
+];
+
+/// Groups only get the commands that are safe to answer in front of everyone
+const GROUP_COMMANDS: &[(&str, &str)] = &[
+    ("groupstats", "command.groupstats"),
+    ("networkstats", "command.networkstats"),
+    ("leaderboard", "command.leaderboard"),
+    ("pin", "command.group_pin"),
+];
+
+/// Client languages that get their own command descriptions; everyone else sees English
+const COMMAND_LANGUAGES: [&str; 1] = ["zh"];
+
+/// The command list for `language` (`None` for English), with each description prefixed
+/// by the environment label outside production
+fn localized_commands(commands: &[(&str, &str)], language: Option<&str>) -> Vec<BotCommand> {
+    commands
+        .iter()
+        .map(|&(command, key)| {
+            let description = i18n::text_in(language.unwrap_or("en"), key, &[]);
+            match CONFIG.environment.label() {
+                Some(label) => BotCommand::new(command, format!("{label} {description}")),
+                None => BotCommand::new(command, description),
+            }
+        })
+        .collect()
+}
+
+/// Registers the command lists for private chats and groups, once in English as the
+/// default and once per [`COMMAND_LANGUAGES`] entry, so each client shows its own language
+async fn set_commands(bot: &Bot) {
+    let scopes = [
+        (BotCommandScope::Default, COMMANDS),
+        (BotCommandScope::AllGroupChats, GROUP_COMMANDS),
+    ];
+    for (scope, commands) in scopes {
+        let languages = std::iter::once(None).chain(COMMAND_LANGUAGES.map(Some));
+        for language in languages {
+            let mut request = bot
+                .set_my_commands(localized_commands(commands, language))
+                .scope(scope.clone());
+            if let Some(language) = language {
+                request = request.language_code(language);
+            }
+            if let Err(e) = request.await {
+                tracing::error!("ERROR setting commands ({scope:?}, {language:?}): {e:?}");
+            }
+        }
+    }
+}
+
+/// Runs the bot until shutdown
+async fn serve(bot: Bot) {
+    let _ = sync_bot_name(&bot)
+        .await
+        .map_err(|e| tracing::error!("ERROR setting bot name: {e:?}"));
+    let _ = bot
+        .set_chat_menu_button()
+        .menu_button(MenuButton::Commands)
+        .send()
+        .await
+        .map_err(|e| tracing::error!("ERROR setting chat menu: {e:?}"));
+    set_commands(&bot).await;
+
+    // Every task holds a clone of this token; cancelling it (on SIGTERM or SIGINT) makes
+    // all of them wind down together
+    let shutdown = CancellationToken::new();
+    smolscale::spawn(shutdown::on_signal(shutdown.clone())).detach();
+    smolscale::spawn(reload::on_hangup()).detach();
+    let mut tasks = vec![
+        supervise("telegram", shutdown.clone(), {
+            let (bot, shutdown) = (bot.clone(), shutdown.clone());
+            move || telegram_task(bot.clone(), shutdown.clone())
+        })
+        .boxed(),
+        supervise("fraud_analyzer", shutdown.clone(), {
+            let (bot, shutdown) = (bot.clone(), shutdown.clone());
+            move || fraud::analyze_loop(bot.clone(), shutdown.clone())
+        })
+        .boxed(),
+        supervise("offline_alerts", shutdown.clone(), {
+            let (bot, shutdown) = (bot.clone(), shutdown.clone());
+            move || alerts::offline_alert_loop(bot.clone(), shutdown.clone())
+        })
+        .boxed(),
+        supervise("claim_retry", shutdown.clone(), {
+            let (bot, shutdown) = (bot.clone(), shutdown.clone());
+            move || claim::retry_pending_loop(bot.clone(), shutdown.clone())
+        })
+        .boxed(),
+        supervise("message_cleanup", shutdown.clone(), {
+            let (bot, shutdown) = (bot.clone(), shutdown.clone());
+            move || cleanup::delete_due_loop(bot.clone(), shutdown.clone())
+        })
+        .boxed(),
+        supervise("event_announcements", shutdown.clone(), {
+            let (bot, shutdown) = (bot.clone(), shutdown.clone());
+            move || events::announce_loop(bot.clone(), shutdown.clone())
+        })
+        .boxed(),
+        supervise("weekly_digest", shutdown.clone(), {
+            let (bot, shutdown) = (bot.clone(), shutdown.clone());
+            move || digest::weekly_digest_loop(bot.clone(), shutdown.clone())
+        })
+        .boxed(),
+        supervise("streak_awards", shutdown.clone(), {
+            let (bot, shutdown) = (bot.clone(), shutdown.clone());
+            move || streaks::award_loop(bot.clone(), shutdown.clone())
+        })
+        .boxed(),
+        supervise("referral_rewards", shutdown.clone(), {
+            let (bot, shutdown) = (bot.clone(), shutdown.clone());
+            move || referrals::reward_loop(bot.clone(), shutdown.clone())
+        })
+        .boxed(),
+        supervise("auto_claim", shutdown.clone(), {
+            let (bot, shutdown) = (bot.clone(), shutdown.clone());
+            move || autoclaim::auto_claim_loop(bot.clone(), shutdown.clone())
+        })
+        .boxed(),
+        supervise("pinned_status", shutdown.clone(), {
+            let (bot, shutdown) = (bot.clone(), shutdown.clone());
+            move || pinned::refresh_loop(bot.clone(), shutdown.clone())
+        })
+        .boxed(),
+    ];
+    if CONFIG.uptime_source.polls() {
+        for fleet in CONFIG.fleets() {
+            // Task names live as long as the process, and fleets only change on restart
+            let name: &'static str = if fleet.name == vm_api::DEFAULT_FLEET {
+                "poller"
+            } else {
+                Box::leak(format!("poller:{}", fleet.name).into_boxed_str())
+            };
+            tasks.push(
+                supervise(name, shutdown.clone(), {
+                    let (bot, shutdown) = (bot.clone(), shutdown.clone());
+                    move || update_uptime_loop(bot.clone(), name, fleet.clone(), shutdown.clone())
+                })
+                .boxed(),
+            );
+        }
+    }
+    if let Some(channel) = CONFIG.stats_channel_id {
+        tasks.push(
+            supervise("channel_stats", shutdown.clone(), {
+                let (bot, shutdown) = (bot.clone(), shutdown.clone());
+                move || channel::daily_post_loop(bot.clone(), ChatId(channel), shutdown.clone())
             })
-            .race(async {
-                notify_uptime_loop(bot).await.unwrap();
+            .boxed(),
+        );
+    }
+
+    let mut jobs = vec![
+        Job::new("notify", "0 * * * *", {
+            let bot = bot.clone();
+            move || notify_uptime(bot.clone())
+        }),
+        Job::new("lapse", "30 3 * * *", {
+            let bot = bot.clone();
+            move || lapse::run(bot.clone())
+        }),
+    ];
+    if let Some(dir) = &CONFIG.backup_dir {
+        let every = format!("@every {}h", CONFIG.backup_interval_hours);
+        jobs.push(Job::new("backup", &every, move || backup::back_up(dir)));
+    }
+    let jobs: Arc<[Job]> = jobs.into();
+    tasks.push(
+        supervise("scheduler", shutdown.clone(), {
+            let shutdown = shutdown.clone();
+            move || scheduler::run(jobs.clone(), shutdown.clone())
+        })
+        .boxed(),
+    );
+    if let Some(addr) = CONFIG.http_listen {
+        tasks.push(
+            supervise("http", shutdown.clone(), {
+                let (bot, shutdown) = (bot.clone(), shutdown.clone());
+                move || http::serve(addr, bot.clone(), shutdown.clone())
             })
-            .await;
-    })
+            .boxed(),
+        );
+    }
+    shutdown::finish(&shutdown, futures_util::future::join_all(tasks)).await;
 }
 
-// ---------------------------- Messages (English / 中文) ----------------------------
-const THANKS_ALREADY_REGISTERED: &str = "Thank you for running a testing VM! Your VM is already registered with us.  / 感谢您运行测试 VM！您的 VM 已经注册成功。";
-
-const REGISTER_SUCCESS: &str =
-    "Your VM has been successfully registered! / 您的测试 VM 已成功注册！";
+/// Makes the bot's display name carry the environment label (and only that label)
+async fn sync_bot_name(bot: &Bot) -> Result<(), RequestError> {
+    let current = bot.get_my_name().await?.name;
+    let base = [Environment::Staging, Environment::Dev]
+        .into_iter()
+        .filter_map(Environment::label)
+        .find_map(|label| current.strip_suffix(&format!(" {label}")))
+        .unwrap_or(&current);
+    let wanted = match CONFIG.environment.label() {
+        Some(label) => format!("{base} {label}"),
+        None => base.to_owned(),
+    };
+    if wanted != current {
+        bot.set_my_name().name(wanted).await?;
+    }
+    Ok(())
+}
 
-const GREETING: &str = "Hey there!
+/// Runs the Telegram dispatcher until shutdown
+async fn telegram_task(bot: Bot, shutdown: CancellationToken) -> anyhow::Result<()> {
+    let me = bot.get_me().await?;
+    tokens::set_bot_username(me.username());
+    let updates = dptree::entry()
+        .branch(Update::filter_message().endpoint(handler))
+        .branch(Update::filter_callback_query().endpoint(callback_handler::<Bot>))
+        .branch(Update::filter_inline_query().endpoint(inline::handle));
+    let mut dispatcher = Dispatcher::builder(bot, updates)
+        .dependencies(dptree::deps![shutdown.clone()])
+        // Private chats are reported by `bot_error::report`; this is what's left, from
+        // groups and inline queries
+        .error_handler(Arc::new(|e: BotError| async move {
+            tracing::error!("handling an update failed: {e}");
+            reporting::capture_error(&e, reporting::Origin::default());
+        }))
+        .build();
+    let dispatcher_shutdown = dispatcher.shutdown_token();
+    dispatcher
+        .dispatch()
+        .race(async {
+            shutdown.cancelled().await;
+            if let Ok(stopped) = dispatcher_shutdown.shutdown() {
+                stopped.await;
+            }
+        })
+        .await;
+    // Left on its own, the dispatcher only returns once shut down
+    shutdown.cancel();
+    Ok(())
+}
 
-To register your testing VM to receive Plus, send us your VM ID with /register vm_id. Make sure your VM is running when you register. / 嗨！若要注册您的测试 VM 并领取 Plus，请使用 /register vm_id。请确保在注册时您的 VM 正在运行。";
+/// Records that the supervised task `task` finished a tick, then waits for the next tick
+/// of `ticker`, returning `false` instead if shutdown is requested first
+async fn next_tick(
+    task: &'static str,
+    ticker: &mut smol::Timer,
+    shutdown: &CancellationToken,
+) -> bool {
+    supervisor::tick(task);
+    async {
+        ticker.next().await;
+        true
+    }
+    .or(async {
+        shutdown.cancelled().await;
+        false
+    })
+    .await
+}
 
-const INVALID_VM: &str = "What you gave me is not a valid VM ID - please double check! / 您给我的不是有效的虚拟机 ID - 请再次检查！";
+/// Sleeps for `delay`, returning `false` instead if shutdown is requested first
+async fn sleep_or_shutdown(delay: Duration, shutdown: &CancellationToken) -> bool {
+    async {
+        smol::Timer::after(delay).await;
+        true
+    }
+    .or(async {
+        shutdown.cancelled().await;
+        false
+    })
+    .await
+}
 
 #[derive(Clone, Debug)]
 enum Command {
     Register(String),
+    Verify,
     Uptime,
+    Status,
+    /// One VM in detail, by id or nickname; `None` picks the chat's only VM or asks which
+    Vm(Option<String>),
+    /// Page of the chat's VMs with buttons for each, counted from 0
+    MyVms(usize),
+    /// Pins a status message that keeps itself up to date, or unpins it when `false`
+    Pin(bool),
+    Chart,
     Unclaimed,
-    Claim,
-    Deregister,
+    /// Time until the next Plus day is earned
+    Next,
+    /// Offers a choice of card splits for that many days, or the whole balance when `None`
+    Claim(Option<i64>),
+    ClaimSplit(claim::Split),
+    /// `Some` re-sends that card's code
+    MyCards(Option<i64>),
+    /// Without `confirmed`, shows what would be forfeited and asks first. `vm` picks one
+    /// VM by id or nickname, otherwise all of the chat's are deregistered.
+    Deregister {
+        vm: Option<String>,
+        confirmed: bool,
+    },
+    /// Without `confirmed`, only explains what would be erased
+    DeleteMyData {
+        confirmed: bool,
+    },
+    Replace {
+        old_vm: String,
+        new_vm: String,
+    },
+    /// Issues a code handing the VM to another chat
+    Transfer {
+        vm: String,
+        with_balance: bool,
+    },
+    /// Redeems a transfer code
+    AcceptTransfer(String),
+    /// An empty `nickname` clears it
+    Rename {
+        vm: String,
+        nickname: String,
+    },
+    /// `None` previews the digest, `Some` opts in or out
+    Digest(Option<bool>),
+    /// `None` shows the current settings
+    Settings(Option<(prefs::Pref, bool)>),
+    HowRewardsWork,
     Menu,
+    NetworkStats,
+    Leaderboard,
+    /// Totals over the testers seen in a group
+    GroupStats,
+    /// `Some` shows the chat under that name on the leaderboard, `None` anonymizes it again
+    LeaderboardName(Option<String>),
+    Referral,
+    /// Shows the automatic claiming schedule
+    AutoClaim,
+    /// `None` turns automatic claiming off
+    SetAutoClaim(Option<autoclaim::Schedule>),
+    /// `None` shows the linked Geph account
+    Link(Option<String>),
+    Unlink,
+    Help(Option<String>),
+    /// Asks for what the command was missing; the next plain message answers it
+    Ask(Dialogue),
+    /// Drops the question the bot is waiting on
+    Cancel,
+    /// Sends the statement to the admins, for a banned or flagged chat; empty asks for one
+    Appeal(String),
+    Admin(AdminCommand),
+}
+
+/// Shorter or more familiar names accepted for commands, with the command they stand for
+const COMMAND_ALIASES: &[(&str, &str)] = &[
+    ("/reg", "/register"),
+    ("/balance", "/unclaimed"),
+    ("/cards", "/mycards"),
+    ("/online", "/status"),
+    ("/stats", "/networkstats"),
+    ("/rewards", "/how_rewards_work"),
+];
+
+/// The command `text` starts with, for logs; arguments can hold codes and ids
+fn command_word(text: &str) -> &str {
+    text.split_whitespace()
+        .next()
+        .filter(|word| word.starts_with('/'))
+        .unwrap_or("(text)")
+}
+
+/// `/transfer`'s arguments: a code to redeem, or a VM optionally followed by "balance"
+fn parse_transfer(first: &str, second: Option<&str>) -> Option<Command> {
+    match (first, second) {
+        (code, None) if code.starts_with(transfer::CODE_PREFIX) => {
+            Some(Command::AcceptTransfer(code.to_owned()))
+        }
+        (vm, None) => Some(Command::Transfer {
+            vm: vm.to_owned(),
+            with_balance: false,
+        }),
+        (vm, Some("balance")) => Some(Command::Transfer {
+            vm: vm.to_owned(),
+            with_balance: true,
+        }),
+        (_, Some(_)) => None,
+    }
+}
+
+/// Words taken as "yes" when the bot asked for a confirmation
+const CONFIRMATIONS: &[&str] = &["yes", "y", "confirm", "ok", "是", "确认", "确定"];
+
+/// The command a plain message stands for while the chat is at `dialogue`: the VM id asked
+/// for during onboarding, or the answer to a question a command asked. A confirmation
+/// answered with anything but "yes" cancels it.
+fn answer_dialogue(dialogue: Dialogue, text: &str, registered: bool) -> Option<Command> {
+    let text = text.trim();
+    let mut words = text.split_whitespace();
+    match dialogue {
+        // A one-word message is the VM id we asked for
+        _ if dialogue.is_onboarding() => {
+            (!registered && !text.contains(' ')).then(|| Command::Register(text.to_owned()))
+        }
+        Dialogue::AwaitingTransferVm => parse_transfer(words.next()?, words.next()),
+        Dialogue::AwaitingReplacementVm { old_vm } => match (words.next()?, words.next()) {
+            (new_vm, None) => Some(Command::Replace {
+                old_vm,
+                new_vm: new_vm.to_owned(),
+            }),
+            _ => None,
+        },
+        Dialogue::AwaitingNickname { vm_id } => Some(Command::Rename {
+            vm: vm_id,
+            nickname: text.to_owned(),
+        }),
+        Dialogue::AwaitingConfirmation { command } => {
+            if CONFIRMATIONS.contains(&text.to_lowercase().as_str()) {
+                parse_command(&command)
+            } else {
+                Some(Command::Cancel)
+            }
+        }
+        _ => None,
+    }
 }
 
 fn parse_command(text: &str) -> Option<Command> {
-    let mut words = text.trim().split_whitespace();
+    let mut words = text.split_whitespace();
     let first = words.next()?;
     // Allow an optional leading mention like "@BotName"
     let cmd = if first.starts_with('/') {
         first
-    } else if first.starts_with('@') {
+    } else if let Some(mention) = first.strip_prefix('@') {
+        if !tokens::is_bot_username(mention) {
+            return None;
+        }
         words.next()?
     } else {
         return None;
     };
+    // Whatever follows the command word, untouched
+    let rest = &text[text.find(cmd).unwrap_or(0) + cmd.len()..];
+    // Groups address commands as "/command@BotName"; ones meant for another bot aren't ours
+    let cmd = match cmd.split_once('@') {
+        Some((_, mention)) if !tokens::is_bot_username(mention) => return None,
+        Some((cmd, _)) => cmd,
+        None => cmd,
+    };
+    let cmd = cmd.to_lowercase();
+    let cmd = COMMAND_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == cmd)
+        .map_or(cmd.as_str(), |&(_, command)| command);
     match cmd {
         "/register" => words.next().map(|id| Command::Register(id.to_owned())),
+        "/verify" => Some(Command::Verify),
         "/uptime" => Some(Command::Uptime),
+        "/status" => Some(Command::Status),
+        "/myvms" => match words.next() {
+            None => Some(Command::MyVms(0)),
+            // The rename buttons; `/rename <vm>` alone clears the nickname instead
+            Some("rename") => Some(Command::Ask(Dialogue::AwaitingNickname {
+                vm_id: words.next()?.to_owned(),
+            })),
+            Some(page) => page
+                .parse::<usize>()
+                .ok()
+                .map(|page| Command::MyVms(page.saturating_sub(1))),
+        },
+        "/vm" => {
+            let vm = words.collect::<Vec<_>>().join(" ");
+            Some(Command::Vm(Some(vm).filter(|vm| !vm.is_empty())))
+        }
+        "/pin" => match words.next() {
+            None => Some(Command::Pin(true)),
+            Some("off") => Some(Command::Pin(false)),
+            Some(_) => None,
+        },
+        "/unpin" => Some(Command::Pin(false)),
+        "/chart" => Some(Command::Chart),
         "/unclaimed" => Some(Command::Unclaimed),
-        "/claim" => Some(Command::Claim),
-        "/deregister" => Some(Command::Deregister),
+        "/next" => Some(Command::Next),
+        "/claim" => match words.next() {
+            None => Some(Command::Claim(None)),
+            Some(arg) => match claim::Split::parse(arg) {
+                Some(split) => Some(Command::ClaimSplit(split)),
+                None => arg.parse().ok().map(|days| Command::Claim(Some(days))),
+            },
+        },
+        // `/history` predates `/mycards` and still lists the cards
+        "/history" => Some(Command::MyCards(None)),
+        "/mycards" => match words.next() {
+            None => Some(Command::MyCards(None)),
+            Some(id) => id
+                .trim_start_matches('#')
+                .parse()
+                .ok()
+                .map(|id| Command::MyCards(Some(id))),
+        },
+        "/deregister" => {
+            // A nickname may have spaces, so the VM is everything before "confirm"
+            let mut words: Vec<&str> = words.collect();
+            let confirmed = words.last() == Some(&"confirm");
+            if confirmed {
+                words.pop();
+            }
+            Some(Command::Deregister {
+                vm: Some(words.join(" ")).filter(|vm| !vm.is_empty()),
+                confirmed,
+            })
+        }
+        "/deletemydata" => match words.next() {
+            None => Some(Command::DeleteMyData { confirmed: false }),
+            Some("confirm") => Some(Command::DeleteMyData { confirmed: true }),
+            Some(_) => None,
+        },
+        "/replace" => {
+            let old_vm = words.next()?.to_owned();
+            match words.next() {
+                Some(new_vm) => Some(Command::Replace {
+                    old_vm,
+                    new_vm: new_vm.to_owned(),
+                }),
+                None => Some(Command::Ask(Dialogue::AwaitingReplacementVm { old_vm })),
+            }
+        }
+        "/transfer" => match words.next() {
+            None => Some(Command::Ask(Dialogue::AwaitingTransferVm)),
+            Some(first) => parse_transfer(first, words.next()),
+        },
+        "/cancel" => Some(Command::Cancel),
+        "/appeal" => Some(Command::Appeal(rest.trim().to_owned())),
+        "/rename" => Some(Command::Rename {
+            vm: words.next()?.to_owned(),
+            nickname: words.collect::<Vec<_>>().join(" "),
+        }),
+        "/digest" => match words.next() {
+            None => Some(Command::Digest(None)),
+            Some("on") => Some(Command::Digest(Some(true))),
+            Some("off") => Some(Command::Digest(Some(false))),
+            Some(_) => None,
+        },
+        "/settings" => match (words.next(), words.next()) {
+            (None, _) => Some(Command::Settings(None)),
+            (Some(name), Some(value)) => {
+                let pref = prefs::Pref::from_name(name)?;
+                let enabled = match value {
+                    "on" => true,
+                    "off" => false,
+                    _ => return None,
+                };
+                Some(Command::Settings(Some((pref, enabled))))
+            }
+            (Some(_), None) => None,
+        },
+        "/how_rewards_work" => Some(Command::HowRewardsWork),
         "/menu" => Some(Command::Menu),
+        "/networkstats" => Some(Command::NetworkStats),
+        "/groupstats" => Some(Command::GroupStats),
+        "/leaderboard" => match words.next() {
+            None => Some(Command::Leaderboard),
+            Some("name") => {
+                let name = words.collect::<Vec<_>>().join(" ");
+                Some(Command::LeaderboardName(Some(name)))
+            }
+            Some("anonymous") => Some(Command::LeaderboardName(None)),
+            Some(_) => None,
+        },
+        "/referral" => Some(Command::Referral),
+        "/autoclaim" => match words.next() {
+            None => Some(Command::AutoClaim),
+            Some("off") => Some(Command::SetAutoClaim(None)),
+            Some(arg) => autoclaim::Schedule::parse(arg).map(|s| Command::SetAutoClaim(Some(s))),
+        },
+        "/link" => Some(Command::Link(words.next().map(str::to_owned))),
+        "/unlink" => Some(Command::Unlink),
+        "/help" => Some(Command::Help(words.next().map(str::to_owned))),
+        // Broadcast text is taken verbatim, line breaks and all
+        "/admin" => admin::parse(rest).map(Command::Admin),
+        "/admin_selftest" => Some(Command::Admin(AdminCommand::SelfTest)),
         _ => None,
     }
 }
@@ -159,263 +1536,2666 @@ fn parse_command(text: &str) -> Option<Command> {
 fn menu_markup(registered: bool) -> InlineKeyboardMarkup {
     if registered {
         InlineKeyboardMarkup::new(vec![
-            vec![InlineKeyboardButton::switch_inline_query_current_chat(
-                "My VM's total uptime / 我的 VM 总运行时间",
+            vec![render::command_button(
+                i18n::text("menu.uptime", &[]),
                 "/uptime",
             )],
-            vec![InlineKeyboardButton::switch_inline_query_current_chat(
-                "View unclaimed Plus days / 查看未领取的 Plus 天数",
+            vec![render::command_button(
+                i18n::text("menu.status", &[]),
+                "/status",
+            )],
+            vec![render::command_button(
+                i18n::text("menu.chart", &[]),
+                "/chart",
+            )],
+            vec![render::command_button(
+                i18n::text("menu.unclaimed", &[]),
                 "/unclaimed",
             )],
-            vec![InlineKeyboardButton::switch_inline_query_current_chat(
-                "Claim Plus / 领取 Plus",
+            vec![render::command_button(
+                i18n::text("menu.claim", &[]),
                 "/claim",
             )],
-            vec![InlineKeyboardButton::switch_inline_query_current_chat(
-                "Deregister VM / 取消注册 VM",
+            vec![render::command_button(
+                i18n::text("menu.mycards", &[]),
+                "/mycards",
+            )],
+            vec![render::command_button(
+                i18n::text("menu.deregister", &[]),
                 "/deregister",
             )],
         ])
     } else {
         InlineKeyboardMarkup::new(vec![vec![
             InlineKeyboardButton::switch_inline_query_current_chat(
-                "Register VM / 注册 VM",
+                i18n::text("menu.register", &[]),
                 "/register ",
             ),
         ]])
     }
 }
 
-async fn send_menu(bot: &Bot, chat_id: ChatId, registered: bool) -> Result<(), RequestError> {
-    bot.send_message(chat_id, "Choose a command: / 请选择一个命令：")
+/// Shows the menu by editing the last one sent to the chat, so repeated `/menu`s don't pile
+/// up; a new message is sent when there is none yet or it can't be edited any more
+async fn send_menu(
+    bot: &impl telegram::Telegram,
+    chat_id: ChatId,
+    registered: bool,
+) -> Result<(), BotError> {
+    let text = i18n::text("menu", &[]);
+    let last: Option<i64> =
+        sqlx::query_scalar("SELECT message_id FROM menu_messages WHERE telegram_chat_id = $1")
+            .bind(chat_id.0)
+            .fetch_optional(&*DB)
+            .await
+            .context("loading the menu message")?;
+    if let Some(message_id) = last.and_then(|id| i32::try_from(id).ok()) {
+        match bot
+            .edit_message_text(chat_id, MessageId(message_id), &text)
+            .reply_markup(menu_markup(registered))
+            .await
+        {
+            Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => return Ok(()),
+            // Deleted by the user, or otherwise out of reach; start over with a fresh one
+            Err(e) => tracing::debug!("editing menu {message_id} in {chat_id} failed: {e}"),
+        }
+    }
+    let sent = bot
+        .send_message(chat_id, text)
         .reply_markup(menu_markup(registered))
         .await?;
-    Ok(())
-}
+    // The menu is already out; failing to remember it only means the next one is new too
+    let remembered = sqlx::query(
+        r#"
+INSERT INTO menu_messages (telegram_chat_id, message_id, updated_at) VALUES ($1, $2, $3)
+ON CONFLICT(telegram_chat_id) DO UPDATE SET
+    message_id = excluded.message_id,
+    updated_at = excluded.updated_at
+        "#,
+    )
+    .bind(chat_id.0)
+    .bind(i64::from(sent.0))
+    .bind(now_unix())
+    .execute(&*DB)
+    .await;
+    if let Err(e) = remembered {
+        tracing::warn!("remembering the menu of {chat_id} failed: {e}");
+    }
+    Ok(())
+}
+
+/// `(vm_id, nickname, last_seen, online_since)` of a VM listed by `/status`
+type StatusRow = (String, Option<String>, Option<i64>, Option<i64>);
+
+/// VMs per page of `/myvms`
+const MY_VMS_PAGE_SIZE: usize = 5;
+
+/// The status of each of `chat_id`'s VMs, by id
+async fn vm_statuses(chat_id: ChatId) -> sqlx::Result<Vec<StatusRow>> {
+    sqlx::query_as(
+        r#"
+SELECT a.vm_id, a.nickname, s.last_seen, s.online_since
+FROM agent_records a LEFT JOIN vm_status s ON s.vm_id = a.vm_id
+WHERE a.telegram_chat_id = $1
+ORDER BY a.vm_id
+        "#,
+    )
+    .bind(chat_id.0)
+    .fetch_all(&*DB)
+    .await
+}
+
+/// Page `page` of `/myvms`, or the last one if there are fewer: each VM's status, with
+/// details, rename and deregister buttons for it, and buttons to the neighbouring pages
+fn my_vms_page(vms: &[StatusRow], page: usize, now: i64) -> (String, InlineKeyboardMarkup) {
+    let pages = vms.len().div_ceil(MY_VMS_PAGE_SIZE).max(1);
+    let page = page.min(pages - 1);
+    let shown = vms
+        .iter()
+        .skip(page * MY_VMS_PAGE_SIZE)
+        .take(MY_VMS_PAGE_SIZE);
+    let mut lines = vec![if pages > 1 {
+        let n = page + 1;
+        i18n::text("myvms.title_page", &[("n", &n), ("pages", &pages)])
+    } else {
+        i18n::text("myvms.title", &[])
+    }];
+    let mut buttons = vec![];
+    for row in shown {
+        let (vm_id, nickname, ..) = row;
+        lines.push(render::vm_status_line(row, now));
+        buttons.push(vec![
+            render::command_button(
+                format!("ℹ️ {}", nicknames::label(vm_id, nickname.as_deref())),
+                format!("/vm {vm_id}"),
+            ),
+            render::command_button(
+                i18n::text("myvms.rename", &[]),
+                format!("/myvms rename {vm_id}"),
+            ),
+            render::command_button(
+                i18n::text("myvms.deregister", &[]),
+                format!("/deregister {vm_id}"),
+            ),
+        ]);
+    }
+    let mut nav = vec![];
+    if page > 0 {
+        nav.push(render::command_button(
+            i18n::text("myvms.previous", &[]),
+            format!("/myvms {page}"),
+        ));
+    }
+    if page + 1 < pages {
+        nav.push(render::command_button(
+            i18n::text("myvms.next", &[]),
+            format!("/myvms {}", page + 2),
+        ));
+    }
+    if !nav.is_empty() {
+        buttons.push(nav);
+    }
+    (lines.join("\n"), InlineKeyboardMarkup::new(buttons))
+}
+
+/// Yes/No buttons under the `/deregister` warning, where yes sends `command`
+fn deregister_markup(command: &str) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![
+        render::command_button(i18n::text("deregister.yes", &[]), command),
+        render::command_button(i18n::text("deregister.no", &[]), "/cancel"),
+    ]])
+}
+
+/// Buttons for the usual automatic claiming schedules
+fn autoclaim_markup() -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![
+        vec![render::command_button(
+            i18n::text("autoclaim.every_sunday", &[]),
+            "/autoclaim sunday",
+        )],
+        vec![render::command_button(
+            i18n::text("autoclaim.every_week", &[]),
+            "/autoclaim 7",
+        )],
+        vec![render::command_button(
+            i18n::text("autoclaim.off_button", &[]),
+            "/autoclaim off",
+        )],
+    ])
+}
+
+/// Buttons for the usual ways to split `days` into giftcards
+fn split_markup(days: i64) -> InlineKeyboardMarkup {
+    let mut rows = vec![vec![render::command_button(
+        i18n::text("claim.one_card", &[("count", &days)]),
+        format!("/claim 1x{days}"),
+    )]];
+    if days <= claim::MAX_CARDS {
+        rows.push(vec![render::command_button(
+            i18n::text("claim.daily_cards", &[("count", &days)]),
+            format!("/claim {days}x1"),
+        )]);
+    }
+    rows.push(vec![
+        InlineKeyboardButton::switch_inline_query_current_chat(
+            i18n::text("claim.custom", &[]),
+            "/claim ",
+        ),
+    ]);
+    InlineKeyboardMarkup::new(rows)
+}
+
+async fn send_claim_outcome(
+    bot: &impl telegram::Telegram,
+    chat_id: ChatId,
+    outcome: ClaimOutcome,
+) -> Result<(), RequestError> {
+    match outcome {
+        ClaimOutcome::Issued { giftcards } => {
+            send_status(
+                bot,
+                chat_id,
+                Indicator::Gift,
+                i18n::text("claim.issued", &[]),
+            )
+            .await?;
+            send_codes(bot, chat_id, &giftcards).await?;
+        }
+        ClaimOutcome::Credited { days, username } => {
+            send_status(
+                bot,
+                chat_id,
+                Indicator::Gift,
+                i18n::text(
+                    "claim.credited",
+                    &[("count", &days), ("username", &username)],
+                ),
+            )
+            .await?;
+        }
+        ClaimOutcome::NothingToClaim => {
+            send_status(
+                bot,
+                chat_id,
+                Indicator::Empty,
+                i18n::text("claim.nothing", &[]),
+            )
+            .await?;
+        }
+        ClaimOutcome::NotEnough { available } => {
+            send_status(
+                bot,
+                chat_id,
+                Indicator::Balance,
+                i18n::text("claim.not_enough", &[("count", &available)]),
+            )
+            .await?;
+        }
+        ClaimOutcome::Queued { days } => {
+            tracing::info!("{days} day(s) queued for chat {chat_id}");
+            send_error(bot, chat_id, ErrorCode::GiftcardBackendDown).await?;
+        }
+        ClaimOutcome::InProgress => {
+            send_error(bot, chat_id, ErrorCode::ClaimInProgress).await?;
+        }
+        ClaimOutcome::UnderReview => {
+            send_error(bot, chat_id, ErrorCode::ClaimUnderReview).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Answers `/start` according to where the chat left off: returning testers get their
+/// summary, and anyone who began registering picks up at the step they reached
+async fn start(
+    bot: &impl telegram::Telegram,
+    chat_id: ChatId,
+    registered: bool,
+) -> Result<(), BotError> {
+    if registered {
+        let (vms, online, up_secs, unclaimed_secs): (i64, i64, i64, i64) = sqlx::query_as(
+            r#"
+SELECT
+    COUNT(*),
+    COUNT(CASE WHEN s.last_seen >= $2 THEN 1 END),
+    CAST(COALESCE(SUM(a.up_secs), 0) AS BIGINT),
+    CAST(COALESCE(SUM(a.up_secs + a.bonus_secs - a.paid_secs), 0) AS BIGINT)
+FROM agent_records a LEFT JOIN vm_status s ON s.vm_id = a.vm_id
+WHERE a.telegram_chat_id = $1
+            "#,
+        )
+        .bind(chat_id.0)
+        .bind(now_unix() - OFFLINE_AFTER_SECS)
+        .fetch_one(&*DB)
+        .await
+        .context("summing uptime for the welcome back")?;
+        let uptime = render::format_duration(up_secs);
+        let unclaimed_days = RewardPolicy::current().days(unclaimed_secs);
+        send_status(
+            bot,
+            chat_id,
+            Indicator::Uptime,
+            i18n::text(
+                "start.welcome_back",
+                &[
+                    ("online", &online),
+                    ("vms", &vms),
+                    ("uptime", &uptime),
+                    ("count", &unclaimed_days),
+                ],
+            ),
+        )
+        .await?;
+        return send_menu(bot, chat_id, true).await;
+    }
+
+    let resumed = dialogue::load(chat_id)
+        .await
+        .context("loading the dialogue")?;
+    match resumed {
+        Some(Dialogue::AwaitingVmId) => {
+            bot.send_message(chat_id, i18n::text("resume_onboarding", &[]))
+                .await?;
+        }
+        Some(Dialogue::VmNotFound { vm_id }) => {
+            bot.send_message(
+                chat_id,
+                i18n::text("start.vm_not_found", &[("vm_id", &vm_id)]),
+            )
+            .await?;
+        }
+        Some(Dialogue::AwaitingOwnershipProof { vm_id }) => {
+            bot.send_message(
+                chat_id,
+                i18n::text("start.awaiting_proof", &[("vm_id", &vm_id)]),
+            )
+            .await?;
+        }
+        // Questions asked by a command don't survive deregistering
+        _ => {
+            bot.send_message(chat_id, i18n::text("greeting", &[]))
+                .await?;
+            dialogue::save(chat_id, &Dialogue::AwaitingVmId)
+                .await
+                .context("starting onboarding")?;
+        }
+    }
+    send_menu(bot, chat_id, false).await
+}
 
 // ---------------------------- Telegram handler ----------------------------
-async fn handler(bot: Bot, msg: Message) -> Result<(), RequestError> {
+#[tracing::instrument(name = "message", skip_all, fields(chat_id = %msg.chat.id))]
+async fn handler(bot: Bot, msg: Message, shutdown: CancellationToken) -> Result<(), BotError> {
+    let migration = match (msg.migrate_to_chat_id(), msg.migrate_from_chat_id()) {
+        (Some(&to), _) => Some((msg.chat.id, to)),
+        (_, Some(&from)) => Some((from, msg.chat.id)),
+        _ => None,
+    };
+    if let Some((from, to)) = migration {
+        chat_migration::migrate(from, to)
+            .await
+            .context("migrating the chat")?;
+        return Ok(());
+    }
+
+    if let Some(user) = msg.left_chat_member() {
+        groups::left(msg.chat.id, user.id)
+            .await
+            .context("forgetting a group member")?;
+        return Ok(());
+    }
+
     let Some(text) = msg.text() else {
         return Ok(());
     };
     let chat_id = msg.chat.id;
+    // In groups it's the sender who may be banned, by the id of their private chat;
+    // private chats are checked by run_command, which buttons go through too
+    let sender = msg.from.as_ref().map(|user| ChatId(user.id.0 as i64));
+    if !msg.chat.is_private()
+        && let Some(sender) = sender
+        && bans::is_banned(sender).await.context("checking bans")?
+    {
+        return Ok(());
+    }
+
+    if community::is_community_chat(chat_id) {
+        return community::handle(&bot, &msg, text).await;
+    }
+    if msg.chat.is_group() || msg.chat.is_supergroup() {
+        return groups::handle(&bot, &msg, text).await;
+    }
+    // Groups only get the failure logged; a private chat is told to try again
+    let result = run_command(bot.clone(), chat_id, text, shutdown).await;
+    bot_error::report(&bot, chat_id, command_word(text), result).await;
+    Ok(())
+}
+
+/// Runs the command behind a tapped menu button, as if the chat had sent it
+#[tracing::instrument(name = "callback", skip_all, fields(chat_id = tracing::field::Empty))]
+async fn callback_handler<T: telegram::Telegram>(
+    bot: T,
+    query: CallbackQuery,
+    shutdown: CancellationToken,
+) -> Result<(), BotError> {
+    // Answer straight away so the button stops spinning, whatever the command does
+    bot.answer_callback_query(query.id.clone()).await?;
+    let (Some(text), Some(message)) = (query.data.as_deref(), query.message.as_ref()) else {
+        return Ok(());
+    };
+    let chat_id = message.chat().id;
+    tracing::Span::current().record("chat_id", tracing::field::display(chat_id));
+    if community::is_community_chat(chat_id) {
+        return Ok(());
+    }
+    let result = run_command(bot.clone(), chat_id, text, shutdown).await;
+    bot_error::report(&bot, chat_id, command_word(text), result).await;
+    Ok(())
+}
+
+/// Handles `text` from a private chat, typed or sent by a menu button
+#[tracing::instrument(name = "command", skip_all, fields(command = command_word(text)))]
+async fn run_command<T: telegram::Telegram>(
+    bot: T,
+    chat_id: ChatId,
+    text: &str,
+    shutdown: CancellationToken,
+) -> Result<(), BotError> {
+    if shutdown.is_cancelled() {
+        send_error(&bot, chat_id, ErrorCode::Maintenance).await?;
+        return Ok(());
+    }
+
+    // Banned chats get no answer at all, not even an error, except to an appeal
+    if !matches!(parse_command(text), Some(Command::Appeal(_)))
+        && bans::is_banned(chat_id).await.context("checking bans")?
+    {
+        tracing::debug!("dropping a command from banned chat {chat_id}");
+        return Ok(());
+    }
+
+    if let Err(e) = inactive::reactivate(chat_id).await {
+        tracing::warn!("could not reactivate chat {chat_id}: {e}");
+    }
+
+    match ratelimit::check(chat_id) {
+        ratelimit::Verdict::Allowed => {}
+        ratelimit::Verdict::Limited { warn } => {
+            if warn {
+                send_error(&bot, chat_id, ErrorCode::SlowDown).await?;
+            }
+            return Ok(());
+        }
+    }
 
-    log::debug!("received message w/ text={text}");
+    tracing::debug!("received message w/ text={text}");
 
-    let registered = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM agent_records WHERE telegram_chat_id = ?)",
+    let registered = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM agent_records WHERE telegram_chat_id = $1",
     )
     .bind(chat_id.0)
     .fetch_one(&*DB)
     .await
-    .map_err(|_| RequestError::RetryAfter(Seconds::from_seconds(5)))?;
+    .context("checking registration")?
+        > 0;
 
-    if text == "/start" || text == "/menu" {
+    if text == "/start" || text == format!("/start {}", inline::START_PARAMETER) {
+        return start(&bot, chat_id, registered).await;
+    }
+    // Referral links arrive as "/start ref-<code>"
+    if let Some(code) = text
+        .strip_prefix("/start ")
+        .map(str::trim)
+        .filter(|payload| payload.starts_with(referrals::CODE_PREFIX))
+    {
+        let attached = referrals::attach(chat_id, code)
+            .await
+            .context("attaching a referral")?;
+        let hours = CONFIG.referral_bonus_hours;
+        let reply = match attached {
+            referrals::Attach::Recorded => i18n::text("referral.invited", &[("count", &hours)]),
+            referrals::Attach::OwnLink => i18n::text("referral.own_link", &[]),
+            referrals::Attach::NotNew => i18n::text("referral.not_new", &[]),
+            referrals::Attach::UnknownCode => i18n::text("referral.unknown_code", &[]),
+        };
+        bot.send_message(chat_id, reply).await?;
+        return start(&bot, chat_id, registered).await;
+    }
+    // Deep links (`t.me/<bot>?start=<token>`) arrive as "/start <token>"
+    let text = match text.strip_prefix("/start ") {
+        Some(payload) if payload.trim().starts_with(tokens::TOKEN_PREFIX) => {
+            &format!("/register {}", payload.trim())
+        }
+        Some(payload) if payload.trim().starts_with(transfer::CODE_PREFIX) => {
+            &format!("/transfer {}", payload.trim())
+        }
+        _ => text,
+    };
+    if text == "/menu" {
         send_menu(&bot, chat_id, registered).await?;
         return Ok(());
     }
 
-    match parse_command(text) {
-        Some(Command::Register(vm_id)) => {
+    let command = match parse_command(text) {
+        // A plain message may be the answer to something we asked
+        None if !text.starts_with('/') => {
+            let answered = async {
+                let Some(dialogue) = dialogue::load(chat_id).await? else {
+                    return Ok(None);
+                };
+                // A question is asked once; onboarding steps move on in the handlers
+                if !dialogue.is_onboarding() {
+                    dialogue::clear(chat_id).await?;
+                }
+                Ok::<_, sqlx::Error>(answer_dialogue(dialogue, text, registered))
+            };
+            answered.await.context("answering the dialogue")?
+        }
+        Some(command) => {
+            dialogue::drop_question(chat_id)
+                .await
+                .context("dropping an abandoned question")?;
+            Some(command)
+        }
+        None => None,
+    };
+    match command {
+        Some(Command::Register(vm_id_or_token)) => {
             if registered {
-                bot.send_message(chat_id, THANKS_ALREADY_REGISTERED).await?;
+                bot.send_message(chat_id, i18n::text("already_registered", &[]))
+                    .await?;
             } else {
-                let result: SqliteQueryResult = sqlx::query(
-                    "UPDATE agent_records SET telegram_chat_id = $1 WHERE vm_id = $2 AND telegram_chat_id IS NULL",
-                )
-                .bind(chat_id.0)
-                .bind(vm_id)
-                .execute(&*DB)
-                .await.map_err(|e| {log::debug!("ERROR: {e}"); RequestError::RetryAfter(Seconds::from_seconds(2))})?;
-                if result.rows_affected() > 0 {
-                    bot.send_message(chat_id, REGISTER_SUCCESS).await?;
+                let token_vm = tokens::resolve(&vm_id_or_token)
+                    .await
+                    .context("resolving a registration token")?;
+                let needs_proof = token_vm.is_none()
+                    && CONFIG.require_ownership_proof
+                    && ownership::is_unlinked(&vm_id_or_token)
+                        .await
+                        .context("checking whether the VM is unlinked")?;
+                if needs_proof {
+                    let token = ownership::challenge(chat_id, &vm_id_or_token)
+                        .await
+                        .context("issuing an ownership token")?;
+                    dialogue::save(
+                        chat_id,
+                        &Dialogue::AwaitingOwnershipProof {
+                            vm_id: vm_id_or_token.clone(),
+                        },
+                    )
+                    .await
+                    .context("saving the ownership step")?;
+                    bot.send_message(
+                        chat_id,
+                        i18n::text(
+                            "register.prove",
+                            &[("vm", &vm_id_or_token), ("token", &token)],
+                        ),
+                    )
+                    .reply_markup(InlineKeyboardMarkup::new(vec![vec![
+                        render::command_button(
+                            i18n::text("register.verify_button", &[]),
+                            "/verify",
+                        ),
+                    ]]))
+                    .await?;
+                    return Ok(());
+                }
+                let vm_id = token_vm.as_deref().unwrap_or(&vm_id_or_token);
+                let linked = link_vm(chat_id, vm_id, token_vm.is_some())
+                    .await
+                    .context("linking the VM")?;
+                if linked {
+                    if token_vm.is_some() {
+                        tokens::consume(&vm_id_or_token)
+                            .await
+                            .context("consuming the registration token")?;
+                    }
+                    dialogue::clear(chat_id)
+                        .await
+                        .context("finishing onboarding")?;
+                    send_status(
+                        &bot,
+                        chat_id,
+                        Indicator::Success,
+                        i18n::text("register_success", &[]),
+                    )
+                    .await?;
                     send_menu(&bot, chat_id, true).await?;
                 } else {
-                    bot.send_message(chat_id, INVALID_VM).await?;
+                    let (code, next) = if vm_id_or_token.starts_with(tokens::TOKEN_PREFIX) {
+                        (ErrorCode::InvalidToken, Dialogue::AwaitingVmId)
+                    } else {
+                        (
+                            ErrorCode::UnknownVm,
+                            Dialogue::VmNotFound {
+                                vm_id: vm_id_or_token.clone(),
+                            },
+                        )
+                    };
+                    dialogue::save(chat_id, &next)
+                        .await
+                        .context("saving the onboarding step")?;
+                    send_error(&bot, chat_id, code).await?;
+                }
+            }
+        }
+        Some(Command::Verify) => {
+            if registered {
+                bot.send_message(chat_id, i18n::text("already_registered", &[]))
+                    .await?;
+            } else {
+                let outcome = ownership::verify(chat_id)
+                    .await
+                    .context("verifying ownership")?;
+                match outcome {
+                    ownership::VerifyOutcome::Verified { vm_id } => {
+                        tracing::info!("chat {chat_id} proved ownership of {vm_id}");
+                        dialogue::clear(chat_id)
+                            .await
+                            .context("finishing onboarding")?;
+                        send_status(
+                            &bot,
+                            chat_id,
+                            Indicator::Success,
+                            i18n::text("register_success", &[]),
+                        )
+                        .await?;
+                        send_menu(&bot, chat_id, true).await?;
+                    }
+                    ownership::VerifyOutcome::NotSeen => {
+                        send_error(&bot, chat_id, ErrorCode::OwnershipNotProven).await?;
+                    }
+                    ownership::VerifyOutcome::Expired => {
+                        send_error(&bot, chat_id, ErrorCode::ChallengeExpired).await?;
+                    }
+                    ownership::VerifyOutcome::Taken => {
+                        send_error(&bot, chat_id, ErrorCode::UnknownVm).await?;
+                    }
+                    ownership::VerifyOutcome::NoChallenge => {
+                        bot.send_message(chat_id, i18n::text("greeting", &[]))
+                            .await?;
+                    }
                 }
             }
         }
         Some(Command::Uptime) => {
             if registered {
-                let secs: i64 = sqlx::query_scalar(
-                    "SELECT up_secs FROM agent_records WHERE telegram_chat_id = ?",
+                let (secs, balance): (i64, i64) = sqlx::query_as(
+                    r#"
+SELECT CAST(COALESCE(SUM(up_secs), 0) AS BIGINT),
+       CAST(COALESCE(SUM(up_secs + bonus_secs - paid_secs), 0) AS BIGINT)
+FROM agent_records WHERE telegram_chat_id = $1
+                    "#,
                 )
                 .bind(chat_id.0)
                 .fetch_one(&*DB)
                 .await
-                .map_err(|e| {
-                    log::debug!("ERROR: {e}");
-                    RequestError::RetryAfter(Seconds::from_seconds(2))
-                })?;
+                .context("summing uptime")?;
                 // let hours = secs / 3600;
                 let mins = secs / 60;
-                bot.send_message(
-                    chat_id,
-                    format!(
-                        "Your VM has been up for {mins} minutes. / 您的 VM 已经运行了 {mins} 分钟。"
-                    ),
+                let policy = RewardPolicy::current();
+                let mut text = format!(
+                    "{}\n{}",
+                    i18n::text("uptime_report.minutes", &[("count", &mins)]),
+                    render::progress_line(policy.progress(balance), policy.secs_per_day)
+                );
+                let event = events::active_at(now_unix())
+                    .await
+                    .context("loading the active event")?;
+                if let Some(event) = event {
+                    let (x, until) = (event.multiplier, render::format_timestamp(event.ends_at));
+                    text.push('\n');
+                    text.push_str(&i18n::text(
+                        "uptime_report.event",
+                        &[("x", &x), ("until", &until)],
+                    ));
+                }
+                let lookup = async {
+                    Ok::<_, sqlx::Error>((
+                        streaks::for_chat(chat_id).await?,
+                        nicknames::for_chat(chat_id).await?,
+                    ))
+                };
+                let (streaks, names) = lookup.await.context("loading streaks and nicknames")?;
+                for (vm_id, streak) in streaks {
+                    let days = streak.days;
+                    let name = nicknames::label(&vm_id, names.get(&vm_id).map(String::as_str));
+                    text.push('\n');
+                    text.push_str(&i18n::text(
+                        "uptime_report.streak",
+                        &[("name", &name), ("days", &days)],
+                    ));
+                    if let Some((milestone, bonus)) = streaks::next_milestone(&streak) {
+                        let (left, hours) = (milestone - days, bonus / 3600);
+                        text.push(' ');
+                        text.push_str(&i18n::text(
+                            "uptime_report.next_milestone",
+                            &[("left", &left), ("hours", &hours)],
+                        ));
+                    }
+                }
+                send_status(&bot, chat_id, Indicator::Uptime, text).await?;
+            } else {
+                bot.send_message(chat_id, i18n::text("greeting", &[]))
+                    .await?;
+            }
+        }
+        Some(Command::Status) => {
+            if registered {
+                let vms = vm_statuses(chat_id).await.context("loading VM statuses")?;
+                let now = now_unix();
+                let lines: Vec<String> = vms
+                    .iter()
+                    .map(|row| render::vm_status_line(row, now))
+                    .collect();
+                bot.send_message(chat_id, lines.join("\n"))
+                    .reply_markup(InlineKeyboardMarkup::new(vec![vec![
+                        render::command_button(i18n::text("status.pin_button", &[]), "/pin"),
+                    ]]))
+                    .await?;
+            } else {
+                bot.send_message(chat_id, i18n::text("greeting", &[]))
+                    .await?;
+            }
+        }
+        Some(Command::MyVms(page)) => {
+            if registered {
+                let vms = vm_statuses(chat_id).await.context("loading VM statuses")?;
+                let (text, markup) = my_vms_page(&vms, page, now_unix());
+                bot.send_message(chat_id, text).reply_markup(markup).await?;
+            } else {
+                bot.send_message(chat_id, i18n::text("greeting", &[]))
+                    .await?;
+            }
+        }
+        Some(Command::Vm(vm)) => {
+            if registered {
+                let vm_id = match vm {
+                    Some(vm) => nicknames::resolve(chat_id, &vm)
+                        .await
+                        .context("resolving the VM")?,
+                    None => {
+                        let vms: Vec<(String, Option<String>)> = sqlx::query_as(
+                            "SELECT vm_id, nickname FROM agent_records WHERE telegram_chat_id = $1 ORDER BY vm_id",
+                        )
+                        .bind(chat_id.0)
+                        .fetch_all(&*DB)
+                        .await
+                        .context("listing the chat's VMs")?;
+                        match vms.as_slice() {
+                            [(vm_id, _)] => Some(vm_id.clone()),
+                            _ => {
+                                let buttons = vms
+                                    .iter()
+                                    .map(|(vm_id, nickname)| {
+                                        vec![render::command_button(
+                                            nicknames::label(vm_id, nickname.as_deref()),
+                                            format!("/vm {vm_id}"),
+                                        )]
+                                    })
+                                    .collect::<Vec<_>>();
+                                bot.send_message(chat_id, i18n::text("vm.which", &[]))
+                                    .reply_markup(InlineKeyboardMarkup::new(buttons))
+                                    .await?;
+                                return Ok(());
+                            }
+                        }
+                    }
+                };
+                let text = match vm_id {
+                    Some(vm_id) => vm_detail::text(chat_id, &vm_id)
+                        .await
+                        .context("loading the VM's details")?,
+                    None => None,
+                };
+                match text {
+                    Some(text) => {
+                        bot.send_message(chat_id, text).await?;
+                    }
+                    None => send_error(&bot, chat_id, ErrorCode::NotYourVm).await?,
+                }
+            } else {
+                bot.send_message(chat_id, i18n::text("greeting", &[]))
+                    .await?;
+            }
+        }
+        Some(Command::Pin(true)) => {
+            if registered {
+                let lookup = async {
+                    Ok::<_, sqlx::Error>((
+                        pinned::current(chat_id).await?,
+                        pinned::text(chat_id).await?,
+                    ))
+                };
+                let (old, text) = lookup.await.context("composing the pinned status")?;
+                let status = bot.send_message(chat_id, text).await?;
+                pinned::remember(chat_id, status)
+                    .await
+                    .context("remembering the pinned status")?;
+                if let Some(old) = old
+                    && let Err(e) = bot.unpin(chat_id, old).await
+                {
+                    tracing::debug!("unpinning old status {old} in {chat_id} failed: {e}");
+                }
+                if let Err(e) = bot.pin(chat_id, status).await {
+                    tracing::debug!("pinning status in {chat_id} failed: {e}");
+                    bot.send_message(chat_id, i18n::text("pin.failed", &[]))
+                        .await?;
+                }
+            } else {
+                bot.send_message(chat_id, i18n::text("greeting", &[]))
+                    .await?;
+            }
+        }
+        Some(Command::Pin(false)) => {
+            let unpinned = async {
+                let old = pinned::current(chat_id).await?;
+                if old.is_some() {
+                    pinned::forget(chat_id).await?;
+                }
+                Ok::<_, sqlx::Error>(old)
+            };
+            let old = unpinned.await.context("unpinning the status")?;
+            let reply = match old {
+                Some(old) => {
+                    if let Err(e) = bot.unpin(chat_id, old).await {
+                        tracing::debug!("unpinning status {old} in {chat_id} failed: {e}");
+                    }
+                    i18n::text("pin.stopped", &[])
+                }
+                None => i18n::text("pin.none", &[]),
+            };
+            bot.send_message(chat_id, reply).await?;
+        }
+        Some(Command::Chart) => {
+            if registered {
+                let days = chart::last_days(30);
+                let recorded: HashMap<String, i64> = sqlx::query_as(
+                    r#"
+SELECT h.day, CAST(SUM(h.up_secs) AS BIGINT)
+FROM uptime_history h JOIN agent_records a ON a.vm_id = h.vm_id
+WHERE a.telegram_chat_id = $1 AND h.day >= $2
+GROUP BY h.day
+                    "#,
                 )
-                .await?;
+                .bind(chat_id.0)
+                .bind(&days[0])
+                .fetch_all(&*DB)
+                .await
+                .context("loading uptime history")?
+                .into_iter()
+                .collect();
+                let series: Vec<(String, i64)> = days
+                    .into_iter()
+                    .map(|day| {
+                        let secs = recorded.get(&day).copied().unwrap_or(0);
+                        (day, secs)
+                    })
+                    .collect();
+                match smol::unblock(move || chart::render_uptime_chart(&series)).await {
+                    Ok(png) => {
+                        bot.send_photo(chat_id, "uptime.png", png)
+                            .caption(i18n::text("chart.caption", &[]))
+                            .await?;
+                    }
+                    Err(e) => {
+                        tracing::error!("rendering chart for {chat_id} failed: {e:#}");
+                        bot.send_message(chat_id, i18n::text("chart.unavailable", &[]))
+                            .await?;
+                    }
+                }
             } else {
-                bot.send_message(chat_id, GREETING).await?;
+                bot.send_message(chat_id, i18n::text("greeting", &[]))
+                    .await?;
             }
         }
         Some(Command::Unclaimed) => {
             if registered {
-                let days: i64 = sqlx::query_scalar(
-                    "SELECT (up_secs - paid_secs) / 86400 FROM agent_records WHERE telegram_chat_id = ?",
+                let secs: i64 = sqlx::query_scalar(
+                    "SELECT CAST(COALESCE(SUM(up_secs + bonus_secs - paid_secs), 0) AS BIGINT) FROM agent_records WHERE telegram_chat_id = $1",
                 )
                 .bind(chat_id.0)
                 .fetch_one(&*DB)
                 .await
-                .map_err(|e| {log::debug!("ERROR: {e}"); RequestError::RetryAfter(Seconds::from_seconds(2))})?;
-                bot.send_message(
+                .context("summing the unclaimed balance")?;
+                let policy = RewardPolicy::current();
+                let days = policy.days(secs);
+                send_status(
+                    &bot,
                     chat_id,
-                    format!("Unclaimed Plus days {days} / 未领取的 Plus 天数：{days}"),
+                    Indicator::Balance,
+                    format!(
+                        "{}\n{}",
+                        i18n::text("unclaimed_days", &[("count", &days)]),
+                        render::progress_line(policy.progress(secs), policy.secs_per_day)
+                    ),
                 )
                 .await?;
             } else {
-                bot.send_message(chat_id, GREETING).await?;
+                bot.send_message(chat_id, i18n::text("greeting", &[]))
+                    .await?;
+            }
+        }
+        Some(Command::Next) => {
+            if registered {
+                let now = now_unix();
+                let lookup = async {
+                    let (balance, online): (i64, i64) = sqlx::query_as(
+                        r#"
+SELECT
+    CAST(COALESCE(SUM(a.up_secs + a.bonus_secs - a.paid_secs), 0) AS BIGINT),
+    COUNT(CASE WHEN s.last_seen >= $2 THEN 1 END)
+FROM agent_records a LEFT JOIN vm_status s ON s.vm_id = a.vm_id
+WHERE a.telegram_chat_id = $1
+                        "#,
+                    )
+                    .bind(chat_id.0)
+                    .bind(now - OFFLINE_AFTER_SECS)
+                    .fetch_one(&*DB)
+                    .await?;
+                    Ok::<_, sqlx::Error>((balance, online, events::active_at(now).await?))
+                };
+                let (balance, online, event) = lookup.await.context("loading the balance")?;
+                let policy = RewardPolicy::current();
+                let needed = policy.remaining(balance);
+                let uptime = render::format_hours_minutes(needed);
+                let text = match policy.time_to_earn(needed, online, event.as_ref(), now) {
+                    Some(wall) => {
+                        let (wall, at) = (
+                            render::format_hours_minutes(wall),
+                            render::format_timestamp(now + wall),
+                        );
+                        i18n::text(
+                            "next.eta",
+                            &[
+                                ("uptime", &uptime),
+                                ("count", &online),
+                                ("wall", &wall),
+                                ("at", &at),
+                            ],
+                        )
+                    }
+                    None => i18n::text("next.offline", &[("uptime", &uptime)]),
+                };
+                send_status(&bot, chat_id, Indicator::Uptime, text).await?;
+            } else {
+                bot.send_message(chat_id, i18n::text("greeting", &[]))
+                    .await?;
+            }
+        }
+        Some(Command::Claim(Some(days))) if days <= 0 => {
+            bot.send_message(chat_id, i18n::text("claim.positive_days", &[]))
+                .await?;
+        }
+        Some(Command::ClaimSplit(split)) if !split.is_valid() => {
+            let max = claim::MAX_CARDS;
+            bot.send_message(chat_id, i18n::text("claim.bad_split", &[("max", &max)]))
+                .await?;
+        }
+        Some(Command::Claim(days)) => {
+            if registered {
+                let lookup = async {
+                    Ok::<_, sqlx::Error>((
+                        claim::available(chat_id).await?,
+                        claim::direct_credit_account(chat_id).await?.is_some(),
+                    ))
+                };
+                let (available, direct) = lookup.await.context("loading the claimable days")?;
+                let outcome = match days.unwrap_or(available) {
+                    _ if available == 0 => ClaimOutcome::NothingToClaim,
+                    days if days > available => ClaimOutcome::NotEnough { available },
+                    // Nothing to choose between: a single day, or days going to the account
+                    days if days == 1 || direct => {
+                        claim::claim(chat_id, Some(claim::Split::single(days)))
+                            .await
+                            .context("claiming a single card")?
+                    }
+                    days => {
+                        bot.send_message(
+                            chat_id,
+                            i18n::text("claim.choose_split", &[("days", &days)]),
+                        )
+                        .reply_markup(split_markup(days))
+                        .await?;
+                        return Ok(());
+                    }
+                };
+                send_claim_outcome(&bot, chat_id, outcome).await?;
+            } else {
+                bot.send_message(chat_id, i18n::text("greeting", &[]))
+                    .await?;
             }
         }
-        Some(Command::Claim) => {
+        Some(Command::ClaimSplit(split)) => {
             if registered {
-                let days: i64 = sqlx::query_scalar(
-                    "SELECT (up_secs - paid_secs) / 86400 FROM agent_records WHERE telegram_chat_id = ?",
+                let outcome = claim::claim(chat_id, Some(split))
+                    .await
+                    .context("claiming split cards")?;
+                send_claim_outcome(&bot, chat_id, outcome).await?;
+            } else {
+                bot.send_message(chat_id, i18n::text("greeting", &[]))
+                    .await?;
+            }
+        }
+        Some(Command::MyCards(None)) => {
+            let cards = giftcards::list(chat_id)
+                .await
+                .context("listing giftcards")?;
+            if cards.is_empty() {
+                send_status(
+                    &bot,
+                    chat_id,
+                    Indicator::Empty,
+                    i18n::text("mycards.none", &[]),
                 )
-                .bind(chat_id.0)
-                .fetch_one(&*DB)
+                .await?;
+            } else {
+                let (text, markup) = giftcards::render(&cards);
+                bot.send_message(chat_id, text).reply_markup(markup).await?;
+            }
+        }
+        Some(Command::MyCards(Some(id))) => {
+            let card = giftcards::get(chat_id, id)
                 .await
-                .map_err(|e| {log::debug!("ERROR: {e}"); RequestError::RetryAfter(Seconds::from_seconds(2))})?;
-                if days > 0 {
-                    let body = json!({
-                        "days_per_card": days,
-                        "num_cards": 1,
-                        "secret": CONFIG.giftcard_api_secret
-                    });
-                    let giftcard = isahc::Request::post(
-                        "https://web-backend.geph.io/support/create-giftcards",
+                .context("loading the giftcard")?;
+            match card {
+                None => {
+                    bot.send_message(chat_id, i18n::text("mycards.unknown", &[("id", &id)]))
+                        .await?;
+                }
+                Some(card) if card.is_redacted() => {
+                    let days = CONFIG.giftcard_resend_max_age_days;
+                    bot.send_message(
+                        chat_id,
+                        i18n::text("mycards.too_old", &[("id", &id), ("days", &days)]),
                     )
-                    .header(isahc::http::header::CONTENT_TYPE, "application/json")
-                    .body(body.to_string())
-                    .map_err(|e| {
-                        log::debug!("ERROR: {e}");
-                        RequestError::RetryAfter(Seconds::from_seconds(2))
-                    })?
-                    .send()
-                    .map_err(|e| {
-                        log::debug!("ERROR: {e}");
-                        RequestError::RetryAfter(Seconds::from_seconds(2))
-                    })?
-                    .text()
-                    .map_err(|e| {
-                        log::debug!("ERROR: {e}");
-                        RequestError::RetryAfter(Seconds::from_seconds(2))
-                    })?;
-                    bot.send_message(chat_id, giftcard).await?;
-                    sqlx::query(
-                        "UPDATE agent_records SET paid_secs = paid_secs + $1 WHERE telegram_chat_id = $2;",
+                    .await?;
+                }
+                Some(card) => {
+                    giftcards::log_resend(chat_id, &card)
+                        .await
+                        .context("logging a giftcard resend")?;
+                    send_status(
+                        &bot,
+                        chat_id,
+                        Indicator::Gift,
+                        i18n::text(
+                            "mycards.resent",
+                            &[
+                                ("id", &id),
+                                ("date", &render::format_date(card.created_at)),
+                                ("count", &card.days),
+                            ],
+                        ),
                     )
-                    .bind(days * 86400)
-                    .bind(chat_id.0)
-                    .execute(&*DB)
+                    .await?;
+                    send_codes(&bot, chat_id, &[card.code]).await?;
+                }
+            }
+        }
+        Some(Command::Deregister {
+            vm: Some(vm),
+            confirmed,
+        }) => {
+            let vm_id = match registered {
+                true => nicknames::resolve(chat_id, &vm)
                     .await
-                    .map_err(|e| {log::debug!("ERROR: {e}"); RequestError::RetryAfter(Seconds::from_seconds(2))})?;
-                } else {
-                    bot.send_message(chat_id, "No unclaimed days yet. / 还没有未领取的天数。")
+                    .context("resolving the VM")?,
+                false => None,
+            };
+            match vm_id {
+                Some(vm_id) if !confirmed => {
+                    let (nickname, secs): (Option<String>, i64) = sqlx::query_as(
+                        "SELECT nickname, up_secs + bonus_secs - paid_secs FROM agent_records WHERE vm_id = $1",
+                    )
+                    .bind(&vm_id)
+                    .fetch_one(&*DB)
+                    .await
+                    .context("loading the balance to forfeit")?;
+                    let name = nicknames::label(&vm_id, nickname.as_deref());
+                    let unclaimed = render::format_duration(secs.max(0));
+                    let command = format!("/deregister {vm_id} confirm");
+                    dialogue::save(
+                        chat_id,
+                        &Dialogue::AwaitingConfirmation {
+                            command: command.clone(),
+                        },
+                    )
+                    .await
+                    .context("asking to confirm deregistering")?;
+                    bot.send_message(
+                        chat_id,
+                        format!(
+                            "{}\n\n{}",
+                            i18n::text("deregister.confirm_vm", &[("name", &name)]),
+                            i18n::text("deregister.forfeit_vm", &[("unclaimed", &unclaimed)])
+                        ),
+                    )
+                    .reply_markup(deregister_markup(&command))
+                    .await?;
+                }
+                Some(vm_id) => {
+                    let name = nicknames::label_of(&vm_id).await.context("naming the VM")?;
+                    unlink_vms(chat_id, Some(&vm_id))
+                        .await
+                        .context("unlinking the VM")?;
+                    send_status(
+                        &bot,
+                        chat_id,
+                        Indicator::Removed,
+                        i18n::text("deregister.done_vm", &[("name", &name)]),
+                    )
+                    .await?;
+                }
+                None if registered => send_error(&bot, chat_id, ErrorCode::NotYourVm).await?,
+                None => {
+                    bot.send_message(chat_id, i18n::text("greeting", &[]))
                         .await?;
                 }
-            } else {
-                bot.send_message(chat_id, GREETING).await?;
             }
         }
-        Some(Command::Deregister) => {
+        Some(Command::Deregister {
+            vm: None,
+            confirmed: false,
+        }) => {
             if registered {
-                sqlx::query(
-                    "UPDATE agent_records SET telegram_chat_id = NULL WHERE telegram_chat_id = ?",
+                let secs: i64 = sqlx::query_scalar(
+                    "SELECT CAST(COALESCE(SUM(up_secs + bonus_secs - paid_secs), 0) AS BIGINT) FROM agent_records WHERE telegram_chat_id = $1",
                 )
                 .bind(chat_id.0)
-                .execute(&*DB)
+                .fetch_one(&*DB)
+                .await
+                .context("summing the balance to forfeit")?;
+                let days = RewardPolicy::current().days(secs);
+                let warning = if days > 0 {
+                    i18n::text("deregister.forfeit", &[("count", &days)])
+                } else {
+                    i18n::text("deregister.forfeit_progress", &[])
+                };
+                dialogue::save(
+                    chat_id,
+                    &Dialogue::AwaitingConfirmation {
+                        command: "/deregister confirm".to_owned(),
+                    },
+                )
                 .await
-                .map_err(|e| {
-                    log::debug!("ERROR: {e}");
-                    RequestError::RetryAfter(Seconds::from_seconds(2))
-                })?;
+                .context("asking to confirm deregistering")?;
                 bot.send_message(
                     chat_id,
-                    "Your VM has been deregistered. / 您的 VM 已取消注册。",
+                    format!("{}\n\n{warning}", i18n::text("deregister.confirm", &[])),
                 )
+                .reply_markup(deregister_markup("/deregister confirm"))
                 .await?;
             } else {
-                bot.send_message(chat_id, GREETING).await?;
+                bot.send_message(chat_id, i18n::text("greeting", &[]))
+                    .await?;
             }
         }
-        Some(Command::Menu) => {
-            send_menu(&bot, chat_id, registered).await?;
-        }
-        None => {
+        Some(Command::Deregister {
+            vm: None,
+            confirmed: true,
+        }) => {
             if registered {
-                send_menu(&bot, chat_id, true).await?;
+                unlink_vms(chat_id, None).await.context("unlinking VMs")?;
+                send_status(
+                    &bot,
+                    chat_id,
+                    Indicator::Removed,
+                    i18n::text("deregister.done", &[]),
+                )
+                .await?;
             } else {
-                bot.send_message(chat_id, GREETING).await?;
+                bot.send_message(chat_id, i18n::text("greeting", &[]))
+                    .await?;
             }
         }
-    }
-    Ok(())
-}
-
-// ---------------------------- Background loops ----------------------------
-async fn update_uptime_loop() -> anyhow::Result<()> {
-    let mut ticker = smol::Timer::interval(Duration::from_secs(60));
-    loop {
-        let url = format!(
-            "http://104.194.80.160:3000/available_vms?secret={}",
-            CONFIG.vm_api_secret
-        );
-        let resp_body = isahc::get(url)?.text()?;
-        let map: HashMap<String, Value> = serde_json::from_str(&resp_body)?;
-
-        for vm_id in map.keys() {
-            log::debug!("updating up_secs for vm_id = {vm_id}");
-            sqlx::query(
-                r#"
-INSERT INTO agent_records (
-    vm_id,
-    telegram_chat_id,
-    up_secs,
-    paid_secs
-)
-VALUES ($1, NULL, 60, 0)
-ON CONFLICT(vm_id) DO UPDATE SET
-    up_secs = agent_records.up_secs + 60;
-            "#,
+        Some(Command::DeleteMyData { confirmed: false }) => {
+            dialogue::save(
+                chat_id,
+                &Dialogue::AwaitingConfirmation {
+                    command: "/deletemydata confirm".to_owned(),
+                },
+            )
+            .await
+            .context("asking to confirm erasure")?;
+            bot.send_message(chat_id, i18n::text("deletemydata.confirm", &[]))
+                .await?;
+        }
+        Some(Command::DeleteMyData { confirmed: true }) => {
+            let receipt = erasure::erase(chat_id)
+                .await
+                .context("erasing the chat's data")?;
+            let (vms, claims, giftcards, other) = (
+                receipt.vms_unlinked,
+                receipt.claims,
+                receipt.giftcards,
+                receipt.other_rows,
+            );
+            send_status(
+                &bot,
+                chat_id,
+                Indicator::Removed,
+                i18n::text(
+                    "deletemydata.done",
+                    &[
+                        ("vms", &vms),
+                        ("claims", &claims),
+                        ("giftcards", &giftcards),
+                        ("other", &other),
+                    ],
+                ),
             )
-            .bind(vm_id)
-            .execute(&*DB)
             .await?;
         }
-        ticker.next().await;
+        Some(Command::Replace { old_vm, new_vm }) => {
+            if registered {
+                // The new VM is claimed the way registration claims one: by a token, or
+                // by placing an ownership token when proof is required
+                let token_vm = tokens::resolve(&new_vm)
+                    .await
+                    .context("resolving a registration token")?;
+                let needs_proof = token_vm.is_none()
+                    && CONFIG.require_ownership_proof
+                    && ownership::is_unlinked(&new_vm)
+                        .await
+                        .context("checking whether the VM is unlinked")?
+                    && !ownership::proves(chat_id, &new_vm)
+                        .await
+                        .context("checking the ownership proof")?;
+                if needs_proof {
+                    let token = ownership::challenge(chat_id, &new_vm)
+                        .await
+                        .context("issuing an ownership token")?;
+                    bot.send_message(
+                        chat_id,
+                        i18n::text(
+                            "replace.prove",
+                            &[("new_vm", &new_vm), ("token", &token), ("old_vm", &old_vm)],
+                        ),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+                let new_id = token_vm.as_deref().unwrap_or(&new_vm);
+                let replaced = async {
+                    // The old VM may be given by its nickname, which moves to the new one
+                    let old_id = nicknames::resolve(chat_id, &old_vm).await?;
+                    replace::replace(chat_id, old_id.as_deref().unwrap_or(&old_vm), new_id).await
+                };
+                let outcome = replaced.await.context("replacing the VM")?;
+                match outcome {
+                    ReplaceOutcome::Replaced { moved_up_secs } => {
+                        if token_vm.is_some() {
+                            tokens::consume(&new_vm)
+                                .await
+                                .context("consuming the registration token")?;
+                        } else {
+                            ownership::clear(chat_id)
+                                .await
+                                .context("clearing the ownership token")?;
+                        }
+                        let new_vm = new_id;
+                        let moved = render::format_duration(moved_up_secs);
+                        send_status(
+                            &bot,
+                            chat_id,
+                            Indicator::Success,
+                            i18n::text(
+                                "replace.done",
+                                &[("new_vm", &new_vm), ("old_vm", &old_vm), ("moved", &moved)],
+                            ),
+                        )
+                        .await?;
+                    }
+                    ReplaceOutcome::NotYours => {
+                        send_error(&bot, chat_id, ErrorCode::NotYourVm).await?;
+                    }
+                    ReplaceOutcome::OldStillOnline => {
+                        send_error(&bot, chat_id, ErrorCode::OldVmStillOnline).await?;
+                    }
+                    ReplaceOutcome::NewUnavailable => {
+                        send_error(&bot, chat_id, ErrorCode::NewVmUnavailable).await?;
+                    }
+                }
+            } else {
+                bot.send_message(chat_id, i18n::text("greeting", &[]))
+                    .await?;
+            }
+        }
+        Some(Command::Transfer { vm, with_balance }) => {
+            if registered {
+                let offered = async {
+                    let vm_id = nicknames::resolve(chat_id, &vm).await?;
+                    transfer::offer(chat_id, vm_id.as_deref().unwrap_or(&vm), with_balance).await
+                };
+                let offer = offered.await.context("offering a transfer")?;
+                match offer {
+                    transfer::Offer::Issued { code, vm_id } => {
+                        let hours = transfer::CODE_TTL_SECS / 3600;
+                        let link = tokens::deep_link(&code)
+                            .map(|link| format!("\n{link}"))
+                            .unwrap_or_default();
+                        let balance = if with_balance {
+                            "transfer.with_balance"
+                        } else {
+                            "transfer.without_balance"
+                        };
+                        let offer = i18n::text(
+                            "transfer.offer",
+                            &[("vm_id", &vm_id), ("code", &code), ("hours", &hours)],
+                        );
+                        let balance = i18n::text(balance, &[]);
+                        bot.send_message(chat_id, format!("{offer}{link}\n{balance}"))
+                            .await?;
+                    }
+                    transfer::Offer::NotYours => {
+                        send_error(&bot, chat_id, ErrorCode::NotYourVm).await?;
+                    }
+                }
+            } else {
+                bot.send_message(chat_id, i18n::text("greeting", &[]))
+                    .await?;
+            }
+        }
+        Some(Command::AcceptTransfer(code)) => {
+            let accepted = transfer::accept(chat_id, &code)
+                .await
+                .context("accepting a transfer")?;
+            let reply = match accepted {
+                transfer::Accept::Transferred {
+                    vm_id,
+                    from,
+                    moved_secs,
+                } => {
+                    let days = RewardPolicy::current().days(moved_secs);
+                    let _ = bot
+                        .send_message(
+                            from,
+                            i18n::text("transfer.given_away", &[("vm_id", &vm_id)]),
+                        )
+                        .await;
+                    if days > 0 {
+                        i18n::text(
+                            "transfer.received_balance",
+                            &[("vm_id", &vm_id), ("count", &days)],
+                        )
+                    } else {
+                        i18n::text("transfer.received", &[("vm_id", &vm_id)])
+                    }
+                }
+                transfer::Accept::InvalidCode => i18n::text("transfer.invalid_code", &[]),
+                transfer::Accept::OwnVm => i18n::text("transfer.own_vm", &[]),
+                transfer::Accept::NoLongerOwned => i18n::text("transfer.no_longer_owned", &[]),
+                transfer::Accept::SenderMustClaim => i18n::text("transfer.sender_must_claim", &[]),
+                transfer::Accept::UnderReview => i18n::text("transfer.under_review", &[]),
+            };
+            bot.send_message(chat_id, reply).await?;
+        }
+        Some(Command::Rename { nickname, .. })
+            if !nickname.is_empty() && !nicknames::valid(&nickname) =>
+        {
+            let max = nicknames::MAX_CHARS;
+            bot.send_message(chat_id, i18n::text("rename.invalid", &[("max", &max)]))
+                .await?;
+        }
+        Some(Command::Rename { vm, nickname }) => {
+            if registered {
+                let nickname = Some(nickname.trim()).filter(|n| !n.is_empty());
+                let outcome = nicknames::rename(chat_id, &vm, nickname)
+                    .await
+                    .context("renaming the VM")?;
+                match outcome {
+                    nicknames::Rename::Renamed {
+                        vm_id,
+                        nickname: Some(nickname),
+                    } => {
+                        send_status(
+                            &bot,
+                            chat_id,
+                            Indicator::Success,
+                            i18n::text(
+                                "rename.done",
+                                &[("vm_id", &vm_id), ("nickname", &nickname)],
+                            ),
+                        )
+                        .await?;
+                    }
+                    nicknames::Rename::Renamed {
+                        vm_id,
+                        nickname: None,
+                    } => {
+                        send_status(
+                            &bot,
+                            chat_id,
+                            Indicator::Success,
+                            i18n::text("rename.cleared", &[("vm_id", &vm_id)]),
+                        )
+                        .await?;
+                    }
+                    nicknames::Rename::Unknown => {
+                        send_error(&bot, chat_id, ErrorCode::NotYourVm).await?;
+                    }
+                    nicknames::Rename::Taken => {
+                        bot.send_message(chat_id, i18n::text("rename.taken", &[]))
+                            .await?;
+                    }
+                }
+            } else {
+                bot.send_message(chat_id, i18n::text("greeting", &[]))
+                    .await?;
+            }
+        }
+        Some(Command::Digest(toggle)) => {
+            if registered {
+                let reply = match toggle {
+                    Some(enabled) => prefs::set(chat_id, prefs::Pref::WeeklyDigest, enabled)
+                        .await
+                        .map(|_| {
+                            if enabled {
+                                i18n::text("digest.on", &[]).to_owned()
+                            } else {
+                                i18n::text("digest.off", &[])
+                            }
+                        }),
+                    None => digest::compose(chat_id).await,
+                };
+                let reply = reply.context("composing the digest")?;
+                bot.send_message(chat_id, reply).await?;
+            } else {
+                bot.send_message(chat_id, i18n::text("greeting", &[]))
+                    .await?;
+            }
+        }
+        Some(Command::Settings(change)) => {
+            let result = async {
+                if let Some((pref, enabled)) = change {
+                    prefs::set(chat_id, pref, enabled).await?;
+                }
+                prefs::load(chat_id).await
+            }
+            .await;
+            let current = result.context("updating settings")?;
+            let (text, markup) = prefs::render(&current);
+            bot.send_message(chat_id, text).reply_markup(markup).await?;
+        }
+        Some(Command::HowRewardsWork) => {
+            let events = async {
+                Ok::<_, sqlx::Error>((
+                    events::active_at(now_unix()).await?,
+                    events::upcoming().await?,
+                ))
+            }
+            .await;
+            let (active, upcoming) = events.context("loading events")?;
+            let text = RewardPolicy::current().explain(active.as_ref(), &upcoming);
+            bot.send_message(chat_id, text).await?;
+        }
+        Some(Command::Menu) => {
+            send_menu(&bot, chat_id, registered).await?;
+        }
+        Some(Command::GroupStats) => {
+            bot.send_message(chat_id, i18n::text("groupstats.group_only", &[]))
+                .await?;
+        }
+        Some(Command::NetworkStats) => {
+            let stats = community::network_stats()
+                .await
+                .context("computing network stats")?;
+            bot.send_message(chat_id, stats).await?;
+        }
+        Some(Command::Leaderboard) => {
+            let board = community::leaderboard()
+                .await
+                .context("computing the leaderboard")?;
+            let rank = community::own_rank(chat_id)
+                .await
+                .context("ranking the chat")?;
+            let own = match rank {
+                Some(rank) => i18n::text(
+                    "leaderboard.rank",
+                    &[
+                        ("rank", &rank.rank),
+                        ("testers", &rank.testers),
+                        ("hours", &(rank.up_secs / 3600)),
+                    ],
+                ),
+                None => i18n::text("leaderboard.no_uptime", &[]),
+            };
+            bot.send_message(
+                chat_id,
+                format!(
+                    "{board}\n\n{own}\n\n{}",
+                    i18n::text("leaderboard.name_hint", &[])
+                ),
+            )
+            .await?;
+        }
+        Some(Command::LeaderboardName(name)) => {
+            if let Some(name) = &name
+                && !community::valid_display_name(name)
+            {
+                bot.send_message(chat_id, i18n::text("leaderboard.invalid_name", &[]))
+                    .await?;
+                return Ok(());
+            }
+            community::set_display_name(chat_id, name.as_deref().map(str::trim))
+                .await
+                .context("setting the leaderboard name")?;
+            let reply = match &name {
+                Some(name) => i18n::text("leaderboard.named", &[("name", &name.trim())]),
+                None => i18n::text("leaderboard.anonymous", &[]).to_owned(),
+            };
+            bot.send_message(chat_id, reply).await?;
+        }
+        Some(Command::Link(None)) => {
+            let linked = geph_account::linked(chat_id)
+                .await
+                .context("loading the linked Geph account")?;
+            let text = match linked {
+                Some(username) => i18n::text("link.linked_to", &[("username", &username)]),
+                None => i18n::text("link.none", &[]),
+            };
+            bot.send_message(chat_id, text).await?;
+        }
+        Some(Command::Link(Some(username))) => {
+            if !geph_account::valid_username(&username) {
+                bot.send_message(chat_id, i18n::text("link.invalid", &[]))
+                    .await?;
+                return Ok(());
+            }
+            geph_account::link(chat_id, &username)
+                .await
+                .context("linking the Geph account")?;
+            let mut text = i18n::text("link.done", &[("username", &username)]);
+            if CONFIG
+                .reward_providers()
+                .contains(&rewards::Kind::DirectCredit)
+            {
+                text.push('\n');
+                text.push_str(&i18n::text("link.direct_hint", &[]));
+            }
+            bot.send_message(chat_id, text).await?;
+        }
+        Some(Command::Unlink) => {
+            let unlinked = geph_account::unlink(chat_id)
+                .await
+                .context("unlinking the Geph account")?;
+            let text = match unlinked {
+                Some(username) => i18n::text("link.unlinked", &[("username", &username)]),
+                None => i18n::text("link.none_unlinked", &[]),
+            };
+            bot.send_message(chat_id, text).await?;
+        }
+        Some(Command::AutoClaim) => {
+            let schedule = autoclaim::get(chat_id)
+                .await
+                .context("loading the autoclaim schedule")?;
+            let text = match schedule {
+                Some(schedule) => {
+                    let (key, count) = schedule.message();
+                    format!(
+                        "{}\n{}",
+                        i18n::text_nested(
+                            "autoclaim.status",
+                            "schedule",
+                            key,
+                            &[("count", &count)]
+                        ),
+                        i18n::text("autoclaim.turn_off", &[])
+                    )
+                }
+                None => i18n::text("autoclaim.off_status", &[]),
+            };
+            bot.send_message(chat_id, text)
+                .reply_markup(autoclaim_markup())
+                .await?;
+        }
+        Some(Command::SetAutoClaim(Some(schedule))) if !schedule.is_valid() => {
+            let max = autoclaim::MAX_THRESHOLD_DAYS;
+            bot.send_message(chat_id, i18n::text("autoclaim.invalid", &[("max", &max)]))
+                .await?;
+        }
+        Some(Command::SetAutoClaim(schedule)) => {
+            if registered {
+                autoclaim::set(chat_id, schedule)
+                    .await
+                    .context("setting the autoclaim schedule")?;
+                let text = match schedule {
+                    Some(schedule) => {
+                        let (key, count) = schedule.message();
+                        i18n::text_nested("autoclaim.set", "schedule", key, &[("count", &count)])
+                    }
+                    None => i18n::text("autoclaim.off", &[]),
+                };
+                bot.send_message(chat_id, text).await?;
+            } else {
+                bot.send_message(chat_id, i18n::text("greeting", &[]))
+                    .await?;
+            }
+        }
+        Some(Command::Referral) => {
+            let summary = referrals::summary(chat_id)
+                .await
+                .context("summarizing referrals")?;
+            let hours = CONFIG.referral_bonus_hours;
+            let link = summary
+                .link
+                .unwrap_or_else(|| i18n::text("referral.link_unavailable", &[]));
+            bot.send_message(
+                chat_id,
+                [
+                    i18n::text("referral.title", &[]),
+                    link,
+                    String::new(),
+                    i18n::text("referral.bonus", &[("count", &hours)]),
+                    String::new(),
+                    i18n::text("referral.invited_count", &[("count", &summary.referred)]),
+                    i18n::text("referral.rewarded_count", &[("count", &summary.rewarded)]),
+                ]
+                .join("\n"),
+            )
+            .await?;
+        }
+        Some(Command::Help(topic)) => match topic.as_deref() {
+            Some("errors") => {
+                bot.send_message(chat_id, errors::help_text()).await?;
+            }
+            Some(name) => {
+                let text = help::topic(name)
+                    .unwrap_or_else(|| i18n::text("help.unknown", &[("name", &name)]));
+                bot.send_message(chat_id, text).await?;
+            }
+            None => {
+                bot.send_message(chat_id, help::overview()).await?;
+            }
+        },
+        Some(Command::Ask(question)) => {
+            if registered {
+                let text = match &question {
+                    Dialogue::AwaitingTransferVm => i18n::text("ask.transfer_vm", &[]),
+                    Dialogue::AwaitingReplacementVm { old_vm } => {
+                        i18n::text("ask.replacement_vm", &[("old_vm", &old_vm)])
+                    }
+                    Dialogue::AwaitingNickname { vm_id } => {
+                        let owned = nicknames::resolve(chat_id, vm_id)
+                            .await
+                            .context("resolving the VM")?;
+                        if owned.as_ref() != Some(vm_id) {
+                            send_error(&bot, chat_id, ErrorCode::NotYourVm).await?;
+                            return Ok(());
+                        }
+                        let name = nicknames::label_of(vm_id).await.context("naming the VM")?;
+                        i18n::text("ask.nickname", &[("name", &name)])
+                    }
+                    _ => return Ok(()),
+                };
+                dialogue::save(chat_id, &question)
+                    .await
+                    .context("saving the question")?;
+                bot.send_message(chat_id, text).await?;
+            } else {
+                bot.send_message(chat_id, i18n::text("greeting", &[]))
+                    .await?;
+            }
+        }
+        Some(Command::Cancel) => {
+            dialogue::clear(chat_id)
+                .await
+                .context("cancelling the dialogue")?;
+            bot.send_message(chat_id, i18n::text("cancel.done", &[]))
+                .await?;
+        }
+        Some(Command::Appeal(statement)) => {
+            let grounds = appeals::grounds(chat_id)
+                .await
+                .context("looking for grounds to appeal")?;
+            let reply = if grounds.is_empty() {
+                i18n::text("appeal.nothing", &[])
+            } else if statement.is_empty() {
+                i18n::text("appeal.needs_statement", &[])
+            } else {
+                match appeals::file(chat_id, &statement)
+                    .await
+                    .context("filing the appeal")?
+                {
+                    Some(id) => {
+                        appeals::forward(&bot, id, chat_id, &statement, &grounds).await;
+                        i18n::text("appeal.sent", &[])
+                    }
+                    None => i18n::text("appeal.pending", &[]).to_owned(),
+                }
+            };
+            bot.send_message(chat_id, reply).await?;
+        }
+        Some(Command::Admin(cmd)) if admin::is_admin(chat_id) => {
+            admin::handle(&bot, chat_id, cmd).await?;
+        }
+        None | Some(Command::Admin(_)) => {
+            // An unknown slash command is most likely a typo of a known one
+            let suggestion = text
+                .strip_prefix('/')
+                .and_then(|_| text.split_whitespace().next())
+                .map(|word| word.split('@').next().unwrap_or(word))
+                .and_then(help::suggest);
+            if let Some(command) = suggestion {
+                let rest = text
+                    .split_once(char::is_whitespace)
+                    .map(|(_, rest)| rest.trim());
+                let corrected = match rest {
+                    Some(rest) if !rest.is_empty() => format!("/{command} {rest}"),
+                    _ => format!("/{command}"),
+                };
+                bot.send_message(
+                    chat_id,
+                    i18n::text("suggest.did_you_mean", &[("corrected", &corrected)]),
+                )
+                .reply_markup(InlineKeyboardMarkup::new(vec![vec![
+                    render::command_button(corrected.clone(), corrected),
+                ]]))
+                .await?;
+            } else if registered {
+                send_menu(&bot, chat_id, true).await?;
+            } else {
+                bot.send_message(chat_id, i18n::text("greeting", &[]))
+                    .await?;
+            }
+        }
     }
+    Ok(())
 }
 
-async fn notify_uptime_loop(bot: Bot) -> anyhow::Result<()> {
-    let mut ticker = smol::Timer::interval(Duration::from_secs(86400));
+// ---------------------------- Background loops ----------------------------
+/// Longest any outbound HTTP request may take, connecting included
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the VM availability endpoint is polled
+const POLL_SECS: i64 = 60;
+/// A VM missing from the poll for longer than this counts as offline
+const OFFLINE_AFTER_SECS: i64 = 3 * POLL_SECS;
+
+/// First retry delay after a failed poll; doubled per consecutive failure
+const POLL_RETRY_MIN: Duration = Duration::from_secs(5);
+const POLL_RETRY_MAX: Duration = Duration::from_secs(300);
+/// Admins are alerted once this many polls in a row have failed
+const POLL_ALERT_AFTER_FAILURES: u32 = 5;
+
+/// Each chat is reminded of its unclaimed days at most this often
+const NOTIFY_INTERVAL_SECS: i64 = 86400;
+
+/// Polls `fleet`'s VM availability every [`POLL_SECS`], as the task called `task`. A
+/// failed poll is retried with jittered exponential backoff rather than failing the task;
+/// since credit is capped per poll period, the retries can't over-credit anyone once the
+/// API comes back. Each fleet has a loop of its own, so one that's down doesn't hold up
+/// the others.
+async fn update_uptime_loop(
+    bot: Bot,
+    task: &'static str,
+    fleet: vm_api::Fleet,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let mut ticker = smol::Timer::interval(Duration::from_secs(POLL_SECS as u64));
+    let mut failures: u32 = 0;
+    let name = &fleet.name;
     loop {
-        let notifications: Vec<(i64, i64)> = sqlx::query_as(
+        match poll_once(&fleet)
+            .instrument(tracing::info_span!("tick", fleet = %name))
+            .await
+        {
+            Ok(()) => {
+                if failures >= POLL_ALERT_AFTER_FAILURES {
+                    admin::notify_admins(
+                        &bot,
+                        &format!("✅ VM availability poll of fleet {name} recovered after {failures} failures."),
+                    )
+                    .await;
+                }
+                failures = 0;
+                if !next_tick(task, &mut ticker, &shutdown).await {
+                    return Ok(());
+                }
+            }
+            Err(e) => {
+                failures += 1;
+                let delay = poll_retry_delay(failures);
+                tracing::warn!(
+                    "VM availability poll of fleet {name} failed ({failures} in a row), retrying in {delay:?}: {e:#}"
+                );
+                if failures == POLL_ALERT_AFTER_FAILURES {
+                    admin::notify_admins(
+                        &bot,
+                        &format!("⚠️ VM availability poll of fleet {name} has failed {failures} times in a row, none of its uptime is being credited. Last error: {e:#}"),
+                    )
+                    .await;
+                }
+                // Failing polls are retried on schedule, so the task isn't stalled
+                supervisor::tick(task);
+                if !sleep_or_shutdown(delay, &shutdown).await {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Backoff before retry number `failures`, with jitter so restarts across deployments
+/// don't hit the API in lockstep
+fn poll_retry_delay(failures: u32) -> Duration {
+    let backoff = POLL_RETRY_MIN
+        .saturating_mul(1 << failures.saturating_sub(1).min(16))
+        .min(POLL_RETRY_MAX);
+    backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
+
+async fn poll_once(fleet: &vm_api::Fleet) -> anyhow::Result<()> {
+    let vms = vm_api::fetch_available(fleet).await?;
+    let now = now_unix();
+    let event = events::active_at(now).await?;
+    let (_write, mut tx) = begin_write().await?;
+    for vm in &vms {
+        record_sighting(&mut tx, vm, now, event.as_ref(), Some(fleet)).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Records one sighting of `vm` at `now`, by the availability poll of `fleet` or a
+/// heartbeat: credits uptime (and any event or fleet bonus) and refreshes its status row.
+/// Metadata fields the sighting didn't include keep their last reported value, and a
+/// heartbeat that wasn't signed by a fleet leaves the VM in the one it was last seen in.
+/// Returns the uptime credited.
+///
+/// Credit is the time since the VM was last seen by either source, so a VM reported by
+/// both is never counted twice.
+async fn record_sighting(
+    conn: &mut AnyConnection,
+    vm: &vm_api::AvailableVm,
+    now: i64,
+    event: Option<&events::RewardEvent>,
+    fleet: Option<&vm_api::Fleet>,
+) -> sqlx::Result<i64> {
+    let vm_id = &vm.vm_id;
+    let fleet_multiplier = match fleet {
+        Some(fleet) => fleet.reward_multiplier,
+        None => {
+            let name: Option<String> =
+                sqlx::query_scalar("SELECT fleet FROM agent_records WHERE vm_id = $1")
+                    .bind(vm_id)
+                    .fetch_optional(&mut *conn)
+                    .await?;
+            name.and_then(|name| CONFIG.fleet(&name))
+                .map_or(1.0, |fleet| fleet.reward_multiplier)
+        }
+    };
+    // Credit the time actually elapsed since this VM was last seen, but never more
+    // than one poll period: a gap longer than that means we can't vouch for it.
+    // Newly seen VMs start at zero since no interval has been observed yet.
+    let last_seen: Option<i64> =
+        sqlx::query_scalar("SELECT last_seen FROM vm_status WHERE vm_id = $1")
+            .bind(vm_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+    let mut credit = last_seen.map_or(0, |t| (now - t).clamp(0, POLL_SECS));
+    // Banned VMs and those of banned chats still show up as online, but earn nothing
+    if credit > 0 && bans::withholds_credit(&mut *conn, vm_id).await? {
+        credit = 0;
+    }
+    // Events and fleets boost the reward, not the recorded uptime
+    let multiplier = event.map_or(1.0, |e| e.multiplier) * fleet_multiplier;
+    let bonus = (credit as f64 * (multiplier - 1.0)).round() as i64;
+    tracing::debug!("crediting {credit}s (+{bonus}s bonus) to vm_id = {vm_id}");
+    sqlx::query(
+        r#"
+INSERT INTO agent_records (
+    vm_id,
+    telegram_chat_id,
+    up_secs,
+    bonus_secs,
+    paid_secs,
+    fleet
+)
+VALUES ($1, NULL, $2, $3, 0, COALESCE($4, $5))
+ON CONFLICT(vm_id) DO UPDATE SET
+    up_secs = agent_records.up_secs + $2,
+    bonus_secs = agent_records.bonus_secs + $3,
+    fleet = COALESCE($4, agent_records.fleet);
+    "#,
+    )
+    .bind(vm_id)
+    .bind(credit)
+    .bind(bonus)
+    .bind(fleet.map(|fleet| fleet.name.as_str()))
+    .bind(vm_api::DEFAULT_FLEET)
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        r#"
+INSERT INTO vm_status (
+    vm_id, last_seen, first_seen, online_since,
+    region, version, bandwidth_mbps, last_heartbeat, extra
+)
+VALUES ($1, $2, $2, $2, $4, $5, $6, $7, $8)
+ON CONFLICT(vm_id) DO UPDATE SET
+    online_since = CASE
+WHEN $2 - vm_status.last_seen > $3 OR vm_status.online_since IS NULL THEN $2
+ELSE vm_status.online_since
+    END,
+    last_seen = excluded.last_seen,
+    region = COALESCE(excluded.region, vm_status.region),
+    version = COALESCE(excluded.version, vm_status.version),
+    bandwidth_mbps = COALESCE(excluded.bandwidth_mbps, vm_status.bandwidth_mbps),
+    last_heartbeat = COALESCE(excluded.last_heartbeat, vm_status.last_heartbeat),
+    extra = COALESCE(excluded.extra, vm_status.extra);
+    "#,
+    )
+    .bind(vm_id)
+    .bind(now)
+    .bind(OFFLINE_AFTER_SECS)
+    .bind(vm.region.as_deref())
+    .bind(vm.version.as_deref())
+    .bind(vm.bandwidth_mbps)
+    .bind(vm.last_heartbeat)
+    .bind((!vm.extra.is_empty()).then(|| Value::Object(vm.extra.clone()).to_string()))
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        r#"
+INSERT INTO uptime_history (vm_id, day, up_secs)
+VALUES ($1, $2, $3)
+ON CONFLICT(vm_id, day) DO UPDATE SET
+    up_secs = uptime_history.up_secs + excluded.up_secs;
+    "#,
+    )
+    .bind(vm_id)
+    .bind(render::format_date(now))
+    .bind(credit)
+    .execute(&mut *conn)
+    .await?;
+    Ok(credit)
+}
+
+/// Links an unowned VM to `chat_id`, returning whether it was still free
+async fn link_vm(chat_id: ChatId, vm_id: &str, via_token: bool) -> sqlx::Result<bool> {
+    let (_write, mut tx) = begin_write().await?;
+    let linked = sqlx::query(
+        r#"
+UPDATE agent_records SET telegram_chat_id = $1, linked_at = $2
+WHERE vm_id = $3 AND telegram_chat_id IS NULL
+  AND NOT EXISTS (SELECT 1 FROM bans WHERE vm_id = $3 AND shadow = 0)
+        "#,
+    )
+    .bind(chat_id.0)
+    .bind(now_unix())
+    .bind(vm_id)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected()
+        > 0;
+    if linked {
+        let via = if via_token { "token" } else { "vm_id" };
+        audit::record(
+            &mut tx,
+            &audit::Actor::Chat(chat_id),
+            "vm_linked",
+            Some(chat_id),
+            serde_json::json!({ "vm_id": vm_id, "via": via }),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(linked)
+}
+
+/// Unlinks every VM of `chat_id`, or only `vm_id` when given; their balances stay with
+/// the VMs
+async fn unlink_vms(chat_id: ChatId, vm_id: Option<&str>) -> sqlx::Result<()> {
+    let (_write, mut tx) = begin_write().await?;
+    let vms: Vec<(String, i64)> = sqlx::query_as(
+        "SELECT vm_id, up_secs + bonus_secs - paid_secs FROM agent_records WHERE telegram_chat_id = $1 AND ($2 IS NULL OR vm_id = $2)",
+    )
+    .bind(chat_id.0)
+    .bind(vm_id)
+    .fetch_all(&mut *tx)
+    .await?;
+    sqlx::query(
+        "UPDATE agent_records SET telegram_chat_id = NULL, linked_at = NULL, nickname = NULL WHERE telegram_chat_id = $1 AND ($2 IS NULL OR vm_id = $2)",
+    )
+    .bind(chat_id.0)
+    .bind(vm_id)
+    .execute(&mut *tx)
+    .await?;
+    for (vm_id, unclaimed_secs) in vms {
+        audit::record(
+            &mut tx,
+            &audit::Actor::Chat(chat_id),
+            "vm_unlinked",
+            Some(chat_id),
+            serde_json::json!({ "vm_id": vm_id, "unclaimed_secs": unclaimed_secs }),
+        )
+        .await?;
+    }
+    tx.commit().await
+}
+
+/// Reminds each chat of its unclaimed days once every [`NOTIFY_INTERVAL_SECS`]. When a
+/// chat is due comes from `user_prefs.notified_at`, not from when the process started, so
+/// a restart neither repeats nor skips a reminder. Runs as the `notify` job.
+async fn notify_uptime(bot: Bot) -> anyhow::Result<()> {
+    let now = now_unix();
+    let notifications: Vec<(i64, i64)> = sqlx::query_as(
+        r#"
+SELECT a.telegram_chat_id, CAST(SUM(a.up_secs + a.bonus_secs - a.paid_secs) AS BIGINT)
+FROM agent_records a LEFT JOIN user_prefs p ON p.telegram_chat_id = a.telegram_chat_id
+WHERE a.telegram_chat_id IS NOT NULL
+  AND COALESCE(p.daily_notify, 1) = 1
+  AND (p.notified_at IS NULL OR p.notified_at <= $1)
+  AND NOT EXISTS(SELECT 1 FROM inactive_chats i WHERE i.telegram_chat_id = a.telegram_chat_id)
+GROUP BY a.telegram_chat_id
+        "#,
+    )
+    .bind(now - NOTIFY_INTERVAL_SECS)
+    .fetch_all(&*DB)
+    .await?;
+
+    let policy = RewardPolicy::current();
+    for (chat_id, unclaimed_secs) in notifications {
+        let new_days = policy.days(unclaimed_secs);
+        if new_days == 0 {
+            continue;
+        }
+        let text = i18n::text("daily.reminder", &[("count", &new_days)]);
+        let chat_id = ChatId(chat_id);
+        let _ = outbox::deliver(chat_id, || {
+            send_status(&bot, chat_id, Indicator::Balance, &text)
+        })
+        .await;
+        sqlx::query(
             r#"
-SELECT telegram_chat_id, (up_secs - paid_secs) / 86400 AS new_days
-FROM agent_records
-WHERE telegram_chat_id IS NOT NULL
-  AND (up_secs - paid_secs) >= 86400
-            "#,
+INSERT INTO user_prefs (telegram_chat_id, notified_at) VALUES ($1, $2)
+ON CONFLICT(telegram_chat_id) DO UPDATE SET notified_at = excluded.notified_at
+                    "#,
         )
-        .fetch_all(&*DB)
+        .bind(chat_id.0)
+        .bind(now)
+        .execute(&*DB)
         .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telegram::{Call, Fake};
+    use teloxide::types::{InlineKeyboardButtonKind, ReplyMarkup};
+
+    /// Sends `text` from `chat_id`'s private chat, as typed
+    async fn send(bot: &Fake, chat_id: ChatId, text: &str) {
+        run_command(bot.clone(), chat_id, text, CancellationToken::new())
+            .await
+            .unwrap();
+    }
+
+    fn last_text(bot: &Fake, chat_id: ChatId) -> String {
+        bot.texts(chat_id).pop().unwrap_or_default()
+    }
+
+    #[test]
+    fn register_accrue_claim_deregister() {
+        smol::block_on(async {
+            let (bot, chat_id, vm_id) = (Fake::default(), testing::chat(), testing::vm_id());
+            let now = now_unix();
+            // Three polls a minute apart credit two minutes, two days under the test config
+            for at in [now - 2 * POLL_SECS, now - POLL_SECS, now] {
+                testing::sight(&vm_id, at).await;
+            }
+
+            send(&bot, chat_id, &format!("/register {vm_id}")).await;
+            let owner: Option<i64> =
+                sqlx::query_scalar("SELECT telegram_chat_id FROM agent_records WHERE vm_id = $1")
+                    .bind(&vm_id)
+                    .fetch_one(&*DB)
+                    .await
+                    .unwrap();
+            assert_eq!(owner, Some(chat_id.0));
+            assert!(
+                bot.texts(chat_id)
+                    .iter()
+                    .any(|text| text.contains(&i18n::text("register_success", &[])))
+            );
+
+            send(&bot, chat_id, "/claim").await;
+            assert!(last_text(&bot, chat_id).contains("How would you like your 2 days?"));
+            send(&bot, chat_id, "/claim 2x1").await;
+            let codes: Vec<String> = bot
+                .texts(chat_id)
+                .into_iter()
+                .filter(|text| text.contains("STAGING-1D-"))
+                .collect();
+            assert_eq!(codes.len(), 2);
+            assert_eq!(giftcards::list(chat_id).await.unwrap().len(), 2);
+            assert_eq!(claim::available(chat_id).await.unwrap(), 0);
+
+            send(&bot, chat_id, "/deregister").await;
+            assert!(last_text(&bot, chat_id).starts_with("Deregister your VM?"));
+            send(&bot, chat_id, "/deregister confirm").await;
+            assert!(last_text(&bot, chat_id).contains("Your VM has been deregistered."));
+            let owner: Option<i64> =
+                sqlx::query_scalar("SELECT telegram_chat_id FROM agent_records WHERE vm_id = $1")
+                    .bind(&vm_id)
+                    .fetch_one(&*DB)
+                    .await
+                    .unwrap();
+            assert_eq!(owner, None);
+        });
+    }
+
+    #[test]
+    fn mycards_lists_issued_cards() {
+        smol::block_on(async {
+            let (bot, chat_id) = (Fake::default(), testing::chat());
+            let now = now_unix();
+            testing::seed_vm(&DB, &testing::vm_id(), Some(chat_id), 0, now).await;
+            let id = testing::seed_giftcard(&DB, chat_id, "CODE-123", 7, now).await;
+
+            send(&bot, chat_id, "/mycards").await;
+            let text = last_text(&bot, chat_id);
+            assert!(text.contains(&format!("#{id}")), "{text}");
+            assert!(text.contains("CODE-123"), "{text}");
+        });
+    }
+
+    #[test]
+    fn vm_details_by_nickname() {
+        smol::block_on(async {
+            let (bot, chat_id, vm_id) = (Fake::default(), testing::chat(), testing::vm_id());
+            testing::seed_vm(&DB, &vm_id, Some(chat_id), 150, now_unix()).await;
+            nicknames::rename(chat_id, &vm_id, Some("home server"))
+                .await
+                .unwrap();
+
+            send(&bot, chat_id, "/vm home server").await;
+            let text = last_text(&bot, chat_id);
+            assert!(text.starts_with("🖥 home server\nID: "), "{text}");
+            assert!(text.contains("🟢 home server: online"), "{text}");
+            assert!(
+                text.contains("Unclaimed: 2m (about 2.5 Plus days)"),
+                "{text}"
+            );
+
+            let stranger = testing::chat();
+            testing::seed_vm(&DB, &testing::vm_id(), Some(stranger), 0, now_unix()).await;
+            send(&bot, stranger, &format!("/vm {vm_id}")).await;
+            assert!(last_text(&bot, stranger).contains("E003"));
+        });
+    }
+
+    #[test]
+    fn myvms_pages() {
+        smol::block_on(async {
+            let (bot, chat_id) = (Fake::default(), testing::chat());
+            let now = now_unix();
+            let mut vm_ids: Vec<String> = (0..MY_VMS_PAGE_SIZE + 1)
+                .map(|_| testing::vm_id())
+                .collect();
+            vm_ids.sort();
+            for vm_id in &vm_ids {
+                testing::seed_vm(&DB, vm_id, Some(chat_id), 0, now).await;
+            }
+            let buttons = |bot: &Fake| -> Vec<String> {
+                let Some(Call::Send(message)) = bot.calls().pop() else {
+                    panic!("nothing sent");
+                };
+                let Some(ReplyMarkup::InlineKeyboard(markup)) = message.reply_markup else {
+                    panic!("no buttons");
+                };
+                markup
+                    .inline_keyboard
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|button| match button.kind {
+                        InlineKeyboardButtonKind::CallbackData(data) => Some(data),
+                        _ => None,
+                    })
+                    .collect()
+            };
+
+            send(&bot, chat_id, "/myvms").await;
+            assert!(last_text(&bot, chat_id).contains("page 1 of 2"));
+            let first = buttons(&bot);
+            assert!(first.contains(&format!("/deregister {}", vm_ids[0])));
+            assert!(first.contains(&"/myvms 2".to_owned()));
+            send(&bot, chat_id, "/myvms 2").await;
+            let second = buttons(&bot);
+            assert!(second.contains(&format!("/vm {}", vm_ids[MY_VMS_PAGE_SIZE])));
+            assert!(second.contains(&"/myvms 1".to_owned()));
+        });
+    }
+
+    #[test]
+    fn rename_and_deregister_one_vm() {
+        smol::block_on(async {
+            let (bot, chat_id, vm_id) = (Fake::default(), testing::chat(), testing::vm_id());
+            let other = testing::vm_id();
+            for vm_id in [&vm_id, &other] {
+                testing::seed_vm(&DB, vm_id, Some(chat_id), 0, now_unix()).await;
+            }
+
+            send(&bot, chat_id, &format!("/myvms rename {vm_id}")).await;
+            send(&bot, chat_id, "attic box").await;
+            assert!(last_text(&bot, chat_id).contains("is now called attic box"));
+
+            send(&bot, chat_id, "/deregister attic box").await;
+            assert!(last_text(&bot, chat_id).starts_with("Deregister attic box?"));
+            send(&bot, chat_id, "yes").await;
+            assert!(last_text(&bot, chat_id).contains("attic box has been deregistered."));
+            let owner: Option<i64> =
+                sqlx::query_scalar("SELECT telegram_chat_id FROM agent_records WHERE vm_id = $1")
+                    .bind(&other)
+                    .fetch_one(&*DB)
+                    .await
+                    .unwrap();
+            assert_eq!(owner, Some(chat_id.0));
+        });
+    }
+
+    #[test]
+    fn admin_adjusts_uptime_with_a_reason() {
+        smol::block_on(async {
+            let (bot, chat_id, vm_id) = (Fake::default(), testing::chat(), testing::vm_id());
+            testing::seed_vm(&DB, &vm_id, Some(chat_id), 600, now_unix()).await;
+            let up_secs = || async {
+                sqlx::query_scalar::<_, i64>("SELECT up_secs FROM agent_records WHERE vm_id = $1")
+                    .bind(&vm_id)
+                    .fetch_one(&*DB)
+                    .await
+                    .unwrap()
+            };
+
+            send(
+                &bot,
+                testing::ADMIN,
+                &format!("/admin adjust {vm_id} 2 no sign"),
+            )
+            .await;
+            send(&bot, testing::ADMIN, &format!("/admin adjust {vm_id} +2")).await;
+            send(
+                &bot,
+                testing::ADMIN,
+                &format!("/admin adjust {vm_id} -1 too much"),
+            )
+            .await;
+            assert_eq!(up_secs().await, 600);
+
+            send(
+                &bot,
+                testing::ADMIN,
+                &format!("/admin adjust {vm_id} +1.5 missed polls on 2026-10-01"),
+            )
+            .await;
+            assert_eq!(up_secs().await, 600 + 5400);
+            let entries = audit::for_chat(chat_id, 10).await.unwrap();
+            let entry = entries
+                .iter()
+                .find(|entry| entry.action == "uptime_adjusted")
+                .expect("audited");
+            assert_eq!(entry.actor, format!("admin:{}", testing::ADMIN));
+            assert!(entry.payload.contains("missed polls on 2026-10-01"));
+        });
+    }
+
+    #[test]
+    fn banned_chats_get_nothing_and_shadow_banned_vms_earn_nothing() {
+        smol::block_on(async {
+            let bot = Fake::default();
+            let (shadowed, banned) = (testing::chat(), testing::chat());
+            let (shadowed_vm, banned_vm) = (testing::vm_id(), testing::vm_id());
+            let now = now_unix();
+            testing::seed_vm(&DB, &shadowed_vm, Some(shadowed), 600, now).await;
+            testing::seed_vm(&DB, &banned_vm, Some(banned), 600, now).await;
+
+            send(
+                &bot,
+                testing::ADMIN,
+                &format!("/admin shadowban chat {shadowed} multiple accounts"),
+            )
+            .await;
+            assert!(last_text(&bot, testing::ADMIN).starts_with("Shadow-banned"));
+            assert_eq!(testing::sight(&shadowed_vm, now + 60).await, 0);
+            // Nothing looks different to the chat itself
+            send(&bot, shadowed, "/status").await;
+            assert!(!last_text(&bot, shadowed).is_empty());
+
+            bans::ban(&bans::Target::Chat(banned), false, "spam", testing::ADMIN)
+                .await
+                .unwrap();
+            send(&bot, banned, "/status").await;
+            assert!(bot.texts(banned).is_empty());
+            assert_eq!(testing::sight(&banned_vm, now + 60).await, 0);
+
+            assert!(
+                bans::unban(&bans::Target::Chat(banned), testing::ADMIN)
+                    .await
+                    .unwrap()
+            );
+            assert_eq!(testing::sight(&banned_vm, now + 120).await, 60);
+            send(&bot, banned, "/status").await;
+            assert!(!bot.texts(banned).is_empty());
+        });
+    }
+
+    #[test]
+    fn replace_refuses_a_banned_vm() {
+        smol::block_on(async {
+            let (bot, chat_id) = (Fake::default(), testing::chat());
+            let (old_vm, banned_vm, new_vm) =
+                (testing::vm_id(), testing::vm_id(), testing::vm_id());
+            let now = now_unix();
+            testing::seed_vm(&DB, &old_vm, Some(chat_id), 600, now - 86400).await;
+            testing::seed_vm(&DB, &banned_vm, None, 0, now).await;
+            testing::seed_vm(&DB, &new_vm, None, 0, now).await;
+            bans::ban(
+                &bans::Target::Vm(banned_vm.clone()),
+                false,
+                "stolen",
+                testing::ADMIN,
+            )
+            .await
+            .unwrap();
+
+            send(&bot, chat_id, &format!("/replace {old_vm} {banned_vm}")).await;
+            let owner: Option<i64> =
+                sqlx::query_scalar("SELECT telegram_chat_id FROM agent_records WHERE vm_id = $1")
+                    .bind(&banned_vm)
+                    .fetch_one(&*DB)
+                    .await
+                    .unwrap();
+            assert_eq!(owner, None);
+
+            send(&bot, chat_id, &format!("/replace {old_vm} {new_vm}")).await;
+            assert!(last_text(&bot, chat_id).contains("now replaces"));
+        });
+    }
+
+    #[test]
+    fn banned_chat_appeals_and_is_let_back_in() {
+        smol::block_on(async {
+            let (bot, chat_id, bystander) = (Fake::default(), testing::chat(), testing::chat());
+            testing::seed_vm(&DB, &testing::vm_id(), Some(chat_id), 600, now_unix()).await;
+            bans::ban(&bans::Target::Chat(chat_id), false, "spam", testing::ADMIN)
+                .await
+                .unwrap();
+
+            send(&bot, bystander, "/appeal let me in").await;
+            assert!(last_text(&bot, bystander).starts_with("There's nothing to appeal"));
+
+            send(&bot, chat_id, "/appeal I only run one VM").await;
+            assert!(last_text(&bot, chat_id).starts_with("📨"));
+            let forwarded = bot.texts(testing::OTHER_ADMIN).pop().unwrap();
+            assert!(forwarded.contains("I only run one VM") && forwarded.contains("Banned: spam"));
+            send(&bot, chat_id, "/appeal me again").await;
+            assert!(last_text(&bot, chat_id).contains("still being reviewed"));
+
+            let id: i64 = sqlx::query_scalar("SELECT id FROM appeals WHERE telegram_chat_id = $1")
+                .bind(chat_id.0)
+                .fetch_one(&*DB)
+                .await
+                .unwrap();
+            send(
+                &bot,
+                testing::OTHER_ADMIN,
+                &format!("/admin appeal_accept {id}"),
+            )
+            .await;
+            assert!(last_text(&bot, chat_id).contains("accepted"));
+            assert!(!bans::is_banned(chat_id).await.unwrap());
+            send(
+                &bot,
+                testing::OTHER_ADMIN,
+                &format!("/admin appeal_reject {id}"),
+            )
+            .await;
+            assert!(last_text(&bot, testing::OTHER_ADMIN).contains("already decided"));
+        });
+    }
+
+    #[test]
+    fn accepted_appeals_keep_shadow_bans_unless_lifted() {
+        smol::block_on(async {
+            let bot = Fake::default();
+            for lift in [false, true] {
+                let chat_id = testing::chat();
+                bans::ban(&bans::Target::Chat(chat_id), true, "farm", testing::ADMIN)
+                    .await
+                    .unwrap();
+                sqlx::query(
+                    "INSERT INTO fraud_flags (telegram_chat_id, kind, evidence, detail, created_at) VALUES ($1, 'burst', $2, 'many VMs', 0)",
+                )
+                .bind(chat_id.0)
+                .bind(chat_id.to_string())
+                .execute(&*DB)
+                .await
+                .unwrap();
+
+                send(&bot, chat_id, "/appeal they're all mine").await;
+                let id: i64 =
+                    sqlx::query_scalar("SELECT id FROM appeals WHERE telegram_chat_id = $1")
+                        .bind(chat_id.0)
+                        .fetch_one(&*DB)
+                        .await
+                        .unwrap();
+                let accept = if lift { " shadow" } else { "" };
+                send(
+                    &bot,
+                    testing::OTHER_ADMIN,
+                    &format!("/admin appeal_accept {id}{accept}"),
+                )
+                .await;
+                assert!(last_text(&bot, chat_id).contains("accepted"));
+                let shadow_bans: i64 =
+                    sqlx::query_scalar("SELECT COUNT(*) FROM bans WHERE telegram_chat_id = $1")
+                        .bind(chat_id.0)
+                        .fetch_one(&*DB)
+                        .await
+                        .unwrap();
+                assert_eq!(shadow_bans, if lift { 0 } else { 1 });
+            }
+        });
+    }
+
+    #[test]
+    fn old_unclaimed_uptime_lapses_after_a_warning() {
+        smol::block_on(async {
+            let (bot, chat_id, vm_id) = (Fake::default(), testing::chat(), testing::vm_id());
+            let now = now_unix();
+            testing::seed_vm(&DB, &vm_id, Some(chat_id), 240, now).await;
+            for (days_ago, up_secs) in [(100, 120), (85, 60), (0, 60)] {
+                sqlx::query("INSERT INTO uptime_history (vm_id, day, up_secs) VALUES ($1, $2, $3)")
+                    .bind(&vm_id)
+                    .bind(render::format_date(now - days_ago * 86400))
+                    .bind(up_secs)
+                    .execute(&*DB)
+                    .await
+                    .unwrap();
+            }
+            let policy = RewardPolicy {
+                expiry_days: Some(90),
+                ..RewardPolicy::current()
+            };
+            let balance = || async {
+                sqlx::query_scalar::<_, i64>(
+                    "SELECT up_secs + bonus_secs - paid_secs FROM agent_records WHERE vm_id = $1",
+                )
+                .bind(&vm_id)
+                .fetch_one(&*DB)
+                .await
+                .unwrap()
+            };
+
+            // The 100-day-old minute is already due and the 85-day-old one will be within
+            // the week, but nothing lapses before the owner has been warned
+            lapse::apply(&bot, &policy, now).await.unwrap();
+            assert_eq!(balance().await, 240);
+            let warnings = bot.texts(chat_id);
+            assert_eq!(warnings.len(), 1);
+            assert!(warnings[0].contains("About 3.0 Plus days"));
+
+            // A day on, the warning hasn't been out for the whole window yet
+            lapse::apply(&bot, &policy, now + 86400).await.unwrap();
+            assert_eq!(balance().await, 240);
+            assert_eq!(bot.texts(chat_id).len(), 1);
+
+            let week = CONFIG.unclaimed_warning_days * 86400;
+            lapse::apply(&bot, &policy, now + week).await.unwrap();
+            assert_eq!(balance().await, 60);
+            assert_eq!(bot.texts(chat_id).len(), 1);
+        });
+    }
+
+    #[test]
+    fn claims_spend_the_balance_of_every_vm() {
+        smol::block_on(async {
+            let (bot, chat_id) = (Fake::default(), testing::chat());
+            let (a, b, c) = (testing::vm_id(), testing::vm_id(), testing::vm_id());
+            let now = now_unix();
+            testing::seed_vm(&DB, &a, Some(chat_id), 0, now).await;
+            testing::seed_vm(&DB, &b, Some(chat_id), 120, now).await;
+            testing::seed_vm(&DB, &c, Some(chat_id), 60, now).await;
+            assert_eq!(claim::available(chat_id).await.unwrap(), 3);
+            send(&bot, chat_id, "/unclaimed").await;
+            assert!(last_text(&bot, chat_id).contains("You have 3 unclaimed Plus days"));
+
+            let outcome = claim::claim(chat_id, Some(claim::Split::single(3)))
+                .await
+                .unwrap();
+            assert!(matches!(outcome, ClaimOutcome::Issued { .. }));
+            for vm_id in [&a, &b, &c] {
+                let balance: i64 = sqlx::query_scalar(
+                    "SELECT up_secs + bonus_secs - paid_secs FROM agent_records WHERE vm_id = $1",
+                )
+                .bind(vm_id)
+                .fetch_one(&*DB)
+                .await
+                .unwrap();
+                assert_eq!(balance, 0, "{vm_id}");
+            }
+        });
+    }
+
+    #[test]
+    fn claims_after_a_vm_leaves_get_a_key_of_their_own() {
+        smol::block_on(async {
+            let chat_id = testing::chat();
+            let (a, b) = (testing::vm_id(), testing::vm_id());
+            testing::seed_vm(&DB, &a, Some(chat_id), 120, now_unix()).await;
+            let claimed = claim::claim(chat_id, None).await.unwrap();
+            assert!(matches!(claimed, ClaimOutcome::Issued { .. }));
+
+            // The chat's paid total goes back to what it was before the first claim
+            sqlx::query("UPDATE agent_records SET telegram_chat_id = NULL WHERE vm_id = $1")
+                .bind(&a)
+                .execute(&*DB)
+                .await
+                .unwrap();
+            testing::seed_vm(&DB, &b, Some(chat_id), 60, now_unix()).await;
+            let claimed = claim::claim(chat_id, None).await.unwrap();
+            assert!(matches!(claimed, ClaimOutcome::Issued { .. }));
+
+            let claims: Vec<(String, i64, String)> = sqlx::query_as(
+                "SELECT idempotency_key, days, status FROM claims WHERE telegram_chat_id = $1 ORDER BY days",
+            )
+            .bind(chat_id.0)
+            .fetch_all(&*DB)
+            .await
+            .unwrap();
+            assert_eq!(claims.len(), 2);
+            assert_ne!(claims[0].0, claims[1].0);
+            assert_eq!((claims[0].1, claims[1].1), (1, 2));
+            assert!(claims.iter().all(|(_, _, status)| status == "issued"));
+        });
+    }
+
+    #[test]
+    fn callback_without_data_is_only_answered() {
+        let query: CallbackQuery = serde_json::from_value(serde_json::json!({
+            "id": "q1",
+            "from": { "id": 7, "is_bot": false, "first_name": "Tester" },
+            "chat_instance": "c1",
+        }))
+        .unwrap();
+        let bot = Fake::default();
+        smol::block_on(callback_handler(
+            bot.clone(),
+            query,
+            CancellationToken::new(),
+        ))
+        .unwrap();
+        assert!(matches!(&bot.calls()[..], [Call::AnswerCallback(id)] if id == "q1"));
+        assert!(bot.texts(ChatId(7)).is_empty());
+    }
 
-        for (chat_id, new_days) in notifications {
-            let _ = bot.send_message(ChatId(chat_id), format!("Thank you for running a testing VM! You have {new_days} day(s) of unclaimed Plus. Use /claim to redeem your days. / 感谢您运营测试 VM！您目前有{new_days}天未领取的Plus。使用 /claim 领取您的天数。")).await;
+    /// The username parse_command tests address the bot by. Until it is known every name
+    /// is taken to be the bot's, so others can only be told apart once it's set.
+    fn bot_named() {
+        tokens::set_bot_username("GephTestBot");
+    }
+
+    #[test]
+    fn parse_command_takes_aliases_mentions_and_any_case() {
+        bot_named();
+        for text in [
+            "/register vm-1",
+            "/reg vm-1",
+            "/REGISTER vm-1",
+            "/Reg   vm-1  ",
+        ] {
+            assert!(
+                matches!(parse_command(text), Some(Command::Register(id)) if id == "vm-1"),
+                "{text:?}"
+            );
         }
+        // Arguments keep their case
+        assert!(
+            matches!(parse_command("/reg VM-Abc"), Some(Command::Register(id)) if id == "VM-Abc")
+        );
+        assert!(matches!(
+            parse_command("/balance"),
+            Some(Command::Unclaimed)
+        ));
+        assert!(matches!(parse_command("/Online"), Some(Command::Status)));
+        assert!(matches!(
+            parse_command("/stats"),
+            Some(Command::NetworkStats)
+        ));
+        assert!(matches!(
+            parse_command("/rewards"),
+            Some(Command::HowRewardsWork)
+        ));
+        assert!(matches!(
+            parse_command("/history"),
+            Some(Command::MyCards(None))
+        ));
+        assert!(matches!(
+            parse_command("/cards #12"),
+            Some(Command::MyCards(Some(12)))
+        ));
 
-        ticker.next().await;
+        for text in [
+            "/status@GephTestBot",
+            "/status@gephtestbot",
+            "/online@GephTestBot",
+            "@GephTestBot /status",
+            "@gephtestbot /STATUS",
+        ] {
+            assert!(
+                matches!(parse_command(text), Some(Command::Status)),
+                "{text:?}"
+            );
+        }
+        // Whatever follows the command is left alone, wherever the mention was
+        for text in [
+            "/appeal@GephTestBot  it was  me ",
+            "@GephTestBot /appeal it was  me",
+        ] {
+            assert!(
+                matches!(parse_command(text), Some(Command::Appeal(why)) if why == "it was  me"),
+                "{text:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_command_leaves_other_bots_and_malformed_input_alone() {
+        bot_named();
+        for text in [
+            "",
+            "   ",
+            "status",
+            "/",
+            "/nosuch",
+            "/status@OtherBot",
+            "/status@",
+            "@OtherBot /status",
+            "@GephTestBot",
+            "@GephTestBot status",
+            "/register",
+            "/claim x",
+            "/claim 3x",
+            "/claim 2x3x4",
+            "/mycards abc",
+            "/myvms two",
+            "/pin maybe",
+            "/digest maybe",
+            "/deletemydata now",
+            "/settings alerts",
+            "/settings nosuch on",
+            "/settings alerts maybe",
+            "/transfer vm-1 everything",
+            "/replace",
+            "/rename",
+            "/leaderboard top",
+        ] {
+            assert!(parse_command(text).is_none(), "{text:?}");
+        }
     }
 }
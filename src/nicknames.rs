@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use serde_json::json;
+use teloxide::types::ChatId;
+
+use crate::{
+    DB,
+    audit::{self, Actor},
+    begin_write,
+};
+
+pub const MAX_CHARS: usize = 32;
+
+pub enum Rename {
+    /// `nickname` is `None` when it was cleared
+    Renamed {
+        vm_id: String,
+        nickname: Option<String>,
+    },
+    /// No VM of this chat has that id or nickname
+    Unknown,
+    /// Another of the chat's VMs already goes by that name
+    Taken,
+}
+
+/// Nicknames are shown in place of the VM id, so they must be short, printable, and
+/// distinct from ids and names the chat already uses
+pub fn valid(nickname: &str) -> bool {
+    !nickname.trim().is_empty()
+        && nickname.chars().count() <= MAX_CHARS
+        && !nickname.chars().any(char::is_control)
+}
+
+/// How a VM is named in messages to its owner
+pub fn label(vm_id: &str, nickname: Option<&str>) -> String {
+    nickname.unwrap_or(vm_id).to_owned()
+}
+
+/// Nicknames of `chat_id`'s VMs that have one, by VM id
+pub async fn for_chat(chat_id: ChatId) -> sqlx::Result<HashMap<String, String>> {
+    let rows: Vec<(String, String)> = sqlx::query_as(
+        "SELECT vm_id, nickname FROM agent_records WHERE telegram_chat_id = $1 AND nickname IS NOT NULL",
+    )
+    .bind(chat_id.0)
+    .fetch_all(&*DB)
+    .await?;
+    Ok(rows.into_iter().collect())
+}
+
+/// The label of a single VM, for background messages about it
+pub async fn label_of(vm_id: &str) -> sqlx::Result<String> {
+    let nickname: Option<Option<String>> =
+        sqlx::query_scalar("SELECT nickname FROM agent_records WHERE vm_id = $1")
+            .bind(vm_id)
+            .fetch_optional(&*DB)
+            .await?;
+    Ok(label(vm_id, nickname.flatten().as_deref()))
+}
+
+/// The id of `chat_id`'s VM called `vm` by id or nickname
+pub async fn resolve(chat_id: ChatId, vm: &str) -> sqlx::Result<Option<String>> {
+    sqlx::query_scalar(
+        r#"
+SELECT vm_id FROM agent_records
+WHERE telegram_chat_id = $1 AND (vm_id = $2 OR nickname = $2)
+ORDER BY CASE WHEN vm_id = $2 THEN 0 ELSE 1 END
+LIMIT 1
+        "#,
+    )
+    .bind(chat_id.0)
+    .bind(vm)
+    .fetch_optional(&*DB)
+    .await
+}
+
+/// Names the VM `vm` (by id or current nickname), or clears its name with `None`
+pub async fn rename(chat_id: ChatId, vm: &str, nickname: Option<&str>) -> sqlx::Result<Rename> {
+    let Some(vm_id) = resolve(chat_id, vm).await? else {
+        return Ok(Rename::Unknown);
+    };
+    let (_write, mut tx) = begin_write().await?;
+    if let Some(nickname) = nickname {
+        let clashes: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM agent_records WHERE telegram_chat_id = $1 AND vm_id <> $2 AND (nickname = $3 OR vm_id = $3)",
+        )
+        .bind(chat_id.0)
+        .bind(&vm_id)
+        .bind(nickname)
+        .fetch_one(&mut *tx)
+        .await?;
+        if clashes > 0 {
+            return Ok(Rename::Taken);
+        }
+    }
+    sqlx::query(
+        "UPDATE agent_records SET nickname = $1 WHERE vm_id = $2 AND telegram_chat_id = $3",
+    )
+    .bind(nickname)
+    .bind(&vm_id)
+    .bind(chat_id.0)
+    .execute(&mut *tx)
+    .await?;
+    audit::record(
+        &mut tx,
+        &Actor::Chat(chat_id),
+        "vm_renamed",
+        Some(chat_id),
+        json!({ "vm_id": vm_id, "nickname": nickname }),
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(Rename::Renamed {
+        vm_id,
+        nickname: nickname.map(str::to_owned),
+    })
+}
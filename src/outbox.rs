@@ -0,0 +1,138 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use teloxide::{RequestError, types::ChatId};
+
+use crate::inactive;
+
+/// Telegram allows about 30 messages per second across all chats
+const GLOBAL_INTERVAL: Duration = Duration::from_millis(1000 / 30);
+/// ...and about one per second to any single chat
+const PER_CHAT_INTERVAL: Duration = Duration::from_secs(1);
+/// `RetryAfter` responses honoured before a message is given up on
+const MAX_RETRIES: u32 = 3;
+
+/// Next free send slot, globally and per chat
+struct Pacer {
+    next_global: Instant,
+    next_per_chat: HashMap<ChatId, Instant>,
+}
+
+static PACER: Lazy<Mutex<Pacer>> = Lazy::new(|| {
+    Mutex::new(Pacer {
+        next_global: Instant::now(),
+        next_per_chat: HashMap::new(),
+    })
+});
+
+static QUEUED: AtomicU64 = AtomicU64::new(0);
+static SENT: AtomicU64 = AtomicU64::new(0);
+static RETRIED: AtomicU64 = AtomicU64::new(0);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Counters for `/metrics`
+pub struct Stats {
+    /// Messages currently waiting for a slot
+    pub queued: u64,
+    pub sent: u64,
+    pub retried: u64,
+    /// Messages given up on, after retries or on a non-retryable error
+    pub dropped: u64,
+}
+
+pub fn stats() -> Stats {
+    Stats {
+        queued: QUEUED.load(Ordering::Relaxed),
+        sent: SENT.load(Ordering::Relaxed),
+        retried: RETRIED.load(Ordering::Relaxed),
+        dropped: DROPPED.load(Ordering::Relaxed),
+    }
+}
+
+/// Claims the earliest slot that respects both the global and `chat_id`'s pacing
+fn reserve(chat_id: ChatId) -> Instant {
+    let now = Instant::now();
+    let mut pacer = PACER.lock().unwrap();
+    pacer.next_per_chat.retain(|_, next| *next > now);
+    let slot = now
+        .max(pacer.next_global)
+        .max(pacer.next_per_chat.get(&chat_id).copied().unwrap_or(now));
+    pacer.next_global = slot + GLOBAL_INTERVAL;
+    pacer
+        .next_per_chat
+        .insert(chat_id, slot + PER_CHAT_INTERVAL);
+    slot
+}
+
+/// Holds every send back until `until`, since a flood wait applies to the whole bot
+fn pause(until: Instant) {
+    let mut pacer = PACER.lock().unwrap();
+    pacer.next_global = pacer.next_global.max(until);
+}
+
+/// Waits until no message is waiting for a slot, or `timeout` passes, and returns how
+/// many are still queued
+pub async fn drain(timeout: Duration) -> u64 {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let queued = QUEUED.load(Ordering::Relaxed);
+        if queued == 0 || Instant::now() >= deadline {
+            return queued;
+        }
+        smol::Timer::after(Duration::from_millis(100)).await;
+    }
+}
+
+/// Sends a bulk message to `chat_id` through the paced queue. `send` is called once per
+/// attempt; a `RetryAfter` from Telegram pauses the whole queue for the requested time and
+/// the message is retried, up to [`MAX_RETRIES`] times. A chat that blocked the bot is
+/// marked inactive so the loops stop queueing for it.
+///
+/// Interactive replies don't go through here: they answer one message each and are
+/// already bounded by [`crate::ratelimit`].
+pub async fn deliver<T, F, Fut>(chat_id: ChatId, send: F) -> Result<T, RequestError>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, RequestError>>,
+{
+    QUEUED.fetch_add(1, Ordering::Relaxed);
+    let mut retries = 0;
+    let result = loop {
+        smol::Timer::at(reserve(chat_id)).await;
+        match send().await {
+            Err(RequestError::RetryAfter(wait)) if retries < MAX_RETRIES => {
+                retries += 1;
+                RETRIED.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!(
+                    "telegram asked to retry after {}s sending to {chat_id}",
+                    wait.seconds()
+                );
+                pause(Instant::now() + wait.duration());
+            }
+            result => break result,
+        }
+    };
+    QUEUED.fetch_sub(1, Ordering::Relaxed);
+    match &result {
+        Ok(_) => {
+            SENT.fetch_add(1, Ordering::Relaxed);
+        }
+        Err(e) => {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+            if let Some(reason) = inactive::dead_chat_reason(e)
+                && let Err(e) = inactive::mark(chat_id, reason).await
+            {
+                tracing::warn!("could not mark chat {chat_id} inactive: {e}");
+            }
+        }
+    }
+    result
+}
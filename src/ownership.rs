@@ -0,0 +1,177 @@
+use rand::{Rng, distributions::Alphanumeric};
+use sqlx::AnyConnection;
+use teloxide::types::ChatId;
+
+use serde_json::json;
+
+use crate::{
+    DB,
+    audit::{self, Actor},
+    begin_write, now_unix,
+};
+
+/// Prefix that tells ownership tokens apart from registration tokens and VM ids
+const TOKEN_PREFIX: &str = "own-";
+/// Metadata field the VM agent reports the placed token in
+const METADATA_FIELD: &str = "ownership_token";
+/// How long a tester has to place the token before starting over
+const CHALLENGE_TTL_SECS: i64 = 3600;
+
+pub enum VerifyOutcome {
+    Verified {
+        vm_id: String,
+    },
+    NoChallenge,
+    Expired,
+    /// The VM hasn't reported the token since the challenge was issued
+    NotSeen,
+    /// Someone else linked the VM in the meantime
+    Taken,
+}
+
+/// Whether `vm_id` is known and nobody has linked it yet
+pub async fn is_unlinked(vm_id: &str) -> sqlx::Result<bool> {
+    let count: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM agent_records WHERE vm_id = $1 AND telegram_chat_id IS NULL",
+    )
+    .bind(vm_id)
+    .fetch_one(&*DB)
+    .await?;
+    Ok(count > 0)
+}
+
+/// Issues a fresh token for `chat_id` to place on `vm_id`, replacing any earlier challenge
+pub async fn challenge(chat_id: ChatId, vm_id: &str) -> sqlx::Result<String> {
+    let token: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(12)
+        .map(char::from)
+        .collect();
+    let token = format!("{TOKEN_PREFIX}{token}");
+    let now = now_unix();
+    sqlx::query(
+        r#"
+INSERT INTO ownership_challenges (telegram_chat_id, vm_id, token, created_at, expires_at)
+VALUES ($1, $2, $3, $4, $5)
+ON CONFLICT(telegram_chat_id) DO UPDATE SET
+    vm_id = excluded.vm_id,
+    token = excluded.token,
+    created_at = excluded.created_at,
+    expires_at = excluded.expires_at
+        "#,
+    )
+    .bind(chat_id.0)
+    .bind(vm_id)
+    .bind(&token)
+    .bind(now)
+    .bind(now + CHALLENGE_TTL_SECS)
+    .execute(&*DB)
+    .await?;
+    Ok(token)
+}
+
+/// Whether `vm_id` has reported `token` since `since`, through either the availability
+/// poll or a heartbeat
+async fn reported(
+    conn: &mut AnyConnection,
+    vm_id: &str,
+    token: &str,
+    since: i64,
+) -> sqlx::Result<bool> {
+    let reported: Option<(i64, Option<String>)> =
+        sqlx::query_as("SELECT last_seen, extra FROM vm_status WHERE vm_id = $1")
+            .bind(vm_id)
+            .fetch_optional(conn)
+            .await?;
+    Ok(reported.is_some_and(|(last_seen, extra)| {
+        last_seen >= since
+            && extra
+                .and_then(|extra| serde_json::from_str::<serde_json::Value>(&extra).ok())
+                .is_some_and(|extra| extra[METADATA_FIELD].as_str() == Some(token))
+    }))
+}
+
+/// Whether `chat_id` has proven it owns `vm_id` through an unexpired challenge, for
+/// commands other than `/verify` that take an unlinked VM; [`clear`] it once used
+pub async fn proves(chat_id: ChatId, vm_id: &str) -> sqlx::Result<bool> {
+    let mut conn = DB.acquire().await?;
+    let challenge: Option<(String, i64)> = sqlx::query_as(
+        "SELECT token, created_at FROM ownership_challenges WHERE telegram_chat_id = $1 AND vm_id = $2 AND expires_at >= $3",
+    )
+    .bind(chat_id.0)
+    .bind(vm_id)
+    .bind(now_unix())
+    .fetch_optional(&mut *conn)
+    .await?;
+    match challenge {
+        Some((token, created_at)) => reported(&mut conn, vm_id, &token, created_at).await,
+        None => Ok(false),
+    }
+}
+
+/// Drops `chat_id`'s challenge, if any
+pub async fn clear(chat_id: ChatId) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM ownership_challenges WHERE telegram_chat_id = $1")
+        .bind(chat_id.0)
+        .execute(&*DB)
+        .await?;
+    Ok(())
+}
+
+/// Links the chat to its challenged VM if the VM has reported the token since the
+/// challenge was issued, through either the availability poll or a heartbeat
+pub async fn verify(chat_id: ChatId) -> anyhow::Result<VerifyOutcome> {
+    let (_write, mut tx) = begin_write().await?;
+    let challenge: Option<(String, String, i64, i64)> = sqlx::query_as(
+        "SELECT vm_id, token, created_at, expires_at FROM ownership_challenges WHERE telegram_chat_id = $1",
+    )
+    .bind(chat_id.0)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some((vm_id, token, created_at, expires_at)) = challenge else {
+        return Ok(VerifyOutcome::NoChallenge);
+    };
+    if now_unix() > expires_at {
+        sqlx::query("DELETE FROM ownership_challenges WHERE telegram_chat_id = $1")
+            .bind(chat_id.0)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        return Ok(VerifyOutcome::Expired);
+    }
+
+    if !reported(&mut tx, &vm_id, &token, created_at).await? {
+        return Ok(VerifyOutcome::NotSeen);
+    }
+
+    let linked = sqlx::query(
+        "UPDATE agent_records SET telegram_chat_id = $1, linked_at = $2 WHERE vm_id = $3 AND telegram_chat_id IS NULL",
+    )
+    .bind(chat_id.0)
+    .bind(now_unix())
+    .bind(&vm_id)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected()
+        > 0;
+    sqlx::query("DELETE FROM ownership_challenges WHERE telegram_chat_id = $1")
+        .bind(chat_id.0)
+        .execute(&mut *tx)
+        .await?;
+    if linked {
+        audit::record(
+            &mut tx,
+            &Actor::Chat(chat_id),
+            "vm_linked",
+            Some(chat_id),
+            json!({ "vm_id": vm_id, "via": "ownership_proof" }),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(if linked {
+        VerifyOutcome::Verified { vm_id }
+    } else {
+        VerifyOutcome::Taken
+    })
+}
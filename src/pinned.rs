@@ -0,0 +1,153 @@
+use std::time::Duration;
+
+use teloxide::{
+    ApiError, RequestError,
+    prelude::*,
+    types::{ChatId, MessageId},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::{
+    CONFIG, DB, StatusRow, groups, i18n, next_tick, now_unix, outbox, policy::RewardPolicy, render,
+};
+
+/// The pinned dashboard: each VM's state, total uptime and unclaimed days, or in a group
+/// the group's stats
+pub async fn text(chat_id: ChatId) -> sqlx::Result<String> {
+    if !chat_id.is_user() {
+        let stats = groups::stats(chat_id).await?;
+        let at = render::format_timestamp(now_unix());
+        return Ok(format!(
+            "{stats}\n{}",
+            i18n::text("pinned.updated", &[("at", &at)])
+        ));
+    }
+    let vms: Vec<StatusRow> = sqlx::query_as(
+        r#"
+SELECT a.vm_id, a.nickname, s.last_seen, s.online_since
+FROM agent_records a LEFT JOIN vm_status s ON s.vm_id = a.vm_id
+WHERE a.telegram_chat_id = $1
+ORDER BY a.vm_id
+        "#,
+    )
+    .bind(chat_id.0)
+    .fetch_all(&*DB)
+    .await?;
+    let (up_secs, balance): (i64, i64) = sqlx::query_as(
+        r#"
+SELECT
+    CAST(COALESCE(SUM(up_secs), 0) AS BIGINT),
+    CAST(COALESCE(SUM(up_secs + bonus_secs - paid_secs), 0) AS BIGINT)
+FROM agent_records WHERE telegram_chat_id = $1
+        "#,
+    )
+    .bind(chat_id.0)
+    .fetch_one(&*DB)
+    .await?;
+
+    let now = now_unix();
+    let mut lines = vec![i18n::text("pinned.title", &[])];
+    if vms.is_empty() {
+        lines.push(i18n::text("pinned.no_vms", &[]));
+    }
+    lines.extend(vms.iter().map(|row| render::vm_status_line(row, now)));
+    let policy = RewardPolicy::current();
+    let (uptime, days) = (render::format_duration(up_secs), policy.days(balance));
+    lines.push(i18n::text("pinned.uptime", &[("uptime", &uptime)]));
+    lines.push(i18n::text("pinned.unclaimed", &[("count", &days)]));
+    lines.push(render::progress_line(
+        policy.progress(balance),
+        policy.secs_per_day,
+    ));
+    let at = render::format_timestamp(now);
+    lines.push(format!(
+        "\n{}",
+        i18n::text("pinned.updated", &[("at", &at)])
+    ));
+    Ok(lines.join("\n"))
+}
+
+/// The chat's pinned status message, if it has one
+pub async fn current(chat_id: ChatId) -> sqlx::Result<Option<MessageId>> {
+    let id: Option<i64> =
+        sqlx::query_scalar("SELECT message_id FROM pinned_status WHERE telegram_chat_id = $1")
+            .bind(chat_id.0)
+            .fetch_optional(&*DB)
+            .await?;
+    Ok(id.and_then(|id| i32::try_from(id).ok()).map(MessageId))
+}
+
+/// Makes `message_id` the chat's pinned status, replacing any earlier one
+pub async fn remember(chat_id: ChatId, message_id: MessageId) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+INSERT INTO pinned_status (telegram_chat_id, message_id, pinned_at) VALUES ($1, $2, $3)
+ON CONFLICT(telegram_chat_id) DO UPDATE SET
+    message_id = excluded.message_id,
+    pinned_at = excluded.pinned_at
+        "#,
+    )
+    .bind(chat_id.0)
+    .bind(i64::from(message_id.0))
+    .bind(now_unix())
+    .execute(&*DB)
+    .await?;
+    Ok(())
+}
+
+pub async fn forget(chat_id: ChatId) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM pinned_status WHERE telegram_chat_id = $1")
+        .bind(chat_id.0)
+        .execute(&*DB)
+        .await?;
+    Ok(())
+}
+
+/// Edits every pinned status with fresh figures each `pinned_status_interval_secs`. A
+/// message the user deleted can't be edited any more and is forgotten, as if unpinned.
+pub async fn refresh_loop(bot: Bot, shutdown: CancellationToken) -> anyhow::Result<()> {
+    let mut ticker = smol::Timer::interval(Duration::from_secs(CONFIG.pinned_status_interval_secs));
+    loop {
+        async {
+            let rows: Vec<(i64, i64)> = sqlx::query_as(
+                r#"
+SELECT p.telegram_chat_id, p.message_id FROM pinned_status p
+WHERE NOT EXISTS(SELECT 1 FROM inactive_chats i WHERE i.telegram_chat_id = p.telegram_chat_id)
+                "#,
+            )
+            .fetch_all(&*DB)
+            .await?;
+            for (chat_id, message_id) in rows {
+                let chat_id = ChatId(chat_id);
+                let Ok(message_id) = i32::try_from(message_id).map(MessageId) else {
+                    continue;
+                };
+                let text = text(chat_id).await?;
+                let edited = outbox::deliver(chat_id, || {
+                    bot.edit_message_text(chat_id, message_id, &text).send()
+                })
+                .await;
+                match edited {
+                    Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => {}
+                    Err(RequestError::Api(
+                        ApiError::MessageToEditNotFound | ApiError::MessageCantBeEdited,
+                    )) => {
+                        tracing::info!("pinned status of {chat_id} is gone, forgetting it");
+                        forget(chat_id).await?;
+                    }
+                    Err(e) => {
+                        tracing::warn!("refreshing the pinned status of {chat_id} failed: {e}")
+                    }
+                }
+            }
+            anyhow::Ok(())
+        }
+        .instrument(tracing::info_span!("tick"))
+        .await?;
+
+        if !next_tick("pinned_status", &mut ticker, &shutdown).await {
+            return Ok(());
+        }
+    }
+}
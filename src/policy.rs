@@ -0,0 +1,300 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::{CONFIG, POLL_SECS, events::RewardEvent, i18n, now_unix, render, streaks};
+
+/// How a balance that isn't a whole number of Plus days converts
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Rounding {
+    /// Whole days only; the remainder stays in the balance
+    #[default]
+    Floor,
+    /// Half a day or more counts as a day. The balance goes negative by the difference,
+    /// which further uptime makes up before the next day is earned.
+    Nearest,
+}
+
+/// Limits `/admin simulate` can try out on top of a [`RewardPolicy`]. None of them are
+/// in force; the default is no limit at all.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    /// Days on which a VM was up for less than this earn nothing
+    pub min_daily_secs: i64,
+    /// Most Plus days a chat can earn per calendar month
+    pub max_days_per_month: Option<i64>,
+}
+
+/// Rules for turning credited uptime into Plus days
+#[derive(Clone, Copy, Debug)]
+pub struct RewardPolicy {
+    /// Seconds of uptime worth one Plus day
+    pub secs_per_day: i64,
+    pub rounding: Rounding,
+    /// Most unclaimed Plus days a VM can hold
+    pub cap_days: Option<i64>,
+    /// Days unclaimed uptime is kept before it lapses
+    pub expiry_days: Option<i64>,
+}
+
+impl RewardPolicy {
+    /// The policy in effect, from `reward_secs_per_day` and `reward_rounding`
+    pub fn current() -> RewardPolicy {
+        RewardPolicy {
+            secs_per_day: CONFIG.reward_secs_per_day,
+            rounding: CONFIG.reward_rounding,
+            cap_days: CONFIG.unclaimed_cap_days,
+            expiry_days: CONFIG.unclaimed_expiry_days,
+        }
+    }
+
+    /// How much of a VM's `balance` lapses: what's left of the `earned_before` it
+    /// accrued before the expiry window once `paid_secs` has been spent on the oldest
+    /// uptime first, then whatever is still over the cap
+    pub fn lapsing(&self, balance: i64, paid_secs: i64, earned_before: i64) -> i64 {
+        let balance = balance.max(0);
+        let expired = match self.expiry_days {
+            Some(_) => (earned_before - paid_secs).clamp(0, balance),
+            None => 0,
+        };
+        let over_cap = match self.cap_days {
+            Some(cap) => (balance - expired - self.secs(cap)).max(0),
+            None => 0,
+        };
+        expired + over_cap
+    }
+
+    /// Plus days a balance of `secs` converts to; never negative
+    pub fn days(&self, secs: i64) -> i64 {
+        self.rounded(secs).div_euclid(self.secs_per_day).max(0)
+    }
+
+    /// Seconds of a balance of `secs` already counted towards its next Plus day, out of
+    /// `secs_per_day`
+    pub fn progress(&self, secs: i64) -> i64 {
+        (self.secs_per_day - self.remaining(secs)).max(0)
+    }
+
+    /// Further balance a balance of `secs` needs before [`Self::days`] goes up by one. A
+    /// balance left negative by rounding has to make up the difference first.
+    pub fn remaining(&self, secs: i64) -> i64 {
+        let secs = self.rounded(secs);
+        if secs < 0 {
+            self.secs_per_day - secs
+        } else {
+            self.secs_per_day - secs.rem_euclid(self.secs_per_day)
+        }
+    }
+
+    /// Wall-clock seconds until `needed` more balance accrues with `online` VMs up the
+    /// whole time, counting the extra credit of `event` until it ends. `None` with no VM
+    /// online.
+    pub fn time_to_earn(
+        &self,
+        needed: i64,
+        online: i64,
+        event: Option<&RewardEvent>,
+        now: i64,
+    ) -> Option<i64> {
+        if online <= 0 {
+            return None;
+        }
+        let mut wall = 0;
+        let mut needed = needed as f64;
+        if let Some(event) = event.filter(|e| e.ends_at > now) {
+            let rate = online as f64 * event.multiplier;
+            let window = (event.ends_at - now) as f64;
+            if needed <= rate * window {
+                return Some((needed / rate).ceil() as i64);
+            }
+            needed -= rate * window;
+            wall += event.ends_at - now;
+        }
+        Some(wall + (needed / online as f64).ceil() as i64)
+    }
+
+    /// `secs` shifted so that whole multiples of `secs_per_day` fall where [`Self::days`]
+    /// ticks over
+    fn rounded(&self, secs: i64) -> i64 {
+        match self.rounding {
+            Rounding::Floor => secs,
+            Rounding::Nearest => secs + self.secs_per_day / 2,
+        }
+    }
+
+    /// Balance spent on `days` Plus days
+    pub fn secs(&self, days: i64) -> i64 {
+        days * self.secs_per_day
+    }
+
+    /// Whole Plus days earned under `limits` from daily uptime samples `(day, up_secs)`,
+    /// where `day` is a `YYYY-MM-DD` string
+    pub fn earned_days<'a>(
+        &self,
+        limits: Limits,
+        samples: impl IntoIterator<Item = (&'a str, i64)>,
+    ) -> i64 {
+        let mut months: BTreeMap<&str, i64> = BTreeMap::new();
+        for (day, up_secs) in samples {
+            if up_secs >= limits.min_daily_secs {
+                *months.entry(&day[..7]).or_default() += up_secs;
+            }
+        }
+        let days: f64 = months
+            .values()
+            .map(|&secs| {
+                let days = secs as f64 / self.secs_per_day as f64;
+                match limits.max_days_per_month {
+                    Some(cap) => days.min(cap as f64),
+                    None => days,
+                }
+            })
+            .sum();
+        match self.rounding {
+            Rounding::Floor => days.floor() as i64,
+            Rounding::Nearest => days.round() as i64,
+        }
+    }
+
+    /// The rules in plain words for `/how_rewards_work`, including the reward event in
+    /// effect (if any) and those still to come
+    pub fn explain(&self, active: Option<&RewardEvent>, upcoming: &[RewardEvent]) -> String {
+        let hours = self.secs_per_day as f64 / 3600.0;
+        let poll = POLL_SECS;
+        let mut lines = vec![
+            i18n::text("policy.title", &[]),
+            i18n::text("policy.rate", &[("hours", &hours)]),
+            i18n::text("policy.polling", &[("poll", &poll)]),
+        ];
+        lines.push(i18n::text("policy.no_min_daily", &[]));
+        if self.rounding == Rounding::Nearest {
+            let half = render::format_duration(self.secs_per_day / 2);
+            lines.push(i18n::text("policy.nearest", &[("half", &half)]));
+        }
+        lines.push(i18n::text("policy.no_monthly_cap", &[]));
+        if let Some(days) = self.expiry_days {
+            lines.push(i18n::text("policy.expiry", &[("days", &days)]));
+        }
+        if let Some(cap) = self.cap_days {
+            lines.push(i18n::text("policy.cap", &[("count", &cap)]));
+        }
+        // Each language lists the bonuses its own way
+        let streaks = CONFIG
+            .message_languages
+            .iter()
+            .map(|language| {
+                let bonuses = streaks::MILESTONES
+                    .iter()
+                    .map(|(days, bonus)| {
+                        let hours = bonus / 3600;
+                        i18n::text_in(
+                            language,
+                            "policy.streak_bonus",
+                            &[("hours", &hours), ("days", days)],
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(&i18n::text_in(language, "policy.list_separator", &[]));
+                i18n::text_in(language, "policy.streaks", &[("bonuses", &bonuses)])
+            })
+            .collect::<Vec<_>>()
+            .join(" / ");
+        lines.push(streaks);
+        if let Some(event) = active {
+            let (x, until) = (event.multiplier, render::format_timestamp(event.ends_at));
+            lines.push(i18n::text(
+                "policy.event_active",
+                &[("x", &x), ("until", &until)],
+            ));
+        }
+        for event in upcoming.iter().filter(|e| e.starts_at > now_unix()) {
+            let x = event.multiplier;
+            let (from, until) = (
+                render::format_timestamp(event.starts_at),
+                render::format_timestamp(event.ends_at),
+            );
+            lines.push(i18n::text(
+                "policy.event_upcoming",
+                &[("x", &x), ("from", &from), ("until", &until)],
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A policy of 100 seconds per day, so balances read as hundredths of a day
+    fn policy(rounding: Rounding) -> RewardPolicy {
+        RewardPolicy {
+            secs_per_day: 100,
+            rounding,
+            cap_days: None,
+            expiry_days: None,
+        }
+    }
+
+    #[test]
+    fn floor_counts_whole_days_only() {
+        let policy = policy(Rounding::Floor);
+        assert_eq!(policy.days(250), 2);
+        assert_eq!((policy.progress(250), policy.remaining(250)), (50, 50));
+        assert_eq!(policy.days(99), 0);
+        assert_eq!(policy.remaining(99), 1);
+        // On a day boundary the next day is still a full day away
+        assert_eq!(policy.days(200), 2);
+        assert_eq!((policy.progress(200), policy.remaining(200)), (0, 100));
+        assert_eq!((policy.progress(0), policy.remaining(0)), (0, 100));
+    }
+
+    #[test]
+    fn nearest_counts_half_a_day_as_a_day() {
+        let policy = policy(Rounding::Nearest);
+        assert_eq!(policy.days(49), 0);
+        assert_eq!(policy.remaining(49), 1);
+        // Exactly half a day rounds up, after which a whole day is needed again
+        assert_eq!(policy.days(50), 1);
+        assert_eq!((policy.progress(50), policy.remaining(50)), (0, 100));
+        assert_eq!(policy.days(149), 1);
+        assert_eq!(policy.days(150), 2);
+        assert_eq!((policy.progress(120), policy.remaining(120)), (70, 30));
+    }
+
+    #[test]
+    fn negative_balances_earn_nothing_until_made_up() {
+        let floor = policy(Rounding::Floor);
+        assert_eq!(floor.days(-50), 0);
+        assert_eq!((floor.progress(-50), floor.remaining(-50)), (0, 150));
+        // A whole day below zero is not a boundary to stop at
+        assert_eq!(floor.remaining(-100), 200);
+
+        // Claiming a day at half a day leaves -50, which is right back at the boundary
+        let nearest = policy(Rounding::Nearest);
+        assert_eq!(nearest.days(-50), 0);
+        assert_eq!((nearest.progress(-50), nearest.remaining(-50)), (0, 100));
+        assert_eq!(nearest.days(-50 + 100), 1);
+        assert_eq!(nearest.remaining(-60), 110);
+        assert_eq!(nearest.days(-60 + 110), 1);
+        assert_eq!(nearest.days(-150), 0);
+    }
+
+    #[test]
+    fn earned_days_applies_the_limits() {
+        let policy = policy(Rounding::Floor);
+        let samples = [("2026-03-01", 30), ("2026-03-02", 290), ("2026-04-01", 500)];
+        assert_eq!(policy.earned_days(Limits::default(), samples), 8);
+        let min_daily = Limits {
+            min_daily_secs: 50,
+            ..Limits::default()
+        };
+        assert_eq!(policy.earned_days(min_daily, samples), 7);
+        let monthly_cap = Limits {
+            max_days_per_month: Some(3),
+            ..Limits::default()
+        };
+        assert_eq!(policy.earned_days(monthly_cap, samples), 6);
+    }
+}
@@ -0,0 +1,156 @@
+use teloxide::types::{ChatId, InlineKeyboardMarkup};
+
+use crate::{DB, i18n, render};
+
+/// A per-chat switch stored in `user_prefs`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pref {
+    DailyRewards,
+    OfflineAlerts,
+    WeeklyDigest,
+    EventAnnouncements,
+    /// Claims credit the linked Geph account instead of producing giftcards
+    DirectCredit,
+}
+
+impl Pref {
+    pub const ALL: [Pref; 5] = [
+        Pref::DailyRewards,
+        Pref::OfflineAlerts,
+        Pref::WeeklyDigest,
+        Pref::EventAnnouncements,
+        Pref::DirectCredit,
+    ];
+
+    /// Word used for the setting in `/settings <name> on|off`
+    pub fn name(self) -> &'static str {
+        match self {
+            Pref::DailyRewards => "daily",
+            Pref::OfflineAlerts => "alerts",
+            Pref::WeeklyDigest => "digest",
+            Pref::EventAnnouncements => "events",
+            Pref::DirectCredit => "direct",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Pref> {
+        Pref::ALL.into_iter().find(|p| p.name() == name)
+    }
+
+    fn column(self) -> &'static str {
+        match self {
+            Pref::DailyRewards => "daily_notify",
+            Pref::OfflineAlerts => "offline_alerts",
+            Pref::WeeklyDigest => "weekly_digest",
+            Pref::EventAnnouncements => "event_announcements",
+            Pref::DirectCredit => "direct_credit",
+        }
+    }
+
+    /// Message naming the setting
+    fn label(self) -> &'static str {
+        match self {
+            Pref::DailyRewards => "prefs.daily",
+            Pref::OfflineAlerts => "prefs.alerts",
+            Pref::WeeklyDigest => "prefs.digest",
+            Pref::EventAnnouncements => "prefs.events",
+            Pref::DirectCredit => "prefs.direct",
+        }
+    }
+}
+
+/// A chat's preferences; chats without a row get the defaults
+#[derive(Clone, Copy, Debug)]
+pub struct Prefs {
+    pub daily_rewards: bool,
+    pub offline_alerts: bool,
+    pub weekly_digest: bool,
+    pub event_announcements: bool,
+    pub direct_credit: bool,
+}
+
+impl Default for Prefs {
+    fn default() -> Self {
+        Prefs {
+            daily_rewards: true,
+            offline_alerts: true,
+            weekly_digest: false,
+            event_announcements: false,
+            direct_credit: false,
+        }
+    }
+}
+
+impl Prefs {
+    pub fn get(&self, pref: Pref) -> bool {
+        match pref {
+            Pref::DailyRewards => self.daily_rewards,
+            Pref::OfflineAlerts => self.offline_alerts,
+            Pref::WeeklyDigest => self.weekly_digest,
+            Pref::EventAnnouncements => self.event_announcements,
+            Pref::DirectCredit => self.direct_credit,
+        }
+    }
+}
+
+pub async fn load(chat_id: ChatId) -> sqlx::Result<Prefs> {
+    let row: Option<(i64, i64, i64, i64, i64)> = sqlx::query_as(
+        "SELECT daily_notify, offline_alerts, weekly_digest, event_announcements, direct_credit FROM user_prefs WHERE telegram_chat_id = $1",
+    )
+    .bind(chat_id.0)
+    .fetch_optional(&*DB)
+    .await?;
+    Ok(row
+        .map(
+            |(daily_rewards, offline_alerts, weekly_digest, event_announcements, direct_credit)| {
+                Prefs {
+                    daily_rewards: daily_rewards != 0,
+                    offline_alerts: offline_alerts != 0,
+                    weekly_digest: weekly_digest != 0,
+                    event_announcements: event_announcements != 0,
+                    direct_credit: direct_credit != 0,
+                }
+            },
+        )
+        .unwrap_or_default())
+}
+
+pub async fn set(chat_id: ChatId, pref: Pref, enabled: bool) -> sqlx::Result<()> {
+    // The column name comes from a closed set, so formatting it into the query is safe
+    let column = pref.column();
+    sqlx::query(&format!(
+        r#"
+INSERT INTO user_prefs (telegram_chat_id, {column}) VALUES ($1, $2)
+ON CONFLICT(telegram_chat_id) DO UPDATE SET {column} = excluded.{column}
+        "#
+    ))
+    .bind(chat_id.0)
+    .bind(i64::from(enabled))
+    .execute(&*DB)
+    .await?;
+    Ok(())
+}
+
+/// The `/settings` overview, with one button per setting that flips it
+pub fn render(prefs: &Prefs) -> (String, InlineKeyboardMarkup) {
+    let mut lines = vec![i18n::text("prefs.title", &[])];
+    let mut buttons = vec![];
+    for pref in Pref::ALL {
+        let on = prefs.get(pref);
+        lines.push(format!(
+            "{} {}",
+            if on { "✅" } else { "🚫" },
+            i18n::text(pref.label(), &[])
+        ));
+        let (action, word) = if on {
+            ("prefs.turn_off", "off")
+        } else {
+            ("prefs.turn_on", "on")
+        };
+        buttons.push(vec![render::command_button(
+            i18n::text_nested(action, "setting", pref.label(), &[]),
+            format!("/settings {} {word}", pref.name()),
+        )]);
+    }
+    (lines.join("\n"), InlineKeyboardMarkup::new(buttons))
+}
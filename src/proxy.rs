@@ -0,0 +1,62 @@
+use isahc::{HttpClient, config::Configurable, http::Uri};
+use once_cell::sync::Lazy;
+
+use crate::{CONFIG, HTTP_TIMEOUT};
+
+/// What `proxy: geph` stands for: the SOCKS5 port a Geph client listens on by default.
+/// `socks5h` so names are resolved by the proxy too, as local DNS may be poisoned.
+pub const GEPH_CLIENT: &str = "socks5h://127.0.0.1:9909";
+
+/// Client for every outbound HTTP request besides Telegram's, through `proxy` when one is
+/// set. libcurl reads `*_proxy` environment variables as usual when it isn't.
+pub static HTTP: Lazy<HttpClient> = Lazy::new(|| {
+    let mut builder = HttpClient::builder();
+    if let Some(url) = CONFIG.proxy_url() {
+        builder = builder
+            .proxy(Some(url.parse().expect("proxy checked by validate")))
+            .proxy_blacklist(CONFIG.no_proxy.iter().cloned());
+    }
+    builder.build().expect("build the HTTP client")
+});
+
+/// Checks that `url` is a proxy every client here can use
+pub fn check(url: &str) -> anyhow::Result<()> {
+    let uri: Uri = url
+        .parse()
+        .map_err(|e| anyhow::anyhow!("proxy {url:?} is not a URL: {e}"))?;
+    anyhow::ensure!(
+        matches!(
+            uri.scheme_str(),
+            Some("http" | "https" | "socks5" | "socks5h")
+        ) && uri.host().is_some(),
+        "proxy {url:?} should look like socks5h://host:port or http://host:port"
+    );
+    Ok(())
+}
+
+fn reqwest_proxy(url: &str) -> reqwest::Proxy {
+    reqwest::Proxy::all(url)
+        .expect("proxy checked by validate")
+        .no_proxy(reqwest::NoProxy::from_string(&CONFIG.no_proxy.join(",")))
+}
+
+/// The client the bot reaches the Bot API with
+pub fn telegram_client() -> reqwest::Client {
+    let mut builder = teloxide::net::default_reqwest_settings();
+    if let Some(url) = CONFIG.proxy_url() {
+        builder = builder.proxy(reqwest_proxy(url));
+    }
+    builder.build().expect("build the Telegram client")
+}
+
+/// The client traces are exported with, or `None` to leave the exporter's own when there's
+/// no proxy
+pub fn otlp_client() -> Option<reqwest::blocking::Client> {
+    let url = CONFIG.proxy_url()?;
+    let client = reqwest::blocking::Client::builder()
+        .proxy(reqwest_proxy(url))
+        .timeout(HTTP_TIMEOUT)
+        .build()
+        .expect("build the OTLP client");
+    Some(client)
+}
@@ -0,0 +1,38 @@
+use std::io::Cursor;
+
+use anyhow::Context;
+use qrcode::{Color, QrCode};
+
+use crate::CONFIG;
+
+/// Pixels per QR module
+const SCALE: u32 = 8;
+/// Light modules around the code; scanners need at least four
+const QUIET_ZONE: u32 = 4;
+
+/// What the QR code for `code` encodes: the redemption link when `giftcard_redeem_url` is
+/// configured, so scanning it opens the redeem page, otherwise the bare code
+pub fn payload(code: &str) -> String {
+    match &CONFIG.giftcard_redeem_url {
+        Some(template) => template.replace("{code}", code),
+        None => code.to_owned(),
+    }
+}
+
+/// Renders `data` as a black-on-white QR code PNG
+pub fn render_png(data: &str) -> anyhow::Result<Vec<u8>> {
+    let qr = QrCode::new(data.as_bytes()).context("encode QR code")?;
+    let modules = qr.width() as u32;
+    let colors = qr.to_colors();
+    let side = (modules + 2 * QUIET_ZONE) * SCALE;
+    let image = image::GrayImage::from_fn(side, side, |x, y| {
+        let (mx, my) = (x / SCALE, y / SCALE);
+        let dark = (QUIET_ZONE..QUIET_ZONE + modules).contains(&mx)
+            && (QUIET_ZONE..QUIET_ZONE + modules).contains(&my)
+            && colors[((my - QUIET_ZONE) * modules + mx - QUIET_ZONE) as usize] == Color::Dark;
+        image::Luma([if dark { 0 } else { 255 }])
+    });
+    let mut png = Cursor::new(Vec::new());
+    image.write_to(&mut png, image::ImageFormat::Png)?;
+    Ok(png.into_inner())
+}
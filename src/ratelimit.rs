@@ -0,0 +1,132 @@
+use std::{collections::HashMap, sync::Mutex, time::Instant};
+
+use once_cell::sync::Lazy;
+use teloxide::types::ChatId;
+
+/// Messages a chat can send back to back before being throttled
+const BURST: f64 = 5.0;
+/// Tokens regained per second, i.e. the sustained rate a chat is allowed
+const REFILL_PER_SEC: f64 = 1.0 / 3.0;
+/// Buckets idle this long are full again and can be forgotten
+const IDLE_SECS: f64 = BURST / REFILL_PER_SEC;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// Whether the chat was already told to slow down since the bucket ran dry
+    warned: bool,
+}
+
+static BUCKETS: Lazy<Mutex<HashMap<ChatId, Bucket>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Allowed,
+    /// Dropped; `warn` is set only for the first dropped message, so a chat that keeps
+    /// hammering doesn't get a reply to each one
+    Limited {
+        warn: bool,
+    },
+}
+
+/// Takes a token from `chat_id`'s bucket. Runs before the handler touches the DB or any
+/// external API, so a throttled message costs nothing
+pub fn check(chat_id: ChatId) -> Verdict {
+    check_at(chat_id, Instant::now())
+}
+
+/// [`check`] as of `now`
+fn check_at(chat_id: ChatId, now: Instant) -> Verdict {
+    let mut buckets = BUCKETS.lock().unwrap();
+    if buckets.len() > 10_000 {
+        buckets.retain(|_, b| now.duration_since(b.updated).as_secs_f64() < IDLE_SECS);
+    }
+    let bucket = buckets.entry(chat_id).or_insert(Bucket {
+        tokens: BURST,
+        updated: now,
+        warned: false,
+    });
+    let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+    bucket.tokens = (bucket.tokens + elapsed * REFILL_PER_SEC).min(BURST);
+    // Another thread may have read the clock first but taken the lock later
+    bucket.updated = bucket.updated.max(now);
+    if bucket.tokens >= 1.0 {
+        bucket.tokens -= 1.0;
+        bucket.warned = false;
+        Verdict::Allowed
+    } else {
+        let warn = !bucket.warned;
+        bucket.warned = true;
+        Verdict::Limited { warn }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testing;
+
+    /// `secs` seconds after `start`
+    fn after(start: Instant, secs: f64) -> Instant {
+        start + Duration::from_secs_f64(secs)
+    }
+
+    #[test]
+    fn a_burst_is_allowed_then_throttled_with_one_warning() {
+        let (chat, start) = (testing::chat(), Instant::now());
+        for _ in 0..BURST as usize {
+            assert_eq!(check_at(chat, start), Verdict::Allowed);
+        }
+        assert_eq!(check_at(chat, start), Verdict::Limited { warn: true });
+        assert_eq!(check_at(chat, start), Verdict::Limited { warn: false });
+        assert_eq!(
+            check_at(chat, after(start, 1.0)),
+            Verdict::Limited { warn: false }
+        );
+    }
+
+    #[test]
+    fn tokens_come_back_at_the_sustained_rate() {
+        let (chat, start) = (testing::chat(), Instant::now());
+        for _ in 0..BURST as usize {
+            check_at(chat, start);
+        }
+        assert_eq!(check_at(chat, start), Verdict::Limited { warn: true });
+        let refill = 1.0 / REFILL_PER_SEC;
+        assert_eq!(check_at(chat, after(start, refill)), Verdict::Allowed);
+        // The warning is given again once the chat runs dry again
+        assert_eq!(
+            check_at(chat, after(start, refill)),
+            Verdict::Limited { warn: true }
+        );
+        // Idle long enough, the bucket is full again but holds no more than a burst
+        let idle = after(start, refill + IDLE_SECS * 10.0);
+        for _ in 0..BURST as usize {
+            assert_eq!(check_at(chat, idle), Verdict::Allowed);
+        }
+        assert_eq!(check_at(chat, idle), Verdict::Limited { warn: true });
+    }
+
+    #[test]
+    fn an_earlier_instant_refills_nothing() {
+        let (chat, start) = (testing::chat(), Instant::now());
+        let later = after(start, 1.0 / REFILL_PER_SEC);
+        for _ in 0..BURST as usize {
+            check_at(chat, later);
+        }
+        assert_eq!(check_at(chat, start), Verdict::Limited { warn: true });
+        assert_eq!(check_at(chat, later), Verdict::Limited { warn: false });
+    }
+
+    #[test]
+    fn chats_have_buckets_of_their_own() {
+        let (chat, other, start) = (testing::chat(), testing::chat(), Instant::now());
+        for _ in 0..=BURST as usize {
+            check_at(chat, start);
+        }
+        assert_eq!(check_at(chat, start), Verdict::Limited { warn: false });
+        assert_eq!(check_at(other, start), Verdict::Allowed);
+    }
+}
@@ -0,0 +1,220 @@
+use std::time::Duration;
+
+use rand::{Rng, distributions::Alphanumeric};
+use serde_json::json;
+use sqlx::AnyConnection;
+use teloxide::{prelude::*, types::ChatId};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::{
+    CONFIG, DB,
+    audit::{self, Actor},
+    begin_write, fraud, i18n, next_tick, now_unix, outbox, tokens,
+};
+
+/// Prefix that tells referral codes apart from registration tokens in `/start` payloads
+pub const CODE_PREFIX: &str = "ref-";
+/// Uptime the referee's VMs must accumulate before either side is rewarded
+const QUALIFYING_SECS: i64 = 86400;
+
+pub enum Attach {
+    Recorded,
+    /// The link belongs to the chat that opened it
+    OwnLink,
+    /// Only testers who have never registered a VM can be referred, and only once
+    NotNew,
+    UnknownCode,
+}
+
+/// Referral link and counts, as shown by `/referral`
+pub struct Summary {
+    /// `None` until the bot's username is known
+    pub link: Option<String>,
+    pub referred: i64,
+    pub rewarded: i64,
+}
+
+/// `chat_id`'s referral code, created on first use
+async fn code_for(chat_id: ChatId) -> sqlx::Result<String> {
+    let code: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(10)
+        .map(char::from)
+        .collect();
+    sqlx::query(
+        "INSERT INTO referral_codes (telegram_chat_id, code) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+    )
+    .bind(chat_id.0)
+    .bind(format!("{CODE_PREFIX}{code}"))
+    .execute(&*DB)
+    .await?;
+    sqlx::query_scalar("SELECT code FROM referral_codes WHERE telegram_chat_id = $1")
+        .bind(chat_id.0)
+        .fetch_one(&*DB)
+        .await
+}
+
+pub async fn summary(chat_id: ChatId) -> sqlx::Result<Summary> {
+    let code = code_for(chat_id).await?;
+    let (referred, rewarded): (i64, i64) = sqlx::query_as(
+        "SELECT COUNT(*), COUNT(rewarded_at) FROM referrals WHERE referrer_chat_id = $1",
+    )
+    .bind(chat_id.0)
+    .fetch_one(&*DB)
+    .await?;
+    Ok(Summary {
+        link: tokens::deep_link(&code),
+        referred,
+        rewarded,
+    })
+}
+
+/// Records that `referee` arrived through `code`.
+///
+/// Only chats that have never had a VM linked count, so nobody can refer an existing
+/// account of their own; and a link opened by its owner is refused outright. Whether the
+/// referral pays out is decided later by [`reward_loop`].
+pub async fn attach(referee: ChatId, code: &str) -> sqlx::Result<Attach> {
+    let (_write, mut tx) = begin_write().await?;
+    let referrer: Option<i64> =
+        sqlx::query_scalar("SELECT telegram_chat_id FROM referral_codes WHERE code = $1")
+            .bind(code)
+            .fetch_optional(&mut *tx)
+            .await?;
+    let Some(referrer) = referrer else {
+        return Ok(Attach::UnknownCode);
+    };
+    if referrer == referee.0 {
+        return Ok(Attach::OwnLink);
+    }
+    let history: i64 = sqlx::query_scalar(
+        r#"
+SELECT
+    (SELECT COUNT(*) FROM agent_records WHERE telegram_chat_id = $1)
+    + (SELECT COUNT(*) FROM referrals WHERE referee_chat_id = $1)
+    + (SELECT COUNT(*) FROM audit_log WHERE telegram_chat_id = $1 AND action = 'vm_linked')
+        "#,
+    )
+    .bind(referee.0)
+    .fetch_one(&mut *tx)
+    .await?;
+    if history > 0 {
+        return Ok(Attach::NotNew);
+    }
+    sqlx::query(
+        "INSERT INTO referrals (referee_chat_id, referrer_chat_id, created_at) VALUES ($1, $2, $3)",
+    )
+    .bind(referee.0)
+    .bind(referrer)
+    .bind(now_unix())
+    .execute(&mut *tx)
+    .await?;
+    audit::record(
+        &mut tx,
+        &Actor::Chat(referee),
+        "referral_recorded",
+        Some(referee),
+        json!({ "referrer": referrer }),
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(Attach::Recorded)
+}
+
+/// Adds `secs` of bonus uptime to one of `chat_id`'s VMs, returning false if it has none
+async fn credit(conn: &mut AnyConnection, chat_id: i64, secs: i64) -> sqlx::Result<bool> {
+    let result = sqlx::query(
+        r#"
+UPDATE agent_records SET bonus_secs = bonus_secs + $1
+WHERE vm_id = (SELECT MIN(vm_id) FROM agent_records WHERE telegram_chat_id = $2)
+        "#,
+    )
+    .bind(secs)
+    .bind(chat_id)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Pays out referrals whose referee has reached a full day of uptime, crediting
+/// `referral_bonus_hours` to both sides. A referral stays pending while either chat is
+/// under fraud review or the referrer has no VM to credit.
+pub async fn reward_loop(bot: Bot, shutdown: CancellationToken) -> anyhow::Result<()> {
+    let mut ticker = smol::Timer::interval(Duration::from_secs(600));
+    loop {
+        async {
+            let due: Vec<(i64, i64)> = sqlx::query_as(
+                r#"
+SELECT r.referee_chat_id, r.referrer_chat_id FROM referrals r
+WHERE r.rewarded_at IS NULL
+  AND (SELECT CAST(COALESCE(SUM(a.up_secs), 0) AS BIGINT)
+       FROM agent_records a WHERE a.telegram_chat_id = r.referee_chat_id) >= $1
+                "#,
+            )
+            .bind(QUALIFYING_SECS)
+            .fetch_all(&*DB)
+            .await?;
+
+            for (referee, referrer) in due {
+                if reward(referee, referrer).await? {
+                    let hours = CONFIG.referral_bonus_hours;
+                    for (chat_id, text) in [
+                        (
+                            referee,
+                            i18n::text("referrals.referee_rewarded", &[("hours", &hours)]),
+                        ),
+                        (
+                            referrer,
+                            i18n::text("referrals.referrer_rewarded", &[("hours", &hours)]),
+                        ),
+                    ] {
+                        let chat_id = ChatId(chat_id);
+                        let _ =
+                            outbox::deliver(chat_id, || bot.send_message(chat_id, &text).send())
+                                .await;
+                    }
+                }
+            }
+            anyhow::Ok(())
+        }
+        .instrument(tracing::info_span!("tick"))
+        .await?;
+
+        if !next_tick("referral_rewards", &mut ticker, &shutdown).await {
+            return Ok(());
+        }
+    }
+}
+
+async fn reward(referee: i64, referrer: i64) -> sqlx::Result<bool> {
+    let secs = CONFIG.referral_bonus_hours * 3600;
+    let (_write, mut tx) = begin_write().await?;
+    if fraud::under_review(&mut tx, ChatId(referee)).await?
+        || fraud::under_review(&mut tx, ChatId(referrer)).await?
+    {
+        return Ok(false);
+    }
+    // Dropping the transaction rolls back a half-applied credit
+    if !credit(&mut tx, referrer, secs).await? || !credit(&mut tx, referee, secs).await? {
+        return Ok(false);
+    }
+    sqlx::query("UPDATE referrals SET rewarded_at = $1 WHERE referee_chat_id = $2")
+        .bind(now_unix())
+        .bind(referee)
+        .execute(&mut *tx)
+        .await?;
+    for (chat_id, role) in [(referee, "referee"), (referrer, "referrer")] {
+        audit::record(
+            &mut tx,
+            &Actor::System,
+            "referral_rewarded",
+            Some(ChatId(chat_id)),
+            json!({ "referee": referee, "referrer": referrer, "role": role, "bonus_secs": secs }),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    tracing::info!("referral of chat {referee} by {referrer} rewarded with {secs}s each");
+    Ok(true)
+}
@@ -0,0 +1,37 @@
+use async_signal::{Signal, Signals};
+use futures_util::StreamExt;
+
+use crate::{CONFIG, read_config};
+
+/// Reloads the config on every SIGHUP, so rotated secrets, endpoints, admins and reward
+/// rates take effect without a restart
+pub async fn on_hangup() {
+    let mut signals = match Signals::new([Signal::Hup]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            tracing::error!("cannot listen for SIGHUP, config reloads are off: {e}");
+            return;
+        }
+    };
+    while let Some(Ok(_)) = signals.next().await {
+        match reload() {
+            Ok(ignored) if ignored.is_empty() => tracing::info!("config reloaded"),
+            Ok(ignored) => tracing::warn!(
+                "config reloaded; changes to {} need a restart and were ignored",
+                ignored.join(", ")
+            ),
+            Err(e) => tracing::error!("config reload failed, keeping the old one: {e:#}"),
+        }
+    }
+}
+
+/// Re-reads the config file and puts it in force, except for the settings that are only
+/// read at startup, whose names are returned if the file changed them. A file that doesn't
+/// parse or validate leaves the running config alone.
+pub fn reload() -> anyhow::Result<Vec<&'static str>> {
+    let mut config = read_config()?;
+    let ignored = config.keep_startup_settings(&CONFIG);
+    config.validate()?;
+    *CONFIG.0.write().unwrap() = Box::leak(Box::new(config));
+    Ok(ignored)
+}
@@ -0,0 +1,235 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::DateTime;
+use once_cell::sync::Lazy;
+use teloxide::{
+    RequestError,
+    types::{ChatId, InlineKeyboardButton, MessageEntity, MessageId, ParseMode},
+    utils::markdown,
+};
+
+use crate::{CONFIG, OFFLINE_AFTER_SECS, StatusRow, i18n, nicknames, qr, telegram::Telegram};
+
+/// Status indicators that prefix bot replies.
+#[derive(Clone, Copy, Debug)]
+pub enum Indicator {
+    Success,
+    Uptime,
+    Balance,
+    Gift,
+    Empty,
+    Removed,
+}
+
+impl Indicator {
+    /// Key used to look up a custom emoji id in the `custom_emoji` config map
+    fn key(self) -> &'static str {
+        match self {
+            Indicator::Success => "success",
+            Indicator::Uptime => "uptime",
+            Indicator::Balance => "balance",
+            Indicator::Gift => "gift",
+            Indicator::Empty => "empty",
+            Indicator::Removed => "removed",
+        }
+    }
+
+    /// Plain unicode emoji used when custom emoji are unavailable
+    fn fallback(self) -> &'static str {
+        match self {
+            Indicator::Success => "✅",
+            Indicator::Uptime => "⏱",
+            Indicator::Balance => "💎",
+            Indicator::Gift => "🎁",
+            Indicator::Empty => "📭",
+            Indicator::Removed => "👋",
+        }
+    }
+
+    fn custom_emoji_id(self) -> Option<&'static String> {
+        CONFIG.custom_emoji.get(self.key())
+    }
+}
+
+/// Whether a chat accepted custom emoji entities the last time we tried.
+/// Chats missing from the map haven't been probed yet.
+static CUSTOM_EMOJI_SUPPORT: Lazy<Mutex<HashMap<ChatId, bool>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Sends `text` prefixed with the given status indicator, rendered as a custom emoji
+/// when one is configured and the chat supports it, otherwise as plain unicode.
+pub async fn send_status(
+    bot: &impl Telegram,
+    chat_id: ChatId,
+    indicator: Indicator,
+    text: impl AsRef<str>,
+) -> Result<MessageId, RequestError> {
+    let rendered = format!("{} {}", indicator.fallback(), text.as_ref());
+    let supported = CUSTOM_EMOJI_SUPPORT
+        .lock()
+        .unwrap()
+        .get(&chat_id)
+        .copied()
+        .unwrap_or(true);
+
+    if let Some(emoji_id) = indicator.custom_emoji_id().filter(|_| supported) {
+        // Telegram measures entity offsets and lengths in UTF-16 code units
+        let length = indicator.fallback().encode_utf16().count();
+        let entity = MessageEntity::custom_emoji(emoji_id.clone(), 0, length);
+        match bot
+            .send_message(chat_id, rendered.clone())
+            .entities(vec![entity])
+            .await
+        {
+            Ok(id) => {
+                CUSTOM_EMOJI_SUPPORT.lock().unwrap().insert(chat_id, true);
+                return Ok(id);
+            }
+            Err(RequestError::Api(e)) => {
+                tracing::debug!("custom emoji rejected in chat {chat_id}: {e}");
+                CUSTOM_EMOJI_SUPPORT.lock().unwrap().insert(chat_id, false);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    bot.send_message(chat_id, rendered).await
+}
+
+/// Longest callback data Telegram accepts, in bytes
+const MAX_CALLBACK_DATA: usize = 64;
+
+/// A button that runs `command` when tapped. Commands too long for callback data are
+/// pasted into the input box instead, for the user to send.
+pub fn command_button(
+    label: impl Into<String>,
+    command: impl Into<String>,
+) -> InlineKeyboardButton {
+    let command = command.into();
+    if command.len() <= MAX_CALLBACK_DATA {
+        InlineKeyboardButton::callback(label, command)
+    } else {
+        InlineKeyboardButton::switch_inline_query_current_chat(label, command)
+    }
+}
+
+/// Sends each giftcard code as its own message, see [`send_code`]. Send the explanation
+/// first with [`send_status`].
+pub async fn send_codes(
+    bot: &impl Telegram,
+    chat_id: ChatId,
+    codes: &[String],
+) -> Result<(), RequestError> {
+    for code in codes {
+        send_code(bot, chat_id, code).await?;
+    }
+    Ok(())
+}
+
+/// Sends a giftcard code as a QR image for redeeming on another device, captioned with the
+/// code in a MarkdownV2 code block, which Telegram clients copy with a tap. Falls back to
+/// the caption alone if the image can't be rendered.
+pub async fn send_code(
+    bot: &impl Telegram,
+    chat_id: ChatId,
+    code: &str,
+) -> Result<MessageId, RequestError> {
+    let caption = markdown::code_block(code);
+    match qr::render_png(&qr::payload(code)) {
+        Ok(png) => {
+            bot.send_photo(chat_id, "giftcard.png", png)
+                .caption(caption)
+                .parse_mode(ParseMode::MarkdownV2)
+                .await
+        }
+        Err(e) => {
+            tracing::error!("rendering QR code for {chat_id} failed: {e:#}");
+            bot.send_message(chat_id, caption)
+                .parse_mode(ParseMode::MarkdownV2)
+                .await
+        }
+    }
+}
+
+/// One VM's line in `/status` and the pinned status: whether it's online, and since when
+pub fn vm_status_line((vm_id, nickname, last_seen, online_since): &StatusRow, now: i64) -> String {
+    let name = nicknames::label(vm_id, nickname.as_deref());
+    match *last_seen {
+        Some(seen) if now - seen <= OFFLINE_AFTER_SECS => {
+            let session = format_duration(now - online_since.unwrap_or(seen));
+            format!(
+                "🟢 {name}: {}",
+                i18n::text("render.online", &[("session", &session)])
+            )
+        }
+        Some(seen) => {
+            let ago = format_duration(now - seen);
+            let at = format_timestamp(seen);
+            format!(
+                "🔴 {name}: {}",
+                i18n::text("render.offline", &[("at", &at), ("ago", &ago)])
+            )
+        }
+        None => format!("⚪ {name}: {}", i18n::text("render.not_seen", &[])),
+    }
+}
+
+/// Cells in [`progress_line`]'s bar
+const PROGRESS_CELLS: i64 = 10;
+
+/// How far `done` of `total` seconds are towards the next Plus day, e.g.
+/// `▓▓▓▓▓▓▓░░░ 17h 32m / 24h toward your next day (73%)`
+pub fn progress_line(done: i64, total: i64) -> String {
+    let done = done.clamp(0, total);
+    let percent = done * 100 / total;
+    let filled = done * PROGRESS_CELLS / total;
+    let bar = format!(
+        "{}{}",
+        "▓".repeat(filled as usize),
+        "░".repeat((PROGRESS_CELLS - filled) as usize)
+    );
+    let (done, total) = (
+        format_duration(done),
+        if total % 3600 == 0 {
+            format!("{}h", total / 3600)
+        } else {
+            format_duration(total)
+        },
+    );
+    let args: &[(&str, &i18n::Arg)] = &[("done", &done), ("total", &total), ("percent", &percent)];
+    format!("{bar} {}", i18n::text("render.progress", args))
+}
+
+/// Renders a duration in seconds compactly, e.g. `2d 3h`, `5h 12m`, or `7m`
+pub fn format_duration(secs: i64) -> String {
+    let secs = secs.max(0);
+    let (days, hours, mins) = (secs / 86400, secs % 86400 / 3600, secs % 3600 / 60);
+    if days > 0 {
+        format!("{days}d {hours}h")
+    } else if hours > 0 {
+        format!("{hours}h {mins}m")
+    } else {
+        format!("{mins}m")
+    }
+}
+
+/// Renders a duration in seconds as hours and minutes, rounding up to the minute, e.g.
+/// `6h 28m` or `31h 0m`
+pub fn format_hours_minutes(secs: i64) -> String {
+    let mins = (secs.max(0) + 59) / 60;
+    format!("{}h {}m", mins / 60, mins % 60)
+}
+
+/// Renders a Unix timestamp as a UTC date, e.g. `2025-05-01`
+pub fn format_date(unix: i64) -> String {
+    DateTime::from_timestamp(unix, 0)
+        .map(|d| d.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+/// Renders a Unix timestamp as UTC date and time, e.g. `2025-05-01 13:37 UTC`
+pub fn format_timestamp(unix: i64) -> String {
+    DateTime::from_timestamp(unix, 0)
+        .map(|d| d.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_default()
+}
@@ -0,0 +1,157 @@
+use teloxide::types::ChatId;
+
+use serde_json::json;
+
+use crate::{
+    OFFLINE_AFTER_SECS,
+    audit::{self, Actor},
+    begin_write, now_unix,
+};
+
+/// The old VM's `(up_secs, bonus_secs, paid_secs, nickname, last_seen)`
+type OldRow = (i64, i64, i64, Option<String>, Option<i64>);
+
+pub enum ReplaceOutcome {
+    Replaced { moved_up_secs: i64 },
+    NotYours,
+    OldStillOnline,
+    NewUnavailable,
+}
+
+/// Moves a tester's balance and history from `old_vm` to `new_vm` after a reinstall.
+///
+/// Only allowed when `old_vm` belongs to the chat and has stopped reporting, and `new_vm`
+/// is reporting, unowned and not banned, so nobody can take over someone else's VM, double
+/// up on two running ones or bring back a banned one. The caller checks the chat's claim
+/// to `new_vm` the way registration does. The old VM keeps its row but is unlinked and zeroed; if it ever returns
+/// it starts over like any unknown VM.
+pub async fn replace(chat_id: ChatId, old_vm: &str, new_vm: &str) -> sqlx::Result<ReplaceOutcome> {
+    let now = now_unix();
+    let (_write, mut tx) = begin_write().await?;
+    let old: Option<OldRow> = sqlx::query_as(
+        r#"
+SELECT a.up_secs, a.bonus_secs, a.paid_secs, a.nickname, s.last_seen
+FROM agent_records a LEFT JOIN vm_status s ON s.vm_id = a.vm_id
+WHERE a.vm_id = $1 AND a.telegram_chat_id = $2
+        "#,
+    )
+    .bind(old_vm)
+    .bind(chat_id.0)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some((up_secs, bonus_secs, paid_secs, nickname, old_seen)) = old else {
+        return Ok(ReplaceOutcome::NotYours);
+    };
+    if old_seen.is_some_and(|t| now - t <= OFFLINE_AFTER_SECS) {
+        return Ok(ReplaceOutcome::OldStillOnline);
+    }
+    let new_available: i64 = sqlx::query_scalar(
+        r#"
+SELECT COUNT(*)
+FROM agent_records a JOIN vm_status s ON s.vm_id = a.vm_id
+WHERE a.vm_id = $1 AND a.telegram_chat_id IS NULL AND s.last_seen >= $2
+  AND NOT EXISTS (SELECT 1 FROM bans WHERE vm_id = $1 AND shadow = 0)
+        "#,
+    )
+    .bind(new_vm)
+    .bind(now - OFFLINE_AFTER_SECS)
+    .fetch_one(&mut *tx)
+    .await?;
+    if new_available == 0 || old_vm == new_vm {
+        return Ok(ReplaceOutcome::NewUnavailable);
+    }
+
+    sqlx::query(
+        r#"
+UPDATE agent_records SET
+    telegram_chat_id = $1,
+    up_secs = up_secs + $2,
+    bonus_secs = bonus_secs + $3,
+    paid_secs = paid_secs + $4,
+    linked_at = $5,
+    nickname = $6
+WHERE vm_id = $7
+        "#,
+    )
+    .bind(chat_id.0)
+    .bind(up_secs)
+    .bind(bonus_secs)
+    .bind(paid_secs)
+    .bind(now_unix())
+    .bind(nickname)
+    .bind(new_vm)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        "UPDATE agent_records SET telegram_chat_id = NULL, linked_at = NULL, nickname = NULL, up_secs = 0, bonus_secs = 0, paid_secs = 0 WHERE vm_id = $1",
+    )
+    .bind(old_vm)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        r#"
+INSERT INTO uptime_history (vm_id, day, up_secs)
+SELECT $1, day, up_secs FROM uptime_history WHERE vm_id = $2
+ON CONFLICT(vm_id, day) DO UPDATE SET up_secs = uptime_history.up_secs + excluded.up_secs
+        "#,
+    )
+    .bind(new_vm)
+    .bind(old_vm)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("DELETE FROM uptime_history WHERE vm_id = $1")
+        .bind(old_vm)
+        .execute(&mut *tx)
+        .await?;
+    // With the history moved the streak carries over, and so must the milestones it has
+    // already been paid for
+    sqlx::query(
+        r#"
+INSERT INTO streak_awards (vm_id, milestone, reached_on, bonus_secs, awarded_at)
+SELECT $1, milestone, reached_on, bonus_secs, awarded_at FROM streak_awards WHERE vm_id = $2
+ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(new_vm)
+    .bind(old_vm)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("DELETE FROM streak_awards WHERE vm_id = $1")
+        .bind(old_vm)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        "UPDATE outages SET vm_id = $1, ended_at = COALESCE(ended_at, $2) WHERE vm_id = $3",
+    )
+    .bind(new_vm)
+    .bind(now)
+    .bind(old_vm)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        r#"
+INSERT INTO vm_replacements (telegram_chat_id, old_vm_id, new_vm_id, moved_up_secs, created_at)
+VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(chat_id.0)
+    .bind(old_vm)
+    .bind(new_vm)
+    .bind(up_secs)
+    .bind(now)
+    .execute(&mut *tx)
+    .await?;
+    audit::record(
+        &mut tx,
+        &Actor::Chat(chat_id),
+        "vm_replaced",
+        Some(chat_id),
+        json!({ "old_vm": old_vm, "new_vm": new_vm, "moved_up_secs": up_secs }),
+    )
+    .await?;
+    tx.commit().await?;
+    tracing::info!("chat {chat_id} replaced {old_vm} with {new_vm}, moving {up_secs}s");
+    Ok(ReplaceOutcome::Replaced {
+        moved_up_secs: up_secs,
+    })
+}
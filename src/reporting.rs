@@ -0,0 +1,123 @@
+use std::{
+    panic::PanicHookInfo,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use hmac::{Hmac, Mac};
+use isahc::config::Configurable;
+use once_cell::sync::Lazy;
+use serde_json::{Value, json};
+use sha2::Sha256;
+use teloxide::types::ChatId;
+
+use crate::{CONFIG, now_unix, proxy};
+
+/// Reports sent per [`REPORT_WINDOW`] at most, so a failure on every update can't flood
+/// the endpoint; the rest are only logged
+const MAX_REPORTS_PER_WINDOW: u32 = 30;
+const REPORT_WINDOW: Duration = Duration::from_secs(60);
+const REPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+static SENT: Lazy<Mutex<(Instant, u32)>> = Lazy::new(|| Mutex::new((Instant::now(), 0)));
+
+/// Where an error happened, as far as the reporter knows
+#[derive(Clone, Copy, Default)]
+pub struct Origin<'a> {
+    /// The command word, never its arguments
+    pub command: Option<&'a str>,
+    pub chat_id: Option<ChatId>,
+}
+
+/// Stands in for a chat id in reports: keyed with the bot token, so it can't be reversed by
+/// hashing every possible id, but the same chat always hashes the same
+pub fn chat_hash(chat_id: ChatId) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(CONFIG.telegram_bot_token.as_bytes())
+        .expect("HMAC accepts any key length");
+    mac.update(chat_id.0.to_string().as_bytes());
+    hex::encode(&mac.finalize().into_bytes()[..8])
+}
+
+fn event(kind: &str, message: &str, origin: Origin, location: Option<String>) -> Value {
+    json!({
+        "kind": kind,
+        "message": message,
+        "command": origin.command,
+        "chat": origin.chat_id.map(chat_hash),
+        "location": location,
+        "environment": CONFIG.environment.name(),
+        "release": env!("CARGO_PKG_VERSION"),
+        "timestamp": now_unix(),
+    })
+}
+
+/// Takes one report from the budget of the current window
+fn allowed() -> bool {
+    let mut sent = SENT.lock().unwrap();
+    if sent.0.elapsed() >= REPORT_WINDOW {
+        *sent = (Instant::now(), 0);
+    }
+    sent.1 += 1;
+    sent.1 <= MAX_REPORTS_PER_WINDOW
+}
+
+fn request(url: &str, event: &Value) -> anyhow::Result<isahc::Request<String>> {
+    let mut request = isahc::Request::post(url)
+        .header(isahc::http::header::CONTENT_TYPE, "application/json")
+        .timeout(REPORT_TIMEOUT);
+    if let Some(token) = &CONFIG.error_report_token {
+        request = request.header(
+            isahc::http::header::AUTHORIZATION,
+            format!("Bearer {token}"),
+        );
+    }
+    Ok(request.body(event.to_string())?)
+}
+
+/// Ships a handler error to `error_report_url` in the background
+pub fn capture_error(error: &dyn std::fmt::Display, origin: Origin) {
+    let Some(url) = &CONFIG.error_report_url else {
+        return;
+    };
+    if !allowed() {
+        return;
+    }
+    let event = event("handler_error", &error.to_string(), origin, None);
+    smolscale::spawn(async move {
+        let sent = async { anyhow::Ok(proxy::HTTP.send_async(request(url, &event)?).await?) };
+        if let Err(e) = sent.await {
+            tracing::warn!("sending an error report failed: {e}");
+        }
+    })
+    .detach();
+}
+
+/// Reports every panic before the default hook prints it. Sent synchronously, since the
+/// panicking thread may be about to take the process down.
+pub fn install_panic_hook() {
+    let Some(url) = &CONFIG.error_report_url else {
+        return;
+    };
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info: &PanicHookInfo| {
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "panic".to_owned());
+        let location = info
+            .location()
+            .map(|l| format!("{}:{}", l.file(), l.line()));
+        if allowed() {
+            let mut event = event("panic", &message, Origin::default(), location);
+            // The span the panic happened in, e.g. `command` or `tick`
+            event["span"] = json!(tracing::Span::current().metadata().map(|m| m.name()));
+            if let Err(e) = request(url, &event).and_then(|request| Ok(proxy::HTTP.send(request)?))
+            {
+                eprintln!("sending a panic report failed: {e}");
+            }
+        }
+        previous(info);
+    }));
+}
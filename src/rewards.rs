@@ -0,0 +1,252 @@
+use futures_util::{FutureExt, future::BoxFuture};
+use isahc::prelude::*;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use teloxide::types::ChatId;
+
+use crate::{CONFIG, HTTP_TIMEOUT, claim::Reservation, geph_account, proxy};
+
+/// What a provider handed out for a reservation
+pub enum Issued {
+    /// One code per card
+    Giftcards(Vec<String>),
+    /// Added straight to the chat's linked Geph account
+    Credited { username: String },
+}
+
+/// A backend that turns reserved days into Plus. Claims go through the providers in
+/// `reward_providers` in turn until one issues them, so handlers never deal with a
+/// particular backend.
+pub trait RewardProvider: Send + Sync {
+    /// How `reward_providers` and the logs refer to it
+    fn name(&self) -> &'static str;
+
+    /// Issues `reservation` for `chat_id`, or `Ok(None)` if it doesn't apply to this claim
+    /// and the next provider should be asked. A retried claim comes with the same
+    /// idempotency key, which backends use to deduplicate.
+    fn issue<'a>(
+        &'a self,
+        chat_id: ChatId,
+        reservation: &'a Reservation,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Issued>>>;
+}
+
+/// The providers that can be listed in `reward_providers`
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    /// Giftcard codes from the `create-giftcards` backend at `giftcard_api_url`
+    Giftcard,
+    /// Days added to the linked Geph account through `plus_credit_api_url`, for chats that
+    /// opted in; claims split into several cards are left to the next provider
+    DirectCredit,
+    /// Fake codes, for rehearsals outside `prod`
+    Mock,
+}
+
+impl Kind {
+    /// Whether the provider issues every claim it's asked to, or may pass some on
+    pub fn issues_all(self) -> bool {
+        self != Kind::DirectCredit
+    }
+
+    fn provider(self) -> Box<dyn RewardProvider> {
+        match self {
+            Kind::Giftcard => Box::new(GiftcardApi {
+                url: CONFIG
+                    .giftcard_api_url()
+                    .expect("giftcard provider without giftcard_api_url"),
+            }),
+            Kind::DirectCredit => Box::new(DirectCredit {
+                url: CONFIG
+                    .plus_credit_api_url
+                    .as_deref()
+                    .expect("direct_credit provider without plus_credit_api_url"),
+            }),
+            Kind::Mock => Box::new(Mock),
+        }
+    }
+}
+
+/// Issues `reservation` through the configured providers. A provider that fails while
+/// another is left to try is logged and skipped; the error of the last one is returned,
+/// and the claim is queued for a retry.
+pub async fn issue(chat_id: ChatId, reservation: &Reservation) -> anyhow::Result<Issued> {
+    let kinds = CONFIG.reward_providers();
+    for (i, kind) in kinds.iter().enumerate() {
+        let provider = kind.provider();
+        match provider.issue(chat_id, reservation).await {
+            Ok(Some(issued)) => return Ok(issued),
+            Ok(None) => {}
+            Err(e) if i + 1 < kinds.len() => tracing::warn!(
+                "{} failed for claim {}, trying the next provider: {e:#}",
+                provider.name(),
+                reservation.key
+            ),
+            Err(e) => return Err(e),
+        }
+    }
+    anyhow::bail!("no reward provider took claim {}", reservation.key)
+}
+
+struct GiftcardApi {
+    url: &'static str,
+}
+
+impl RewardProvider for GiftcardApi {
+    fn name(&self) -> &'static str {
+        "giftcard"
+    }
+
+    fn issue<'a>(
+        &'a self,
+        _chat_id: ChatId,
+        reservation: &'a Reservation,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Issued>>> {
+        async move {
+            let codes = request_giftcard(self.url, reservation).await?;
+            Ok(Some(Issued::Giftcards(codes)))
+        }
+        .boxed()
+    }
+}
+
+/// Builds the request to the giftcard backend at `url` for a reservation without sending it
+pub fn giftcard_request(
+    reservation: &Reservation,
+    url: &str,
+) -> anyhow::Result<isahc::Request<String>> {
+    let body = json!({
+        "days_per_card": reservation.days_per_card(),
+        "num_cards": reservation.num_cards,
+        "secret": CONFIG.giftcard_api_secret
+    });
+    Ok(isahc::Request::post(url)
+        .header(isahc::http::header::CONTENT_TYPE, "application/json")
+        .header("Idempotency-Key", &reservation.key)
+        .timeout(HTTP_TIMEOUT)
+        .body(body.to_string())?)
+}
+
+/// Pulls the codes out of a giftcard backend response. Older deployments answer with one
+/// code per line; newer ones with JSON, either a string, a list of strings, or an object
+/// holding those under `code`, `codes`, `giftcard` or `giftcards`.
+fn parse_codes(text: &str) -> Vec<String> {
+    fn from_json(value: &serde_json::Value) -> Option<Vec<String>> {
+        match value {
+            serde_json::Value::String(code) => Some(vec![code.trim().to_owned()]),
+            // A purely numeric code on its own line also parses as JSON
+            serde_json::Value::Number(code) => Some(vec![code.to_string()]),
+            serde_json::Value::Array(items) => items
+                .iter()
+                .map(|item| item.as_str().map(|code| code.trim().to_owned()))
+                .collect(),
+            serde_json::Value::Object(fields) => ["codes", "giftcards", "code", "giftcard"]
+                .iter()
+                .find_map(|key| fields.get(*key))
+                .and_then(from_json),
+            _ => None,
+        }
+    }
+
+    let codes = match serde_json::from_str(text) {
+        Ok(value) => from_json(&value).unwrap_or_default(),
+        Err(_) => text.lines().map(|line| line.trim().to_owned()).collect(),
+    };
+    codes.into_iter().filter(|code| !code.is_empty()).collect()
+}
+
+/// Requests the reservation's cards, returning their codes
+#[tracing::instrument(
+    name = "giftcard_api.create",
+    skip_all,
+    fields(
+        otel.kind = "client",
+        key = %reservation.key,
+        cards = reservation.num_cards,
+        http.response.status_code = tracing::field::Empty,
+    )
+)]
+async fn request_giftcard(url: &str, reservation: &Reservation) -> anyhow::Result<Vec<String>> {
+    let mut response = proxy::HTTP
+        .send_async(giftcard_request(reservation, url)?)
+        .await?;
+    tracing::Span::current().record("http.response.status_code", response.status().as_u16());
+    let text = response.text().await?;
+    // An error page must not be handed out as a giftcard code
+    anyhow::ensure!(
+        response.status().is_success(),
+        "giftcard backend returned {}: {text}",
+        response.status()
+    );
+    let codes = parse_codes(&text);
+    anyhow::ensure!(
+        codes.len() as i64 == reservation.num_cards,
+        "giftcard backend returned {} code(s) for {} card(s): {text}",
+        codes.len(),
+        reservation.num_cards
+    );
+    Ok(codes)
+}
+
+struct DirectCredit {
+    url: &'static str,
+}
+
+impl RewardProvider for DirectCredit {
+    fn name(&self) -> &'static str {
+        "direct_credit"
+    }
+
+    fn issue<'a>(
+        &'a self,
+        chat_id: ChatId,
+        reservation: &'a Reservation,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Issued>>> {
+        async move {
+            // Split claims are cards to hand out, not days for the user's own account
+            if reservation.num_cards != 1 {
+                return Ok(None);
+            }
+            let Some(username) = crate::claim::direct_credit_account(chat_id).await? else {
+                return Ok(None);
+            };
+            geph_account::credit(self.url, &username, reservation.days, &reservation.key).await?;
+            Ok(Some(Issued::Credited { username }))
+        }
+        .boxed()
+    }
+}
+
+struct Mock;
+
+impl RewardProvider for Mock {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn issue<'a>(
+        &'a self,
+        _chat_id: ChatId,
+        reservation: &'a Reservation,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Issued>>> {
+        async move { Ok(Some(Issued::Giftcards(mock_codes(reservation)))) }.boxed()
+    }
+}
+
+/// The codes the mock provider issues. They're derived from the reservation key, so a
+/// retried claim gets the same ones, and marked as fake so nobody mistakes them for real
+/// Plus.
+pub fn mock_codes(reservation: &Reservation) -> Vec<String> {
+    let digest = hex::encode(Sha256::digest(reservation.key.as_bytes()));
+    (1..=reservation.num_cards)
+        .map(|card| {
+            format!(
+                "STAGING-{}D-{}-{card}",
+                reservation.days_per_card(),
+                &digest[..8]
+            )
+        })
+        .collect()
+}
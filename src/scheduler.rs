@@ -0,0 +1,484 @@
+use std::{
+    collections::HashMap, future::Future, panic::AssertUnwindSafe, str::FromStr, sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Context as _;
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, TimeDelta, Timelike};
+use futures_util::{FutureExt, future::BoxFuture};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::{CONFIG, DB, now_unix, reporting, sleep_or_shutdown, supervisor};
+
+/// The scheduler wakes at least this often, so a newly due job never waits long and the
+/// supervisor sees it tick
+const MAX_SLEEP_SECS: i64 = 60;
+/// How far ahead a cron expression is searched for its next time before it's taken to
+/// never fire (`0 0 30 2 *`)
+const SEARCH_DAYS: u64 = 5 * 366;
+
+/// When a job runs: a cron expression or a fixed interval since its last run, written as
+///
+/// - five cron fields, `minute hour day-of-month month day-of-week`, in UTC. Each is `*`, a
+///   number, a range `a-b` or a list of those, optionally with a step (`*/15`, `8-18/2`).
+///   Day of week counts from Sunday, 0 or 7. As in Vixie cron, when both day fields are
+///   restricted a day matching either one counts.
+/// - `@hourly`, `@daily`, `@weekly` or `@monthly`
+/// - `@every <n><s|m|h|d>`, e.g. `@every 6h`
+#[derive(Clone, Debug)]
+pub enum Schedule {
+    Cron(Cron),
+    Every(i64),
+}
+
+#[derive(Clone, Debug)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day-of-month and day-of-week fields were both something other than `*`
+    either_day: bool,
+}
+
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        let cron = match s {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            _ => match s.strip_prefix("@every ") {
+                Some(every) => return Ok(Schedule::Every(parse_interval(every.trim())?)),
+                None => s,
+            },
+        };
+        let fields: Vec<&str> = cron.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            anyhow::bail!("{s:?} should have five fields: minute hour day month weekday");
+        };
+        let mut weekdays = parse_field(weekday, 0, 7).context("day of week")?;
+        // 7 is Sunday too
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        let schedule = Schedule::Cron(Cron {
+            minutes: parse_field(minute, 0, 59).context("minute")?,
+            hours: parse_field(hour, 0, 23).context("hour")?,
+            days: parse_field(day, 1, 31).context("day of month")?,
+            months: parse_field(month, 1, 12).context("month")?,
+            weekdays,
+            either_day: !day.starts_with('*') && !weekday.starts_with('*'),
+        });
+        anyhow::ensure!(
+            schedule.next_after(now_unix()).is_some(),
+            "{s:?} never fires"
+        );
+        Ok(schedule)
+    }
+}
+
+/// `90s`, `15m`, `6h` or `1d` in seconds
+fn parse_interval(s: &str) -> anyhow::Result<i64> {
+    let unit = s.chars().last().unwrap_or_default();
+    let number = &s[..s.len() - unit.len_utf8()];
+    let unit_secs = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        _ => anyhow::bail!("interval {s:?} should end in s, m, h or d"),
+    };
+    let number: i64 = number
+        .parse()
+        .with_context(|| format!("interval {s:?} should start with a number"))?;
+    anyhow::ensure!(number > 0, "interval {s:?} must be positive");
+    Ok(number * unit_secs)
+}
+
+/// One cron field as a bitmask of the values it allows
+fn parse_field(field: &str, min: u32, max: u32) -> anyhow::Result<u64> {
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(step.parse::<u32>()?)),
+            None => (part, None),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (start.parse()?, end.parse()?),
+            // `5/10` runs from 5 to the end of the range
+            None if step.is_some() => (range.parse()?, max),
+            None => {
+                let value = range.parse()?;
+                (value, value)
+            }
+        };
+        anyhow::ensure!(
+            min <= start && start <= end && end <= max,
+            "{part:?} is outside {min}-{max}"
+        );
+        let step = step.unwrap_or(1);
+        anyhow::ensure!(step > 0, "step in {part:?} must be positive");
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+impl Cron {
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        if self.either_day {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+}
+
+impl Schedule {
+    /// The first time the job should run after `after`, in Unix seconds, or `None` if the
+    /// expression never matches
+    pub fn next_after(&self, after: i64) -> Option<i64> {
+        let cron = match self {
+            Schedule::Every(secs) => return Some(after + secs),
+            Schedule::Cron(cron) => cron,
+        };
+        let mut time: NaiveDateTime =
+            DateTime::from_timestamp(after - after.rem_euclid(60) + 60, 0)?.naive_utc();
+        let limit = time.checked_add_days(chrono::Days::new(SEARCH_DAYS))?;
+        while time < limit {
+            let date = time.date();
+            if cron.months & (1 << date.month()) == 0 {
+                let (year, month) = match date.month() {
+                    12 => (date.year() + 1, 1),
+                    month => (date.year(), month + 1),
+                };
+                time = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !cron.day_matches(date) {
+                time = date.succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if cron.hours & (1 << time.hour()) == 0 {
+                time = time.with_minute(0)? + TimeDelta::hours(1);
+            } else if cron.minutes & (1 << time.minute()) == 0 {
+                time += TimeDelta::minutes(1);
+            } else {
+                return Some(time.and_utc().timestamp());
+            }
+        }
+        None
+    }
+}
+
+type Run = Arc<dyn Fn() -> BoxFuture<'static, anyhow::Result<()>> + Send + Sync>;
+
+/// A unit of background work run by [`run`]
+#[derive(Clone)]
+pub struct Job {
+    name: &'static str,
+    schedule: Schedule,
+    run: Run,
+}
+
+impl Job {
+    /// A job called `name` that runs on `schedule`, unless `job_schedules` gives it
+    /// another one
+    pub fn new<F, Fut>(name: &'static str, schedule: &str, run: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let schedule = CONFIG
+            .job_schedules
+            .get(name)
+            .map_or(schedule, String::as_str);
+        Job {
+            name,
+            schedule: schedule
+                .parse()
+                .unwrap_or_else(|e| panic!("schedule of job {name}: {e:#}")),
+            run: Arc::new(move || run().boxed()),
+        }
+    }
+}
+
+/// When each job last ran, from `scheduled_jobs`
+async fn last_runs() -> sqlx::Result<HashMap<String, i64>> {
+    let rows: Vec<(String, i64)> = sqlx::query_as("SELECT name, last_run_at FROM scheduled_jobs")
+        .fetch_all(&*DB)
+        .await?;
+    Ok(rows.into_iter().collect())
+}
+
+async fn record(name: &str, ran_at: i64, error: Option<String>) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+INSERT INTO scheduled_jobs (name, last_run_at, last_error) VALUES ($1, $2, $3)
+ON CONFLICT(name) DO UPDATE SET
+    last_run_at = excluded.last_run_at,
+    last_error = excluded.last_error
+        "#,
+    )
+    .bind(name)
+    .bind(ran_at)
+    .bind(error)
+    .execute(&*DB)
+    .await?;
+    Ok(())
+}
+
+/// Runs one job, turning its error or panic into a logged and reported failure so it
+/// can't take the others down
+async fn run_job(job: &Job, now: i64) -> anyhow::Result<()> {
+    let result = AssertUnwindSafe((job.run)()).catch_unwind().await;
+    let error = match result {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(format!("{e:#}")),
+        Err(_) => Some("panicked".to_owned()),
+    };
+    if let Some(error) = &error {
+        tracing::error!("job {} failed: {error}", job.name);
+        reporting::capture_error(
+            &format!("job {} failed: {error}", job.name),
+            reporting::Origin::default(),
+        );
+    }
+    record(job.name, now, error).await?;
+    Ok(())
+}
+
+/// Runs `jobs` on their schedules until shutdown. Last runs live in `scheduled_jobs`, so
+/// schedules carry over restarts: a job that never ran runs straight away, and one whose
+/// time passed while the bot was down runs once on startup however many runs it missed.
+/// Due jobs run side by side, and the next are only looked at once they're all done; a
+/// job still running when shutdown is requested is allowed to finish.
+pub async fn run(jobs: Arc<[Job]>, shutdown: CancellationToken) -> anyhow::Result<()> {
+    for name in CONFIG.job_schedules.keys() {
+        if !jobs.iter().any(|job| job.name == name) {
+            tracing::warn!("job_schedules names {name:?}, which isn't a job");
+        }
+    }
+    loop {
+        let now = now_unix();
+        let last_runs = last_runs().await?;
+        let mut due = vec![];
+        let mut next_wake = now + MAX_SLEEP_SECS;
+        for job in jobs.iter() {
+            let next = match last_runs.get(job.name) {
+                Some(&last) => job.schedule.next_after(last),
+                None => Some(now),
+            };
+            match next {
+                Some(next) if next <= now => {
+                    if next + MAX_SLEEP_SECS < now {
+                        tracing::info!("job {} was due at {next}, catching up", job.name);
+                    }
+                    due.push(job);
+                    if let Some(next) = job.schedule.next_after(now) {
+                        next_wake = next_wake.min(next);
+                    }
+                }
+                Some(next) => next_wake = next_wake.min(next),
+                None => {}
+            }
+        }
+        futures_util::future::try_join_all(
+            due.into_iter().map(|job| {
+                run_job(job, now).instrument(tracing::info_span!("job", name = job.name))
+            }),
+        )
+        .await?;
+
+        supervisor::tick("scheduler");
+        let delay = (next_wake - now_unix()).clamp(1, MAX_SLEEP_SECS);
+        if !sleep_or_shutdown(Duration::from_secs(delay as u64), &shutdown).await {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// `2026-03-14 09:30`-style UTC time in Unix seconds
+    fn at(time: &str) -> i64 {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S")
+            .or_else(|_| NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M"))
+            .unwrap()
+            .and_utc()
+            .timestamp()
+    }
+
+    fn next(schedule: &str, after: &str) -> Option<i64> {
+        schedule.parse::<Schedule>().unwrap().next_after(at(after))
+    }
+
+    /// The values a field's mask allows, smallest first
+    fn values(mask: u64) -> Vec<u32> {
+        (0..64).filter(|value| mask & (1 << value) != 0).collect()
+    }
+
+    #[test]
+    fn fields_take_steps_ranges_and_lists() {
+        assert_eq!(values(parse_field("*/15", 0, 59).unwrap()), [0, 15, 30, 45]);
+        assert_eq!(
+            values(parse_field("8-18/2", 0, 23).unwrap()),
+            [8, 10, 12, 14, 16, 18]
+        );
+        assert_eq!(values(parse_field("5/20", 0, 59).unwrap()), [5, 25, 45]);
+        assert_eq!(values(parse_field("1,3,5-6", 1, 31).unwrap()), [1, 3, 5, 6]);
+        assert_eq!(
+            values(parse_field("*", 1, 12).unwrap()),
+            (1..=12).collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            next("*/15 * * * *", "2026-03-14 09:31"),
+            Some(at("2026-03-14 09:45"))
+        );
+        assert_eq!(
+            next("0 8-18/2 * * *", "2026-03-14 09:00"),
+            Some(at("2026-03-14 10:00"))
+        );
+        assert_eq!(
+            next("0 0 * * 1,3", "2026-03-14 12:00"),
+            Some(at("2026-03-16 00:00"))
+        );
+        // 7 is Sunday as well as 0
+        assert_eq!(
+            next("0 0 * * 7", "2026-03-14 12:00"),
+            Some(at("2026-03-15 00:00"))
+        );
+        // With both day fields restricted, either one will do: the 20th or a Monday
+        assert_eq!(
+            next("0 0 20 * 1", "2026-03-14 12:00"),
+            Some(at("2026-03-16 00:00"))
+        );
+        assert_eq!(
+            next("0 0 20 * 1", "2026-03-16 12:00"),
+            Some(at("2026-03-20 00:00"))
+        );
+        assert_eq!(
+            next("@every 90s", "2026-03-14 09:30:10"),
+            Some(at("2026-03-14 09:31:40"))
+        );
+        assert_eq!(
+            next("@every 6h", "2026-03-14 09:30"),
+            Some(at("2026-03-14 15:30"))
+        );
+    }
+
+    #[test]
+    fn invalid_schedules_are_refused() {
+        for schedule in [
+            "",
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * 32 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "5-1 * * * *",
+            "*/0 * * * *",
+            "*/x * * * *",
+            "a * * * *",
+            "1,,2 * * * *",
+            "-5 * * * *",
+            "@yearly",
+            // Valid fields, but no such day
+            "0 0 30 2 *",
+            "@every 0h",
+            "@every -1m",
+            "@every 5x",
+            "@every h",
+            "@every",
+        ] {
+            assert!(schedule.parse::<Schedule>().is_err(), "{schedule:?}");
+        }
+    }
+
+    #[test]
+    fn next_after_rolls_over_hours_days_and_years() {
+        assert_eq!(
+            next("@hourly", "2026-03-14 09:59:30"),
+            Some(at("2026-03-14 10:00"))
+        );
+        // Strictly after: a run on the hour is followed by the next hour's
+        assert_eq!(
+            next("@hourly", "2026-03-14 10:00"),
+            Some(at("2026-03-14 11:00"))
+        );
+        assert_eq!(
+            next("30 23 * * *", "2026-03-14 23:45"),
+            Some(at("2026-03-15 23:30"))
+        );
+        assert_eq!(
+            next("15 0 * * *", "2026-03-14 23:59"),
+            Some(at("2026-03-15 00:15"))
+        );
+        assert_eq!(
+            next("@daily", "2026-12-31 23:59:59"),
+            Some(at("2027-01-01 00:00"))
+        );
+        assert_eq!(
+            next("@monthly", "2026-02-28 00:00"),
+            Some(at("2026-03-01 00:00"))
+        );
+        assert_eq!(
+            next("0 0 31 * *", "2026-04-01 00:00"),
+            Some(at("2026-05-31 00:00"))
+        );
+        // Leap days are years apart
+        assert_eq!(
+            next("0 0 29 2 *", "2026-03-01 00:00"),
+            Some(at("2028-02-29 00:00"))
+        );
+    }
+
+    #[test]
+    fn an_overdue_job_catches_up_once() {
+        smol::block_on(async {
+            let now = now_unix();
+            // Last ran a week ago, so some 168 hourly runs were missed
+            record("test_overdue", now - 7 * 86400, None).await.unwrap();
+            record("test_not_due", now, None).await.unwrap();
+
+            let shutdown = CancellationToken::new();
+            let runs: Arc<[AtomicUsize; 3]> = Arc::new(Default::default());
+            let job = |name, schedule, n: usize| {
+                let (runs, shutdown) = (runs.clone(), shutdown.clone());
+                Job::new(name, schedule, move || {
+                    runs[n].fetch_add(1, Ordering::Relaxed);
+                    // Stops the scheduler once this round of jobs is done
+                    shutdown.cancel();
+                    async { Ok(()) }
+                })
+            };
+            let jobs = [
+                job("test_overdue", "@hourly", 0),
+                job("test_not_due", "@every 1d", 1),
+                job("test_never_ran", "@weekly", 2),
+            ];
+            run(jobs.into(), shutdown.clone()).await.unwrap();
+
+            let counts: Vec<usize> = runs.iter().map(|n| n.load(Ordering::Relaxed)).collect();
+            assert_eq!(counts, [1, 0, 1]);
+            let last = last_runs().await.unwrap();
+            assert!(last["test_overdue"] >= now);
+            assert!(last["test_never_ran"] >= now);
+            assert_eq!(last["test_not_due"], now);
+        });
+    }
+}
@@ -0,0 +1,163 @@
+use rand::Rng;
+use teloxide::types::ChatId;
+
+use crate::{
+    CONFIG, POLL_SECS, begin_write,
+    claim::{self, Reserve},
+    now_unix,
+    policy::RewardPolicy,
+    record_sighting, rewards, vm_api,
+};
+
+/// Pipeline stages in the order they run; a failure skips everything after it
+const STAGES: [&str; 5] = ["parse", "insert", "poll", "unclaimed", "dry-run claim"];
+/// Whole days the synthetic VM should have earned once the simulated poll credits it
+const FIXTURE_DAYS: i64 = 2;
+
+/// Runs the poll → balance → claim pipeline against a synthetic VM and reports each stage.
+///
+/// Everything happens in one transaction that is rolled back at the end, and the giftcard
+/// backend is never called, so the check is safe to run against production.
+pub async fn run() -> String {
+    let mut passed = Vec::new();
+    let result = run_stages(&mut passed).await;
+    let mut lines: Vec<String> = passed
+        .iter()
+        .zip(STAGES)
+        .map(|(detail, stage)| format!("✅ {stage}: {detail}"))
+        .collect();
+    let healthy = match result {
+        Ok(()) => true,
+        Err(e) => {
+            lines.push(format!("❌ {}: {e:#}", STAGES[passed.len()]));
+            lines.extend(
+                STAGES[passed.len() + 1..]
+                    .iter()
+                    .map(|stage| format!("⏭ {stage}: skipped")),
+            );
+            false
+        }
+    };
+    format!(
+        "Self-test {} (all changes rolled back):\n{}",
+        if healthy { "passed" } else { "FAILED" },
+        lines.join("\n")
+    )
+}
+
+/// Pushes a one-line detail to `passed` for each stage that succeeds
+async fn run_stages(passed: &mut Vec<String>) -> anyhow::Result<()> {
+    let suffix: u32 = rand::thread_rng().r#gen();
+    let vm_id = format!("selftest-{suffix:08x}");
+    // Far below anything Telegram assigns, so it can't collide with a real chat
+    let chat_id = ChatId(i64::MIN / 2 - i64::from(suffix));
+    let now = now_unix();
+    let policy = RewardPolicy::current();
+    let fixture_secs = policy.secs(FIXTURE_DAYS);
+
+    let fixture = serde_json::json!({
+        &vm_id: {
+            "region": "selftest",
+            "version": "0.0.0",
+            "bandwidth_mbps": 100.0,
+            "last_heartbeat": now,
+        }
+    });
+    let vms = vm_api::parse(&fixture.to_string(), now)?;
+    let vm = match vms.as_slice() {
+        [vm] if vm.vm_id == vm_id && vm.region.as_deref() == Some("selftest") => vm.clone(),
+        _ => anyhow::bail!("fixture parsed as {vms:?}"),
+    };
+    passed.push(format!("fixture parsed as {vm_id}"));
+
+    let (_write, mut tx) = begin_write().await?;
+    sqlx::query(
+        "INSERT INTO agent_records (vm_id, telegram_chat_id, up_secs, bonus_secs, paid_secs) VALUES ($1, $2, $3, 0, 0)",
+    )
+    .bind(&vm_id)
+    .bind(chat_id.0)
+    .bind(fixture_secs - POLL_SECS)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        "INSERT INTO vm_status (vm_id, last_seen, first_seen, online_since) VALUES ($1, $2, $2, $2)",
+    )
+    .bind(&vm_id)
+    .bind(now - POLL_SECS)
+    .execute(&mut *tx)
+    .await?;
+    passed.push(format!("linked to synthetic chat {chat_id}"));
+
+    // No event, so the expected credit doesn't depend on what admins have scheduled
+    let credited = record_sighting(&mut tx, &vm, now, None, None).await?;
+    anyhow::ensure!(
+        credited == POLL_SECS,
+        "credited {credited}s instead of {POLL_SECS}s"
+    );
+    let (up_secs, region): (i64, Option<String>) = sqlx::query_as(
+        "SELECT a.up_secs, s.region FROM agent_records a JOIN vm_status s ON s.vm_id = a.vm_id WHERE a.vm_id = $1",
+    )
+    .bind(&vm_id)
+    .fetch_one(&mut *tx)
+    .await?;
+    anyhow::ensure!(
+        up_secs == fixture_secs,
+        "expected {fixture_secs}s of uptime after the tick, found {up_secs}s"
+    );
+    anyhow::ensure!(
+        region.as_deref() == Some("selftest"),
+        "metadata not stored, region is {region:?}"
+    );
+    passed.push(format!("credited {POLL_SECS}s and stored metadata"));
+
+    let (balance, _) = claim::balance(&mut tx, chat_id).await?;
+    let days = policy.days(balance);
+    anyhow::ensure!(
+        days == FIXTURE_DAYS,
+        "expected {FIXTURE_DAYS} unclaimed days, found {days}"
+    );
+    passed.push(format!("{days} day(s) unclaimed"));
+
+    let reservation = match claim::reserve_in(&mut tx, chat_id, None).await? {
+        Reserve::Ready(reservation) => reservation,
+        Reserve::Nothing => anyhow::bail!("nothing to claim"),
+        Reserve::NotEnough { available } => anyhow::bail!("only {available} day(s) available"),
+        Reserve::Busy => anyhow::bail!("claim lock already held"),
+        Reserve::Held => anyhow::bail!("claims held for fraud review"),
+    };
+    anyhow::ensure!(
+        reservation.days == FIXTURE_DAYS,
+        "reserved {} days instead of {FIXTURE_DAYS}",
+        reservation.days
+    );
+    let (balance, _) = claim::balance(&mut tx, chat_id).await?;
+    let left = policy.days(balance);
+    anyhow::ensure!(left == 0, "{left} day(s) still unclaimed after reserving");
+    let mut providers = vec![];
+    for kind in CONFIG.reward_providers() {
+        providers.push(match kind {
+            rewards::Kind::Giftcard => {
+                let url = CONFIG.giftcard_api_url().unwrap_or_default();
+                format!(
+                    "POST to {}",
+                    rewards::giftcard_request(&reservation, url)?.uri()
+                )
+            }
+            rewards::Kind::DirectCredit => "direct credit if linked".to_owned(),
+            rewards::Kind::Mock => format!(
+                "mock codes {}",
+                rewards::mock_codes(&reservation).join(", ")
+            ),
+        });
+    }
+    passed.push(format!(
+        "reserved {} day(s) as {}; would issue through {} (not sent)",
+        reservation.days,
+        reservation.key,
+        providers.join(", then ")
+    ));
+
+    // Dropping the transaction uncommitted rolls back every stage
+    drop(tx);
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use async_signal::{Signal, Signals};
+use futures_util::StreamExt;
+use smol::future::FutureExt;
+use tokio_util::sync::CancellationToken;
+
+use crate::{CONFIG, DB, outbox};
+
+/// Cancels `shutdown` on the first SIGTERM or SIGINT. A second one exits at once, for when
+/// the graceful path is stuck.
+pub async fn on_signal(shutdown: CancellationToken) {
+    let mut signals = match Signals::new([Signal::Term, Signal::Int]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            tracing::error!("cannot listen for signals, shutdown won't be graceful: {e}");
+            return;
+        }
+    };
+    if let Some(Ok(signal)) = signals.next().await {
+        tracing::info!(
+            "received {signal:?}, shutting down within {}s",
+            CONFIG.shutdown_grace_secs
+        );
+        shutdown.cancel();
+    }
+    if let Some(Ok(signal)) = signals.next().await {
+        tracing::warn!("received {signal:?} again, exiting without waiting");
+        std::process::exit(130);
+    }
+}
+
+/// Waits for `tasks` to stop, then for the send queue to empty, then checkpoints and
+/// closes the database. Once `shutdown` is cancelled all of that has
+/// `shutdown_grace_secs` to finish; whatever is still running then is abandoned.
+pub async fn finish<F>(shutdown: &CancellationToken, mut tasks: F)
+where
+    F: Future + Unpin,
+{
+    let stopped = async {
+        (&mut tasks).await;
+        true
+    }
+    .or(async {
+        shutdown.cancelled().await;
+        false
+    })
+    .await;
+    let deadline = Instant::now() + Duration::from_secs(CONFIG.shutdown_grace_secs);
+    let stopped = stopped
+        || async {
+            tasks.await;
+            true
+        }
+        .or(async {
+            smol::Timer::at(deadline).await;
+            false
+        })
+        .await;
+    if stopped {
+        tracing::info!("all tasks stopped");
+    } else {
+        tracing::warn!("tasks still running after the grace period, abandoning them");
+    }
+
+    let queued = outbox::drain(deadline.saturating_duration_since(Instant::now())).await;
+    if queued > 0 {
+        tracing::warn!("{queued} queued message(s) not sent");
+    }
+
+    let closed = async {
+        checkpoint().await;
+        DB.close().await;
+        true
+    }
+    .or(async {
+        smol::Timer::at(deadline).await;
+        false
+    })
+    .await;
+    if closed {
+        tracing::info!("database closed, exiting");
+    } else {
+        tracing::warn!("database still in use after the grace period, exiting anyway");
+    }
+}
+
+/// Folds SQLite's write-ahead log back into the database file, so the file alone is a
+/// complete copy once the process is gone
+async fn checkpoint() {
+    if !CONFIG.database_url.starts_with("sqlite")
+        || !CONFIG.sqlite_journal_mode.eq_ignore_ascii_case("wal")
+    {
+        return;
+    }
+    if let Err(e) = sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+        .execute(&*DB)
+        .await
+    {
+        tracing::warn!("checkpointing the database failed: {e}");
+    }
+}
@@ -0,0 +1,143 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use hmac::{Hmac, Mac};
+use hyper::{HeaderMap, Method};
+use once_cell::sync::Lazy;
+use sha2::Sha256;
+
+use crate::{CONFIG, now_unix};
+
+/// Names the fleet whose secret signed the request
+pub const FLEET_HEADER: &str = "x-fleet";
+/// Unix time the request was signed at
+pub const TIMESTAMP_HEADER: &str = "x-timestamp";
+/// Hex HMAC-SHA256 of [`signed_message`] under the fleet's secret
+pub const SIGNATURE_HEADER: &str = "x-signature";
+
+/// Signatures already accepted, with the time they stop being replayable
+static SEEN: Lazy<Mutex<HashMap<Vec<u8>, i64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Whether control endpoints require signatures, i.e. any fleet secret is configured
+pub fn required() -> bool {
+    !CONFIG.fleet_hmac_secrets.is_empty()
+}
+
+/// What gets signed: the timestamp, method and path bind the body to one request, so a
+/// captured signature can't be reused for another endpoint or after the window
+fn signed_message(timestamp: i64, method: &Method, path: &str, body: &[u8]) -> Vec<u8> {
+    let mut message = format!("{timestamp}\n{method}\n{path}\n").into_bytes();
+    message.extend_from_slice(body);
+    message
+}
+
+/// Verifies a signed request, returning the fleet that signed it.
+///
+/// The timestamp must be within `hmac_window_secs` of now, and each signature is accepted
+/// only once, so neither a stale nor a replayed request gets through.
+pub fn verify(
+    headers: &HeaderMap,
+    method: &Method,
+    path: &str,
+    body: &[u8],
+) -> Result<String, &'static str> {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    let fleet = header(FLEET_HEADER).ok_or("missing fleet header")?;
+    let secret = CONFIG
+        .fleet_hmac_secrets
+        .get(fleet)
+        .ok_or("unknown fleet")?;
+    let timestamp: i64 = header(TIMESTAMP_HEADER)
+        .and_then(|t| t.parse().ok())
+        .ok_or("missing or malformed timestamp")?;
+    let signature = header(SIGNATURE_HEADER)
+        .and_then(|s| hex::decode(s).ok())
+        .ok_or("missing or malformed signature")?;
+
+    let now = now_unix();
+    if (now - timestamp).abs() > CONFIG.hmac_window_secs {
+        return Err("timestamp outside the allowed window");
+    }
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(&signed_message(timestamp, method, path, body));
+    mac.verify_slice(&signature)
+        .map_err(|_| "signature mismatch")?;
+
+    let mut seen = SEEN.lock().unwrap();
+    seen.retain(|_, expires| *expires > now);
+    if seen
+        .insert(signature, timestamp + CONFIG.hmac_window_secs)
+        .is_some()
+    {
+        return Err("replayed request");
+    }
+    Ok(fleet.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::HeaderValue;
+
+    use super::*;
+
+    /// Headers signing `body` for `POST path` as the test config's `default` fleet
+    fn signed(path: &str, body: &[u8], timestamp: i64) -> HeaderMap {
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"test").unwrap();
+        mac.update(&signed_message(timestamp, &Method::POST, path, body));
+        let signature = hex::encode(mac.finalize().into_bytes());
+        let mut headers = HeaderMap::new();
+        headers.insert(FLEET_HEADER, HeaderValue::from_static("default"));
+        headers.insert(TIMESTAMP_HEADER, HeaderValue::from(timestamp));
+        headers.insert(SIGNATURE_HEADER, signature.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn verify_accepts_a_valid_signature_once() {
+        let path = "/api/vm/signing-valid/heartbeat";
+        let headers = signed(path, b"{}", now_unix());
+        assert_eq!(
+            verify(&headers, &Method::POST, path, b"{}"),
+            Ok("default".to_owned())
+        );
+        assert_eq!(
+            verify(&headers, &Method::POST, path, b"{}"),
+            Err("replayed request")
+        );
+    }
+
+    #[test]
+    fn verify_refuses_a_tampered_request() {
+        let path = "/api/vm/signing-tampered/heartbeat";
+        let headers = signed(path, b"{}", now_unix());
+        let other_path = "/api/vm/signing-other/heartbeat";
+        assert_eq!(
+            verify(&headers, &Method::POST, path, br#"{"region":"us"}"#),
+            Err("signature mismatch")
+        );
+        assert_eq!(
+            verify(&headers, &Method::POST, other_path, b"{}"),
+            Err("signature mismatch")
+        );
+        let mut unknown = headers.clone();
+        unknown.insert(FLEET_HEADER, HeaderValue::from_static("elsewhere"));
+        assert_eq!(
+            verify(&unknown, &Method::POST, path, b"{}"),
+            Err("unknown fleet")
+        );
+        // Rejected attempts don't use the signature up
+        assert!(verify(&headers, &Method::POST, path, b"{}").is_ok());
+    }
+
+    #[test]
+    fn verify_refuses_timestamps_outside_the_window() {
+        let path = "/api/vm/signing-window/heartbeat";
+        for skew in [CONFIG.hmac_window_secs + 1, -CONFIG.hmac_window_secs - 1] {
+            let headers = signed(path, b"{}", now_unix() + skew);
+            assert_eq!(
+                verify(&headers, &Method::POST, path, b"{}"),
+                Err("timestamp outside the allowed window")
+            );
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use chrono::{Days, NaiveDate, Utc};
+use serde_json::json;
+use teloxide::{prelude::*, types::ChatId};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::{
+    DB,
+    audit::{self, Actor},
+    begin_write, i18n, next_tick, nicknames, now_unix, outbox,
+};
+
+/// A day counts towards a streak with at most an hour missing, so a reboot or a failed
+/// poll doesn't break it
+const FULL_DAY_SECS: i64 = 23 * 3600;
+/// Streak lengths in days that earn bonus time, and how much
+pub const MILESTONES: &[(i64, i64)] = &[(7, 12 * 3600), (30, 2 * 86400)];
+
+/// A run of consecutive full days that is still going: it ends today or, since today
+/// isn't over yet, yesterday
+#[derive(Clone, Copy)]
+pub struct Streak {
+    pub started_on: NaiveDate,
+    pub days: i64,
+}
+
+impl Streak {
+    /// The day this streak reached `milestone` days, if it has
+    fn reached_on(&self, milestone: i64) -> Option<NaiveDate> {
+        (self.days >= milestone).then(|| self.started_on + Days::new(milestone as u64 - 1))
+    }
+}
+
+/// The streak formed by `full_days` (`YYYY-MM-DD`, any order) as of `today`
+fn current_streak(full_days: &[String], today: NaiveDate) -> Option<Streak> {
+    let mut days: Vec<NaiveDate> = full_days
+        .iter()
+        .filter_map(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+        .collect();
+    days.sort_unstable_by(|a, b| b.cmp(a));
+    let latest = *days.first()?;
+    if latest < today.pred_opt()? {
+        return None;
+    }
+    let mut streak = Streak {
+        started_on: latest,
+        days: 1,
+    };
+    for day in &days[1..] {
+        if streak.started_on.pred_opt() != Some(*day) {
+            break;
+        }
+        streak.started_on = *day;
+        streak.days += 1;
+    }
+    Some(streak)
+}
+
+/// Full days of every VM linked to `chat_id`, or of every linked VM when `None`
+async fn full_days(chat_id: Option<ChatId>) -> sqlx::Result<BTreeMap<String, (i64, Vec<String>)>> {
+    let rows: Vec<(String, i64, String)> = sqlx::query_as(
+        r#"
+SELECT h.vm_id, a.telegram_chat_id, h.day
+FROM uptime_history h JOIN agent_records a ON a.vm_id = h.vm_id
+WHERE a.telegram_chat_id IS NOT NULL
+  AND ($1 IS NULL OR a.telegram_chat_id = $1)
+  AND h.up_secs >= $2
+        "#,
+    )
+    .bind(chat_id.map(|c| c.0))
+    .bind(FULL_DAY_SECS)
+    .fetch_all(&*DB)
+    .await?;
+    let mut by_vm: BTreeMap<String, (i64, Vec<String>)> = BTreeMap::new();
+    for (vm_id, owner, day) in rows {
+        by_vm.entry(vm_id).or_insert((owner, vec![])).1.push(day);
+    }
+    Ok(by_vm)
+}
+
+/// Current streaks of `chat_id`'s VMs, as shown by `/uptime`
+pub async fn for_chat(chat_id: ChatId) -> sqlx::Result<Vec<(String, Streak)>> {
+    let today = Utc::now().date_naive();
+    Ok(full_days(Some(chat_id))
+        .await?
+        .into_iter()
+        .filter_map(|(vm_id, (_, days))| Some((vm_id, current_streak(&days, today)?)))
+        .collect())
+}
+
+/// The next milestone `streak` is working towards, with its bonus
+pub fn next_milestone(streak: &Streak) -> Option<(i64, i64)> {
+    MILESTONES
+        .iter()
+        .copied()
+        .find(|(days, _)| *days > streak.days)
+}
+
+/// Credits streak milestones as VMs reach them. It runs hourly, so a milestone is paid
+/// within an hour of the day that completes it ending, or of the bot coming back up.
+pub async fn award_loop(bot: Bot, shutdown: CancellationToken) -> anyhow::Result<()> {
+    let mut ticker = smol::Timer::interval(Duration::from_secs(3600));
+    loop {
+        async {
+            let today = Utc::now().date_naive();
+            for (vm_id, (owner, days)) in full_days(None).await? {
+                let Some(streak) = current_streak(&days, today) else {
+                    continue;
+                };
+                for &(milestone, bonus_secs) in MILESTONES {
+                    let Some(reached_on) = streak.reached_on(milestone) else {
+                        continue;
+                    };
+                    if award(&vm_id, owner, milestone, reached_on, bonus_secs).await? {
+                        let hours = bonus_secs / 3600;
+                        let name = nicknames::label_of(&vm_id).await?;
+                        let text = i18n::text(
+                            "streaks.milestone",
+                            &[("name", &name), ("days", &milestone), ("hours", &hours)],
+                        );
+                        let chat_id = ChatId(owner);
+                        let _ =
+                            outbox::deliver(chat_id, || bot.send_message(chat_id, &text).send())
+                                .await;
+                    }
+                }
+            }
+            anyhow::Ok(())
+        }
+        .instrument(tracing::info_span!("tick"))
+        .await?;
+
+        if !next_tick("streak_awards", &mut ticker, &shutdown).await {
+            return Ok(());
+        }
+    }
+}
+
+/// Pays one milestone, returning false if this streak was already paid for it
+async fn award(
+    vm_id: &str,
+    owner: i64,
+    milestone: i64,
+    reached_on: NaiveDate,
+    bonus_secs: i64,
+) -> sqlx::Result<bool> {
+    let reached_on = reached_on.format("%Y-%m-%d").to_string();
+    let (_write, mut tx) = begin_write().await?;
+    let inserted = sqlx::query(
+        r#"
+INSERT INTO streak_awards (vm_id, milestone, reached_on, bonus_secs, awarded_at)
+VALUES ($1, $2, $3, $4, $5)
+ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(vm_id)
+    .bind(milestone)
+    .bind(&reached_on)
+    .bind(bonus_secs)
+    .bind(now_unix())
+    .execute(&mut *tx)
+    .await?
+    .rows_affected()
+        > 0;
+    if !inserted {
+        return Ok(false);
+    }
+    sqlx::query("UPDATE agent_records SET bonus_secs = bonus_secs + $1 WHERE vm_id = $2")
+        .bind(bonus_secs)
+        .bind(vm_id)
+        .execute(&mut *tx)
+        .await?;
+    audit::record(
+        &mut tx,
+        &Actor::System,
+        "streak_bonus",
+        Some(ChatId(owner)),
+        json!({ "vm_id": vm_id, "milestone": milestone, "reached_on": reached_on, "bonus_secs": bonus_secs }),
+    )
+    .await?;
+    tx.commit().await?;
+    tracing::info!("vm {vm_id} reached a {milestone}-day streak, credited {bonus_secs}s");
+    Ok(true)
+}
@@ -0,0 +1,147 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    panic::AssertUnwindSafe,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use smol::future::FutureExt;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::now_unix;
+
+/// First restart delay after a task fails; doubled on every consecutive failure
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+/// A task that stayed up this long before failing starts over from [`MIN_BACKOFF`]
+const HEALTHY_RUN: Duration = Duration::from_secs(600);
+/// A looping task is stalled once it has gone this many of its usual tick intervals, plus
+/// [`STALL_GRACE_SECS`], without finishing a tick
+const STALL_INTERVALS: i64 = 3;
+const STALL_GRACE_SECS: i64 = 120;
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    Running,
+    Backoff,
+    Stopped,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TaskStatus {
+    pub state: TaskState,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+    /// Unix time of the last state change
+    pub since: i64,
+    /// Unix time a looping task last finished a tick, since it was last started
+    pub last_tick: Option<i64>,
+    /// Seconds between its last two ticks
+    pub tick_interval: Option<i64>,
+}
+
+impl TaskStatus {
+    /// Whether the task is running but hasn't finished a tick for much longer than usual,
+    /// e.g. stuck on a request that never returns. Tasks that don't tick, or haven't ticked
+    /// twice yet, are never stalled.
+    pub fn stalled(&self, now: i64) -> bool {
+        let Some(interval) = self.tick_interval else {
+            return false;
+        };
+        let last = self.last_tick.unwrap_or(self.since);
+        self.state == TaskState::Running
+            && now - last > STALL_INTERVALS * interval + STALL_GRACE_SECS
+    }
+}
+
+static TASKS: Lazy<Mutex<BTreeMap<&'static str, TaskStatus>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// Snapshot of every supervised task, keyed by name
+pub fn task_statuses() -> BTreeMap<&'static str, TaskStatus> {
+    TASKS.lock().unwrap().clone()
+}
+
+fn set_state(name: &'static str, state: TaskState, error: Option<String>) {
+    let mut tasks = TASKS.lock().unwrap();
+    let status = tasks.entry(name).or_insert(TaskStatus {
+        state,
+        restarts: 0,
+        last_error: None,
+        since: now_unix(),
+        last_tick: None,
+        tick_interval: None,
+    });
+    if state == TaskState::Running && status.state == TaskState::Backoff {
+        status.restarts += 1;
+    }
+    status.last_tick = None;
+    status.state = state;
+    status.since = now_unix();
+    if error.is_some() {
+        status.last_error = error;
+    }
+}
+
+/// Records that the task `name` finished a tick of its loop
+pub fn tick(name: &'static str) {
+    let now = now_unix();
+    if let Some(status) = TASKS.lock().unwrap().get_mut(name) {
+        if let Some(last) = status.last_tick {
+            status.tick_interval = Some(now - last);
+        }
+        status.last_tick = Some(now);
+    }
+}
+
+/// Runs the task produced by `spawn` until shutdown, restarting it with exponential
+/// backoff whenever it fails, panics, or exits on its own. Failures never propagate to
+/// other tasks.
+pub async fn supervise<F, Fut>(name: &'static str, shutdown: CancellationToken, mut spawn: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<()>>,
+{
+    let mut backoff = MIN_BACKOFF;
+    while !shutdown.is_cancelled() {
+        set_state(name, TaskState::Running, None);
+        let started = Instant::now();
+        // Everything the task logs is tagged with its name
+        let task = spawn().instrument(tracing::info_span!("task", name));
+        let result = AssertUnwindSafe(task).catch_unwind().await;
+        if shutdown.is_cancelled() {
+            break;
+        }
+        let error = match result {
+            Ok(Ok(())) => "exited unexpectedly".to_owned(),
+            Ok(Err(e)) => format!("{e:#}"),
+            Err(_) => "panicked".to_owned(),
+        };
+        if started.elapsed() >= HEALTHY_RUN {
+            backoff = MIN_BACKOFF;
+        }
+        tracing::error!("task {name} failed, restarting in {backoff:?}: {error}");
+        set_state(name, TaskState::Backoff, Some(error));
+
+        let cancelled = async {
+            smol::Timer::after(backoff).await;
+            false
+        }
+        .or(async {
+            shutdown.cancelled().await;
+            true
+        })
+        .await;
+        if cancelled {
+            break;
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+    set_state(name, TaskState::Stopped, None);
+    tracing::info!("task {name} stopped");
+}
@@ -0,0 +1,419 @@
+use std::future::IntoFuture;
+
+use futures_util::{FutureExt, future::BoxFuture};
+use teloxide::{
+    RequestError,
+    prelude::*,
+    requests::HasPayload,
+    types::{
+        ChatId, InlineKeyboardMarkup, InputFile, MessageEntity, MessageId, ParseMode, ReplyMarkup,
+    },
+};
+
+/// What a message carries
+#[derive(Clone, Debug)]
+pub enum Body {
+    Text(String),
+    /// An image shown inline
+    Photo {
+        file_name: String,
+        bytes: Vec<u8>,
+    },
+    /// A file to download
+    Document {
+        file_name: String,
+        bytes: Vec<u8>,
+    },
+}
+
+/// A message to send, put together by [`Telegram::send_message`] and its siblings
+#[derive(Clone, Debug)]
+pub struct Outgoing {
+    pub chat_id: ChatId,
+    pub body: Body,
+    /// Shown under a photo or document
+    pub caption: Option<String>,
+    pub reply_markup: Option<ReplyMarkup>,
+    pub entities: Option<Vec<MessageEntity>>,
+    pub parse_mode: Option<ParseMode>,
+}
+
+#[cfg(test)]
+impl Outgoing {
+    /// The text or caption the chat sees
+    pub fn text(&self) -> Option<&str> {
+        match &self.body {
+            Body::Text(text) => Some(text),
+            _ => self.caption.as_deref(),
+        }
+    }
+}
+
+/// A new text for a message sent earlier
+#[derive(Clone, Debug)]
+pub struct Edit {
+    pub chat_id: ChatId,
+    pub message_id: MessageId,
+    pub text: String,
+    pub reply_markup: Option<InlineKeyboardMarkup>,
+}
+
+/// The Telegram calls made while answering a chat. [`Bot`] makes them against the Bot API;
+/// tests swap in a fake that records them, so a handler can be run without a network and
+/// the messages it produced checked.
+///
+/// Implementations only provide the five primitives; the `send_message`-style builders
+/// mirror teloxide's, so handlers read the same whichever client they're given.
+pub trait Telegram: Clone + Send + Sync + 'static {
+    fn send(&self, message: Outgoing) -> BoxFuture<'_, Result<MessageId, RequestError>>;
+
+    fn edit(&self, edit: Edit) -> BoxFuture<'_, Result<(), RequestError>>;
+
+    /// Stops a tapped button's spinner
+    fn answer_callback(&self, query_id: String) -> BoxFuture<'_, Result<(), RequestError>>;
+
+    /// Pins without notifying the chat; the bot only pins its own status messages
+    fn pin(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+    ) -> BoxFuture<'_, Result<(), RequestError>>;
+
+    fn unpin(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+    ) -> BoxFuture<'_, Result<(), RequestError>>;
+
+    fn send_message(&self, chat_id: ChatId, text: impl Into<String>) -> SendMessage<'_, Self> {
+        SendMessage::new(self, chat_id, Body::Text(text.into()))
+    }
+
+    fn send_photo(
+        &self,
+        chat_id: ChatId,
+        file_name: impl Into<String>,
+        bytes: Vec<u8>,
+    ) -> SendMessage<'_, Self> {
+        let file_name = file_name.into();
+        SendMessage::new(self, chat_id, Body::Photo { file_name, bytes })
+    }
+
+    fn send_document(
+        &self,
+        chat_id: ChatId,
+        file_name: impl Into<String>,
+        bytes: Vec<u8>,
+    ) -> SendMessage<'_, Self> {
+        let file_name = file_name.into();
+        SendMessage::new(self, chat_id, Body::Document { file_name, bytes })
+    }
+
+    fn edit_message_text(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+        text: impl Into<String>,
+    ) -> EditMessageText<'_, Self> {
+        EditMessageText {
+            client: self,
+            edit: Edit {
+                chat_id,
+                message_id,
+                text: text.into(),
+                reply_markup: None,
+            },
+        }
+    }
+
+    fn answer_callback_query(
+        &self,
+        query_id: impl Into<String>,
+    ) -> BoxFuture<'_, Result<(), RequestError>> {
+        self.answer_callback(query_id.into())
+    }
+}
+
+/// A message being put together; awaiting it sends it
+pub struct SendMessage<'a, T: ?Sized> {
+    client: &'a T,
+    message: Outgoing,
+}
+
+impl<'a, T: Telegram> SendMessage<'a, T> {
+    fn new(client: &'a T, chat_id: ChatId, body: Body) -> Self {
+        SendMessage {
+            client,
+            message: Outgoing {
+                chat_id,
+                body,
+                caption: None,
+                reply_markup: None,
+                entities: None,
+                parse_mode: None,
+            },
+        }
+    }
+
+    pub fn caption(mut self, caption: impl Into<String>) -> Self {
+        self.message.caption = Some(caption.into());
+        self
+    }
+
+    pub fn reply_markup(mut self, markup: impl Into<ReplyMarkup>) -> Self {
+        self.message.reply_markup = Some(markup.into());
+        self
+    }
+
+    pub fn entities(mut self, entities: Vec<MessageEntity>) -> Self {
+        self.message.entities = Some(entities);
+        self
+    }
+
+    pub fn parse_mode(mut self, parse_mode: ParseMode) -> Self {
+        self.message.parse_mode = Some(parse_mode);
+        self
+    }
+}
+
+impl<'a, T: Telegram> IntoFuture for SendMessage<'a, T> {
+    type Output = Result<MessageId, RequestError>;
+    type IntoFuture = BoxFuture<'a, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        self.client.send(self.message)
+    }
+}
+
+/// An edit being put together; awaiting it applies it
+pub struct EditMessageText<'a, T: ?Sized> {
+    client: &'a T,
+    edit: Edit,
+}
+
+impl<T: Telegram> EditMessageText<'_, T> {
+    pub fn reply_markup(mut self, markup: InlineKeyboardMarkup) -> Self {
+        self.edit.reply_markup = Some(markup);
+        self
+    }
+}
+
+impl<'a, T: Telegram> IntoFuture for EditMessageText<'a, T> {
+    type Output = Result<(), RequestError>;
+    type IntoFuture = BoxFuture<'a, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        self.client.edit(self.edit)
+    }
+}
+
+impl Telegram for Bot {
+    fn send(&self, message: Outgoing) -> BoxFuture<'_, Result<MessageId, RequestError>> {
+        async move {
+            let Outgoing {
+                chat_id,
+                body,
+                caption,
+                reply_markup,
+                entities,
+                parse_mode,
+            } = message;
+            let sent = match body {
+                Body::Text(text) => {
+                    let mut request = Requester::send_message(self, chat_id, text);
+                    let payload = request.payload_mut();
+                    payload.reply_markup = reply_markup;
+                    payload.entities = entities;
+                    payload.parse_mode = parse_mode;
+                    request.await?
+                }
+                Body::Photo { file_name, bytes } => {
+                    let file = InputFile::memory(bytes).file_name(file_name);
+                    let mut request = Requester::send_photo(self, chat_id, file);
+                    let payload = request.payload_mut();
+                    payload.caption = caption;
+                    payload.reply_markup = reply_markup;
+                    payload.caption_entities = entities;
+                    payload.parse_mode = parse_mode;
+                    request.await?
+                }
+                Body::Document { file_name, bytes } => {
+                    let file = InputFile::memory(bytes).file_name(file_name);
+                    let mut request = Requester::send_document(self, chat_id, file);
+                    let payload = request.payload_mut();
+                    payload.caption = caption;
+                    payload.reply_markup = reply_markup;
+                    payload.caption_entities = entities;
+                    payload.parse_mode = parse_mode;
+                    request.await?
+                }
+            };
+            Ok(sent.id)
+        }
+        .boxed()
+    }
+
+    fn edit(&self, edit: Edit) -> BoxFuture<'_, Result<(), RequestError>> {
+        async move {
+            let mut request =
+                Requester::edit_message_text(self, edit.chat_id, edit.message_id, edit.text);
+            request.payload_mut().reply_markup = edit.reply_markup;
+            request.await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn answer_callback(&self, query_id: String) -> BoxFuture<'_, Result<(), RequestError>> {
+        async move {
+            Requester::answer_callback_query(self, query_id).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn pin(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+    ) -> BoxFuture<'_, Result<(), RequestError>> {
+        async move {
+            Requester::pin_chat_message(self, chat_id, message_id)
+                .disable_notification(true)
+                .await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn unpin(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+    ) -> BoxFuture<'_, Result<(), RequestError>> {
+        async move {
+            Requester::unpin_chat_message(self, chat_id)
+                .message_id(message_id)
+                .await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// A [`Telegram`] that records every call instead of making it, for tests
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct Fake {
+    calls: std::sync::Arc<std::sync::Mutex<Vec<Call>>>,
+}
+
+#[cfg(test)]
+#[derive(Clone, Debug)]
+pub enum Call {
+    Send(Outgoing),
+    Edit(Edit),
+    AnswerCallback(String),
+    Pin(ChatId, MessageId),
+    Unpin(ChatId, MessageId),
+}
+
+#[cfg(test)]
+impl Fake {
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// The texts and captions sent to `chat_id`, oldest first
+    pub fn texts(&self, chat_id: ChatId) -> Vec<String> {
+        self.calls()
+            .iter()
+            .filter_map(|call| match call {
+                Call::Send(message) if message.chat_id == chat_id => {
+                    message.text().map(str::to_owned)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Records `call`, returning the id the next sent message gets
+    fn record(&self, call: Call) -> MessageId {
+        let mut calls = self.calls.lock().unwrap();
+        calls.push(call);
+        MessageId(calls.len() as i32)
+    }
+}
+
+#[cfg(test)]
+impl Telegram for Fake {
+    fn send(&self, message: Outgoing) -> BoxFuture<'_, Result<MessageId, RequestError>> {
+        let id = self.record(Call::Send(message));
+        async move { Ok(id) }.boxed()
+    }
+
+    fn edit(&self, edit: Edit) -> BoxFuture<'_, Result<(), RequestError>> {
+        self.record(Call::Edit(edit));
+        async { Ok(()) }.boxed()
+    }
+
+    fn answer_callback(&self, query_id: String) -> BoxFuture<'_, Result<(), RequestError>> {
+        self.record(Call::AnswerCallback(query_id));
+        async { Ok(()) }.boxed()
+    }
+
+    fn pin(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+    ) -> BoxFuture<'_, Result<(), RequestError>> {
+        self.record(Call::Pin(chat_id, message_id));
+        async { Ok(()) }.boxed()
+    }
+
+    fn unpin(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+    ) -> BoxFuture<'_, Result<(), RequestError>> {
+        self.record(Call::Unpin(chat_id, message_id));
+        async { Ok(()) }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builders_reach_the_primitives() {
+        let bot = Fake::default();
+        let chat = ChatId(1);
+        smol::block_on(async {
+            let sent = bot
+                .send_message(chat, "hi")
+                .parse_mode(ParseMode::MarkdownV2)
+                .await?;
+            bot.edit_message_text(chat, sent, "edited").await?;
+            bot.pin(chat, sent).await?;
+            bot.unpin(chat, sent).await?;
+            bot.send_photo(chat, "a.png", vec![1])
+                .caption("photo")
+                .await?;
+            Ok::<_, RequestError>(())
+        })
+        .unwrap();
+
+        assert_eq!(bot.texts(chat), ["hi", "photo"]);
+        let calls = bot.calls();
+        assert!(matches!(&calls[0], Call::Send(m) if m.parse_mode == Some(ParseMode::MarkdownV2)));
+        assert!(
+            matches!(&calls[1], Call::Edit(e) if e.message_id == MessageId(1) && e.text == "edited")
+        );
+        assert!(matches!(calls[2], Call::Pin(c, MessageId(1)) if c == chat));
+        assert!(matches!(calls[3], Call::Unpin(c, MessageId(1)) if c == chat));
+        assert!(
+            matches!(&calls[4], Call::Send(m) if matches!(&m.body, Body::Photo { file_name, .. } if file_name == "a.png"))
+        );
+    }
+}
@@ -0,0 +1,70 @@
+use opentelemetry::{KeyValue, trace::TracerProvider as _};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig, WithHttpConfig};
+use opentelemetry_sdk::{Resource, trace::SdkTracerProvider};
+use tracing_subscriber::{EnvFilter, Layer, layer::SubscriberExt, util::SubscriberInitExt};
+
+use crate::{CONFIG, proxy, vm_api};
+
+/// Name the bot's traces are reported under
+const SERVICE_NAME: &str = "geph-testing-bot";
+
+/// Sets up logging, and trace export too when `otlp_endpoint` is set. Spans are exported
+/// from a background thread in batches; hand the returned provider to [`shutdown`] on exit
+/// so the last batch isn't lost.
+pub fn init() -> Option<SdkTracerProvider> {
+    // `RUST_LOG` takes the usual directives, e.g. `geph_testing_bot::claim=trace,info`
+    let log_filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(CONFIG.environment.default_log_filter()));
+    let log_filter = scrub(log_filter);
+    let provider = CONFIG.otlp_endpoint.as_ref().map(|endpoint| {
+        let mut exporter = SpanExporter::builder().with_http().with_endpoint(endpoint);
+        if let Some(client) = proxy::otlp_client() {
+            exporter = exporter.with_http_client(client);
+        }
+        let exporter = exporter.build().expect("build OTLP span exporter");
+        let resource = Resource::builder()
+            .with_service_name(SERVICE_NAME)
+            .with_attribute(KeyValue::new(
+                "deployment.environment.name",
+                CONFIG.environment.name(),
+            ))
+            .build();
+        SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(resource)
+            .build()
+    });
+    let traces = provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer(SERVICE_NAME))
+            .with_filter(scrub(EnvFilter::new(&CONFIG.otlp_filter)))
+    });
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(log_filter))
+        .with(traces)
+        .init();
+    provider
+}
+
+/// Keeps isahc's request spans, which record the whole URL, out of `filter` while any
+/// fleet's VM API secret is sent in the query
+fn scrub(filter: EnvFilter) -> EnvFilter {
+    if CONFIG
+        .fleets()
+        .iter()
+        .any(|fleet| fleet.auth() == vm_api::Auth::Query)
+    {
+        filter.add_directive("isahc=info".parse().unwrap())
+    } else {
+        filter
+    }
+}
+
+/// Sends the spans still waiting in the batch
+pub fn shutdown(provider: Option<SdkTracerProvider>) {
+    if let Some(provider) = provider
+        && let Err(e) = provider.shutdown()
+    {
+        eprintln!("flushing traces failed: {e}");
+    }
+}
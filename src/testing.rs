@@ -0,0 +1,150 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use sqlx::AnyPool;
+use teloxide::types::ChatId;
+
+use crate::{Config, DB, open_pool, record_sighting, vm_api::AvailableVm};
+
+/// The config tests run under: an in-memory database, mock rewards since there's no
+/// `giftcard_api_url` outside `prod`, and a minute of uptime per reward day so accrual
+/// doesn't take thousands of polls
+const CONFIG_YAML: &str = r#"
+telegram_bot_token: "0:test"
+vm_api_secret: test
+giftcard_api_secret: test
+environment: dev
+sqlite_journal_mode: memory
+reward_secs_per_day: 60
+admin_chat_ids: [1, 2]
+fleet_hmac_secrets: { default: test }
+"#;
+
+/// The admin chats of the test config, below any id [`chat`] hands out. Tests share their
+/// rate limits, so the second one spares [`ADMIN`]'s.
+pub const ADMIN: ChatId = ChatId(1);
+pub const OTHER_ADMIN: ChatId = ChatId(2);
+
+/// Tests share one process-wide [`DB`], so each takes its own chats and VMs from here
+static NEXT_ID: AtomicI64 = AtomicI64::new(1_000);
+
+/// What [`crate::CONFIG`] holds under `cargo test`
+pub fn config() -> Config {
+    let mut config: Config = serde_yaml::from_str(CONFIG_YAML).expect("parse the test config");
+    config.database_url = memory_url();
+    config.validate().expect("validate the test config");
+    config
+}
+
+/// A new in-memory database. `sqlite::memory:` itself won't do: `sqlx::Any` parses the URL
+/// again for every connection, and each would get a database of its own. A named one in
+/// shared-cache mode is the same for the whole pool.
+fn memory_url() -> String {
+    let n = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    format!("sqlite:file:geph-test-{n}?mode=memory&cache=shared")
+}
+
+/// A fresh in-memory database with every migration applied, for tests that need one to
+/// themselves. Handlers use [`DB`], which the test config points at another such database.
+pub async fn pool() -> AnyPool {
+    open_pool(&memory_url()).await
+}
+
+/// A chat no other test uses
+pub fn chat() -> ChatId {
+    ChatId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+}
+
+/// A VM id no other test uses
+pub fn vm_id() -> String {
+    format!("vm-test-{}", NEXT_ID.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Reports `vm_id` as available at `at`, the way a poll of the availability API does,
+/// returning the uptime credited
+pub async fn sight(vm_id: &str, at: i64) -> i64 {
+    let vm = AvailableVm {
+        vm_id: vm_id.to_owned(),
+        region: None,
+        version: None,
+        bandwidth_mbps: None,
+        last_heartbeat: None,
+        extra: Default::default(),
+    };
+    let mut conn = DB.acquire().await.unwrap();
+    record_sighting(&mut conn, &vm, at, None, None)
+        .await
+        .unwrap()
+}
+
+/// Adds a VM owned by `chat_id`, or unowned when `None`, with `up_secs` of unclaimed uptime,
+/// last seen at `seen_at`
+pub async fn seed_vm(
+    pool: &AnyPool,
+    vm_id: &str,
+    chat_id: Option<ChatId>,
+    up_secs: i64,
+    seen_at: i64,
+) {
+    sqlx::query(
+        "INSERT INTO agent_records (vm_id, telegram_chat_id, up_secs, bonus_secs, paid_secs) VALUES ($1, $2, $3, 0, 0)",
+    )
+    .bind(vm_id)
+    .bind(chat_id.map(|c| c.0))
+    .bind(up_secs)
+    .execute(pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO vm_status (vm_id, last_seen, first_seen, online_since) VALUES ($1, $2, $2, $2)",
+    )
+    .bind(vm_id)
+    .bind(seen_at)
+    .execute(pool)
+    .await
+    .unwrap();
+}
+
+/// Records a giftcard of `days` as already issued to `chat_id`, returning its id
+pub async fn seed_giftcard(
+    pool: &AnyPool,
+    chat_id: ChatId,
+    code: &str,
+    days: i64,
+    created_at: i64,
+) -> i64 {
+    sqlx::query_scalar(
+        "INSERT INTO giftcards (telegram_chat_id, code, days, created_at) VALUES ($1, $2, $3, $4) RETURNING id",
+    )
+    .bind(chat_id.0)
+    .bind(code)
+    .bind(days)
+    .bind(created_at)
+    .fetch_one(pool)
+    .await
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pools_are_migrated_and_separate() {
+        smol::block_on(async {
+            let (first, second) = (pool().await, pool().await);
+            let applied: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM _sqlx_migrations")
+                .fetch_one(&first)
+                .await
+                .unwrap();
+            let files = std::fs::read_dir("migrations/sqlite").unwrap().count();
+            assert_eq!(applied, files as i64);
+
+            seed_vm(&first, "vm-only-in-first", None, 60, 0).await;
+            let in_second: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM agent_records")
+                .fetch_one(&second)
+                .await
+                .unwrap();
+            assert_eq!(in_second, 0);
+        });
+    }
+}
@@ -0,0 +1,79 @@
+use once_cell::sync::OnceCell;
+use rand::{Rng, distributions::Alphanumeric};
+
+use crate::{DB, now_unix};
+
+/// Prefix that tells registration tokens apart from raw VM ids
+pub const TOKEN_PREFIX: &str = "reg-";
+
+/// The bot's Telegram username, learned when the dispatcher starts
+static BOT_USERNAME: OnceCell<String> = OnceCell::new();
+
+pub fn set_bot_username(username: &str) {
+    let _ = BOT_USERNAME.set(username.to_owned());
+}
+
+/// Whether `name` (without the `@`) is this bot, ignoring case as Telegram does. Before the
+/// username is known every name is taken to be ours.
+pub fn is_bot_username(name: &str) -> bool {
+    BOT_USERNAME
+        .get()
+        .is_none_or(|username| username.eq_ignore_ascii_case(name))
+}
+
+/// `t.me` link that opens a private chat with the bot, once its username is known
+pub fn bot_link() -> Option<String> {
+    BOT_USERNAME
+        .get()
+        .map(|username| format!("https://t.me/{username}"))
+}
+
+/// `t.me` link that opens the bot and redeems `token` in one tap, once the bot's username
+/// is known
+pub fn deep_link(token: &str) -> Option<String> {
+    BOT_USERNAME
+        .get()
+        .map(|username| format!("https://t.me/{username}?start={token}"))
+}
+
+/// Issues a one-time token that links whoever redeems it to `vm_id`
+pub async fn issue(vm_id: &str, ttl_secs: i64) -> sqlx::Result<String> {
+    let token: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect();
+    let token = format!("{TOKEN_PREFIX}{token}");
+    let now = now_unix();
+    sqlx::query(
+        "INSERT INTO registration_tokens (token, vm_id, created_at, expires_at) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(&token)
+    .bind(vm_id)
+    .bind(now)
+    .bind(now + ttl_secs)
+    .execute(&*DB)
+    .await?;
+    Ok(token)
+}
+
+/// Returns the VM id behind an unexpired token
+pub async fn resolve(token: &str) -> sqlx::Result<Option<String>> {
+    if !token.starts_with(TOKEN_PREFIX) {
+        return Ok(None);
+    }
+    sqlx::query_scalar("SELECT vm_id FROM registration_tokens WHERE token = $1 AND expires_at > $2")
+        .bind(token)
+        .bind(now_unix())
+        .fetch_optional(&*DB)
+        .await
+}
+
+/// Invalidates a token once it has been redeemed
+pub async fn consume(token: &str) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM registration_tokens WHERE token = $1")
+        .bind(token)
+        .execute(&*DB)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,189 @@
+use rand::{Rng, distributions::Alphanumeric};
+use serde_json::json;
+use teloxide::types::ChatId;
+
+use crate::{
+    audit::{self, Actor},
+    begin_write, fraud, now_unix,
+};
+
+/// Prefix that tells transfer codes apart from VM ids in `/transfer` and `/start`
+pub const CODE_PREFIX: &str = "xfr-";
+/// How long a transfer code can be redeemed
+pub const CODE_TTL_SECS: i64 = 24 * 3600;
+
+pub enum Offer {
+    Issued { code: String, vm_id: String },
+    NotYours,
+}
+
+pub enum Accept {
+    Transferred {
+        vm_id: String,
+        from: ChatId,
+        moved_secs: i64,
+    },
+    /// Unknown, expired or already redeemed
+    InvalidCode,
+    OwnVm,
+    /// The VM changed hands or was unlinked since the code was issued
+    NoLongerOwned,
+    /// Leaving the balance behind needs another of the sender's VMs to hold it
+    SenderMustClaim,
+    /// Balances don't move out of a chat under fraud review
+    UnderReview,
+}
+
+/// Issues a code that hands `vm_id` to whoever redeems it, replacing any earlier code for
+/// the same VM
+pub async fn offer(from: ChatId, vm_id: &str, with_balance: bool) -> sqlx::Result<Offer> {
+    let (_write, mut tx) = begin_write().await?;
+    let owned: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM agent_records WHERE vm_id = $1 AND telegram_chat_id = $2",
+    )
+    .bind(vm_id)
+    .bind(from.0)
+    .fetch_one(&mut *tx)
+    .await?;
+    if owned == 0 {
+        return Ok(Offer::NotYours);
+    }
+    let code: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect();
+    let code = format!("{CODE_PREFIX}{code}");
+    let now = now_unix();
+    sqlx::query("DELETE FROM vm_transfers WHERE vm_id = $1")
+        .bind(vm_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        r#"
+INSERT INTO vm_transfers (code, vm_id, from_chat_id, with_balance, created_at, expires_at)
+VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+    )
+    .bind(&code)
+    .bind(vm_id)
+    .bind(from.0)
+    .bind(i64::from(with_balance))
+    .bind(now)
+    .bind(now + CODE_TTL_SECS)
+    .execute(&mut *tx)
+    .await?;
+    audit::record(
+        &mut tx,
+        &Actor::Chat(from),
+        "vm_transfer_offered",
+        Some(from),
+        json!({ "vm_id": vm_id, "with_balance": with_balance }),
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(Offer::Issued {
+        code,
+        vm_id: vm_id.to_owned(),
+    })
+}
+
+/// Redeems `code` for chat `to`, moving the VM (and its balance, if the code says so) in
+/// one transaction. The VM's uptime history stays with it, as it does for any relink; its
+/// nickname doesn't.
+pub async fn accept(to: ChatId, code: &str) -> sqlx::Result<Accept> {
+    let (_write, mut tx) = begin_write().await?;
+    let offer: Option<(String, i64, i64)> = sqlx::query_as(
+        "SELECT vm_id, from_chat_id, with_balance FROM vm_transfers WHERE code = $1 AND expires_at > $2",
+    )
+    .bind(code)
+    .bind(now_unix())
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some((vm_id, from, with_balance)) = offer else {
+        return Ok(Accept::InvalidCode);
+    };
+    let (from, with_balance) = (ChatId(from), with_balance != 0);
+    if from == to {
+        return Ok(Accept::OwnVm);
+    }
+    let balance: Option<i64> = sqlx::query_scalar(
+        "SELECT up_secs + bonus_secs - paid_secs FROM agent_records WHERE vm_id = $1 AND telegram_chat_id = $2",
+    )
+    .bind(&vm_id)
+    .bind(from.0)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(balance) = balance else {
+        return Ok(Accept::NoLongerOwned);
+    };
+    let moved_secs = if with_balance {
+        if balance > 0 && fraud::under_review(&mut tx, from).await? {
+            return Ok(Accept::UnderReview);
+        }
+        balance
+    } else {
+        if balance != 0 {
+            let kept = sqlx::query(
+                r#"
+UPDATE agent_records SET bonus_secs = bonus_secs + $1
+WHERE vm_id = (SELECT MIN(vm_id) FROM agent_records WHERE telegram_chat_id = $2 AND vm_id <> $3)
+                "#,
+            )
+            .bind(balance)
+            .bind(from.0)
+            .bind(&vm_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            // A negative balance, left by rounding, is simply dropped with the VM
+            if kept == 0 && balance > 0 {
+                return Ok(Accept::SenderMustClaim);
+            }
+        }
+        0
+    };
+    sqlx::query(
+        r#"
+UPDATE agent_records SET
+    telegram_chat_id = $1,
+    linked_at = $2,
+    nickname = NULL,
+    paid_secs = paid_secs + $3
+WHERE vm_id = $4
+        "#,
+    )
+    .bind(to.0)
+    .bind(now_unix())
+    .bind(balance - moved_secs)
+    .bind(&vm_id)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("DELETE FROM vm_transfers WHERE code = $1")
+        .bind(code)
+        .execute(&mut *tx)
+        .await?;
+    for (chat_id, role) in [(from, "sender"), (to, "recipient")] {
+        audit::record(
+            &mut tx,
+            &Actor::Chat(to),
+            "vm_transferred",
+            Some(chat_id),
+            json!({
+                "vm_id": vm_id,
+                "from": from.0,
+                "to": to.0,
+                "role": role,
+                "moved_secs": moved_secs,
+            }),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    tracing::info!("vm {vm_id} transferred from chat {from} to {to} with {moved_secs}s");
+    Ok(Accept::Transferred {
+        vm_id,
+        from,
+        moved_secs,
+    })
+}
@@ -0,0 +1,430 @@
+use std::{collections::HashMap, path::PathBuf, sync::Mutex};
+
+use ed25519_dalek::{Signature, VerifyingKey};
+use isahc::{
+    config::{CaCertificate, SslOption},
+    prelude::*,
+};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{HTTP_TIMEOUT, now_unix, proxy};
+
+/// The endpoint polled before `vm_api_url` existed, which takes the secret in the query
+pub const LEGACY_URL: &str = "http://104.194.80.160:3000/available_vms";
+/// What [`fetch_available`]'s errors show in place of the secret
+const REDACTED: &str = "[redacted]";
+/// The fleet the top-level `vm_api_*` settings describe, and that VMs first seen through a
+/// heartbeat belong to
+pub const DEFAULT_FLEET: &str = "default";
+
+/// One availability API and the VMs it reports. The top-level `vm_api_*` settings are the
+/// [`DEFAULT_FLEET`]; each entry of `fleets` adds another, polled on its own.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Fleet {
+    /// What `agent_records.fleet` and the logs call it
+    pub name: String,
+    pub url: String,
+    pub secret: String,
+    /// Defaults to `query` for [`LEGACY_URL`] and to `header` for any other
+    #[serde(default)]
+    pub auth: Option<Auth>,
+    #[serde(default = "default_tls_verify")]
+    pub tls_verify: bool,
+    #[serde(default)]
+    pub ca_path: Option<PathBuf>,
+    #[serde(default)]
+    pub public_key: Option<String>,
+    /// Reward per second of uptime relative to the default fleet's, paid out as bonus
+    /// the way reward events are
+    #[serde(default = "default_reward_multiplier")]
+    pub reward_multiplier: f64,
+}
+
+fn default_tls_verify() -> bool {
+    true
+}
+
+fn default_reward_multiplier() -> f64 {
+    1.0
+}
+
+impl Fleet {
+    pub fn auth(&self) -> Auth {
+        match self.auth {
+            Some(auth) => auth,
+            None if self.url == LEGACY_URL => Auth::Query,
+            None => Auth::Header,
+        }
+    }
+
+    fn public_key(&self) -> Option<VerifyingKey> {
+        let key = self.public_key.as_deref()?;
+        Some(public_key(key).expect("public_key checked by validate"))
+    }
+
+    /// The name of its `field` in the config, for errors
+    fn setting(&self, field: &str) -> String {
+        if self.name == DEFAULT_FLEET {
+            format!("vm_api_{field}")
+        } else {
+            format!("fleets.{}.{field}", self.name)
+        }
+    }
+
+    pub fn validate(&self, prod: bool) -> anyhow::Result<()> {
+        let setting = |field| self.setting(field);
+        anyhow::ensure!(
+            !self.secret.is_empty(),
+            "{} can't be empty",
+            setting("secret")
+        );
+        let https = self.url.starts_with("https://");
+        anyhow::ensure!(
+            https || self.url.starts_with("http://"),
+            "{} must be an http(s) URL",
+            setting("url")
+        );
+        anyhow::ensure!(
+            https || (self.tls_verify && self.ca_path.is_none()),
+            "{} and {} only apply to an https {}",
+            setting("tls_verify"),
+            setting("ca_path"),
+            setting("url")
+        );
+        anyhow::ensure!(
+            self.tls_verify || !prod,
+            "{} can't be turned off in prod; trust the certificate with {}",
+            setting("tls_verify"),
+            setting("ca_path")
+        );
+        if let Some(key) = &self.public_key {
+            public_key(key).map_err(|e| anyhow::anyhow!("{}: {e}", setting("public_key")))?;
+        }
+        if let Some(path) = &self.ca_path {
+            anyhow::ensure!(
+                path.is_file(),
+                "{} {} is not a file",
+                setting("ca_path"),
+                path.display()
+            );
+        }
+        anyhow::ensure!(
+            self.reward_multiplier > 0.0,
+            "{} must be positive",
+            setting("reward_multiplier")
+        );
+        Ok(())
+    }
+}
+
+/// How the VM API is handed `vm_api_secret`
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Auth {
+    Header,
+    /// In the URL, where anything that logs URLs sees it. isahc's request spans are
+    /// capped at `info` while it's in use, see [`crate::telemetry::init`].
+    Query,
+}
+
+/// Signed responses older than this are refused, so a recorded one can't be replayed later
+const MAX_SIGNATURE_AGE_SECS: i64 = 300;
+/// `signed_at` of the newest signed response accepted per fleet, which any later one must
+/// exceed
+static LAST_SIGNED_AT: Lazy<Mutex<HashMap<String, i64>>> = Lazy::new(Default::default);
+
+/// A response signed with the key the fleet's `public_key` names. The signature is over the
+/// bytes of `payload`, which holds a [`SignedPayload`] as JSON text.
+#[derive(Deserialize)]
+struct Envelope {
+    payload: String,
+    /// Hex-encoded ed25519 signature
+    signature: String,
+}
+
+#[derive(Deserialize)]
+struct SignedPayload {
+    /// Unix time the response was signed
+    signed_at: i64,
+    /// What an unsigned response holds
+    vms: Map<String, Value>,
+}
+
+/// Longest VM id the availability API is trusted to send
+const MAX_VM_ID_LEN: usize = 128;
+/// Heartbeats this far in the future are clock skew at best and are discarded
+const MAX_HEARTBEAT_SKEW_SECS: i64 = 300;
+
+/// One entry of the availability response, after validation
+#[derive(Clone, Debug, PartialEq)]
+pub struct AvailableVm {
+    pub vm_id: String,
+    pub region: Option<String>,
+    pub version: Option<String>,
+    pub bandwidth_mbps: Option<f64>,
+    /// Unix time the VM last checked in with the fleet, as reported upstream
+    pub last_heartbeat: Option<i64>,
+    /// Fields not modelled above, kept so later features have them without a schema change
+    pub extra: Map<String, Value>,
+}
+
+/// Per-VM object as sent by the availability API. Every field is optional because older
+/// deployments answer with bare values.
+#[derive(Debug, Default, Deserialize)]
+struct RawVm {
+    #[serde(default)]
+    region: Option<String>,
+    #[serde(default)]
+    version: Option<String>,
+    #[serde(default, alias = "bandwidth")]
+    bandwidth_mbps: Option<f64>,
+    #[serde(default, alias = "heartbeat")]
+    last_heartbeat: Option<i64>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// Fetches and validates the VMs `fleet` currently reports as available. The secret is
+/// scrubbed from errors, since a server may well echo the URL or headers it was sent.
+// The URL may carry the secret, so it stays out of the span
+#[tracing::instrument(
+    name = "vm_api.available_vms",
+    skip_all,
+    fields(
+        otel.kind = "client",
+        fleet = %fleet.name,
+        http.response.status_code = tracing::field::Empty,
+    )
+)]
+pub async fn fetch_available(fleet: &Fleet) -> anyhow::Result<Vec<AvailableVm>> {
+    fetch(fleet)
+        .await
+        .map_err(|e| anyhow::anyhow!(format!("{e:#}").replace(&fleet.secret, REDACTED)))
+}
+
+async fn fetch(fleet: &Fleet) -> anyhow::Result<Vec<AvailableVm>> {
+    let url = &fleet.url;
+    let mut request = match fleet.auth() {
+        Auth::Header => isahc::Request::get(url).header(
+            isahc::http::header::AUTHORIZATION,
+            format!("Bearer {}", fleet.secret),
+        ),
+        Auth::Query => {
+            let separator = if url.contains('?') { '&' } else { '?' };
+            isahc::Request::get(format!("{url}{separator}secret={}", fleet.secret))
+        }
+    };
+    if !fleet.tls_verify {
+        request = request.ssl_options(
+            SslOption::DANGER_ACCEPT_INVALID_CERTS | SslOption::DANGER_ACCEPT_INVALID_HOSTS,
+        );
+    }
+    if let Some(path) = &fleet.ca_path {
+        request = request.ssl_ca_certificate(CaCertificate::file(path));
+    }
+    let request = request.timeout(HTTP_TIMEOUT).body(())?;
+    let mut response = proxy::HTTP.send_async(request).await?;
+    tracing::Span::current().record("http.response.status_code", response.status().as_u16());
+    let body = response.text().await?;
+    // An error page isn't an empty fleet
+    anyhow::ensure!(
+        response.status().is_success(),
+        "VM API returned {}: {body}",
+        response.status()
+    );
+    match fleet.public_key() {
+        Some(key) => open(&fleet.name, &body, &key, now_unix()),
+        None => parse(&body, now_unix()),
+    }
+}
+
+/// Parses a signed response, refusing it unless it carries a valid signature by `key`
+/// and is newer than the fleet's last one, so a compromised endpoint can't credit any
+/// uptime
+fn open(fleet: &str, body: &str, key: &VerifyingKey, now: i64) -> anyhow::Result<Vec<AvailableVm>> {
+    let envelope: Envelope = serde_json::from_str(body)
+        .map_err(|e| anyhow::anyhow!("VM API response is not signed: {e}"))?;
+    let signature = hex::decode(&envelope.signature)
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .ok_or_else(|| anyhow::anyhow!("VM API signature is not 64 hex-encoded bytes"))?;
+    key.verify_strict(envelope.payload.as_bytes(), &signature)
+        .map_err(|_| anyhow::anyhow!("VM API signature does not match the fleet's public key"))?;
+
+    let payload: SignedPayload = serde_json::from_str(&envelope.payload)?;
+    anyhow::ensure!(
+        payload.signed_at > now - MAX_SIGNATURE_AGE_SECS
+            && payload.signed_at <= now + MAX_HEARTBEAT_SKEW_SECS,
+        "VM API response was signed at {}, too far from now ({now})",
+        payload.signed_at
+    );
+    let mut last_signed_at = LAST_SIGNED_AT.lock().unwrap();
+    let last = last_signed_at.entry(fleet.to_owned()).or_insert(i64::MIN);
+    anyhow::ensure!(
+        payload.signed_at > *last,
+        "VM API response signed at {} is no newer than the last one ({last})",
+        payload.signed_at
+    );
+    *last = payload.signed_at;
+    drop(last_signed_at);
+    Ok(entries(payload.vms, now))
+}
+
+/// Reads a fleet's `public_key`, hex-encoded
+fn public_key(hex_key: &str) -> anyhow::Result<VerifyingKey> {
+    let bytes: [u8; 32] = hex::decode(hex_key.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| anyhow::anyhow!("must be 32 hex-encoded bytes"))?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| anyhow::anyhow!("not an ed25519 key: {e}"))
+}
+
+/// Parses the availability response. A malformed document is an error; a malformed entry
+/// is logged and skipped, and a malformed field is logged and dropped, so one bad VM can't
+/// stop the rest of the fleet from being credited.
+pub fn parse(body: &str, now: i64) -> anyhow::Result<Vec<AvailableVm>> {
+    let entries_by_id: Map<String, Value> = serde_json::from_str(body)?;
+    Ok(entries(entries_by_id, now))
+}
+
+fn entries(entries_by_id: Map<String, Value>, now: i64) -> Vec<AvailableVm> {
+    entries_by_id
+        .into_iter()
+        .filter_map(|(vm_id, value)| entry(vm_id, value, now))
+        .collect()
+}
+
+/// Validates one VM and whatever metadata came with it, which is the same whether it was
+/// listed by the availability API or sent as a heartbeat body. `None` if the id is invalid.
+pub fn entry(vm_id: String, value: Value, now: i64) -> Option<AvailableVm> {
+    if !valid_vm_id(&vm_id) {
+        tracing::warn!("skipping VM with invalid id {vm_id:?}");
+        return None;
+    }
+    let raw = match value {
+        Value::Object(_) => match serde_json::from_value::<RawVm>(value) {
+            Ok(raw) => raw,
+            Err(e) => {
+                tracing::warn!("ignoring malformed metadata for {vm_id}: {e}");
+                RawVm::default()
+            }
+        },
+        _ => RawVm::default(),
+    };
+    Some(validate(vm_id, raw, now))
+}
+
+pub fn valid_vm_id(vm_id: &str) -> bool {
+    !vm_id.is_empty()
+        && vm_id.len() <= MAX_VM_ID_LEN
+        && vm_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+fn validate(vm_id: String, raw: RawVm, now: i64) -> AvailableVm {
+    let short_text = |field: &str, value: Option<String>| {
+        let value = value
+            .map(|v| v.trim().to_owned())
+            .filter(|v| !v.is_empty())?;
+        if value.len() > 64 || value.chars().any(char::is_control) {
+            tracing::warn!("dropping invalid {field} {value:?} for {vm_id}");
+            return None;
+        }
+        Some(value)
+    };
+    let region = short_text("region", raw.region);
+    let version = short_text("version", raw.version);
+    let bandwidth_mbps = raw.bandwidth_mbps.filter(|b| {
+        let ok = b.is_finite() && *b >= 0.0;
+        if !ok {
+            tracing::warn!("dropping invalid bandwidth {b} for {vm_id}");
+        }
+        ok
+    });
+    let last_heartbeat = raw.last_heartbeat.filter(|t| {
+        let ok = *t > 0 && *t <= now + MAX_HEARTBEAT_SKEW_SECS;
+        if !ok {
+            tracing::warn!("dropping invalid heartbeat {t} for {vm_id}");
+        }
+        ok
+    });
+    AvailableVm {
+        vm_id,
+        region,
+        version,
+        bandwidth_mbps,
+        last_heartbeat,
+        extra: raw.extra,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+    use serde_json::json;
+
+    use super::*;
+
+    const NOW: i64 = 1_790_000_000;
+
+    /// A response listing one VM, signed at `signed_at` by `key`
+    fn signed(key: &SigningKey, signed_at: i64) -> String {
+        let payload =
+            json!({ "signed_at": signed_at, "vms": { "vm-1": { "region": "sg" } } }).to_string();
+        let signature = hex::encode(key.sign(payload.as_bytes()).to_bytes());
+        json!({ "payload": payload, "signature": signature }).to_string()
+    }
+
+    #[test]
+    fn open_accepts_a_fresh_signature_by_the_fleet_key() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let vms = open("open-good", &signed(&key, NOW), &key.verifying_key(), NOW).unwrap();
+        assert_eq!(vms.len(), 1);
+        assert_eq!(vms[0].vm_id, "vm-1");
+        assert_eq!(vms[0].region.as_deref(), Some("sg"));
+    }
+
+    #[test]
+    fn open_refuses_a_tampered_payload() {
+        let key = SigningKey::from_bytes(&[2; 32]);
+        let body = signed(&key, NOW).replace("sg", "us");
+        assert!(open("open-bad", &body, &key.verifying_key(), NOW).is_err());
+        let body = json!({ "payload": "{}", "signature": "not hex" }).to_string();
+        assert!(open("open-bad", &body, &key.verifying_key(), NOW).is_err());
+        assert!(open("open-bad", "{}", &key.verifying_key(), NOW).is_err());
+    }
+
+    #[test]
+    fn open_refuses_another_key() {
+        let (key, other) = (
+            SigningKey::from_bytes(&[3; 32]),
+            SigningKey::from_bytes(&[4; 32]),
+        );
+        let body = signed(&other, NOW);
+        assert!(open("open-other", &body, &key.verifying_key(), NOW).is_err());
+    }
+
+    #[test]
+    fn open_refuses_replayed_and_stale_responses() {
+        let key = SigningKey::from_bytes(&[5; 32]);
+        let (fleet, public) = ("open-replay", key.verifying_key());
+        let body = signed(&key, NOW);
+        assert!(open(fleet, &body, &public, NOW).is_ok());
+        assert!(open(fleet, &body, &public, NOW + 1).is_err());
+        assert!(open(fleet, &signed(&key, NOW - 1), &public, NOW + 1).is_err());
+        assert!(open(fleet, &signed(&key, NOW + 1), &public, NOW + 1).is_ok());
+        // Each fleet keeps its own high-water mark
+        assert!(open("open-replay-2", &body, &public, NOW + 1).is_ok());
+
+        let fleet = "open-stale";
+        let too_old = NOW - MAX_SIGNATURE_AGE_SECS;
+        assert!(open(fleet, &signed(&key, too_old), &public, NOW).is_err());
+        let too_new = NOW + MAX_HEARTBEAT_SKEW_SECS + 1;
+        assert!(open(fleet, &signed(&key, too_new), &public, NOW).is_err());
+        // Refused responses don't move the mark
+        assert!(open(fleet, &signed(&key, NOW - 10), &public, NOW).is_ok());
+    }
+}
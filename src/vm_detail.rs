@@ -0,0 +1,75 @@
+use teloxide::types::ChatId;
+
+use crate::{DB, i18n, nicknames, now_unix, policy::RewardPolicy, render, streaks, vm_api};
+
+/// `(nickname, up_secs, balance, fleet, last_seen, online_since, region, version)`
+type DetailRow = (
+    Option<String>,
+    i64,
+    i64,
+    String,
+    Option<i64>,
+    Option<i64>,
+    Option<String>,
+    Option<String>,
+);
+
+/// What `/vm` shows about one of `chat_id`'s VMs, or `None` if it isn't theirs
+pub async fn text(chat_id: ChatId, vm_id: &str) -> sqlx::Result<Option<String>> {
+    let row: Option<DetailRow> = sqlx::query_as(
+        r#"
+SELECT a.nickname, a.up_secs, a.up_secs + a.bonus_secs - a.paid_secs, a.fleet,
+       s.last_seen, s.online_since, s.region, s.version
+FROM agent_records a LEFT JOIN vm_status s ON s.vm_id = a.vm_id
+WHERE a.vm_id = $1 AND a.telegram_chat_id = $2
+        "#,
+    )
+    .bind(vm_id)
+    .bind(chat_id.0)
+    .fetch_optional(&*DB)
+    .await?;
+    let Some((nickname, up_secs, balance, fleet, last_seen, online_since, region, version)) = row
+    else {
+        return Ok(None);
+    };
+    let streak = streaks::for_chat(chat_id)
+        .await?
+        .into_iter()
+        .find_map(|(id, streak)| (id == vm_id).then_some(streak));
+
+    let now = now_unix();
+    let name = nicknames::label(vm_id, nickname.as_deref());
+    let mut lines = vec![format!("🖥 {name}")];
+    if nickname.is_some() {
+        lines.push(format!("ID: {vm_id}"));
+    }
+    let status = (vm_id.to_owned(), nickname, last_seen, online_since);
+    lines.push(render::vm_status_line(&status, now));
+    // Older agents report neither
+    let reported = |key: &str, value: &Option<String>| match value {
+        Some(value) => i18n::text(key, &[("value", value)]),
+        None => i18n::text_nested(key, "value", "vm_detail.unknown", &[]),
+    };
+    lines.push(reported("vm_detail.region", &region));
+    lines.push(reported("vm_detail.version", &version));
+    if fleet != vm_api::DEFAULT_FLEET {
+        lines.push(i18n::text("vm_detail.fleet", &[("fleet", &fleet)]));
+    }
+    let uptime = render::format_duration(up_secs);
+    lines.push(i18n::text("vm_detail.uptime", &[("uptime", &uptime)]));
+    lines.push(match streak {
+        Some(streak) => i18n::text("vm_detail.streak", &[("days", &streak.days)]),
+        None => i18n::text("vm_detail.no_streak", &[]),
+    });
+    // A balance is claimed across all of a chat's VMs, so this is only this one's share
+    let policy = RewardPolicy::current();
+    let (unclaimed, days) = (
+        render::format_duration(balance.max(0)),
+        format!("{:.1}", balance.max(0) as f64 / policy.secs_per_day as f64),
+    );
+    lines.push(i18n::text(
+        "vm_detail.unclaimed",
+        &[("unclaimed", &unclaimed), ("days", &days)],
+    ));
+    Ok(Some(lines.join("\n")))
+}